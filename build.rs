@@ -0,0 +1,12 @@
+// compiles the protobuf definitions of the gRPC service when it's built in, with protox so that protoc isn't needed
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/modus.proto");
+        let descriptors = protox::compile(["proto/modus.proto"], ["proto"])?;
+        tonic_build::configure()
+            .build_client(false)
+            .compile_fds(descriptors)?;
+    }
+    Ok(())
+}
@@ -1,23 +1,44 @@
 use proc_macro::TokenStream;
-use syn::{DeriveInput, Ident};
+use syn::{DeriveInput, Fields, Ident, Type};
 
 fn impl_from_trait(ast: DeriveInput) -> TokenStream {
     let ident = ast.ident;
 
-    let fields_idents: Vec<Ident> = match ast.data {
+    let variants: Vec<(Ident, Option<Type>)> = match ast.data {
         syn::Data::Struct(_) => panic!("Structs are not supported by From"),
-        syn::Data::Enum(ref data) => data.variants.iter().map(|f| f.ident.clone()).collect(),
+        syn::Data::Enum(ref data) => data
+            .variants
+            .iter()
+            .map(|v| match v.fields {
+                Fields::Unit => (v.ident.clone(), None),
+                Fields::Unnamed(ref f) if f.unnamed.len() == 1 => {
+                    (v.ident.clone(), Some(f.unnamed[0].ty.clone()))
+                }
+                _ => panic!("Only unit variants and variants with a single unnamed field are supported by From"),
+            })
+            .collect(),
         syn::Data::Union(_) => panic!("Unions are not supported by From"),
     };
 
     let mut tokens = quote::quote!();
-    for variant in fields_idents {
-        tokens.extend(quote::quote! {
-            impl From<#variant> for #ident {
-                fn from (_e: #variant) -> Self {
-                    #ident::#variant
+    for (variant, payload) in variants {
+        tokens.extend(match payload {
+            // the variant is named after the type it's converted from, which is discarded
+            None => quote::quote! {
+                impl From<#variant> for #ident {
+                    fn from (_e: #variant) -> Self {
+                        #ident::#variant
+                    }
+                }
+            },
+            // the type it's converted from is kept in the variant
+            Some(ty) => quote::quote! {
+                impl From<#ty> for #ident {
+                    fn from (e: #ty) -> Self {
+                        #ident::#variant(e)
+                    }
                 }
-            }
+            },
         });
     }
     tokens.into()
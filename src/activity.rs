@@ -0,0 +1,91 @@
+//! Trading activity
+//!
+//! Summarizes how actively the portfolio has been traded from its transactions alone, without fetching any prices:
+//! the value bought and sold, the number of trades, the round trips (shares bought and then sold, every partial sale being one),
+//! how long the sold shares were held on average and the annual turnover.
+//!
+//! The turnover is the lesser of the value bought and sold over the average capital invested at cost, per year, in percentage,
+//! so that buying the initial holdings isn't counted as trading. Values are summed up as they were traded, in the currency of
+//! every ticker, and open positions are held until today.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::activity::activity;
+//! # use modus::stock_returns::Portfolio;
+//! # fn run(portfolio: Portfolio) {
+//!  let report = activity(&portfolio);
+//!  println!("{:?}", report);
+//! # }
+//! ```
+
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::stock_returns::Portfolio;
+
+/// Holds the trading activity of the portfolio
+#[derive(Debug, Serialize)]
+pub struct Activity {
+    pub purchases: f64,
+    pub sales: f64,
+    pub trades: usize,
+    pub round_trips: usize,
+    pub open_positions: usize,
+    // in days, None if nothing has been sold
+    pub average_holding_period: Option<f64>,
+    pub turnover: f64,
+    pub years: f64,
+}
+
+/// Returns the trading activity of the portfolio
+pub fn activity(item: &Portfolio) -> Activity {
+    let today = Utc::now().date_naive();
+    // the buy and the sell (or today) of every lot, with its cost and the value it was sold for
+    let lots: Vec<(NaiveDate, NaiveDate, f64, Option<f64>)> = item
+        .lots()
+        .iter()
+        .filter_map(|n| {
+            let quantity = n.quantity();
+            Some((
+                n.buy_date()?,
+                n.sell_date().unwrap_or(today),
+                n.buy_price() * quantity,
+                n.sell_price().map(|p| p * quantity),
+            ))
+        })
+        .collect();
+    let start = lots.iter().map(|l| l.0).min().unwrap_or(today);
+    let end = lots.iter().map(|l| l.1).max().unwrap_or(today);
+    let days = (end - start).num_days().max(1) as f64;
+    let purchases: f64 = lots.iter().map(|l| l.2).sum();
+    let sales: f64 = lots.iter().filter_map(|l| l.3).sum();
+    // the capital invested at cost on an average day
+    let invested: f64 = lots
+        .iter()
+        .map(|(buy, sell, cost, _)| cost * (*sell - *buy).num_days() as f64)
+        .sum::<f64>()
+        / days;
+    let held: Vec<f64> = lots
+        .iter()
+        .filter(|l| l.3.is_some())
+        .map(|(buy, sell, _, _)| (*sell - *buy).num_days() as f64)
+        .collect();
+    let years = days / 365.25;
+    // every equity is one buy, followed by as many sales as closed lots
+    let buys = item.equities().len();
+    Activity {
+        purchases,
+        sales,
+        trades: buys + held.len(),
+        round_trips: held.len(),
+        open_positions: lots.len() - held.len(),
+        average_holding_period: (!held.is_empty())
+            .then(|| held.iter().sum::<f64>() / held.len() as f64),
+        turnover: if invested > 0.0 {
+            purchases.min(sales) / invested / years * 100.0
+        } else {
+            0.0
+        },
+        years,
+    }
+}
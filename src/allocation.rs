@@ -0,0 +1,101 @@
+//! Allocation breakdown
+//!
+//! Breaks the current allocation of the portfolio down by currency, exchange, instrument type (e.g., EQUITY or ETF) and sector.
+//! The tickers without a sector, such as funds, are grouped as Unclassified.
+//! Only the positions that haven't been sold are taken into account, each one weighted by its market value in USD at the latest price.
+//! The prices, currencies and exchange rates come from the provider of the portfolio, while the exchange, instrument type and
+//! sector are always those Yahoo Finance reports, the other providers not classifying the tickers.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::allocation::breakdown;
+//! # use modus::stock_returns::Portfolio;
+//! # async fn run(portfolio: Portfolio) {
+//!  if let Ok(s) = breakdown(&portfolio).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use time::{Duration, OffsetDateTime};
+
+use crate::fx::FxService;
+use crate::market::fundamentals;
+use crate::provider::{DataProvider, Interval, ProviderError};
+use crate::stock_returns::{Portfolio, StocksError};
+use crate::yahoo_finance::{get_latest, YahooError};
+
+/// Holds the weights in percentage of market value of every currency, exchange, instrument type and sector
+#[derive(Debug, Default, Serialize)]
+pub struct Breakdown {
+    pub currency: BTreeMap<String, f64>,
+    pub exchange: BTreeMap<String, f64>,
+    pub instrument_type: BTreeMap<String, f64>,
+    pub sector: BTreeMap<String, f64>,
+}
+
+// the tickers fetched at the same time
+const CONCURRENT_REQUESTS: usize = 8;
+
+/// Returns the current allocation of the portfolio by currency, exchange, instrument type and sector
+pub async fn breakdown(item: &Portfolio) -> Result<Breakdown, StocksError> {
+    let mut breakdown = Breakdown::default();
+    let mut total = 0.0;
+    let provider = &item.data_provider()?;
+    let fx = &FxService::new(provider);
+    let now = &OffsetDateTime::now_utc();
+    let start = &(*now - Duration::days(7));
+    // the tickers are fetched concurrently rather than one after the other
+    let requests: Vec<_> = item
+        .open_quantities()
+        .into_iter()
+        .map(|(ticker, quantity)| async move {
+            let quotes = provider
+                .get_quotes(ticker, start, now, Interval::Daily)
+                .await
+                .map_err(|e| e.context(ticker, start, now))?;
+            let Some(quote) = quotes.last() else {
+                return Err(StocksError::from(
+                    ProviderError::from(YahooError::EmptyDataSet).context(ticker, start, now),
+                ));
+            };
+            let currency = provider.metadata(ticker).await?.currency;
+            // the exchange rate to USD at the latest price as well
+            let rate = fx.usd_rate(&currency, now).await?;
+            let (metadata, _) = get_latest(ticker).await?;
+            let sector = fundamentals(ticker).await?.sector;
+            Ok::<_, StocksError>((currency, metadata, quote.close * rate * quantity, sector))
+        })
+        .collect();
+    let holdings: Vec<_> = stream::iter(requests)
+        .buffered(CONCURRENT_REQUESTS)
+        .try_collect()
+        .await?;
+    for (currency, metadata, value, sector) in holdings {
+        total += value;
+        *breakdown.currency.entry(currency).or_insert(0.0) += value;
+        *breakdown
+            .exchange
+            .entry(metadata.exchange_name)
+            .or_insert(0.0) += value;
+        *breakdown
+            .instrument_type
+            .entry(metadata.instrument_type)
+            .or_insert(0.0) += value;
+        *breakdown
+            .sector
+            .entry(sector.unwrap_or_else(|| "Unclassified".to_string()))
+            .or_insert(0.0) += value;
+    }
+    for weights in [
+        &mut breakdown.currency,
+        &mut breakdown.exchange,
+        &mut breakdown.instrument_type,
+        &mut breakdown.sector,
+    ] {
+        weights.values_mut().for_each(|v| *v = *v / total * 100.0);
+    }
+    Ok(breakdown)
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde_json::Value;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::http::{client, send};
+use crate::provider::{timestamp, within, DataProvider, Metadata};
+use crate::yahoo_finance::{Interval, ProviderError, Quote};
+
+#[derive(Error, Debug)]
+pub enum AlphaVantageError {
+    #[error("deserializing the response from Alpha Vantage failed: {0}")]
+    DeserializeFailed(#[from] serde_json::Error),
+    #[error("Alpha Vantage refused the request: {0}")]
+    Refused(String),
+    #[error("Alpha Vantage returned no time series")]
+    EmptyDataSet,
+    #[error("Alpha Vantage doesn't know the ticker")]
+    UnknownTicker,
+    #[error("the intraday quotes from Alpha Vantage aren't supported")]
+    Intraday,
+}
+
+/// Alpha Vantage, which needs an API key, read from MODUS_ALPHA_VANTAGE_KEY by from_env
+///
+/// The free key is limited to a few requests a minute, so it's better suited as a fallback for when Yahoo rate limits.
+#[derive(Debug, Clone)]
+pub struct AlphaVantage {
+    key: String,
+}
+
+impl AlphaVantage {
+    pub fn new(key: impl Into<String>) -> AlphaVantage {
+        AlphaVantage { key: key.into() }
+    }
+
+    /// Returns the provider with the key in MODUS_ALPHA_VANTAGE_KEY, None if it isn't set
+    pub fn from_env() -> Option<AlphaVantage> {
+        std::env::var("MODUS_ALPHA_VANTAGE_KEY")
+            .ok()
+            .map(AlphaVantage::new)
+    }
+
+    async fn query(&self, parameters: &str) -> Result<Value, ProviderError> {
+        let response = send(client()?.get(format!(
+            "https://www.alphavantage.co/query?{parameters}&apikey={}",
+            self.key
+        )))
+        .await?
+        .text()
+        .await?;
+        let response: Value = serde_json::from_str(&response).map_err(AlphaVantageError::from)?;
+        // errors and rate limits come as a message with a status of 200
+        for message in ["Error Message", "Note", "Information"] {
+            if let Some(reason) = response.get(message).and_then(Value::as_str) {
+                return Err(AlphaVantageError::Refused(reason.to_string()).into());
+            }
+        }
+        Ok(response)
+    }
+}
+
+// the function of the series at the interval, e.g., TIME_SERIES_WEEKLY
+fn function(prefix: &str, interval: Interval) -> Result<String, AlphaVantageError> {
+    let suffix = match interval {
+        Interval::Daily => "DAILY",
+        Interval::Weekly => "WEEKLY",
+        Interval::Monthly => "MONTHLY",
+        Interval::Minute | Interval::FiveMinutes | Interval::Hourly => {
+            return Err(AlphaVantageError::Intraday)
+        }
+    };
+    Ok(format!("{prefix}_{suffix}"))
+}
+
+// the records of the time series keyed by their date, the fields being named after their position, e.g., "4. close"
+fn parse(response: &Value) -> Result<Vec<Quote>, AlphaVantageError> {
+    let series = response
+        .as_object()
+        .and_then(|o| o.iter().find(|(key, _)| key.contains("Time Series")))
+        .and_then(|(_, series)| series.as_object())
+        .ok_or(AlphaVantageError::EmptyDataSet)?;
+    let mut quotes = Vec::new();
+    for (date, record) in series {
+        let fields: BTreeMap<&str, f64> = record
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, value)| {
+                let name = name.split_once(". ").map_or(name.as_str(), |(_, n)| n);
+                Some((name, value.as_str()?.parse::<f64>().ok()?))
+            })
+            .collect();
+        let (Ok(date), Some(close)) = (date.parse::<NaiveDate>(), fields.get("close")) else {
+            continue;
+        };
+        let field = |name: &str| fields.get(name).copied().unwrap_or(0.0);
+        quotes.push(Quote {
+            timestamp: timestamp(date),
+            open: field("open"),
+            high: field("high"),
+            low: field("low"),
+            volume: field("volume") as u64,
+            close: *close,
+            adjclose: fields.get("adjusted close").copied().unwrap_or(*close),
+            ..Default::default()
+        });
+    }
+    Ok(quotes)
+}
+
+impl DataProvider for AlphaVantage {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let quotes = async {
+            let function = match interval {
+                // the daily adjusted series is only in the premium tier
+                Interval::Daily => function("TIME_SERIES", interval)?,
+                _ => function("TIME_SERIES", interval)? + "_ADJUSTED",
+            };
+            let response = self
+                .query(&format!(
+                    "function={function}&symbol={ticker}&outputsize=full"
+                ))
+                .await?;
+            Ok(within(parse(&response)?, start, end))
+        };
+        quotes
+            .await
+            .map_err(|e: ProviderError| e.context(ticker, start, end))
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        let response = self
+            .query(&format!("function=SYMBOL_SEARCH&keywords={ticker}"))
+            .await?;
+        response
+            .get("bestMatches")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .find(|m| {
+                m.get("1. symbol")
+                    .and_then(Value::as_str)
+                    .is_some_and(|s| s.eq_ignore_ascii_case(ticker))
+            })
+            .and_then(|m| m.get("8. currency").and_then(Value::as_str))
+            .map(|currency| Metadata {
+                symbol: ticker.to_uppercase(),
+                currency: currency.to_string(),
+            })
+            .ok_or_else(|| AlphaVantageError::UnknownTicker.into())
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let pair = format!("{currency}USD");
+        let rates = async {
+            let response = self
+                .query(&format!(
+                    "function={}&from_symbol={currency}&to_symbol=USD&outputsize=full",
+                    function("FX", interval)?
+                ))
+                .await?;
+            Ok(within(parse(&response)?, start, end))
+        };
+        rates
+            .await
+            .map_err(|e: ProviderError| e.context(&pair, start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reads_the_records_of_the_series_in_chronological_order() {
+        let response = json!({
+            "Meta Data": {"2. Symbol": "IBM"},
+            "Weekly Adjusted Time Series": {
+                "2024-05-10": {"1. open": "167.5", "2. high": "170.3", "3. low": "165.1", "4. close": "167.2",
+                    "5. adjusted close": "165.6", "6. volume": "3200000"},
+                "2024-05-03": {"1. open": "166.0", "2. high": "168.9", "3. low": "164.2", "4. close": "165.7",
+                    "5. adjusted close": "164.1", "6. volume": "2900000"},
+                "not a date": {"4. close": "1.0"}
+            }
+        });
+        let quotes = parse(&response).unwrap();
+        assert_eq!(quotes.len(), 2);
+        let first = NaiveDate::from_ymd_opt(2024, 5, 3).unwrap();
+        assert_eq!(quotes[0].timestamp, timestamp(first));
+        assert_eq!((quotes[0].close, quotes[0].adjclose), (165.7, 164.1));
+        assert_eq!((quotes[1].high, quotes[1].volume), (170.3, 3200000));
+    }
+
+    #[test]
+    fn the_unadjusted_series_are_their_own_adjusted_close() {
+        let response = json!({
+            "Time Series FX (Daily)": {"2024-05-03": {"1. open": "1.07", "4. close": "1.08"}}
+        });
+        let quotes = parse(&response).unwrap();
+        assert_eq!(
+            (quotes[0].close, quotes[0].adjclose, quotes[0].low),
+            (1.08, 1.08, 0.0)
+        );
+    }
+
+    #[test]
+    fn a_response_without_a_series_is_empty() {
+        let response = json!({"Meta Data": {}});
+        assert!(matches!(
+            parse(&response),
+            Err(AlphaVantageError::EmptyDataSet)
+        ));
+        assert!(matches!(
+            function("TIME_SERIES", Interval::Hourly),
+            Err(AlphaVantageError::Intraday)
+        ));
+        assert_eq!(function("FX", Interval::Monthly).unwrap(), "FX_MONTHLY");
+    }
+}
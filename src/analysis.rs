@@ -0,0 +1,97 @@
+//! Portfolio analysis
+//!
+//! Runs the returns, the risk report, with its drawdowns, and the benchmark comparison of a portfolio at once, over a single
+//! set of quotes: the daily history of every ticker and of the benchmark is fetched once and shared by all of them, rather
+//! than each of total_returns, risk and compare fetching it again.
+//!
+//! As for risk and compare, the contribution of every holding to the variance and the benchmark are computed from the quotes
+//! in USD, and the benchmark comparison is None when the portfolio doesn't include one.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::analysis::analyze;
+//! # use modus::stock_returns::{Equity, Portfolio, Transaction, TransactionDate};
+//! # async fn run() {
+//!  let buy = Transaction::new(TransactionDate::new(2023, 2, 1), 354.0);
+//!  let portfolio = Portfolio::new(vec![Equity::new("MSFT", buy, None, 3.0)]).with_benchmark("SPY");
+//!  if let Ok(s) = analyze(&portfolio).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use futures::try_join;
+
+use crate::benchmark::{against, BenchmarkComparison};
+use crate::holdings::contribution;
+use crate::provider::DataProvider;
+use crate::risk::{risk_metrics, volatility, RiskReport};
+use crate::stock_returns::{
+    cagr, histories, histories_returns, mean_return, portfolio_range, years, Aggregation,
+    MeanReturn, Portfolio, StocksError,
+};
+use crate::yahoo_finance::Interval;
+
+/// Holds the daily returns of the portfolio in percentage and their summary, its risk report and its comparison with the benchmark
+#[derive(Debug)]
+pub struct Analysis {
+    pub returns: BTreeMap<String, f64>,
+    pub volatility: f64,
+    pub cagr: f64,
+    pub years: f64,
+    pub mean_return: MeanReturn,
+    pub risk: RiskReport,
+    pub benchmark: Option<BenchmarkComparison>,
+}
+
+/// Returns the analysis of the portfolio with the quotes of its provider
+pub async fn analyze(item: &Portfolio) -> Result<Analysis, StocksError> {
+    analyze_with(&item.data_provider()?, item).await
+}
+
+/// Returns the same analysis as analyze with the quotes fetched from the given provider
+pub async fn analyze_with(
+    provider: &impl DataProvider,
+    item: &Portfolio,
+) -> Result<Analysis, StocksError> {
+    let (start, end) = portfolio_range(item)?;
+    // the benchmark is fetched along with the holdings, unless it's one of them
+    let benchmark = item
+        .benchmark()
+        .filter(|ticker| !item.tickers().contains(ticker));
+    let (histories, benchmark_history) =
+        try_join!(histories(provider, item, Interval::Daily), async {
+            match benchmark {
+                Some(ticker) => Ok(Some(
+                    provider
+                        .get_history(ticker, &start, &end, Interval::Daily)
+                        .await?,
+                )),
+                None => Ok(None),
+            }
+        })?;
+    let returns = histories_returns(item, &histories, Interval::Daily)?;
+    let usd: BTreeMap<String, _> = histories
+        .iter()
+        .map(|(ticker, history)| (ticker.to_string(), history.quotes.clone()))
+        .collect();
+    let benchmark = item.benchmark().map(|ticker| {
+        let quotes = match &benchmark_history {
+            Some(history) => &history.quotes,
+            None => &usd[ticker],
+        };
+        against(ticker, &returns, quotes)
+    });
+    Ok(Analysis {
+        volatility: volatility(&returns, Interval::Daily),
+        cagr: cagr(&returns),
+        years: years(&returns),
+        mean_return: mean_return(&returns, Aggregation::Daily),
+        risk: RiskReport {
+            contribution: Some(contribution(item, &usd)),
+            ..risk_metrics(&returns, item.rfr(), item.confidence())
+        },
+        benchmark,
+        returns,
+    })
+}
@@ -0,0 +1,484 @@
+//! Strategy simulation
+//!
+//! Simulates investing in a set of tickers with target weights over historical data, in USD, and rebalancing back
+//! to those weights at a given frequency (monthly, quarterly or yearly).
+//! The result is compared against buy-and-hold, that is, investing with the same weights at the beginning and never trading again.
+//! Only the dates every ticker has in common are simulated.
+//!
+//! Turnover is the one-way traded value over the value of the portfolio, summed up for every rebalance, and every ticker
+//! bought or sold in a rebalance counts as a transaction.
+//! Contributions can be added periodically, being invested at the target weights, and every trade can cost a fraction of
+//! its value, which is taken from the portfolio. Both apply to buy-and-hold as well. The risk metrics of the strategy are
+//! computed from its returns like those of a portfolio, see the risk module.
+//!
+//! It can also simulate dollar-cost averaging: investing a fixed amount split by the weights at the first date of every period
+//! (weekly, monthly, quarterly or yearly), returning the units bought, their cost basis and value, and the return on
+//! what has been invested up to every date.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::backtest::{rebalance, Strategy};
+//! # use serde_json::json;
+//! # async fn run() -> serde_json::Result<()> {
+//!  let strategy: Strategy = serde_json::from_value(json!({"weights": {"MSFT": 0.6, "KO": 0.4},
+//!     "start": {"year": 2015, "month": 1, "day": 1}, "frequency": "quarterly", "value": 10000.0,
+//!     "contributions": {"amount": 500.0, "frequency": "monthly"}, "cost": 0.001}))?;
+//!  if let Ok(s) = rebalance(&strategy).await { println!("{:?}", s); }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use time::macros::time;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::provider::{get_histories, Interval, Source};
+use crate::risk::{risk_metrics, RiskReport};
+use crate::statistics::dot;
+use crate::stock_returns::{
+    data_provider, validate_weights, StocksError, TransactionDate, Violation,
+};
+
+/// Holds the target weights, the simulated range (until now unless an end is given) and how often the portfolio is rebalanced,
+/// along with the initial value (1 by default), an optional schedule of contributions, the cost of trading as a fraction
+/// of the traded value (0.001 for 10 basis points) and the annual risk-free rate for the risk metrics
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Strategy {
+    weights: BTreeMap<String, f64>,
+    start: TransactionDate,
+    #[serde(default)]
+    end: Option<TransactionDate>,
+    frequency: Frequency,
+    #[serde(default = "default_value")]
+    value: f64,
+    #[serde(default)]
+    contributions: Option<Contributions>,
+    #[serde(default)]
+    cost: f64,
+    #[serde(default)]
+    rfr: f64,
+    // the provider the quotes are fetched from, the one in MODUS_PROVIDER by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<Source>,
+}
+
+impl Strategy {
+    /// Checks the weights, the range, the value, the contributions and the cost, returning all the violations found
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        validate_weights("weights", &self.weights, &mut violations);
+        validate_range(&self.start, self.end.as_ref(), &mut violations);
+        if !self.value.is_finite() || self.value <= 0.0 {
+            violations.push(Violation::new("value", "must be positive"));
+        }
+        if let Some(contributions) = self.contributions {
+            if !contributions.amount.is_finite() || contributions.amount < 0.0 {
+                violations.push(Violation::new(
+                    "contributions.amount",
+                    "must be a number that isn't negative",
+                ));
+            }
+        }
+        if !(0.0..1.0).contains(&self.cost) {
+            violations.push(Violation::new(
+                "cost",
+                "must be a fraction of the traded value, from 0 up to 1",
+            ));
+        }
+        if !self.rfr.is_finite() {
+            violations.push(Violation::new("rfr", "must be a number"));
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+fn default_value() -> f64 {
+    1.0
+}
+
+// checks that the start and the end are real dates and that the end isn't before the start
+fn validate_range(
+    start: &TransactionDate,
+    end: Option<&TransactionDate>,
+    violations: &mut Vec<Violation>,
+) {
+    let start = start.validate("start", violations);
+    if let Some(end) = end {
+        match (start, end.validate("end", violations)) {
+            (Some(start), Some(end)) if end < start => {
+                violations.push(Violation::new("end", "must not be before the start"))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The amount added to the portfolio at the first date of every period, split by the target weights
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Contributions {
+    amount: f64,
+    frequency: Frequency,
+}
+
+/// How often something happens during a simulation
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl Frequency {
+    // identifies the period a date belongs to, a new period starting when it changes
+    fn period(&self, date: &NaiveDate) -> (i32, u32) {
+        match self {
+            Frequency::Weekly => (date.iso_week().year(), date.iso_week().week()),
+            Frequency::Monthly => (date.year(), date.month()),
+            Frequency::Quarterly => (date.year(), (date.month() - 1) / 3),
+            Frequency::Yearly => (date.year(), 0),
+        }
+    }
+}
+
+/// Holds the cumulative returns in percentage of the strategy and of buy-and-hold, along with the trading it took,
+/// what trading cost and the risk metrics of the strategy.
+/// The returns are time-weighted, so that contributions don't count as growth
+#[derive(Debug, Serialize)]
+pub struct Simulation {
+    pub returns: BTreeMap<String, f64>,
+    pub buy_and_hold: BTreeMap<String, f64>,
+    pub turnover: f64,
+    pub rebalances: u32,
+    pub transactions: u32,
+    pub costs: f64,
+    pub value: f64,
+    pub risk: RiskReport,
+}
+
+/// Simulates the strategy with periodic rebalancing and buy-and-hold over the same range
+pub async fn rebalance(item: &Strategy) -> Result<Simulation, StocksError> {
+    let start = item.start.at(time!(0:00:00))?;
+    let end = match item.end {
+        Some(end) => end.at(time!(23:59:59))?,
+        None => OffsetDateTime::now_utc(),
+    };
+    let prices = prices(item.provider, item.weights.keys(), &start, &end).await?;
+    let total: f64 = item.weights.values().sum();
+    let weights: Vec<f64> = item.weights.values().map(|w| w / total).collect();
+    let strategy = simulate(&prices, &weights, item, true);
+    let buy_and_hold = simulate(&prices, &weights, item, false);
+    Ok(Simulation {
+        risk: risk_metrics(&strategy.returns, item.rfr, &[0.95, 0.99]),
+        returns: strategy.returns,
+        buy_and_hold: buy_and_hold.returns,
+        turnover: strategy.turnover,
+        rebalances: strategy.rebalances,
+        transactions: strategy.transactions,
+        costs: strategy.costs,
+        value: strategy.value,
+    })
+}
+
+/// Holds the tickers with the weights the amount is split by, the simulated range (until now unless an end is given),
+/// the amount in USD invested in every period and how often
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Plan {
+    weights: BTreeMap<String, f64>,
+    amount: f64,
+    start: TransactionDate,
+    #[serde(default)]
+    end: Option<TransactionDate>,
+    frequency: Frequency,
+    // the provider the quotes are fetched from, the one in MODUS_PROVIDER by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<Source>,
+}
+
+/// Holds the holdings at the end, the total invested and its value in USD, and the return in percentage
+/// of the holdings over what had been invested at every date
+#[derive(Debug, Serialize)]
+pub struct Accumulation {
+    pub holdings: BTreeMap<String, Holding>,
+    pub investments: u32,
+    pub invested: f64,
+    pub value: f64,
+    pub returns: BTreeMap<String, f64>,
+}
+
+impl Plan {
+    /// Checks the weights, the amount and the range, returning all the violations found
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        validate_weights("weights", &self.weights, &mut violations);
+        if !self.amount.is_finite() || self.amount <= 0.0 {
+            violations.push(Violation::new("amount", "must be positive"));
+        }
+        validate_range(&self.start, self.end.as_ref(), &mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Holds the units bought of a ticker, what they cost and their value at the end, in USD
+#[derive(Debug, Serialize)]
+pub struct Holding {
+    pub units: f64,
+    pub cost_basis: f64,
+    pub value: f64,
+}
+
+/// Simulates investing the amount at the first date of every period
+pub async fn dca(item: &Plan) -> Result<Accumulation, StocksError> {
+    let start = item.start.at(time!(0:00:00))?;
+    let end = match item.end {
+        Some(end) => end.at(time!(23:59:59))?,
+        None => OffsetDateTime::now_utc(),
+    };
+    let prices = prices(item.provider, item.weights.keys(), &start, &end).await?;
+    let total: f64 = item.weights.values().sum();
+    let weights: Vec<f64> = item.weights.values().map(|w| w / total).collect();
+    let mut units = vec![0.0; weights.len()];
+    let mut cost = vec![0.0; weights.len()];
+    let (mut investments, mut returns) = (0, BTreeMap::new());
+    let mut period = None;
+    for (date, p) in prices.iter() {
+        if period != Some(item.frequency.period(date)) {
+            period = Some(item.frequency.period(date));
+            investments += 1;
+            for (i, w) in weights.iter().enumerate() {
+                units[i] += item.amount * w / p[i];
+                cost[i] += item.amount * w;
+            }
+        }
+        let invested: f64 = cost.iter().sum();
+        returns.insert(date.to_string(), (dot(&units, p) / invested - 1.0) * 100.0);
+    }
+    let last = prices.values().last();
+    let holdings: BTreeMap<String, Holding> = item
+        .weights
+        .keys()
+        .enumerate()
+        .map(|(i, ticker)| {
+            (
+                ticker.clone(),
+                Holding {
+                    units: units[i],
+                    cost_basis: cost[i],
+                    value: last.map(|p| units[i] * p[i]).unwrap_or(0.0),
+                },
+            )
+        })
+        .collect();
+    Ok(Accumulation {
+        investments,
+        invested: cost.iter().sum(),
+        value: holdings.values().map(|h| h.value).sum(),
+        holdings,
+        returns,
+    })
+}
+
+// the prices of every ticker from the provider, in the same order as the tickers, at the dates they all have in common
+pub(crate) async fn prices(
+    provider: Option<Source>,
+    tickers: impl Iterator<Item = &String>,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+) -> Result<BTreeMap<NaiveDate, Vec<f64>>, StocksError> {
+    let tickers: Vec<&str> = tickers.map(String::as_str).collect();
+    let histories = get_histories(
+        &data_provider(provider)?,
+        tickers.iter().copied(),
+        start,
+        end,
+        Interval::Daily,
+    )
+    .await?;
+    let series: Vec<BTreeMap<NaiveDate, f64>> = histories
+        .iter()
+        .map(|history| {
+            history
+                .quotes
+                .iter()
+                .map(|q| {
+                    (
+                        DateTime::from_timestamp(q.timestamp as i64, 0)
+                            .unwrap_or_default()
+                            .date_naive(),
+                        q.adjclose,
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    Ok(series
+        .first()
+        .map(|first| {
+            first
+                .keys()
+                .filter_map(|date| {
+                    series
+                        .iter()
+                        .map(|s| s.get(date).copied())
+                        .collect::<Option<Vec<f64>>>()
+                        .map(|p| (*date, p))
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+// the outcome of trading the prices in one way
+struct Run {
+    returns: BTreeMap<String, f64>,
+    turnover: f64,
+    rebalances: u32,
+    transactions: u32,
+    costs: f64,
+    value: f64,
+}
+
+// invests at the weights at the beginning and adds the contributions, rebalancing back to the weights unless it's buy-and-hold
+fn simulate(
+    prices: &BTreeMap<NaiveDate, Vec<f64>>,
+    weights: &[f64],
+    strategy: &Strategy,
+    rebalancing: bool,
+) -> Run {
+    let mut run = Run {
+        returns: BTreeMap::new(),
+        turnover: 0.0,
+        rebalances: 0,
+        transactions: 0,
+        costs: 0.0,
+        value: 0.0,
+    };
+    let first = match prices.values().next() {
+        Some(first) => first,
+        None => return run,
+    };
+    // units of each ticker bought with the initial value, net of what buying them costs
+    let net = strategy.value * (1.0 - strategy.cost);
+    run.costs = strategy.value * strategy.cost;
+    let mut units: Vec<f64> = weights
+        .iter()
+        .zip(first.iter())
+        .map(|(w, p)| w * net / p)
+        .collect();
+    // the portfolio is split into shares so that contributions buy shares at the value of the day instead of adding to the return
+    let mut shares = strategy.value;
+    let period = |frequency: Frequency| prices.keys().next().map(|d| frequency.period(d));
+    let mut rebalanced = period(strategy.frequency);
+    let mut contributed = strategy.contributions.and_then(|c| period(c.frequency));
+    for (date, p) in prices.iter() {
+        let mut value = dot(&units, p);
+        if let Some(contributions) = strategy.contributions {
+            if contributed != Some(contributions.frequency.period(date)) {
+                contributed = Some(contributions.frequency.period(date));
+                shares += contributions.amount * shares / value;
+                run.costs += contributions.amount * strategy.cost;
+                let net = contributions.amount * (1.0 - strategy.cost);
+                for ((u, w), p) in units.iter_mut().zip(weights.iter()).zip(p.iter()) {
+                    *u += w * net / p;
+                }
+                value = dot(&units, p);
+            }
+        }
+        if rebalancing && rebalanced != Some(strategy.frequency.period(date)) {
+            rebalanced = Some(strategy.frequency.period(date));
+            let traded: Vec<f64> = weights
+                .iter()
+                .zip(units.iter())
+                .zip(p.iter())
+                .map(|((w, u), p)| (w * value - u * p).abs())
+                .collect();
+            let cost = traded.iter().sum::<f64>() * strategy.cost;
+            run.rebalances += 1;
+            run.transactions += traded.iter().filter(|t| **t > 1e-9 * value).count() as u32;
+            // one-way, as every sale pays for a purchase
+            run.turnover += traded.iter().sum::<f64>() / 2.0 / value * 100.0;
+            run.costs += cost;
+            value -= cost;
+            units = weights
+                .iter()
+                .zip(p.iter())
+                .map(|(w, p)| w * value / p)
+                .collect();
+        }
+        run.returns
+            .insert(date.to_string(), (value / shares - 1.0) * 100.0);
+        run.value = value;
+    }
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn fields(violations: Result<(), Vec<Violation>>) -> Vec<String> {
+        violations
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| v.field)
+            .collect()
+    }
+
+    fn strategy(body: Value) -> Strategy {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn accepts_a_strategy_with_the_defaults() {
+        let item = strategy(json!({
+            "weights": {"MSFT": 0.6, "KO": 0.4},
+            "start": {"year": 2015, "month": 1, "day": 1},
+            "frequency": "quarterly"
+        }));
+        assert!(item.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_an_end_before_the_start_and_costs_that_take_everything() {
+        let item = strategy(json!({
+            "weights": {"MSFT": 1.0},
+            "start": {"year": 2015, "month": 2, "day": 1},
+            "end": {"year": 2015, "month": 1, "day": 31},
+            "frequency": "monthly",
+            "cost": 1.0,
+            "contributions": {"amount": -5.0, "frequency": "monthly"}
+        }));
+        assert_eq!(
+            fields(item.validate()),
+            vec!["end", "contributions.amount", "cost"]
+        );
+    }
+
+    #[test]
+    fn rejects_a_plan_without_weights_amount_or_real_dates() {
+        let item: Plan = serde_json::from_value(json!({
+            "weights": {},
+            "amount": 0.0,
+            "start": {"year": 2023, "month": 2, "day": 30},
+            "frequency": "weekly"
+        }))
+        .unwrap();
+        assert_eq!(fields(item.validate()), vec!["weights", "amount", "start"]);
+    }
+}
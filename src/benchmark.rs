@@ -0,0 +1,147 @@
+//! Benchmark comparison
+//!
+//! Compares the portfolio against a benchmark ticker (e.g., SPY) over the same range, the benchmark being fetched from the provider
+//! of the portfolio and converted to USD like any other holding.
+//! Only the dates both series have in common are taken into account.
+//!
+//! The tracking error is the annualized standard deviation of the daily returns in excess of the benchmark, and the
+//! information ratio is the annualized mean of those excess returns over the tracking error.
+//! The relative drawdown is the deepest fall of the portfolio over the benchmark from its previous peak, i.e., the worst
+//! stretch of underperformance, and the upside (downside) capture ratio is the mean daily return of the portfolio over that
+//! of the benchmark on the days the benchmark rose (fell), both in percentage.
+//!
+//! The function compare takes a Portfolio with a benchmark and returns a Result<Option<BenchmarkComparison>, StocksError>,
+//! the Option being None when the portfolio doesn't include a benchmark.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::benchmark::compare;
+//! # use modus::stock_returns::{Equity, Portfolio, Transaction, TransactionDate};
+//! # async fn run() {
+//!  let buy = Transaction::new(TransactionDate::new(2023, 2, 1), 354.0);
+//!  let portfolio = Portfolio::new(vec![Equity::new("MSFT", buy, None, 3.0)]).with_benchmark("SPY");
+//!  if let Ok(Some(s)) = compare(&portfolio).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::DateTime;
+use futures::try_join;
+use serde::Serialize;
+
+use crate::provider::{DataProvider, Interval, Quote};
+use crate::statistics::{covariance, mean, period_returns, std_dev, variance, PERIODS_PER_YEAR};
+use crate::stock_returns::{portfolio_range, total_returns_with, Currency, Portfolio, StocksError};
+
+/// Holds the comparison between the portfolio and the benchmark, returns are in percentage
+#[derive(Debug, Serialize)]
+pub struct BenchmarkComparison {
+    pub benchmark: String,
+    pub excess_return: f64,
+    pub beta: f64,
+    pub alpha: f64,
+    pub correlation: f64,
+    pub tracking_error: f64,
+    pub information_ratio: f64,
+    pub relative_drawdown: f64,
+    pub upside_capture: f64,
+    pub downside_capture: f64,
+}
+
+/// Returns the excess return, beta, annualized alpha, correlation, annualized tracking error, information ratio,
+/// maximum relative drawdown and upside and downside capture ratios of the portfolio versus its benchmark
+pub async fn compare(item: &Portfolio) -> Result<Option<BenchmarkComparison>, StocksError> {
+    let ticker = match item.benchmark() {
+        Some(ticker) => ticker,
+        None => return Ok(None),
+    };
+    let provider = item.data_provider()?;
+    let (start, end) = portfolio_range(item)?;
+    // the benchmark is fetched from the same provider as the holdings and along with them
+    let (portfolio, benchmark) = try_join!(
+        total_returns_with(&provider, item, Interval::Daily, Currency::Usd),
+        async {
+            Ok(provider
+                .get_history(ticker, &start, &end, Interval::Daily)
+                .await?
+                .quotes)
+        }
+    )?;
+    Ok(Some(against(ticker, &portfolio, &benchmark)))
+}
+
+// the comparison of the cumulative returns of the portfolio in percentage with the daily quotes of the benchmark
+pub(crate) fn against(
+    ticker: &str,
+    portfolio: &BTreeMap<String, f64>,
+    benchmark: &[Quote],
+) -> BenchmarkComparison {
+    // the benchmark value at every date, keyed like the portfolio returns
+    let benchmark: BTreeMap<String, f64> = benchmark
+        .iter()
+        .map(|q| {
+            (
+                DateTime::from_timestamp(q.timestamp as i64, 0)
+                    .unwrap_or_default()
+                    .date_naive()
+                    .to_string(),
+                q.adjclose,
+            )
+        })
+        .collect();
+    // keeps the dates both have in common, turning the cumulative percentage back into a value
+    let (portfolio_values, benchmark_values): (Vec<f64>, Vec<f64>) = portfolio
+        .iter()
+        .filter_map(|(date, r)| benchmark.get(date).map(|b| (1.0 + r / 100.0, *b)))
+        .unzip();
+    comparison(ticker, &portfolio_values, &benchmark_values)
+}
+
+fn comparison(ticker: &str, portfolio: &[f64], benchmark: &[f64]) -> BenchmarkComparison {
+    let total = |values: &[f64]| match (values.first(), values.last()) {
+        (Some(first), Some(last)) => last / first - 1.0,
+        _ => 0.0,
+    };
+    let (rp, rb) = (period_returns(portfolio), period_returns(benchmark));
+    let beta = covariance(&rp, &rb) / variance(&rb);
+    // the daily returns in excess of the benchmark
+    let active: Vec<f64> = rp.iter().zip(rb.iter()).map(|(p, b)| p - b).collect();
+    let tracking_error = std_dev(&active) * PERIODS_PER_YEAR.sqrt();
+    BenchmarkComparison {
+        benchmark: ticker.to_string(),
+        excess_return: (total(portfolio) - total(benchmark)) * 100.0,
+        beta,
+        // Jensen's alpha with a null risk-free rate
+        alpha: (mean(&rp) - beta * mean(&rb)) * PERIODS_PER_YEAR * 100.0,
+        correlation: covariance(&rp, &rb) / (std_dev(&rp) * std_dev(&rb)),
+        tracking_error: tracking_error * 100.0,
+        information_ratio: mean(&active) * PERIODS_PER_YEAR / tracking_error,
+        relative_drawdown: relative_drawdown(portfolio, benchmark) * 100.0,
+        upside_capture: capture(&rp, &rb, |b| b > 0.0) * 100.0,
+        downside_capture: capture(&rp, &rb, |b| b < 0.0) * 100.0,
+    }
+}
+
+// the deepest fall of the value of the portfolio relative to the benchmark from its previous peak, negative
+fn relative_drawdown(portfolio: &[f64], benchmark: &[f64]) -> f64 {
+    let mut peak = f64::MIN;
+    portfolio
+        .iter()
+        .zip(benchmark.iter())
+        .fold(0.0, |worst: f64, (p, b)| {
+            let relative = p / b;
+            peak = peak.max(relative);
+            worst.min(relative / peak - 1.0)
+        })
+}
+
+// the mean return of the portfolio over the mean return of the benchmark on the dates the benchmark moved as selected
+fn capture(portfolio: &[f64], benchmark: &[f64], selected: impl Fn(f64) -> bool) -> f64 {
+    let (p, b): (Vec<f64>, Vec<f64>) = portfolio
+        .iter()
+        .zip(benchmark.iter())
+        .filter(|(_, b)| selected(**b))
+        .unzip();
+    mean(&p) / mean(&b)
+}
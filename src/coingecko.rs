@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate};
+use serde::Deserialize;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::http::{client, send};
+use crate::provider::{timestamp, within, DataProvider, Metadata};
+use crate::yahoo_finance::{Interval, ProviderError, Quote};
+
+#[derive(Error, Debug)]
+pub enum CoinGeckoError {
+    #[error("deserializing the response from CoinGecko failed: {0}")]
+    DeserializeFailed(#[from] serde_json::Error),
+    #[error("CoinGecko refused the request: {0}")]
+    Refused(String),
+    #[error("the intraday quotes from CoinGecko aren't supported")]
+    Intraday,
+    #[error("CoinGecko has no exchange rates of {0}, its prices are all in USD")]
+    NoExchangeRates(String),
+}
+
+/// CoinGecko, for the coins that Yahoo doesn't list, with the demo API key in MODUS_COINGECKO_KEY if it's set
+///
+/// Tickers are the ids of the coins in CoinGecko, e.g., bitcoin or matic-network, the symbols of the most traded ones, e.g., BTC
+/// or BTC-USD, being mapped to them. The prices are in USD, the close of every day being the last price CoinGecko has for it,
+/// and as the coins trade all week long their returns have a data point for every day.
+#[derive(Debug, Default, Clone)]
+pub struct CoinGecko {
+    key: Option<String>,
+}
+
+// the ids of the coins with the most common symbols
+const COINS: [(&str, &str); 12] = [
+    ("BTC", "bitcoin"),
+    ("ETH", "ethereum"),
+    ("USDT", "tether"),
+    ("BNB", "binancecoin"),
+    ("SOL", "solana"),
+    ("XRP", "ripple"),
+    ("USDC", "usd-coin"),
+    ("ADA", "cardano"),
+    ("DOGE", "dogecoin"),
+    ("AVAX", "avalanche-2"),
+    ("DOT", "polkadot"),
+    ("LTC", "litecoin"),
+];
+
+// the id of the coin of the ticker, the ticker itself if it isn't a known symbol
+fn id(ticker: &str) -> String {
+    let symbol = ticker
+        .len()
+        .checked_sub(4)
+        .filter(|&i| ticker.is_char_boundary(i) && ticker[i..].eq_ignore_ascii_case("-USD"))
+        .map_or(ticker, |i| &ticker[..i]);
+    COINS
+        .iter()
+        .find(|(s, _)| s.eq_ignore_ascii_case(symbol))
+        .map_or_else(|| symbol.to_lowercase(), |(_, id)| id.to_string())
+}
+
+// the prices and volumes in the range, every point being its time in milliseconds and the value
+#[derive(Deserialize, Debug)]
+struct Chart {
+    #[serde(default)]
+    prices: Vec<(f64, f64)>,
+    #[serde(default)]
+    total_volumes: Vec<(f64, f64)>,
+    // unknown coins have an error and rate limited requests a status
+    error: Option<String>,
+    status: Option<Status>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Status {
+    error_message: Option<String>,
+}
+
+impl CoinGecko {
+    pub fn new(key: Option<String>) -> CoinGecko {
+        CoinGecko { key }
+    }
+
+    /// Returns the provider with the key in MODUS_COINGECKO_KEY, without one if it isn't set
+    pub fn from_env() -> CoinGecko {
+        CoinGecko::new(std::env::var("MODUS_COINGECKO_KEY").ok())
+    }
+
+    async fn chart(
+        &self,
+        id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<Chart, ProviderError> {
+        let mut request = client()?.get(format!(
+            "https://api.coingecko.com/api/v3/coins/{id}/market_chart/range?vs_currency=usd&from={}&to={}",
+            start.unix_timestamp(),
+            end.unix_timestamp()
+        ));
+        if let Some(key) = &self.key {
+            request = request.header("x-cg-demo-api-key", key);
+        }
+        let response = send(request).await?.text().await?;
+        let chart: Chart = serde_json::from_str(&response).map_err(CoinGeckoError::from)?;
+        match chart
+            .error
+            .clone()
+            .or(chart.status.as_ref().and_then(|s| s.error_message.clone()))
+        {
+            Some(error) => Err(CoinGeckoError::Refused(error).into()),
+            None => Ok(chart),
+        }
+    }
+}
+
+// the last price and the volume of every day, week (from Monday) or month, dated by its first day, as CoinGecko gives
+// several prices a day for the shorter ranges
+fn bars(chart: Chart, interval: Interval) -> Result<Vec<Quote>, CoinGeckoError> {
+    let period = |date: NaiveDate| match interval {
+        Interval::Daily => Ok(date),
+        Interval::Weekly => Ok(date - Duration::days(date.weekday().num_days_from_monday() as i64)),
+        Interval::Monthly => Ok(date.with_day(1).unwrap_or(date)),
+        Interval::Minute | Interval::FiveMinutes | Interval::Hourly => {
+            Err(CoinGeckoError::Intraday)
+        }
+    };
+    let date = |ms: f64| {
+        DateTime::from_timestamp_millis(ms as i64)
+            .unwrap_or_default()
+            .date_naive()
+    };
+    let volumes: BTreeMap<i64, f64> = chart
+        .total_volumes
+        .iter()
+        .map(|(ms, volume)| (*ms as i64, *volume))
+        .collect();
+    let mut periods: BTreeMap<NaiveDate, Quote> = BTreeMap::new();
+    for (ms, price) in chart.prices {
+        let start = period(date(ms))?;
+        let volume = volumes.get(&(ms as i64)).copied().unwrap_or_default() as u64;
+        periods
+            .entry(start)
+            .and_modify(|q| {
+                q.high = q.high.max(price);
+                q.low = q.low.min(price);
+                q.close = price;
+                q.adjclose = price;
+                q.volume = volume;
+            })
+            .or_insert(Quote {
+                timestamp: timestamp(start),
+                open: price,
+                high: price,
+                low: price,
+                volume,
+                close: price,
+                adjclose: price,
+                ..Default::default()
+            });
+    }
+    Ok(periods.into_values().collect())
+}
+
+impl DataProvider for CoinGecko {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let quotes = async {
+            let chart = self.chart(&id(ticker), start, end).await?;
+            Ok(within(bars(chart, interval)?, start, end))
+        };
+        quotes
+            .await
+            .map_err(|e: ProviderError| e.context(ticker, start, end))
+    }
+
+    // every price is in USD
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        Ok(Metadata {
+            symbol: id(ticker),
+            currency: "USD".to_string(),
+        })
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        _start: &OffsetDateTime,
+        _end: &OffsetDateTime,
+        _interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        Err(CoinGeckoError::NoExchangeRates(currency.to_string()).into())
+    }
+
+    // there are no stocks in CoinGecko
+    async fn probe(&self) -> Result<(), ProviderError> {
+        let end = OffsetDateTime::now_utc();
+        self.get_quotes(
+            "BTC",
+            &(end - time::Duration::days(7)),
+            &end,
+            Interval::Daily,
+        )
+        .await
+        .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: f64 = 60.0 * 60.0 * 1000.0;
+    // 2024-01-01, a Monday
+    const MONDAY: f64 = 1704067200000.0;
+
+    fn chart(prices: &[(f64, f64)]) -> Chart {
+        Chart {
+            prices: prices.to_vec(),
+            total_volumes: prices.iter().map(|(ms, _)| (*ms, 1000.0)).collect(),
+            error: None,
+            status: None,
+        }
+    }
+
+    #[test]
+    fn the_days_close_at_their_last_price() {
+        let prices = [
+            (MONDAY, 100.0),
+            (MONDAY + 6.0 * HOUR, 120.0),
+            (MONDAY + 12.0 * HOUR, 90.0),
+            (MONDAY + 24.0 * HOUR, 95.0),
+        ];
+        let quotes = bars(chart(&prices), Interval::Daily).unwrap();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(
+            quotes[0].timestamp,
+            timestamp(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap())
+        );
+        assert_eq!(
+            (
+                quotes[0].open,
+                quotes[0].high,
+                quotes[0].low,
+                quotes[0].close,
+                quotes[0].adjclose
+            ),
+            (100.0, 120.0, 90.0, 90.0, 90.0)
+        );
+        assert_eq!((quotes[1].close, quotes[1].volume), (95.0, 1000));
+    }
+
+    #[test]
+    fn the_weeks_start_on_monday_and_the_months_on_their_first_day() {
+        let prices = [
+            (MONDAY + 24.0 * HOUR, 100.0),
+            (MONDAY + 7.0 * 24.0 * HOUR, 110.0),
+            (MONDAY + 31.0 * 24.0 * HOUR, 120.0),
+        ];
+        let dates = |interval| -> Vec<u64> {
+            bars(chart(&prices), interval)
+                .unwrap()
+                .iter()
+                .map(|q| q.timestamp)
+                .collect()
+        };
+        let day = |m, d| timestamp(NaiveDate::from_ymd_opt(2024, m, d).unwrap());
+        assert_eq!(dates(Interval::Weekly), [day(1, 1), day(1, 8), day(1, 29)]);
+        assert_eq!(dates(Interval::Monthly), [day(1, 1), day(2, 1)]);
+        assert!(matches!(
+            bars(chart(&prices), Interval::Hourly),
+            Err(CoinGeckoError::Intraday)
+        ));
+    }
+
+    #[test]
+    fn the_common_symbols_are_mapped_to_the_ids() {
+        assert_eq!(id("BTC-USD"), "bitcoin");
+        assert_eq!(id("eth"), "ethereum");
+        assert_eq!(id("matic-network"), "matic-network");
+        assert_eq!(id("PEPE-usd"), "pepe");
+    }
+}
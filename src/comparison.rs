@@ -0,0 +1,182 @@
+//! Portfolio comparison
+//!
+//! Compares several named portfolios against each other, e.g., your own picks versus your pension fund.
+//! The returns of every portfolio are computed as usual and then restricted to the dates all of them have in common,
+//! rebased so that every series starts at zero at the first of those dates.
+//!
+//! Over that common period, every portfolio gets its total return, compound annual growth rate, annualized volatility,
+//! Sharpe ratio (with its own risk-free rate) and maximum drawdown, along with the excess return over every other portfolio
+//! and the correlation matrix of the daily returns.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::comparison::{compare_portfolios, Portfolios};
+//! # use modus::stock_returns::Portfolio;
+//! # use serde_json::json;
+//! # async fn run(picks: Portfolio, pension: Portfolio) -> serde_json::Result<()> {
+//!  let portfolios: Portfolios = serde_json::from_value(json!({"portfolios": {"picks": picks, "pension": pension}}))?;
+//!  if let Ok(Some(s)) = compare_portfolios(&portfolios).await { println!("{:?}", s); }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::holdings::Matrix;
+use crate::risk::risk_metrics;
+use crate::statistics::{covariance, period_returns, std_dev};
+use crate::stock_returns::{cagr, total_returns, Interval, Portfolio, StocksError, Violation};
+
+/// Holds the portfolios to compare, keyed by name
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Portfolios {
+    portfolios: BTreeMap<String, Portfolio>,
+}
+
+impl Portfolios {
+    /// Checks every portfolio, the fields of the violations being prefixed by the name of the portfolio
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        if self.portfolios.is_empty() {
+            violations.push(Violation::new(
+                "portfolios",
+                "must have at least one portfolio",
+            ));
+        }
+        for (name, portfolio) in self.portfolios.iter() {
+            if let Err(v) = portfolio.validate() {
+                violations.extend(v.into_iter().map(|v| Violation {
+                    field: format!("portfolios.{name}.{}", v.field),
+                    ..v
+                }));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Merges every portfolio into one, in the order of their names, keeping the settings of the first one
+    pub fn merged(self) -> Option<Portfolio> {
+        self.portfolios.into_values().reduce(Portfolio::merge)
+    }
+}
+
+/// Holds the rebased returns of every portfolio on the common dates, their statistics and how they relate to each other
+#[derive(Debug, Serialize)]
+pub struct PortfolioComparison {
+    pub returns: BTreeMap<String, BTreeMap<String, f64>>,
+    pub statistics: BTreeMap<String, Summary>,
+    pub excess_return: Matrix,
+    pub correlation: Matrix,
+}
+
+/// Holds the performance of a portfolio over the common period, in percentage
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub total_return: f64,
+    pub cagr: f64,
+    pub volatility: f64,
+    pub sharpe: f64,
+    pub max_drawdown: f64,
+}
+
+/// Returns the comparison of the portfolios, None if they have no dates in common
+pub async fn compare_portfolios(
+    item: &Portfolios,
+) -> Result<Option<PortfolioComparison>, StocksError> {
+    let mut series = BTreeMap::new();
+    for (name, portfolio) in item.portfolios.iter() {
+        series.insert(
+            name.clone(),
+            total_returns(portfolio, Interval::Daily).await?,
+        );
+    }
+    let common: Vec<&String> = match series.values().next() {
+        Some(first) => first
+            .keys()
+            .filter(|date| series.values().all(|s| s.contains_key(*date)))
+            .collect(),
+        None => return Ok(None),
+    };
+    if common.is_empty() {
+        return Ok(None);
+    }
+    // the cumulative returns on the common dates, starting at zero
+    let returns: BTreeMap<String, BTreeMap<String, f64>> = series
+        .iter()
+        .map(|(name, s)| {
+            let base = 1.0 + s[common[0]] / 100.0;
+            (
+                name.clone(),
+                common
+                    .iter()
+                    .map(|date| {
+                        (
+                            (*date).clone(),
+                            ((1.0 + s[*date] / 100.0) / base - 1.0) * 100.0,
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+    let statistics: BTreeMap<String, Summary> = returns
+        .iter()
+        .map(|(name, r)| {
+            let metrics = risk_metrics(r, item.portfolios[name].rfr(), &[]);
+            (
+                name.clone(),
+                Summary {
+                    total_return: r.values().last().copied().unwrap_or(0.0),
+                    cagr: cagr(r),
+                    volatility: metrics.volatility,
+                    sharpe: metrics.sharpe,
+                    max_drawdown: metrics.max_drawdown,
+                },
+            )
+        })
+        .collect();
+    let daily: BTreeMap<&String, Vec<f64>> = returns
+        .iter()
+        .map(|(name, r)| {
+            let values: Vec<f64> = r.values().map(|r| 1.0 + r / 100.0).collect();
+            (name, period_returns(&values))
+        })
+        .collect();
+    Ok(Some(PortfolioComparison {
+        excess_return: pairwise(&returns, |a, b| {
+            statistics[a].total_return - statistics[b].total_return
+        }),
+        correlation: pairwise(&returns, |a, b| {
+            let (x, y) = (&daily[a], &daily[b]);
+            covariance(x, y) / (std_dev(x) * std_dev(y))
+        }),
+        returns,
+        statistics,
+    }))
+}
+
+// applies the statistic to every pair of portfolios by name
+fn pairwise<T>(
+    portfolios: &BTreeMap<String, T>,
+    statistic: impl Fn(&String, &String) -> f64,
+) -> Matrix {
+    portfolios
+        .keys()
+        .map(|a| {
+            (
+                a.clone(),
+                portfolios
+                    .keys()
+                    .map(|b| (b.clone(), statistic(a, b)))
+                    .collect(),
+            )
+        })
+        .collect()
+}
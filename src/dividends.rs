@@ -0,0 +1,219 @@
+//! Dividend income
+//!
+//! Summarizes the dividends the portfolio has received, using the dividend events its provider returns along with the quotes,
+//! which only Yahoo Finance reports. The events of every ticker are fetched concurrently.
+//! A position receives a dividend when its ex-dividend date falls between the buy and the sell dates, both included.
+//! The amounts are gross and in the currency of the ticker, and the yield on cost is the income over what was paid for the
+//! positions that received it.
+//!
+//! The income is grouped by calendar year unless told otherwise.
+//!
+//! For the positions still held, the trailing yield is the dividends per share with an ex-dividend date in the last twelve months
+//! over the latest price (the yield on market value) and over the average price paid for the shares held (the yield on cost).
+//!
+//! The events themselves, the dividends, splits and capital gain distributions of every ticker since the first buy, can also
+//! be had as they are.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::dividends::dividend_income;
+//! # use modus::stock_returns::{Aggregation, Portfolio};
+//! # async fn run(portfolio: Portfolio) {
+//!  if let Ok(s) = dividend_income(&portfolio, Aggregation::Yearly).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::DateTime;
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use time::{Duration, OffsetDateTime};
+
+use crate::provider::DataProvider;
+use crate::stock_returns::{portfolio_range, Aggregation, Portfolio, StocksError};
+pub use crate::yahoo_finance::{CapitalGain, Dividend, Events, Split};
+use crate::yahoo_finance::{Interval, ProviderError, YahooError};
+
+// the tickers fetched at the same time
+const CONCURRENT_REQUESTS: usize = 8;
+
+// the currency and the events of every ticker in the range, fetched concurrently from the provider and in the same order
+async fn ticker_events<'a>(
+    provider: &impl DataProvider,
+    tickers: impl IntoIterator<Item = &'a str>,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+) -> Result<Vec<(String, Events)>, ProviderError> {
+    // the requests are made up front, so that the future holds no closure over the tickers and stays Send
+    let requests: Vec<_> = tickers
+        .into_iter()
+        .map(|ticker| provider.get_events(ticker, start, end))
+        .collect();
+    stream::iter(requests)
+        .buffered(CONCURRENT_REQUESTS)
+        .try_collect()
+        .await
+}
+
+/// Holds the currency of the ticker, its total income and the income of every period
+#[derive(Debug, Serialize)]
+pub struct DividendIncome {
+    pub currency: String,
+    pub total: f64,
+    pub periods: BTreeMap<String, PeriodIncome>,
+}
+
+/// Holds the gross income of a period and its yield on cost in percentage
+#[derive(Debug, Default, Serialize)]
+pub struct PeriodIncome {
+    pub gross: f64,
+    pub yield_on_cost: f64,
+    // what was paid for the positions that received dividends in the period
+    #[serde(skip)]
+    cost: f64,
+}
+
+/// Returns the dividend income of every ticker by period
+pub async fn dividend_income(
+    item: &Portfolio,
+    aggregation: Aggregation,
+) -> Result<BTreeMap<String, DividendIncome>, StocksError> {
+    let (start, end) = portfolio_range(item)?;
+    let tickers = item.tickers();
+    let events = ticker_events(
+        &item.data_provider()?,
+        tickers.iter().copied(),
+        &start,
+        &end,
+    )
+    .await?;
+    let mut income = BTreeMap::new();
+    for (ticker, (currency, events)) in tickers.into_iter().zip(events) {
+        let dividends = events.dividends;
+        let mut periods: BTreeMap<String, PeriodIncome> = BTreeMap::new();
+        for n in item.lots().iter().filter(|n| n.ticker() == ticker) {
+            let (buy, sell) = n.range()?;
+            // the periods in which the position received dividends, its cost counting once in each
+            let mut received = BTreeSet::new();
+            for dividend in dividends.iter().filter(|d| {
+                (buy.unix_timestamp()..=sell.unix_timestamp()).contains(&(d.date as i64))
+            }) {
+                let date = DateTime::from_timestamp(dividend.date as i64, 0)
+                    .unwrap_or_default()
+                    .date_naive()
+                    .to_string();
+                let period = aggregation.period(&date).to_string();
+                periods.entry(period.clone()).or_default().gross += dividend.amount * n.quantity();
+                received.insert(period);
+            }
+            for period in received {
+                periods.entry(period).or_default().cost += n.buy_price() * n.quantity();
+            }
+        }
+        periods
+            .values_mut()
+            .for_each(|p| p.yield_on_cost = p.gross / p.cost * 100.0);
+        income.insert(
+            ticker.to_string(),
+            DividendIncome {
+                currency,
+                total: periods.values().map(|p| p.gross).sum(),
+                periods,
+            },
+        );
+    }
+    Ok(income)
+}
+
+/// Holds the dividends per share of the last twelve months and the yield they give on the latest price and on the cost of the shares held,
+/// in the currency of the ticker and the yields in percentage
+#[derive(Debug, Serialize)]
+pub struct TrailingYield {
+    pub currency: String,
+    pub quantity: f64,
+    pub dividends_per_share: f64,
+    pub price: f64,
+    pub cost_per_share: f64,
+    pub yield_on_value: f64,
+    pub yield_on_cost: f64,
+}
+
+/// Returns the trailing yield of every ticker still held
+pub async fn trailing_yield(
+    item: &Portfolio,
+) -> Result<BTreeMap<String, TrailingYield>, StocksError> {
+    let provider = item.data_provider()?;
+    let now = OffsetDateTime::now_utc();
+    let held = item.open_quantities();
+    let year = now - Duration::days(365);
+    let events = ticker_events(&provider, held.keys().copied(), &year, &now).await?;
+    let week = now - Duration::days(7);
+    let requests: Vec<_> = held
+        .keys()
+        .map(|ticker| provider.get_quotes(ticker, &week, &now, Interval::Daily))
+        .collect();
+    let latest: Vec<_> = stream::iter(requests)
+        .buffered(CONCURRENT_REQUESTS)
+        .try_collect()
+        .await?;
+    let mut yields = BTreeMap::new();
+    for (((ticker, quantity), (currency, events)), quotes) in
+        held.into_iter().zip(events).zip(latest)
+    {
+        let dividends = events.dividends;
+        // the latest close in the currency of the ticker, from the last week of quotes
+        let latest = quotes.last().ok_or_else(|| {
+            ProviderError::from(YahooError::EmptyDataSet).context(ticker, &week, &now)
+        })?;
+        let cost: f64 = item
+            .lots()
+            .iter()
+            .filter(|n| n.ticker() == ticker && n.sell_date().is_none())
+            .map(|n| n.buy_price() * n.quantity())
+            .sum();
+        let dividends_per_share: f64 = dividends.iter().map(|d| d.amount).sum();
+        let cost_per_share = cost / quantity;
+        yields.insert(
+            ticker.to_string(),
+            TrailingYield {
+                currency,
+                quantity,
+                dividends_per_share,
+                price: latest.close,
+                cost_per_share,
+                yield_on_value: dividends_per_share / latest.close * 100.0,
+                yield_on_cost: dividends_per_share / cost_per_share * 100.0,
+            },
+        );
+    }
+    Ok(yields)
+}
+
+/// Holds the currency of the ticker and its events, the amounts being per share
+#[derive(Debug, Serialize)]
+pub struct TickerEvents {
+    pub currency: String,
+    #[serde(flatten)]
+    pub events: Events,
+}
+
+/// Returns the dividends, splits and capital gains of every ticker in the range of the portfolio
+pub async fn corporate_actions(
+    item: &Portfolio,
+) -> Result<BTreeMap<String, TickerEvents>, StocksError> {
+    let (start, end) = portfolio_range(item)?;
+    let tickers = item.tickers();
+    let events = ticker_events(
+        &item.data_provider()?,
+        tickers.iter().copied(),
+        &start,
+        &end,
+    )
+    .await?;
+    Ok(tickers
+        .into_iter()
+        .zip(events)
+        .map(|(ticker, (currency, events))| (ticker.to_string(), TickerEvents { currency, events }))
+        .collect())
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use thiserror::Error;
+
+use crate::http::{client, send};
+use crate::yahoo_finance::{FetchError, ProviderError};
+
+#[derive(Error, Debug)]
+pub enum EcbError {
+    #[error("the series from the ECB couldn't be read: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("the ECB returned an empty series")]
+    EmptyDataSet,
+}
+
+/// The harmonised index of consumer prices of the euro area, monthly
+pub const HICP: &str = "ICP/M.U2.N.000000.4.INX";
+
+/// The key of the daily euro foreign exchange reference rate of the currency, the units of it a euro is worth
+pub fn reference_rate(currency: &str) -> String {
+    format!("EXR/D.{}.EUR.SP00.A", currency.to_uppercase())
+}
+
+/// Returns the observations of an ECB series between the dates, by date, the monthly ones being dated the first of the month
+pub async fn get_series(
+    key: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<BTreeMap<NaiveDate, f64>, ProviderError> {
+    let observations = async {
+        // the data API needs no key, and answers with no data at all when there are no observations in the range
+        let response = send(client()?.get(format!(
+            "https://data-api.ecb.europa.eu/service/data/{key}?startPeriod={start}&endPeriod={end}&format=csvdata"
+        )))
+        .await?
+        .text()
+        .await?;
+        Ok(parse(&response)?)
+    };
+    observations.await.map_err(|e: ProviderError| {
+        FetchError {
+            ticker: key.to_string(),
+            start: start.to_string(),
+            end: end.to_string(),
+            message: e.to_string(),
+        }
+        .into()
+    })
+}
+
+// a header naming the columns, of which the period (YYYY-MM-DD or YYYY-MM) and the value, and a row per observation
+fn parse(csv: &str) -> Result<BTreeMap<NaiveDate, f64>, EcbError> {
+    let mut reader = ReaderBuilder::new().from_reader(csv.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let (Some(period), Some(value)) = (column("TIME_PERIOD"), column("OBS_VALUE")) else {
+        return Err(EcbError::EmptyDataSet);
+    };
+    let mut observations = BTreeMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let date = record.get(period).and_then(|d| {
+            d.parse::<NaiveDate>()
+                .ok()
+                .or_else(|| format!("{d}-01").parse().ok())
+        });
+        // the missing observations are NaN
+        let value = record
+            .get(value)
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| v.is_finite());
+        if let (Some(date), Some(value)) = (date, value) {
+            observations.insert(date, value);
+        }
+    }
+    if observations.is_empty() {
+        return Err(EcbError::EmptyDataSet);
+    }
+    Ok(observations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_daily_and_monthly_observations() {
+        let csv = "KEY,FREQ,TIME_PERIOD,OBS_VALUE\n\
+                   EXR.D.USD.EUR.SP00.A,D,2024-05-02,1.0702\n\
+                   EXR.D.USD.EUR.SP00.A,D,2024-05-03,NaN\n\
+                   ICP.M.U2.N.000000.4.INX,M,2024-04,126.5\n";
+        let observations = parse(csv).unwrap();
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[&date(2024, 5, 2)], 1.0702);
+        assert_eq!(observations[&date(2024, 4, 1)], 126.5);
+        // no data at all when there are no observations
+        assert!(matches!(parse(""), Err(EcbError::EmptyDataSet)));
+        assert_eq!(reference_rate("gbp"), "EXR/D.GBP.EUR.SP00.A");
+    }
+}
@@ -0,0 +1,197 @@
+//! Factor exposure
+//!
+//! Regresses the daily returns of the portfolio in excess of the risk-free rate on the market, size and value factors of the
+//! [Fama–French three-factor model](https://en.wikipedia.org/wiki/Fama%E2%80%93French_three-factor_model) by ordinary least squares,
+//! so that one can see how much of the performance is due to each factor and how much is left unexplained.
+//!
+//! The factors can be supplied by date (YYYY-MM-DD) as daily returns in decimals (0.01 for 1%), e.g., from the data library of Kenneth French,
+//! along with the daily risk-free rate. Otherwise they are approximated with ETFs fetched from the provider of the portfolio: the market is SPY in excess
+//! of the risk-free rate of the portfolio, size is small caps over large caps (IWM minus SPY) and value is value over growth
+//! (IWD minus IWF).
+//!
+//! The alpha is the annualized intercept in percentage, the loadings are the sensitivity to each factor and R² the share of the
+//! variance of the excess returns that the factors explain. Only the dates both the portfolio and the factors have are used.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::factors::{exposure, FactorRequest};
+//! # async fn run(request: FactorRequest) {
+//!  if let Ok(Some(s)) = exposure(&request).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::holdings::ticker_returns;
+use crate::provider::get_ticker_quotes;
+use crate::statistics::{mean, solve, PERIODS_PER_YEAR};
+use crate::stock_returns::{
+    portfolio_range, total_returns, Interval, Portfolio, StocksError, Violation,
+};
+
+/// Holds the portfolio along with the factors by date, which are approximated with ETFs when missing
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FactorRequest {
+    #[serde(flatten)]
+    portfolio: Portfolio,
+    #[serde(default)]
+    factors: Option<BTreeMap<String, Factors>>,
+}
+
+impl FactorRequest {
+    pub fn portfolio(&self) -> &Portfolio {
+        &self.portfolio
+    }
+
+    /// Checks the portfolio and the dates and returns of the factors, returning all the violations found
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = match self.portfolio.validate() {
+            Ok(()) => Vec::new(),
+            Err(violations) => violations,
+        };
+        for (date, f) in self.factors.iter().flatten() {
+            if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+                violations.push(Violation::new(
+                    format!("factors.{date}"),
+                    "must be keyed by a date (YYYY-MM-DD)",
+                ));
+            }
+            if ![f.market, f.size, f.value, f.rf]
+                .iter()
+                .all(|x| x.is_finite())
+            {
+                violations.push(Violation::new(format!("factors.{date}"), "must be numbers"));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// The daily returns of the factors and the daily risk-free rate, in decimals
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Factors {
+    market: f64,
+    size: f64,
+    value: f64,
+    #[serde(default)]
+    rf: f64,
+}
+
+/// Holds the annualized alpha in percentage, the loadings on every factor and how well they explain the returns
+#[derive(Debug, Serialize)]
+pub struct FactorExposure {
+    pub alpha: f64,
+    pub market: f64,
+    pub size: f64,
+    pub value: f64,
+    pub r_squared: f64,
+    pub observations: usize,
+}
+
+// the ETFs the factors are approximated with
+const PROXIES: [&str; 4] = ["SPY", "IWM", "IWD", "IWF"];
+
+/// Returns the factor exposure of the portfolio, None if there aren't enough dates in common to regress or the factors are collinear
+pub async fn exposure(item: &FactorRequest) -> Result<Option<FactorExposure>, StocksError> {
+    let cumulative = total_returns(&item.portfolio, Interval::Daily).await?;
+    let returns = daily_returns(&cumulative);
+    let factors = match &item.factors {
+        Some(factors) => factors.clone(),
+        None => proxies(&item.portfolio).await?,
+    };
+    let (y, x): (Vec<f64>, Vec<[f64; 4]>) = returns
+        .iter()
+        .filter_map(|(date, r)| {
+            factors
+                .get(*date)
+                .map(|f| (r - f.rf, [1.0, f.market, f.size, f.value]))
+        })
+        .unzip();
+    Ok(regress(&y, &x))
+}
+
+// the return of every date from the cumulative returns in percentage
+fn daily_returns(returns: &BTreeMap<String, f64>) -> BTreeMap<&String, f64> {
+    let mut previous = 1.0;
+    returns
+        .iter()
+        .map(|(date, r)| {
+            let value = 1.0 + r / 100.0;
+            let daily = value / previous - 1.0;
+            previous = value;
+            (date, daily)
+        })
+        .collect()
+}
+
+// the factors built from the returns of the ETFs over the holding period of the portfolio
+async fn proxies(item: &Portfolio) -> Result<BTreeMap<String, Factors>, StocksError> {
+    let (start, end) = portfolio_range(item)?;
+    let quotes = get_ticker_quotes(
+        &item.data_provider()?,
+        PROXIES,
+        &start,
+        &end,
+        Interval::Daily,
+    )
+    .await?;
+    let returns = ticker_returns(&quotes);
+    let rf = item.rfr() / PERIODS_PER_YEAR;
+    Ok(returns["SPY"]
+        .iter()
+        .filter_map(|(date, spy)| {
+            let [iwm, iwd, iwf] = ["IWM", "IWD", "IWF"].map(|t| returns[t].get(date).copied());
+            Some((
+                date.to_string(),
+                Factors {
+                    market: spy - rf,
+                    size: iwm? - spy,
+                    value: iwd? - iwf?,
+                    rf,
+                },
+            ))
+        })
+        .collect())
+}
+
+// ordinary least squares through the normal equations, the first regressor being the intercept
+fn regress(y: &[f64], x: &[[f64; 4]]) -> Option<FactorExposure> {
+    if y.len() <= x.first()?.len() {
+        return None;
+    }
+    let xtx: Vec<Vec<f64>> = (0..4)
+        .map(|i| {
+            (0..4)
+                .map(|j| x.iter().map(|r| r[i] * r[j]).sum())
+                .collect()
+        })
+        .collect();
+    let xty: Vec<f64> = (0..4)
+        .map(|i| x.iter().zip(y.iter()).map(|(r, y)| r[i] * y).sum())
+        .collect();
+    let b = solve(&xtx, &xty)?;
+    let fitted = |r: &[f64; 4]| r.iter().zip(b.iter()).map(|(x, b)| x * b).sum::<f64>();
+    let mean_y = mean(y);
+    let residual: f64 = x
+        .iter()
+        .zip(y.iter())
+        .map(|(r, y)| (y - fitted(r)).powi(2))
+        .sum();
+    let total: f64 = y.iter().map(|y| (y - mean_y).powi(2)).sum();
+    Some(FactorExposure {
+        alpha: b[0] * PERIODS_PER_YEAR * 100.0,
+        market: b[1],
+        size: b[2],
+        value: b[3],
+        r_squared: 1.0 - residual / total,
+        observations: y.len(),
+    })
+}
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::provider::{timestamp, within, DataProvider, Metadata};
+use crate::yahoo_finance::{Interval, ProviderError, Quote};
+
+#[derive(Error, Debug)]
+pub enum FilesError {
+    #[error("the local file couldn't be opened: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the local CSV couldn't be read: {0}")]
+    Csv(#[from] csv::Error),
+    #[cfg(feature = "parquet")]
+    #[error("the local Parquet file couldn't be read: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("there's no local file for {0}")]
+    NotFound(String),
+}
+
+// the quotes of a file along with the currency of every row
+type Rows = Vec<(Quote, Option<String>)>;
+
+/// Quotes kept in local files named after their ticker, e.g., AAPL.csv, in a directory read from MODUS_DATA_DIR by from_env
+///
+/// Every file has a header with the date (YYYY-MM-DD or a unix timestamp) and the close and optionally the open, high, low,
+/// volume, adjclose (the close if missing) and currency (USD if missing). The exchange rates of a currency go in a file named
+/// after the pair, e.g., EURUSD.csv, its close being the USD a unit is worth. Parquet files (e.g., AAPL.parquet) with the same
+/// columns are read as well with the parquet feature. As the quotes don't change, the analyses are reproducible and need no network.
+#[derive(Debug, Clone)]
+pub struct Files {
+    directory: PathBuf,
+}
+
+impl Files {
+    pub fn new(directory: impl Into<PathBuf>) -> Files {
+        Files {
+            directory: directory.into(),
+        }
+    }
+
+    /// Returns the provider reading from the directory in MODUS_DATA_DIR, None if it isn't set
+    pub fn from_env() -> Option<Files> {
+        std::env::var("MODUS_DATA_DIR").ok().map(Files::new)
+    }
+
+    // the quotes in the file of the ticker, None if there's no file
+    fn read(&self, name: &str) -> Result<Option<Rows>, FilesError> {
+        let csv = self.directory.join(format!("{name}.csv"));
+        if csv.exists() {
+            return Ok(Some(read_csv(&csv)?));
+        }
+        #[cfg(feature = "parquet")]
+        {
+            let parquet = self.directory.join(format!("{name}.parquet"));
+            if parquet.exists() {
+                return Ok(Some(read_parquet(&parquet)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn read_csv(path: &Path) -> Result<Rows, FilesError> {
+    let mut reader = ReaderBuilder::new().from_path(path)?;
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let row: BTreeMap<String, String> = headers
+            .iter()
+            .cloned()
+            .zip(record.iter().map(|v| v.trim().to_string()))
+            .collect();
+        rows.extend(quote(&row));
+    }
+    Ok(rows)
+}
+
+#[cfg(feature = "parquet")]
+fn read_parquet(path: &Path) -> Result<Rows, FilesError> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    let reader = SerializedFileReader::new(std::fs::File::open(path)?)?;
+    let mut rows = Vec::new();
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let row: BTreeMap<String, String> = row
+            .get_column_iter()
+            .map(|(name, field)| {
+                let value = match field {
+                    Field::Str(s) => s.clone(),
+                    Field::Date(days) => {
+                        (NaiveDate::default() + chrono::Duration::days(*days as i64)).to_string()
+                    }
+                    Field::TimestampMillis(ms) => (ms / 1000).to_string(),
+                    Field::TimestampMicros(us) => (us / 1_000_000).to_string(),
+                    f => f.to_string(),
+                };
+                (name.to_lowercase(), value)
+            })
+            .collect();
+        rows.extend(quote(&row));
+    }
+    Ok(rows)
+}
+
+// the quote in the row, None if it has no date or close
+fn quote(row: &BTreeMap<String, String>) -> Option<(Quote, Option<String>)> {
+    let number = |name: &str| row.get(name).and_then(|v| v.parse::<f64>().ok());
+    let date = row.get("date")?;
+    let timestamp = match date.get(..10).and_then(|d| d.parse::<NaiveDate>().ok()) {
+        Some(date) => timestamp(date),
+        None => date.parse::<u64>().ok()?,
+    };
+    let close = number("close")?;
+    Some((
+        Quote {
+            timestamp,
+            open: number("open").unwrap_or(0.0),
+            high: number("high").unwrap_or(0.0),
+            low: number("low").unwrap_or(0.0),
+            volume: number("volume").unwrap_or(0.0) as u64,
+            close,
+            adjclose: number("adjclose").or(number("adj close")).unwrap_or(close),
+            ..Default::default()
+        },
+        row.get("currency").filter(|c| !c.is_empty()).cloned(),
+    ))
+}
+
+impl DataProvider for Files {
+    // the interval is the one of the file
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        _interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let rows = self
+            .read(ticker)
+            .map_err(|e| ProviderError::from(e).context(ticker, start, end))?;
+        Ok(within(
+            rows.into_iter().flatten().map(|(q, _)| q).collect(),
+            start,
+            end,
+        ))
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        let currency = self
+            .read(ticker)?
+            .ok_or_else(|| FilesError::NotFound(ticker.to_string()))?
+            .into_iter()
+            .find_map(|(_, currency)| currency);
+        Ok(Metadata {
+            symbol: ticker.to_string(),
+            currency: currency.unwrap_or_else(|| "USD".to_string()),
+        })
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        _interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let pair = format!("{currency}USD");
+        let rates = async {
+            let rows = self
+                .read(&pair)?
+                .ok_or_else(|| FilesError::NotFound(pair.clone()))?;
+            Ok(within(
+                rows.into_iter().map(|(q, _)| q).collect(),
+                start,
+                end,
+            ))
+        };
+        rates
+            .await
+            .map_err(|e: FilesError| ProviderError::from(e).context(&pair, start, end))
+    }
+
+    // the directory can be read, whatever the tickers it has files for
+    async fn probe(&self) -> Result<(), ProviderError> {
+        std::fs::read_dir(&self.directory).map_err(FilesError::from)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a directory of its own for every test, as they run concurrently
+    fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("modus-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file, content) in files {
+            std::fs::write(directory.join(file), content).unwrap();
+        }
+        directory
+    }
+
+    fn row(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn the_rows_without_a_date_or_a_close_are_skipped() {
+        let (unix, currency) = quote(&row(&[("date", "1704067200"), ("close", "10.5")])).unwrap();
+        assert_eq!(
+            (unix.timestamp, unix.adjclose, unix.open),
+            (1704067200, 10.5, 0.0)
+        );
+        assert_eq!(currency, None);
+        let (adjusted, _) = quote(&row(&[
+            ("date", "2024-01-02T00:00:00Z"),
+            ("close", "10.5"),
+            ("adj close", "9.5"),
+        ]))
+        .unwrap();
+        assert_eq!(adjusted.adjclose, 9.5);
+        assert!(quote(&row(&[("close", "10.5")])).is_none());
+        assert!(quote(&row(&[("date", "2024-01-02"), ("close", "n/a")])).is_none());
+    }
+
+    #[actix_web::test]
+    async fn reads_the_quotes_currency_and_rates_from_the_csv_files() {
+        let directory = directory(
+            "csv",
+            &[
+                (
+                    "SAP.DE.csv",
+                    " Date , Close ,Volume,Currency\n2024-01-02,140.5,1000,EUR\n2024-01-03,141.0,1200,EUR\n",
+                ),
+                ("EURUSD.csv", "date,close\n2024-01-02,1.09\n2024-01-03,1.10\n"),
+            ],
+        );
+        let files = Files::new(&directory);
+        let start = OffsetDateTime::from_unix_timestamp(1704153600).unwrap();
+        let end = OffsetDateTime::from_unix_timestamp(1704240000).unwrap();
+        let quotes = files
+            .get_quotes("SAP.DE", &start, &end, Interval::Daily)
+            .await
+            .unwrap();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(
+            (quotes[1].close, quotes[1].adjclose, quotes[1].volume),
+            (141.0, 141.0, 1200)
+        );
+        assert_eq!(files.metadata("SAP.DE").await.unwrap().currency, "EUR");
+        let rates = files
+            .fx_rates("EUR", &start, &end, Interval::Daily)
+            .await
+            .unwrap();
+        assert_eq!(
+            rates.iter().map(|r| r.close).collect::<Vec<_>>(),
+            [1.09, 1.10]
+        );
+        assert!(files.metadata("AAPL").await.is_err());
+        assert!(files.probe().await.is_ok());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
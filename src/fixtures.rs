@@ -0,0 +1,125 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::provider::{timestamp, within, DataProvider, Metadata};
+use crate::yahoo_finance::{Interval, ProviderError, Quote};
+
+#[derive(Error, Debug)]
+pub enum FixturesError {
+    #[error("the fixture of {0} couldn't be read: {1}")]
+    Invalid(&'static str, serde_json::Error),
+    #[error("there's no fixture for {0}, only for AAPL, MSFT, SAP.DE, SPY, IWM, IWD and IWF and the EURUSD rates")]
+    NotFound(String),
+}
+
+// the fixtures built into the library, by ticker or pair
+const FIXTURES: [(&str, &str); 8] = [
+    ("AAPL", include_str!("../fixtures/AAPL.json")),
+    ("MSFT", include_str!("../fixtures/MSFT.json")),
+    ("SAP.DE", include_str!("../fixtures/SAP.DE.json")),
+    ("SPY", include_str!("../fixtures/SPY.json")),
+    ("IWM", include_str!("../fixtures/IWM.json")),
+    ("IWD", include_str!("../fixtures/IWD.json")),
+    ("IWF", include_str!("../fixtures/IWF.json")),
+    ("EURUSD", include_str!("../fixtures/EURUSD.json")),
+];
+
+#[derive(Deserialize)]
+struct Fixture {
+    currency: String,
+    quotes: Vec<Bar>,
+}
+
+// a daily close dated YYYY-MM-DD, the other figures being optional
+#[derive(Deserialize)]
+struct Bar {
+    date: String,
+    close: f64,
+    open: Option<f64>,
+    high: Option<f64>,
+    low: Option<f64>,
+    volume: Option<u64>,
+    adjclose: Option<f64>,
+}
+
+/// Synthetic daily quotes from 2022 to 2024 bundled into the library, so that the tests and demos need neither the network
+/// nor any files
+///
+/// They cover AAPL, MSFT and SPY, the IWM, IWD and IWF factor proxies and SAP.DE, in EUR, along with the EURUSD rates.
+/// The prices are a random walk rather than the real ones, and every interval is given the daily quotes.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Fixtures;
+
+impl Fixtures {
+    fn read(&self, name: &str) -> Result<Fixture, FixturesError> {
+        let (name, json) = FIXTURES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .ok_or_else(|| FixturesError::NotFound(name.to_string()))?;
+        serde_json::from_str(json).map_err(|e| FixturesError::Invalid(name, e))
+    }
+
+    fn quotes(
+        &self,
+        name: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let fixture = self
+            .read(name)
+            .map_err(|e| ProviderError::from(e).context(name, start, end))?;
+        let quotes = fixture
+            .quotes
+            .into_iter()
+            .filter_map(|b| {
+                Some(Quote {
+                    timestamp: timestamp(b.date.parse::<NaiveDate>().ok()?),
+                    open: b.open.unwrap_or(b.close),
+                    high: b.high.unwrap_or(b.close),
+                    low: b.low.unwrap_or(b.close),
+                    volume: b.volume.unwrap_or(0),
+                    close: b.close,
+                    adjclose: b.adjclose.unwrap_or(b.close),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Ok(within(quotes, start, end))
+    }
+}
+
+impl DataProvider for Fixtures {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        _interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        self.quotes(ticker, start, end)
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        Ok(Metadata {
+            symbol: ticker.to_uppercase(),
+            currency: self.read(ticker)?.currency,
+        })
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        _interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        self.quotes(&format!("{currency}USD"), start, end)
+    }
+
+    // the fixtures are built in
+    async fn probe(&self) -> Result<(), ProviderError> {
+        Ok(())
+    }
+}
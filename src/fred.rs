@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate, Utc};
+use csv::ReaderBuilder;
+use thiserror::Error;
+
+use crate::http::{client, send};
+use crate::yahoo_finance::{FetchError, ProviderError};
+
+#[derive(Error, Debug)]
+pub enum FredError {
+    #[error("the series from FRED couldn't be read: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("FRED returned an empty series")]
+    EmptyDataSet,
+}
+
+/// The consumer price index for all urban consumers, seasonally adjusted and monthly
+pub const CPI: &str = "CPIAUCSL";
+
+/// The series of the market yield of the US Treasury securities closest to the maturity in years, in percentage and daily
+fn treasury(maturity: u8) -> &'static str {
+    match maturity {
+        0 => "DTB3",
+        1 => "DGS1",
+        2 => "DGS2",
+        3 => "DGS3",
+        4..=5 => "DGS5",
+        6..=7 => "DGS7",
+        8..=14 => "DGS10",
+        15..=24 => "DGS20",
+        _ => "DGS30",
+    }
+}
+
+/// Returns the latest yield of the Treasury securities closest to the maturity in years, as a fraction, e.g., 0.045 for 4.5%
+pub async fn treasury_yield(maturity: u8) -> Result<f64, ProviderError> {
+    let end = Utc::now().date_naive();
+    // a few weeks back, so that there's an observation despite the holidays and the days FRED hasn't published yet
+    let observations = get_series(treasury(maturity), end - Duration::days(30), end).await?;
+    Ok(observations
+        .values()
+        .next_back()
+        .copied()
+        .unwrap_or_default()
+        / 100.0)
+}
+
+/// Returns the observations of a FRED series between the dates, by date, the missing ones being left out
+pub async fn get_series(
+    series: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<BTreeMap<NaiveDate, f64>, ProviderError> {
+    let observations = async {
+        // the CSV download of the graphs doesn't need an API key
+        let response = send(client()?.get(format!(
+            "https://fred.stlouisfed.org/graph/fredgraph.csv?id={series}&cosd={start}&coed={end}"
+        )))
+        .await?
+        .text()
+        .await?;
+        Ok(parse(&response)?)
+    };
+    observations.await.map_err(|e: ProviderError| {
+        FetchError {
+            ticker: series.to_string(),
+            start: start.to_string(),
+            end: end.to_string(),
+            message: e.to_string(),
+        }
+        .into()
+    })
+}
+
+// a header with the date and the name of the series and a row per observation, missing ones being a dot
+fn parse(csv: &str) -> Result<BTreeMap<NaiveDate, f64>, FredError> {
+    let mut observations = BTreeMap::new();
+    for record in ReaderBuilder::new().from_reader(csv.as_bytes()).records() {
+        let record = record?;
+        if let (Some(date), Some(value)) = (
+            record.get(0).and_then(|d| d.parse::<NaiveDate>().ok()),
+            record.get(1).and_then(|v| v.parse::<f64>().ok()),
+        ) {
+            observations.insert(date, value);
+        }
+    }
+    if observations.is_empty() {
+        return Err(FredError::EmptyDataSet);
+    }
+    Ok(observations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_observations_leaving_out_the_missing_ones() {
+        let csv = "observation_date,DGS1\n2024-05-01,5.18\n2024-05-02,.\n2024-05-03,5.12\n";
+        let observations = parse(csv).unwrap();
+        assert_eq!(observations.len(), 2);
+        assert_eq!(
+            observations[&NaiveDate::from_ymd_opt(2024, 5, 3).unwrap()],
+            5.12
+        );
+        assert!(matches!(
+            parse("observation_date,DGS1\n2024-05-02,.\n"),
+            Err(FredError::EmptyDataSet)
+        ));
+    }
+
+    #[test]
+    fn the_maturities_have_the_treasury_closest_to_them() {
+        let series: Vec<&str> = [0, 1, 4, 7, 10, 20, 40].into_iter().map(treasury).collect();
+        assert_eq!(
+            series,
+            ["DTB3", "DGS1", "DGS5", "DGS7", "DGS10", "DGS20", "DGS30"]
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Exchange rates
+//!
+//! Converts amounts between currencies at the daily rates of a provider. The rates of every currency are fetched as the USD
+//! a unit of it is worth and kept for as long as the service lives, so valuing many positions in the same currency costs a
+//! single request, a later date outside the range fetched so far widening it. Any other pair is triangulated through USD,
+//! e.g., a euro in pounds is the USD a euro is worth over the USD a pound is.
+//!
+//! The daily rates of any pair over a range are given by fx_series, from the rates against USD of a provider, and by ecb_series
+//! from the euro reference rates the ECB publishes every working day, the official fixes many statements are valued at.
+//!
+//! Usage:
+//! ```no_run
+//! # use chrono::NaiveDate;
+//! # use modus::fx::{ecb_series, fx_series, FxService};
+//! # use modus::provider::Yahoo;
+//! # use time::OffsetDateTime;
+//! # async fn run(start: NaiveDate, end: NaiveDate) {
+//!  let fx = FxService::new(&Yahoo);
+//!  if let Ok(s) = fx.rate("EUR", "GBP", &OffsetDateTime::now_utc()).await { println!("{:?}", s); }
+//!  if let Ok(s) = fx_series(&Yahoo, "EUR", "GBP", start..=end).await { println!("{:?}", s); }
+//!  if let Ok(s) = ecb_series("EUR", "GBP", start..=end).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+
+use chrono::{DateTime, NaiveDate, NaiveTime};
+use futures::lock::Mutex;
+use time::{Duration, OffsetDateTime};
+
+use crate::ecb::{get_series, reference_rate};
+use crate::provider::{within, DataProvider};
+use crate::stock_returns::StocksError;
+use crate::yahoo_finance::{FetchError, Interval, ProviderError, Quote};
+
+// the rates of a currency along with the range they were fetched for
+struct Series {
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    quotes: Vec<Quote>,
+}
+
+// how far back from a date the rates are fetched, so there's one even if the market was closed for a few days
+pub(crate) const LOOKBACK: Duration = Duration::days(7);
+
+/// The exchange rates of a provider, cached by currency
+pub struct FxService<'a, P: ?Sized> {
+    provider: &'a P,
+    interval: Interval,
+    series: Mutex<HashMap<String, Series>>,
+}
+
+impl<'a, P: DataProvider + ?Sized> FxService<'a, P> {
+    pub fn new(provider: &'a P) -> FxService<'a, P> {
+        FxService {
+            provider,
+            interval: Interval::Daily,
+            series: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fetches the rates at the interval rather than daily, so that the quotes at that interval are converted at the rates
+    /// of their own periods
+    pub fn with_interval(self, interval: Interval) -> FxService<'a, P> {
+        FxService { interval, ..self }
+    }
+
+    pub(crate) fn provider(&self) -> &'a P {
+        self.provider
+    }
+
+    /// Returns the USD value of a unit of the currency at every date of the interval in the range, none for USD itself
+    pub async fn usd_series(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        if currency == "USD" {
+            return Ok(Vec::new());
+        }
+        let mut series = self.series.lock().await;
+        let cached = series.get(currency);
+        if !cached.is_some_and(|s| s.start <= *start && s.end >= *end) {
+            // the range fetched before is kept, so that the dates asked for earlier don't have to be fetched again
+            let (start, end) =
+                cached.map_or((*start, *end), |s| (s.start.min(*start), s.end.max(*end)));
+            let quotes = self
+                .provider
+                .fx_rates(currency, &start, &end, self.interval)
+                .await?;
+            series.insert(currency.to_string(), Series { start, end, quotes });
+        }
+        Ok(within(series[currency].quotes.clone(), start, end))
+    }
+
+    /// Returns the USD a unit of the currency was worth at the close of the date, or of the last day before it with a rate
+    pub async fn usd_rate(
+        &self,
+        currency: &str,
+        date: &OffsetDateTime,
+    ) -> Result<f64, ProviderError> {
+        if currency == "USD" {
+            return Ok(1.0);
+        }
+        let start = *date - LOOKBACK;
+        match self.usd_series(currency, &start, date).await?.last() {
+            Some(quote) => Ok(quote.close),
+            None => Err(ProviderError::FetchError(FetchError {
+                ticker: format!("{currency}USD"),
+                start: start.date().to_string(),
+                end: date.date().to_string(),
+                message: "there are no exchange rates in the range".to_string(),
+            })),
+        }
+    }
+
+    /// Returns the units of the currency `to` a unit of `from` was worth at the date
+    pub async fn rate(
+        &self,
+        from: &str,
+        to: &str,
+        date: &OffsetDateTime,
+    ) -> Result<f64, ProviderError> {
+        if from == to {
+            return Ok(1.0);
+        }
+        Ok(self.usd_rate(from, date).await? / self.usd_rate(to, date).await?)
+    }
+}
+
+// the USD a unit of the currency was worth at the close of every date in the range
+async fn closes<P: DataProvider>(
+    fx: &FxService<'_, P>,
+    currency: &str,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+) -> Result<BTreeMap<NaiveDate, f64>, ProviderError> {
+    Ok(fx
+        .usd_series(currency, start, end)
+        .await?
+        .into_iter()
+        .map(|q| {
+            (
+                DateTime::from_timestamp(q.timestamp as i64, 0)
+                    .unwrap_or_default()
+                    .date_naive(),
+                q.close,
+            )
+        })
+        .collect())
+}
+
+/// Returns the units of the quote currency a unit of the base one was worth at the close of every date in the range by the
+/// rates of the provider, by date (YYYY-MM-DD), e.g., EUR and GBP for the pounds a euro was worth. The dates are those the
+/// base currency has a rate, or the quote one if the base is USD, the rate of the other currency being its last one up to the date
+pub async fn fx_series<P: DataProvider>(
+    provider: &P,
+    base: &str,
+    quote: &str,
+    range: RangeInclusive<NaiveDate>,
+) -> Result<BTreeMap<String, f64>, StocksError> {
+    let (base, quote) = (base.to_uppercase(), quote.to_uppercase());
+    let at = |date: NaiveDate, time: NaiveTime| {
+        OffsetDateTime::from_unix_timestamp(date.and_time(time).and_utc().timestamp())
+    };
+    let start = at(*range.start(), NaiveTime::MIN)?;
+    let end = at(
+        *range.end(),
+        NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default(),
+    )?;
+    let fx = FxService::new(provider);
+    let base_rates = closes(&fx, &base, &start, &end).await?;
+    // from a week before the start as well, so that the first dates have a rate too
+    let quote_rates = closes(&fx, &quote, &(start - LOOKBACK), &end).await?;
+    let dates: Vec<NaiveDate> = match base.as_str() {
+        "USD" => quote_rates.range(range.clone()).map(|(d, _)| *d).collect(),
+        _ => base_rates.keys().copied().collect(),
+    };
+    // USD has no rates, a USD being worth a USD every day
+    Ok(dates
+        .into_iter()
+        .filter_map(|date| {
+            let b = match base.as_str() {
+                "USD" => 1.0,
+                _ => *base_rates.get(&date)?,
+            };
+            let q = match quote.as_str() {
+                "USD" => 1.0,
+                _ => *quote_rates.range(..=date).next_back()?.1,
+            };
+            Some((date.to_string(), b / q))
+        })
+        .collect())
+}
+
+// the units of the currency a euro was worth on every date in the range, None for the euro itself
+async fn per_euro(
+    currency: &str,
+    range: &RangeInclusive<NaiveDate>,
+) -> Result<Option<BTreeMap<NaiveDate, f64>>, ProviderError> {
+    match currency {
+        "EUR" => Ok(None),
+        _ => Ok(Some(
+            get_series(&reference_rate(currency), *range.start(), *range.end()).await?,
+        )),
+    }
+}
+
+/// Returns the units of the quote currency a unit of the base one was worth on every date in the range by the ECB's reference
+/// rates, by date (YYYY-MM-DD), the pairs without EUR being triangulated through it
+pub async fn ecb_series(
+    base: &str,
+    quote: &str,
+    range: RangeInclusive<NaiveDate>,
+) -> Result<BTreeMap<String, f64>, StocksError> {
+    let (base, quote) = (base.to_uppercase(), quote.to_uppercase());
+    let base_rates = per_euro(&base, &range).await?;
+    let quote_rates = per_euro(&quote, &range).await?;
+    // the ECB publishes the rates of every currency on the same dates
+    let dates: Vec<NaiveDate> = base_rates
+        .as_ref()
+        .or(quote_rates.as_ref())
+        .map(|rates| rates.keys().copied().collect())
+        .unwrap_or_default();
+    let rate = |rates: &Option<BTreeMap<NaiveDate, f64>>, date: &NaiveDate| match rates {
+        Some(rates) => rates.get(date).copied(),
+        None => Some(1.0),
+    };
+    Ok(dates
+        .into_iter()
+        .filter_map(|date| {
+            Some((
+                date.to_string(),
+                rate(&quote_rates, &date)? / rate(&base_rates, &date)?,
+            ))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::provider::Fixtures;
+
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 1, d).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn the_series_are_those_of_the_provider() {
+        let euro = fx_series(&Fixtures, "eur", "USD", day(3)..=day(9))
+            .await
+            .unwrap();
+        assert_eq!(euro.len(), 5);
+        assert_eq!(euro["2022-01-03"], 1.13);
+        assert_eq!(euro["2022-01-07"], 1.1016);
+        // the dates are those of the euro when it's the quote as well
+        let dollar = fx_series(&Fixtures, "USD", "EUR", day(3)..=day(9))
+            .await
+            .unwrap();
+        assert_eq!(
+            dollar.keys().collect::<Vec<_>>(),
+            euro.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(dollar["2022-01-04"], 1.0 / 1.1305);
+    }
+
+    #[actix_web::test]
+    async fn the_rate_at_a_date_is_the_last_one_up_to_it() {
+        let fx = FxService::new(&Fixtures);
+        // a Sunday, at the rate of the Friday before
+        let sunday = OffsetDateTime::from_unix_timestamp(1641686400).unwrap();
+        assert_eq!(fx.usd_rate("EUR", &sunday).await.unwrap(), 1.1016);
+        assert_eq!(fx.rate("EUR", "EUR", &sunday).await.unwrap(), 1.0);
+        assert_eq!(fx.rate("USD", "EUR", &sunday).await.unwrap(), 1.0 / 1.1016);
+        assert!(fx.usd_rate("GBP", &sunday).await.is_err());
+    }
+}
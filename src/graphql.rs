@@ -0,0 +1,346 @@
+//! GraphQL schema
+//!
+//! The quotes, the returns and risk of portfolios and the values of options as one graph, so that a dashboard asks for the
+//! fields it shows, and nothing else, in a single request. The portfolios and options are given as the JSON the HTTP
+//! endpoints take, and every field of a portfolio or an option is only computed when it's asked for, e.g.:
+//!
+//! ```graphql
+//! {
+//!   portfolio(input: {portfolio: [...]}) {
+//!     returns(aggregation: YEARLY) { cagr series { date value } }
+//!     risk { sharpe maxDrawdown }
+//!   }
+//!   option(input: {form: "Call", underlying: 100, strike: 100, maturity: 1, volatility: 0.2}) { blackScholes }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+
+use async_graphql::{
+    EmptyMutation, EmptySubscription, Enum, Error, ErrorExtensions, Json, Object, Result, Schema,
+    SimpleObject,
+};
+
+use crate::market::{live_quote, live_quotes, LiveQuote};
+use crate::options::{bs_price, expected, kelly_ratio, Options};
+use crate::risk::{risk, volatility};
+use crate::stock_returns::{
+    aggregate, cagr, mean_return, total_returns_in, years, Aggregation, Currency, Interval,
+    Portfolio, ProviderError, StocksError, Violation,
+};
+
+/// The schema, which the server executes the requests with
+pub type ModusSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Returns the schema, there being no mutations nor subscriptions
+pub fn schema() -> ModusSchema {
+    Schema::new(Query, EmptyMutation, EmptySubscription)
+}
+
+// the reason of the error, along with the ticker and dates that failed when they're known
+fn error(e: StocksError) -> Error {
+    match e {
+        StocksError::ComponentRange(e) => Error::new("Failed to convert the date")
+            .extend_with(|_, x| x.set("reason", e.to_string())),
+        StocksError::ProviderError(ProviderError::FetchError(e)) => {
+            Error::new("The quotes couldn't be fetched").extend_with(|_, x| {
+                x.set("ticker", e.ticker.clone());
+                x.set("start", e.start.clone());
+                x.set("end", e.end.clone());
+                x.set("reason", e.message.clone());
+            })
+        }
+        StocksError::ProviderError(e) => Error::new("The quotes couldn't be fetched")
+            .extend_with(|_, x| x.set("reason", e.to_string())),
+    }
+}
+
+// the violations by field, as the endpoints report them
+fn invalid(violations: Vec<Violation>) -> Error {
+    Error::new("The input is invalid").extend_with(|_, x| {
+        for v in &violations {
+            x.set(v.field.clone(), v.message.clone());
+        }
+    })
+}
+
+// a value of a series by date (YYYY-MM-DD)
+#[derive(SimpleObject)]
+struct Point {
+    date: String,
+    value: f64,
+}
+
+fn points(series: BTreeMap<String, f64>) -> Vec<Point> {
+    series
+        .into_iter()
+        .map(|(date, value)| Point { date, value })
+        .collect()
+}
+
+/// The root of the graph
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The latest quote of a ticker
+    async fn quote(&self, ticker: String) -> Result<Quote> {
+        Ok(live_quote(&ticker).await.map_err(error)?.into())
+    }
+
+    /// The latest quotes of several tickers, leaving out those Yahoo doesn't know
+    async fn quotes(&self, tickers: Vec<String>) -> Result<Vec<Quote>> {
+        let quotes = live_quotes(tickers.iter().map(String::as_str))
+            .await
+            .map_err(error)?;
+        Ok(quotes.into_values().map(Quote::from).collect())
+    }
+
+    /// A portfolio, given as the endpoints take it, its returns and risk being computed when they're asked for
+    async fn portfolio(&self, input: Json<Portfolio>) -> Result<PortfolioNode> {
+        let item = input.0;
+        item.validate().map_err(invalid)?;
+        Ok(PortfolioNode(item))
+    }
+
+    /// An option, given as the endpoints take it, the risk-free rate being the yield of the US Treasury securities of the
+    /// closest maturity if it's missing, or zero with the fixtures and files providers
+    async fn option(&self, input: Json<Options>) -> Result<OptionNode> {
+        let item = input.0;
+        item.validate().map_err(invalid)?;
+        Ok(OptionNode(item.with_market_rfr().await.map_err(error)?))
+    }
+}
+
+/// The latest price of a ticker in its currency and its change since the previous close
+#[derive(SimpleObject)]
+struct Quote {
+    symbol: String,
+    currency: String,
+    price: f64,
+    time: u64,
+    previous_close: f64,
+    change: f64,
+    change_percent: f64,
+    pre_market: Option<f64>,
+    post_market: Option<f64>,
+}
+
+impl From<LiveQuote> for Quote {
+    fn from(q: LiveQuote) -> Quote {
+        Quote {
+            symbol: q.symbol,
+            currency: q.currency,
+            price: q.price,
+            time: q.time,
+            previous_close: q.previous_close,
+            change: q.change,
+            change_percent: q.change_percent,
+            pre_market: q.pre_market,
+            post_market: q.post_market,
+        }
+    }
+}
+
+#[derive(Enum, Copy, Clone, PartialEq, Eq)]
+enum Period {
+    Daily,
+    Monthly,
+    Yearly,
+}
+
+impl From<Period> for Aggregation {
+    fn from(period: Period) -> Aggregation {
+        match period {
+            Period::Daily => Aggregation::Daily,
+            Period::Monthly => Aggregation::Monthly,
+            Period::Yearly => Aggregation::Yearly,
+        }
+    }
+}
+
+/// A portfolio
+pub struct PortfolioNode(Portfolio);
+
+#[Object(name = "Portfolio")]
+impl PortfolioNode {
+    /// The cumulative returns in percentage since the first buy, as /v1/equities/returns gives them
+    async fn returns(
+        &self,
+        #[graphql(default_with = "Period::Daily")] aggregation: Period,
+    ) -> Result<Returns> {
+        let returns = total_returns_in(&self.0, Interval::Daily, Currency::Usd)
+            .await
+            .map_err(error)?;
+        Ok(Returns {
+            returns,
+            aggregation: aggregation.into(),
+        })
+    }
+
+    /// The risk report, as /v1/equities/risk gives it
+    async fn risk(&self) -> Result<Risk> {
+        let report = risk(&self.0).await.map_err(error)?;
+        Ok(Risk {
+            volatility: report.volatility,
+            sharpe: report.sharpe,
+            sortino: report.sortino,
+            calmar: report.calmar,
+            omega: report.omega,
+            downside_deviation: report.downside_deviation,
+            ulcer_index: report.ulcer_index,
+            max_drawdown: report.max_drawdown,
+            max_drawdown_duration: report.max_drawdown_duration,
+            drawdown: points(report.drawdown),
+            value_at_risk: report
+                .value_at_risk
+                .into_iter()
+                .map(|v| ValueAtRisk {
+                    confidence: v.confidence,
+                    var: v.var,
+                    cvar: v.cvar,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// The daily returns of a portfolio, the measures being computed from them as they're asked for
+struct Returns {
+    returns: BTreeMap<String, f64>,
+    aggregation: Aggregation,
+}
+
+#[Object]
+impl Returns {
+    /// The returns by date, or by month (YYYY-MM) or year (YYYY)
+    async fn series(&self) -> Vec<Point> {
+        points(aggregate(&self.returns, self.aggregation))
+    }
+
+    async fn annualized_volatility(&self) -> f64 {
+        volatility(&self.returns, Interval::Daily)
+    }
+
+    async fn cagr(&self) -> f64 {
+        cagr(&self.returns)
+    }
+
+    async fn years(&self) -> f64 {
+        years(&self.returns)
+    }
+
+    /// The geometric and arithmetic mean of the returns of every period
+    async fn mean_return(&self) -> MeanReturn {
+        let mean = mean_return(&self.returns, self.aggregation);
+        MeanReturn {
+            geometric: mean.geometric,
+            arithmetic: mean.arithmetic,
+            periods: mean.periods,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct MeanReturn {
+    geometric: f64,
+    arithmetic: f64,
+    periods: usize,
+}
+
+/// The annualized risk-adjusted ratios of a portfolio and its drawdowns, in percentage
+#[derive(SimpleObject)]
+struct Risk {
+    volatility: f64,
+    sharpe: f64,
+    sortino: f64,
+    calmar: f64,
+    omega: f64,
+    downside_deviation: f64,
+    ulcer_index: f64,
+    max_drawdown: f64,
+    max_drawdown_duration: i64,
+    drawdown: Vec<Point>,
+    value_at_risk: Vec<ValueAtRisk>,
+}
+
+/// The daily loss in percentage that is only exceeded with a probability of 1 - confidence and the average loss when it is
+#[derive(SimpleObject)]
+struct ValueAtRisk {
+    confidence: f64,
+    var: f64,
+    cvar: f64,
+}
+
+/// An option
+pub struct OptionNode(Options);
+
+#[Object(name = "Option")]
+impl OptionNode {
+    /// The value with the Black-Scholes formula
+    async fn black_scholes(&self) -> f64 {
+        bs_price(&self.0)
+    }
+
+    /// The value based on 10000 simulations
+    async fn monte_carlo(&self) -> Result<f64> {
+        expected(&self.0).map_err(|_| Error::new("Some iterations couldn't be completed"))
+    }
+
+    /// The Kelly fraction, null without the market price of the option
+    async fn kelly(&self) -> Option<f64> {
+        kelly_ratio(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Request, Variables};
+    use serde_json::{json, Value};
+
+    use crate::options::OptionType;
+    use crate::provider::Source;
+    use crate::stock_returns::{Equity, Transaction, TransactionDate};
+
+    use super::*;
+
+    async fn execute(query: &str, input: impl serde::Serialize) -> async_graphql::Response {
+        let variables = Variables::from_json(json!({"input": input}));
+        schema()
+            .execute(Request::new(query).variables(variables))
+            .await
+    }
+
+    fn aapl() -> Portfolio {
+        let buy = Transaction::new(TransactionDate::new(2022, 1, 3), 180.0);
+        Portfolio::new(vec![Equity::new("AAPL", buy, None, 10.0)]).with_provider(Source::Fixtures)
+    }
+
+    #[actix_web::test]
+    async fn only_the_fields_asked_for_are_given() {
+        let option = Options::new(OptionType::Call, 100.0, 95.0, 2, 0.25).with_rfr(0.04);
+        let response = execute(
+            "query($input: JSON!) { option(input: $input) { blackScholes kelly } }",
+            &option,
+        )
+        .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["option"]["blackScholes"], json!(bs_price(&option)));
+        assert_eq!(data["option"]["kelly"], Value::Null);
+        assert!(data["option"].get("monteCarlo").is_none());
+    }
+
+    #[actix_web::test]
+    async fn the_portfolio_is_validated_before_its_fields_are_computed() {
+        let query = "query($input: JSON!) { portfolio(input: $input) { returns(aggregation: YEARLY) { years series { date } } } }";
+        let response = execute(query, aapl()).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        let series = &data["portfolio"]["returns"]["series"];
+        assert_eq!(series[0]["date"], "2022");
+        assert_eq!(series.as_array().unwrap().len(), 3);
+        let response = execute(query, Portfolio::new(Vec::new())).await;
+        assert_eq!(response.errors[0].message, "The input is invalid");
+    }
+}
@@ -0,0 +1,321 @@
+//! gRPC service
+//!
+//! The returns of portfolios and the values of options over gRPC, as defined in proto/modus.proto, for the clients that
+//! would rather have typed messages than JSON. The messages are converted into the types of the library and the values
+//! are computed as the HTTP endpoints compute them, the errors being given the status code closest to the HTTP one.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::grpc::service;
+//! # use std::net::SocketAddr;
+//! # use tonic::transport::Server;
+//! # async fn run(addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+//!  Server::builder().add_service(service()).serve(addr).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+// the errors are the Status tonic answers with, however large it is
+#![allow(clippy::result_large_err)]
+
+use tonic::{Request, Response, Status};
+
+use crate::options::{bs_price, expected, kelly_ratio, OptionType, Options};
+use crate::provider::Source;
+use crate::risk::volatility;
+use crate::stock_returns::{
+    aggregate, cagr, mean_return, total_returns_in, years, Aggregation, Currency, Equity, Interval,
+    Portfolio, ProviderError, Sale, StocksError, Transaction, TransactionDate, Violation,
+};
+
+/// The messages and the service generated from proto/modus.proto
+pub mod proto {
+    tonic::include_proto!("modus.v1");
+}
+
+use proto::modus_server::{Modus, ModusServer};
+
+/// The service, to be added to a tonic server
+pub fn service() -> ModusServer<ModusService> {
+    ModusServer::new(ModusService)
+}
+
+/// Answers the calls of the service with the library
+#[derive(Debug, Default)]
+pub struct ModusService;
+
+// a message field that must be set
+fn required<T>(field: Option<T>, name: &str) -> Result<T, Status> {
+    field.ok_or_else(|| Status::invalid_argument(format!("{name} is required")))
+}
+
+fn transaction(t: Option<proto::Transaction>, name: &str) -> Result<Transaction, Status> {
+    let t = required(t, name)?;
+    let date = required(t.date, &format!("{name}.date"))?;
+    // the day is checked along with the rest when the portfolio is validated, so it's only clamped to fit in here
+    let day = u8::try_from(date.day).unwrap_or(u8::MAX);
+    Ok(Transaction::new(
+        TransactionDate::new(date.year, date.month, day),
+        t.price,
+    ))
+}
+
+fn portfolio(p: Option<proto::Portfolio>) -> Result<Portfolio, Status> {
+    let p = required(p, "portfolio")?;
+    let equities = p
+        .portfolio
+        .into_iter()
+        .enumerate()
+        .map(|(i, n)| {
+            let field = |name: &str| format!("portfolio[{i}].{name}");
+            let sell = n
+                .sell
+                .map(|s| transaction(Some(s), &field("sell")))
+                .transpose()?;
+            let sales = n
+                .sales
+                .into_iter()
+                .enumerate()
+                .map(|(j, s)| {
+                    Ok(Sale::new(
+                        transaction(s.sell, &field(&format!("sales[{j}].sell")))?,
+                        f64::from(s.quantity),
+                    ))
+                })
+                .collect::<Result<_, Status>>()?;
+            Ok(Equity::new(
+                n.ticker,
+                transaction(n.buy, &field("buy"))?,
+                sell,
+                // whole shares, as the messages had them before the fractional ones were supported
+                f64::from(n.quantity),
+            )
+            .with_sales(sales))
+        })
+        .collect::<Result<_, Status>>()?;
+    let mut item = Portfolio::new(equities).with_rfr(p.rfr);
+    if let Some(benchmark) = p.benchmark {
+        item = item.with_benchmark(benchmark);
+    }
+    if !p.confidence.is_empty() {
+        item = item.with_confidence(p.confidence);
+    }
+    if let Some(provider) = p.provider {
+        let source: Source = serde_json::from_value(serde_json::Value::String(provider.clone()))
+            .map_err(|_| Status::invalid_argument(format!("there's no provider {provider}")))?;
+        item = item.with_provider(source);
+    }
+    item.validate().map_err(invalid)?;
+    Ok(item)
+}
+
+// the same violations as the HTTP endpoints report, in one message
+fn invalid(violations: Vec<Violation>) -> Status {
+    Status::invalid_argument(
+        violations
+            .iter()
+            .map(|v| format!("{}: {}", v.field, v.message))
+            .collect::<Vec<_>>()
+            .join("; "),
+    )
+}
+
+async fn options(o: proto::Options) -> Result<Options, Status> {
+    let form = match o.form() {
+        proto::OptionType::Call => OptionType::Call,
+        proto::OptionType::Put => OptionType::Put,
+    };
+    let maturity = u8::try_from(o.maturity)
+        .map_err(|_| Status::invalid_argument("maturity must be at most 255 years"))?;
+    let mut item = Options::new(form, o.underlying, o.strike, maturity, o.volatility);
+    if let Some(rfr) = o.rfr {
+        item = item.with_rfr(rfr);
+    }
+    if let Some(market_price) = o.market_price {
+        item = item.with_market_price(market_price);
+    }
+    item.validate().map_err(invalid)?;
+    item.with_market_rfr().await.map_err(status)
+}
+
+// the status of the HTTP status code the endpoints answer the error with
+fn status(e: StocksError) -> Status {
+    match e {
+        StocksError::ComponentRange(e) => Status::invalid_argument(e.to_string()),
+        StocksError::ProviderError(ProviderError::Timeout(e)) => {
+            Status::deadline_exceeded(e.to_string())
+        }
+        StocksError::ProviderError(e) => Status::unavailable(e.to_string()),
+    }
+}
+
+#[tonic::async_trait]
+impl Modus for ModusService {
+    async fn returns(
+        &self,
+        request: Request<proto::ReturnsRequest>,
+    ) -> Result<Response<proto::Returns>, Status> {
+        let request = request.into_inner();
+        let aggregation = match request.aggregation() {
+            proto::Aggregation::Daily => Aggregation::Daily,
+            proto::Aggregation::Monthly => Aggregation::Monthly,
+            proto::Aggregation::Yearly => Aggregation::Yearly,
+        };
+        let item = portfolio(request.portfolio)?;
+        let res = total_returns_in(&item, Interval::Daily, Currency::Usd)
+            .await
+            .map_err(status)?;
+        let mean = mean_return(&res, aggregation);
+        Ok(Response::new(proto::Returns {
+            returns: aggregate(&res, aggregation).into_iter().collect(),
+            annualized_volatility: volatility(&res, Interval::Daily),
+            cagr: cagr(&res),
+            years: years(&res),
+            mean_return: Some(proto::MeanReturn {
+                geometric: mean.geometric,
+                arithmetic: mean.arithmetic,
+                periods: mean.periods as u64,
+            }),
+        }))
+    }
+
+    async fn black_scholes(
+        &self,
+        request: Request<proto::Options>,
+    ) -> Result<Response<proto::Value>, Status> {
+        let item = options(request.into_inner()).await?;
+        Ok(Response::new(proto::Value {
+            value: bs_price(&item),
+        }))
+    }
+
+    async fn monte_carlo(
+        &self,
+        request: Request<proto::Options>,
+    ) -> Result<Response<proto::Value>, Status> {
+        let item = options(request.into_inner()).await?;
+        let value = expected(&item)
+            .map_err(|_| Status::internal("some iterations couldn't be completed"))?;
+        Ok(Response::new(proto::Value { value }))
+    }
+
+    async fn kelly(
+        &self,
+        request: Request<proto::Options>,
+    ) -> Result<Response<proto::Value>, Status> {
+        let item = options(request.into_inner()).await?;
+        let value = kelly_ratio(&item).ok_or_else(|| {
+            Status::invalid_argument("the market price of the option is required")
+        })?;
+        Ok(Response::new(proto::Value { value }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::yahoo_finance::Timeout;
+
+    use super::*;
+
+    fn transaction(year: i32, month: u32, day: u32, price: f64) -> Option<proto::Transaction> {
+        Some(proto::Transaction {
+            date: Some(proto::Date { year, month, day }),
+            price,
+        })
+    }
+
+    fn message(buy: Option<proto::Transaction>) -> proto::Portfolio {
+        proto::Portfolio {
+            portfolio: vec![proto::Equity {
+                ticker: "AAPL".to_string(),
+                buy,
+                sell: None,
+                quantity: 10,
+                sales: vec![proto::Sale {
+                    sell: transaction(2022, 6, 1, 150.0),
+                    quantity: 4,
+                }],
+            }],
+            benchmark: None,
+            rfr: 0.02,
+            confidence: Vec::new(),
+            provider: Some("fixtures".to_string()),
+        }
+    }
+
+    #[test]
+    fn the_portfolio_message_is_converted_and_validated() {
+        let item = portfolio(Some(message(transaction(2022, 1, 3, 180.0)))).unwrap();
+        let equity = &item.equities()[0];
+        assert_eq!((equity.ticker(), equity.quantity()), ("AAPL", 10.0));
+        assert_eq!(equity.sales()[0].quantity(), 4.0);
+        let missing = portfolio(Some(message(None))).unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::InvalidArgument);
+        assert_eq!(missing.message(), "portfolio[0].buy is required");
+        // a day past the end of the month is reported as the HTTP endpoints report it
+        let invalid = portfolio(Some(message(transaction(2022, 1, 300, 180.0)))).unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+        assert!(invalid.message().starts_with("portfolio[0].buy.date"));
+        let mut unknown = message(transaction(2022, 1, 3, 180.0));
+        unknown.provider = Some("bloomberg".to_string());
+        assert_eq!(
+            portfolio(Some(unknown)).unwrap_err().message(),
+            "there's no provider bloomberg"
+        );
+    }
+
+    #[test]
+    fn the_errors_have_the_status_closest_to_the_http_one() {
+        let timeout = StocksError::ProviderError(ProviderError::Timeout(Timeout(
+            std::time::Duration::from_secs(30),
+        )));
+        assert_eq!(status(timeout).code(), tonic::Code::DeadlineExceeded);
+        let unavailable = StocksError::ProviderError(
+            crate::provider::ConfigError::Missing("tiingo", "MODUS_TIINGO_KEY").into(),
+        );
+        assert_eq!(status(unavailable).code(), tonic::Code::Unavailable);
+    }
+
+    #[actix_web::test]
+    async fn the_calls_are_answered_as_the_endpoints_answer_them() {
+        let option = proto::Options {
+            form: proto::OptionType::Put as i32,
+            underlying: 100.0,
+            strike: 110.0,
+            maturity: 1,
+            volatility: 0.2,
+            rfr: Some(0.03),
+            market_price: None,
+        };
+        let value = ModusService
+            .black_scholes(Request::new(option))
+            .await
+            .unwrap();
+        let expected =
+            bs_price(&Options::new(OptionType::Put, 100.0, 110.0, 1, 0.2).with_rfr(0.03));
+        assert_eq!(value.into_inner().value, expected);
+        let kelly = ModusService.kelly(Request::new(option)).await.unwrap_err();
+        assert_eq!(kelly.code(), tonic::Code::InvalidArgument);
+        let flat = proto::Options {
+            volatility: 0.0,
+            ..option
+        };
+        let invalid = ModusService
+            .black_scholes(Request::new(flat))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.message(), "volatility: must be positive");
+        let request = proto::ReturnsRequest {
+            portfolio: Some(message(transaction(2022, 1, 3, 180.0))),
+            aggregation: proto::Aggregation::Monthly as i32,
+        };
+        let returns = ModusService
+            .returns(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(returns.returns.keys().all(|month| month.len() == 7));
+        assert!(returns.returns.contains_key("2022-06"));
+        assert!(returns.years > 2.0);
+    }
+}
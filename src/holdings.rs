@@ -0,0 +1,237 @@
+//! Analytics across holdings
+//!
+//! Looks at the tickers of the portfolio individually rather than at the portfolio as a whole.
+//! Every ticker is fetched from the provider of the portfolio over its holding period, from the earliest buy to the latest sell,
+//! with its prices in USD.
+//!
+//! The function correlation returns the pairwise [correlation](https://en.wikipedia.org/wiki/Pearson_correlation_coefficient) matrix
+//! of the daily returns of the tickers, each pair being compared over the dates both have in common.
+//!
+//! The function risk_contribution returns the annualized covariance matrix instead and decomposes the variance of the portfolio
+//! into the contribution of each holding, weighted by the market value of the shares still held at the latest price, the
+//! positions sold being left out:
+//! the marginal contribution is how much the volatility of the portfolio grows per unit of weight and the percentage
+//! contribution is the share of the variance that is due to the holding.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::holdings::correlation;
+//! # use modus::stock_returns::Portfolio;
+//! # async fn run(portfolio: Portfolio) {
+//!  if let Ok(s) = correlation(&portfolio).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate};
+use serde::Serialize;
+
+use crate::provider::{get_ticker_quotes, Interval, Quote};
+use crate::statistics::{covariance, std_dev, PERIODS_PER_YEAR};
+use crate::stock_returns::{portfolio_range, Portfolio, StocksError};
+
+/// A symmetric matrix keyed by ticker in both dimensions
+pub type Matrix = BTreeMap<String, BTreeMap<String, f64>>;
+
+/// Holds the annualized covariance matrix and the contribution of every holding to the risk of the portfolio
+#[derive(Debug, Serialize)]
+pub struct RiskContribution {
+    pub covariance: Matrix,
+    pub holdings: BTreeMap<String, HoldingRisk>,
+}
+
+/// Holds the weight of a holding, its marginal contribution to the volatility and its share of the variance, in percentage
+#[derive(Debug, Serialize)]
+pub struct HoldingRisk {
+    pub weight: f64,
+    pub marginal: f64,
+    pub percentage: f64,
+}
+
+/// Returns the correlation matrix of the daily returns of the tickers in the portfolio
+pub async fn correlation(item: &Portfolio) -> Result<Matrix, StocksError> {
+    Ok(pairwise(
+        &ticker_returns(&ticker_quotes(item).await?),
+        |x, y| covariance(x, y) / (std_dev(x) * std_dev(y)),
+    ))
+}
+
+/// Returns the covariance matrix and the decomposition of the variance of the portfolio among its holdings
+pub async fn risk_contribution(item: &Portfolio) -> Result<RiskContribution, StocksError> {
+    Ok(contribution(item, &ticker_quotes(item).await?))
+}
+
+// the decomposition of the variance from the daily quotes of every ticker
+pub(crate) fn contribution(
+    item: &Portfolio,
+    quotes: &BTreeMap<String, Vec<Quote>>,
+) -> RiskContribution {
+    let covariance = pairwise(&ticker_returns(quotes), |x, y| {
+        covariance(x, y) * PERIODS_PER_YEAR
+    });
+    // the market value of the shares still held of every ticker at the latest price
+    let values: BTreeMap<&str, f64> = item
+        .open_quantities()
+        .into_iter()
+        .filter(|(_, quantity)| *quantity > 0.0)
+        .map(|(ticker, quantity)| {
+            let price = quotes
+                .get(ticker)
+                .and_then(|q| q.last())
+                .map(|q| q.adjclose)
+                .unwrap_or(0.0);
+            (ticker, price * quantity)
+        })
+        .collect();
+    let total: f64 = values.values().sum();
+    let weights: BTreeMap<&str, f64> = values.iter().map(|(t, v)| (*t, v / total)).collect();
+    // the covariance of every holding with the portfolio as a whole
+    let with_portfolio: BTreeMap<&str, f64> = weights
+        .keys()
+        .map(|a| {
+            (
+                *a,
+                weights
+                    .iter()
+                    .map(|(b, w)| w * covariance[*a][*b])
+                    .sum::<f64>(),
+            )
+        })
+        .collect();
+    let variance: f64 = weights.iter().map(|(t, w)| w * with_portfolio[t]).sum();
+    RiskContribution {
+        holdings: weights
+            .iter()
+            .map(|(ticker, w)| {
+                (
+                    ticker.to_string(),
+                    HoldingRisk {
+                        weight: w * 100.0,
+                        marginal: with_portfolio[ticker] / variance.sqrt() * 100.0,
+                        percentage: w * with_portfolio[ticker] / variance * 100.0,
+                    },
+                )
+            })
+            .collect(),
+        covariance,
+    }
+}
+
+// the quotes of every ticker over the holding period of the portfolio, from its provider
+async fn ticker_quotes(item: &Portfolio) -> Result<BTreeMap<String, Vec<Quote>>, StocksError> {
+    let (start, end) = portfolio_range(item)?;
+    Ok(get_ticker_quotes(
+        &item.data_provider()?,
+        item.tickers(),
+        &start,
+        &end,
+        Interval::Daily,
+    )
+    .await?)
+}
+
+// the daily returns of every ticker, keyed by date
+pub(crate) fn ticker_returns(
+    quotes: &BTreeMap<String, Vec<Quote>>,
+) -> BTreeMap<String, BTreeMap<NaiveDate, f64>> {
+    quotes
+        .iter()
+        .map(|(ticker, quotes)| {
+            (
+                ticker.clone(),
+                quotes
+                    .windows(2)
+                    .map(|w| {
+                        (
+                            DateTime::from_timestamp(w[1].timestamp as i64, 0)
+                                .unwrap_or_default()
+                                .date_naive(),
+                            w[1].adjclose / w[0].adjclose - 1.0,
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+// applies the statistic to every pair of tickers over the dates both have in common
+fn pairwise(
+    returns: &BTreeMap<String, BTreeMap<NaiveDate, f64>>,
+    statistic: impl Fn(&[f64], &[f64]) -> f64,
+) -> Matrix {
+    returns
+        .iter()
+        .map(|(a, x)| {
+            (
+                a.clone(),
+                returns
+                    .iter()
+                    .map(|(b, y)| {
+                        let (x, y): (Vec<f64>, Vec<f64>) = x
+                            .iter()
+                            .filter_map(|(date, rx)| y.get(date).map(|ry| (*rx, *ry)))
+                            .unzip();
+                        (b.clone(), statistic(&x, &y))
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+    use crate::stock_returns::{Equity, Transaction, TransactionDate};
+
+    // the daily quotes from the first of January 2023, one per adjusted close
+    fn quotes(adjcloses: &[f64]) -> Vec<Quote> {
+        adjcloses
+            .iter()
+            .enumerate()
+            .map(|(i, adjclose)| Quote {
+                timestamp: NaiveDate::from_ymd_opt(2023, 1, 1 + i as u32)
+                    .unwrap()
+                    .and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap())
+                    .and_utc()
+                    .timestamp() as u64,
+                close: *adjclose,
+                adjclose: *adjclose,
+                ..Quote::default()
+            })
+            .collect()
+    }
+
+    fn buy(price: f64) -> Transaction {
+        Transaction::new(TransactionDate::new(2023, 1, 1), price)
+    }
+
+    #[test]
+    fn weighs_only_the_shares_still_held() {
+        let sell = Transaction::new(TransactionDate::new(2023, 1, 3), 12.0);
+        let item = Portfolio::new(vec![
+            Equity::new("MSFT", buy(10.0), None, 1.0),
+            Equity::new("MSFT", buy(10.0), Some(sell.clone()), 3.0),
+            Equity::new("AAPL", buy(10.0), None, 2.0),
+            Equity::new("SPY", buy(10.0), Some(sell), 5.0),
+        ]);
+        let quotes = BTreeMap::from([
+            ("MSFT".to_string(), quotes(&[10.0, 11.0, 10.5, 12.0])),
+            ("AAPL".to_string(), quotes(&[10.0, 9.0, 9.5, 12.0])),
+            ("SPY".to_string(), quotes(&[10.0, 10.5, 10.0, 11.0])),
+        ]);
+        let risk = contribution(&item, &quotes);
+        assert_eq!(
+            risk.holdings.keys().collect::<Vec<_>>(),
+            vec!["AAPL", "MSFT"]
+        );
+        assert!((risk.holdings["MSFT"].weight - 100.0 / 3.0).abs() < 1e-9);
+        assert!((risk.holdings["AAPL"].weight - 200.0 / 3.0).abs() < 1e-9);
+        let percentages: f64 = risk.holdings.values().map(|h| h.percentage).sum();
+        assert!((percentages - 100.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::rt::time::sleep;
+use rand::Rng;
+use reqwest::{Certificate, Client, Proxy, RequestBuilder, Response, StatusCode};
+use tracing::{debug, warn};
+
+use crate::provider::ConfigError;
+use crate::yahoo_finance::{ProviderError, Timeout};
+
+// a fairly common user agent is necessary because otherwise Yahoo rate limits us
+const USER_AGENT: &str = "curl/7.68.0";
+
+// the seconds set by the variable, the default if it isn't set or isn't a number
+fn seconds(variable: &str, default: u64) -> Duration {
+    Duration::from_secs(
+        std::env::var(variable)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default),
+    )
+}
+
+// the client every provider sends its requests with, going through the proxy in MODUS_PROXY, e.g., http://proxy.corp:3128,
+// trusting the root certificates in the PEM file MODUS_CA_CERT points to besides the system ones and identifying itself
+// with MODUS_USER_AGENT. The connections are kept open for MODUS_POOL_IDLE seconds after their last request, so that the
+// next requests to the same host don't have to connect again, with TCP keep-alives every MODUS_KEEPALIVE seconds, and
+// connecting gives up after MODUS_CONNECT_TIMEOUT seconds and the whole request after MODUS_REQUEST_TIMEOUT seconds
+fn build() -> Result<Client, ConfigError> {
+    let invalid =
+        |variable, e: &dyn std::fmt::Display| ConfigError::Invalid(variable, e.to_string());
+    let mut builder = Client::builder()
+        .user_agent(std::env::var("MODUS_USER_AGENT").unwrap_or_else(|_| USER_AGENT.to_string()))
+        .connect_timeout(seconds("MODUS_CONNECT_TIMEOUT", 10))
+        .timeout(request_timeout())
+        .pool_idle_timeout(seconds("MODUS_POOL_IDLE", 90))
+        .tcp_keepalive(seconds("MODUS_KEEPALIVE", 60));
+    if let Ok(proxy) = std::env::var("MODUS_PROXY") {
+        builder = builder.proxy(Proxy::all(proxy).map_err(|e| invalid("MODUS_PROXY", &e))?);
+    }
+    if let Ok(path) = std::env::var("MODUS_CA_CERT") {
+        let pem = std::fs::read(path).map_err(|e| invalid("MODUS_CA_CERT", &e))?;
+        for certificate in
+            Certificate::from_pem_bundle(&pem).map_err(|e| invalid("MODUS_CA_CERT", &e))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    builder.build().map_err(|e| invalid("the HTTP client", &e))
+}
+
+fn request_timeout() -> Duration {
+    seconds("MODUS_REQUEST_TIMEOUT", 30)
+}
+
+/// Returns how long all the requests for a portfolio are given, MODUS_DEADLINE seconds, 2 minutes by default
+pub(crate) fn deadline() -> Duration {
+    seconds("MODUS_DEADLINE", 120)
+}
+
+fn configured() -> &'static Result<Client, ConfigError> {
+    static CLIENT: OnceLock<Result<Client, ConfigError>> = OnceLock::new();
+    CLIENT.get_or_init(build)
+}
+
+// the shared client, so that the connections are reused, failing if it couldn't be configured
+pub(crate) fn client() -> Result<&'static Client, ProviderError> {
+    configured()
+        .as_ref()
+        .map_err(|e| ProviderError::ConfigError(e.clone()))
+}
+
+// whether the client could be configured, and why not
+pub(crate) fn check() -> Result<(), ConfigError> {
+    configured().as_ref().map(|_| ()).map_err(Clone::clone)
+}
+
+// how many times a request is sent before giving up, set by MODUS_RETRIES, and the wait before the first retry in
+// milliseconds, set by MODUS_RETRY_BACKOFF, which doubles after every retry. The requests to every host are limited to
+// MODUS_RATE_LIMIT a second, 0 lifting the limit, with bursts of up to MODUS_RATE_BURST of them
+struct Policy {
+    attempts: u32,
+    backoff: u64,
+    rate: f64,
+    burst: f64,
+}
+
+fn policy() -> &'static Policy {
+    static POLICY: OnceLock<Policy> = OnceLock::new();
+    POLICY.get_or_init(|| {
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        Policy {
+            attempts: var("MODUS_RETRIES").unwrap_or(3).max(1) as u32,
+            backoff: var("MODUS_RETRY_BACKOFF").unwrap_or(500),
+            rate: var("MODUS_RATE_LIMIT").unwrap_or(4) as f64,
+            burst: var("MODUS_RATE_BURST").unwrap_or(8).max(1) as f64,
+        }
+    })
+}
+
+// the requests a host can still be sent right away, negative when some are already waiting for their turn
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// waits until the host can be sent another request, the bucket being shared by all the requests in flight so that
+// concurrent portfolios don't get throttled either
+async fn throttle(host: &str) {
+    let policy = policy();
+    if policy.rate <= 0.0 {
+        return;
+    }
+    static BUCKETS: OnceLock<Mutex<HashMap<String, Bucket>>> = OnceLock::new();
+    let wait = {
+        let mut buckets = BUCKETS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let bucket = buckets.entry(host.to_string()).or_insert(Bucket {
+            tokens: policy.burst,
+            updated: now,
+        });
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * policy.rate;
+        bucket.tokens = (bucket.tokens + refill).min(policy.burst) - 1.0;
+        bucket.updated = now;
+        // the token is taken now, so the request waits for the ones owed to those before it as well
+        (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / policy.rate))
+    };
+    if let Some(wait) = wait {
+        sleep(wait).await;
+    }
+}
+
+// the responses worth trying again, those of a throttled or momentarily unavailable server
+pub(crate) fn transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// sends the request once the rate limit allows it, retrying after the rate limits and the transient errors with an exponential backoff and a random jitter,
+// so that the concurrent requests don't retry all at once, or after the time the server asks for if it says so.
+// The last response is returned as it is for the provider to make sense of it
+pub(crate) async fn send(request: RequestBuilder) -> Result<Response, ProviderError> {
+    send_up_to(request, policy().attempts).await
+}
+
+// sends the request once the rate limit allows it without retrying it, for the callers that have another way around a refusal
+pub(crate) async fn send_once(request: RequestBuilder) -> Result<Response, ProviderError> {
+    send_up_to(request, 1).await
+}
+
+async fn send_up_to(request: RequestBuilder, attempts: u32) -> Result<Response, ProviderError> {
+    // a request that timed out is told apart from the ones that failed
+    let failed = |e: reqwest::Error| match e.is_timeout() {
+        true => ProviderError::from(Timeout(request_timeout())),
+        false => ProviderError::from(e),
+    };
+    let policy = policy();
+    let host = request
+        .try_clone()
+        .and_then(|r| r.build().ok())
+        .and_then(|r| r.url().host_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        throttle(&host).await;
+        // the body of a streamed request can't be sent again
+        let (Some(retry), true) = (request.try_clone(), attempt < attempts) else {
+            let response = request.send().await;
+            if let Ok(response) = &response {
+                debug!(
+                    host,
+                    attempt,
+                    status = response.status().as_u16(),
+                    "received a response"
+                );
+            }
+            return response.map_err(failed);
+        };
+        let response = retry.send().await;
+        match &response {
+            Ok(response) => {
+                debug!(
+                    host,
+                    attempt,
+                    status = response.status().as_u16(),
+                    "received a response"
+                )
+            }
+            Err(e) => debug!(host, attempt, error = %e, "the request failed"),
+        }
+        let wait = match response {
+            Ok(response) if transient(response.status()) => response
+                .headers()
+                .get("Retry-After")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(|seconds| seconds * 1000),
+            Err(e) if e.is_connect() || e.is_timeout() => None,
+            result => return result.map_err(failed),
+        };
+        let backoff = policy.backoff << (attempt - 1).min(16);
+        let jitter = rand::thread_rng().gen_range(0, backoff / 2 + 1);
+        // a server asking for more than a minute is as good as down
+        let wait = wait.unwrap_or(backoff + jitter).min(60_000);
+        warn!(host, attempt, wait_ms = wait, "retrying the request");
+        sleep(Duration::from_millis(wait)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::*;
+
+    // a server answering with the status the first times it's asked and then with OK, along with how many times it was
+    async fn serve(status: u16, times: u32) -> (String, Arc<AtomicU32>) {
+        let hits = Arc::new(AtomicU32::new(0));
+        let counted = hits.clone();
+        let server = HttpServer::new(move || {
+            let hits = counted.clone();
+            App::new().default_service(web::to(move || {
+                let hit = hits.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    match hit <= times {
+                        true => HttpResponse::build(
+                            actix_web::http::StatusCode::from_u16(status).unwrap(),
+                        )
+                        .insert_header(("Retry-After", "0"))
+                        .finish(),
+                        false => HttpResponse::Ok().body("ok"),
+                    }
+                }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (url, hits)
+    }
+
+    #[actix_web::test]
+    async fn retries_the_throttled_and_unavailable_responses() {
+        let (url, hits) = serve(503, 2).await;
+        let response = send(client().unwrap().get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn gives_the_last_response_once_the_attempts_run_out() {
+        let (url, hits) = serve(429, u32::MAX).await;
+        let response = send(client().unwrap().get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(hits.load(Ordering::SeqCst), policy().attempts);
+    }
+
+    #[actix_web::test]
+    async fn the_requests_sent_once_are_not_retried() {
+        let (url, hits) = serve(429, u32::MAX).await;
+        let response = send_once(client().unwrap().get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn the_other_errors_are_not_retried() {
+        let (url, hits) = serve(404, 1).await;
+        let response = send(client().unwrap().get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(transient(StatusCode::BAD_GATEWAY));
+        assert!(!transient(StatusCode::UNAUTHORIZED));
+    }
+
+    #[actix_web::test]
+    async fn the_host_is_sent_a_burst_and_then_the_rate_a_second() {
+        let policy = policy();
+        let started = Instant::now();
+        for _ in 0..policy.burst as u32 {
+            throttle("burst.example.com").await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(100));
+        // the other hosts have buckets of their own
+        throttle("other.example.com").await;
+        assert!(started.elapsed() < Duration::from_millis(100));
+        for _ in 0..2 {
+            throttle("burst.example.com").await;
+        }
+        assert!(started.elapsed() >= Duration::from_secs_f64(1.8 / policy.rate));
+    }
+}
@@ -0,0 +1,445 @@
+//! Broker transaction import
+//!
+//! Converts the transaction history exported by a broker as CSV into a Portfolio, so that it doesn't have to be written by hand.
+//! The format is detected from the file itself, the supported ones being:
+//! - Interactive Brokers, the Trades section of the activity statement (only stocks, every other asset category is ignored)
+//! - Degiro, the English Transactions export, which has no tickers, the ticker of every ISIN being the first one Yahoo Finance
+//!   finds when searched for it
+//! - Trading212, the history export (only buys and sells, deposits, dividends and the like are ignored)
+//!
+//! The trades are replayed in chronological order and every sale is matched against the oldest shares still held (FIFO),
+//! so a buy that is partially sold becomes a closed position and an open one. Prices are kept in the currency they were traded in
+//! and fractional quantities, as those of Trading212, are kept as they are.
+//! The rows of a Degiro export whose ISIN doesn't match any ticker are all reported, rather than left with the ISIN as a ticker
+//! that no provider would know.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::import::import;
+//! # async fn run() -> std::io::Result<()> {
+//!  let csv = std::fs::read("Transactions.csv")?;
+//!  if let Ok(portfolio) = import(&csv).await { println!("{:?}", portfolio); }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use csv::{ReaderBuilder, StringRecord};
+use futures::{stream, StreamExt, TryStreamExt};
+use thiserror::Error;
+
+use crate::market::search;
+use crate::stock_returns::{
+    Equity, Portfolio, StocksError, Transaction, TransactionDate, Violation,
+};
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("the CSV couldn't be read")]
+    Csv(#[from] csv::Error),
+    #[error("the CSV isn't an Interactive Brokers, Degiro or Trading212 export")]
+    UnknownFormat,
+    #[error("line {0} has a missing or invalid field")]
+    InvalidRow(u64),
+    #[error("some ISINs don't match any ticker")]
+    UnknownIsins(Vec<Violation>),
+    #[error("the tickers of the ISINs couldn't be looked up")]
+    Lookup(StocksError),
+    #[error("more shares of {0} were sold than bought")]
+    Oversold(String),
+    #[error("the CSV has no trades")]
+    NoTrades,
+}
+
+/// The brokers whose exports can be imported
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Broker {
+    InteractiveBrokers,
+    Degiro,
+    Trading212,
+}
+
+// a single buy (positive quantity) or sell (negative quantity), along with the line of the export it's on
+struct Trade {
+    ticker: String,
+    time: NaiveDateTime,
+    price: f64,
+    quantity: f64,
+    line: u64,
+}
+
+// an open position, the shares bought at a date and price that haven't been sold yet
+struct Lot {
+    date: NaiveDate,
+    price: f64,
+    quantity: f64,
+}
+
+// the shares below which a lot is taken as sold, the fractional quantities not always adding up exactly
+const DUST: f64 = 1e-9;
+
+// the ISINs looked up at the same time
+const CONCURRENT_REQUESTS: usize = 8;
+
+/// Parses a broker export into a Portfolio, looking up the tickers of the ISINs of Degiro
+pub async fn import(csv: &[u8]) -> Result<Portfolio, ImportError> {
+    let (broker, trades) = parse(csv)?;
+    let trades = match broker {
+        Broker::Degiro => {
+            let isins: BTreeSet<&str> = trades.iter().map(|t| t.ticker.as_str()).collect();
+            let symbols = lookup(isins).await.map_err(ImportError::Lookup)?;
+            with_symbols(trades, &symbols)?
+        }
+        _ => trades,
+    };
+    portfolio(trades)
+}
+
+// the broker of the export and its trades
+fn parse(csv: &[u8]) -> Result<(Broker, Vec<Trade>), ImportError> {
+    let records: Vec<StringRecord> = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(csv)
+        .records()
+        .collect::<Result<_, _>>()?;
+    let broker = detect(&records).ok_or(ImportError::UnknownFormat)?;
+    let trades = match broker {
+        Broker::InteractiveBrokers => interactive_brokers(&records)?,
+        Broker::Degiro => degiro(&records)?,
+        Broker::Trading212 => trading212(&records)?,
+    };
+    Ok((broker, trades))
+}
+
+// the ticker of every ISIN, the first match of searching Yahoo for it, None if nothing matches
+async fn lookup(isins: BTreeSet<&str>) -> Result<BTreeMap<String, Option<String>>, StocksError> {
+    let requests: Vec<_> = isins
+        .into_iter()
+        .map(|isin| async move {
+            let symbol = search(isin).await?.into_iter().next().map(|m| m.symbol);
+            Ok::<_, StocksError>((isin.to_string(), symbol))
+        })
+        .collect();
+    stream::iter(requests)
+        .buffered(CONCURRENT_REQUESTS)
+        .try_collect()
+        .await
+}
+
+// replaces the ISINs of the trades with their tickers, reporting every trade whose ISIN has none
+fn with_symbols(
+    trades: Vec<Trade>,
+    symbols: &BTreeMap<String, Option<String>>,
+) -> Result<Vec<Trade>, ImportError> {
+    let mut violations = Vec::new();
+    let trades = trades
+        .into_iter()
+        .filter_map(|trade| match symbols.get(&trade.ticker) {
+            Some(Some(symbol)) => Some(Trade {
+                ticker: symbol.clone(),
+                ..trade
+            }),
+            _ => {
+                violations.push(Violation::new(
+                    format!("line {}", trade.line),
+                    format!("the ISIN {} doesn't match any ticker", trade.ticker),
+                ));
+                None
+            }
+        })
+        .collect();
+    if violations.is_empty() {
+        Ok(trades)
+    } else {
+        Err(ImportError::UnknownIsins(violations))
+    }
+}
+
+/// Identifies the broker from the headers of the export
+pub fn detect(records: &[StringRecord]) -> Option<Broker> {
+    let first = records.first()?;
+    let has = |name: &str| first.iter().any(|field| field == name);
+    if has("Action") && has("No. of shares") {
+        Some(Broker::Trading212)
+    } else if has("Product") && has("ISIN") && has("Quantity") {
+        Some(Broker::Degiro)
+    } else if records
+        .iter()
+        .any(|r| r.get(0) == Some("Trades") && r.get(1) == Some("Header"))
+    {
+        Some(Broker::InteractiveBrokers)
+    } else {
+        None
+    }
+}
+
+fn interactive_brokers(records: &[StringRecord]) -> Result<Vec<Trade>, ImportError> {
+    let mut trades = Vec::new();
+    // the columns are given by the header of the section, which may be repeated
+    let mut header: Option<&StringRecord> = None;
+    for record in records.iter().filter(|r| r.get(0) == Some("Trades")) {
+        match record.get(1) {
+            Some("Header") => header = Some(record),
+            Some("Data") => {
+                let header = header.ok_or(ImportError::UnknownFormat)?;
+                let field = |name: &str| column(header, record, name);
+                // the subtotals and totals have another discriminator
+                if field("DataDiscriminator") != Some("Order")
+                    || field("Asset Category") != Some("Stocks")
+                {
+                    continue;
+                }
+                let line = line(record);
+                // e.g., 2023-02-01, 10:15:00
+                let time = field("Date/Time")
+                    .and_then(|t| NaiveDateTime::parse_from_str(t, "%Y-%m-%d, %H:%M:%S").ok())
+                    .ok_or(ImportError::InvalidRow(line))?;
+                trades.push(Trade {
+                    ticker: field("Symbol")
+                        .ok_or(ImportError::InvalidRow(line))?
+                        .to_string(),
+                    time,
+                    price: number(field("T. Price"), line)?,
+                    quantity: number(field("Quantity"), line)?,
+                    line,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(trades)
+}
+
+fn degiro(records: &[StringRecord]) -> Result<Vec<Trade>, ImportError> {
+    let header = &records[0];
+    records[1..]
+        .iter()
+        .map(|record| {
+            let field = |name: &str| column(header, record, name);
+            let line = line(record);
+            // e.g., 01-02-2023 and 15:30
+            let date = field("Date")
+                .and_then(|d| NaiveDate::parse_from_str(d, "%d-%m-%Y").ok())
+                .ok_or(ImportError::InvalidRow(line))?;
+            let time = field("Time")
+                .and_then(|t| NaiveTime::parse_from_str(t, "%H:%M").ok())
+                .unwrap_or_default();
+            Ok(Trade {
+                ticker: field("ISIN")
+                    .ok_or(ImportError::InvalidRow(line))?
+                    .to_string(),
+                time: date.and_time(time),
+                price: number(field("Price"), line)?,
+                // sales have negative quantities
+                quantity: number(field("Quantity"), line)?,
+                line,
+            })
+        })
+        .collect()
+}
+
+fn trading212(records: &[StringRecord]) -> Result<Vec<Trade>, ImportError> {
+    let header = &records[0];
+    let mut trades = Vec::new();
+    for record in records[1..].iter() {
+        let field = |name: &str| column(header, record, name);
+        let line = line(record);
+        // e.g., Market buy or Limit sell
+        let sign = match field("Action") {
+            Some(action) if action.ends_with("buy") => 1.0,
+            Some(action) if action.ends_with("sell") => -1.0,
+            _ => continue,
+        };
+        // e.g., 2023-02-01 14:30:05, sometimes with milliseconds
+        let time = field("Time")
+            .and_then(|t| NaiveDateTime::parse_from_str(t.get(..19)?, "%Y-%m-%d %H:%M:%S").ok())
+            .ok_or(ImportError::InvalidRow(line))?;
+        trades.push(Trade {
+            ticker: field("Ticker")
+                .ok_or(ImportError::InvalidRow(line))?
+                .to_string(),
+            time,
+            price: number(field("Price / share"), line)?,
+            quantity: sign * number(field("No. of shares"), line)?,
+            line,
+        });
+    }
+    Ok(trades)
+}
+
+// replays the trades, matching every sale against the oldest shares held
+fn portfolio(mut trades: Vec<Trade>) -> Result<Portfolio, ImportError> {
+    // stable, so trades at the same time keep the order of the export
+    trades.sort_by_key(|t| t.time);
+    let mut equities = Vec::new();
+    let mut lots: BTreeMap<String, VecDeque<Lot>> = BTreeMap::new();
+    for trade in trades.into_iter().filter(|t| t.quantity != 0.0) {
+        let held = lots.entry(trade.ticker.clone()).or_default();
+        if trade.quantity > 0.0 {
+            held.push_back(Lot {
+                date: trade.time.date(),
+                price: trade.price,
+                quantity: trade.quantity,
+            });
+            continue;
+        }
+        let mut remaining = -trade.quantity;
+        while remaining > DUST {
+            let lot = held
+                .front_mut()
+                .ok_or_else(|| ImportError::Oversold(trade.ticker.clone()))?;
+            let sold = lot.quantity.min(remaining);
+            equities.push(Equity::new(
+                trade.ticker.clone(),
+                Transaction::new(TransactionDate::from(lot.date), lot.price),
+                Some(Transaction::new(
+                    TransactionDate::from(trade.time.date()),
+                    trade.price,
+                )),
+                sold,
+            ));
+            lot.quantity -= sold;
+            remaining -= sold;
+            if lot.quantity <= DUST {
+                held.pop_front();
+            }
+        }
+    }
+    // whatever hasn't been sold is still held
+    for (ticker, held) in lots {
+        for lot in held {
+            equities.push(Equity::new(
+                ticker.clone(),
+                Transaction::new(TransactionDate::from(lot.date), lot.price),
+                None,
+                lot.quantity,
+            ));
+        }
+    }
+    if equities.is_empty() {
+        return Err(ImportError::NoTrades);
+    }
+    Ok(Portfolio::new(equities))
+}
+
+// the field of the record under the column with that name in the header
+fn column<'a>(header: &StringRecord, record: &'a StringRecord, name: &str) -> Option<&'a str> {
+    header
+        .iter()
+        .position(|h| h == name)
+        .and_then(|i| record.get(i))
+        .map(str::trim)
+}
+
+fn line(record: &StringRecord) -> u64 {
+    record.position().map(|p| p.line()).unwrap_or(0)
+}
+
+// thousands separators are dropped, e.g., 1,000
+fn number(field: Option<&str>, line: u64) -> Result<f64, ImportError> {
+    field
+        .and_then(|f| f.replace(',', "").parse::<f64>().ok())
+        .filter(|x| x.is_finite())
+        .ok_or(ImportError::InvalidRow(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the ticker, the quantity and whether it was sold of every equity
+    fn equities(item: &Portfolio) -> Vec<(&str, f64, bool)> {
+        item.equities()
+            .iter()
+            .map(|n| (n.ticker(), n.quantity(), n.sell().is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn imports_only_the_stock_orders_of_interactive_brokers() {
+        let csv = b"Statement,Header,Field Name,Field Value
+Trades,Header,DataDiscriminator,Asset Category,Currency,Symbol,Date/Time,Quantity,T. Price
+Trades,Data,Order,Stocks,USD,MSFT,\"2023-02-01, 10:15:00\",\"1,000\",250.5
+Trades,Data,Order,Stocks,USD,MSFT,\"2023-03-01, 11:00:00\",-400,260
+Trades,SubTotal,,Stocks,USD,MSFT,,600,
+Trades,Data,Order,Forex,USD,EUR.USD,\"2023-02-01, 10:15:00\",100,1.1
+";
+        let (broker, trades) = parse(csv).unwrap();
+        assert_eq!(broker, Broker::InteractiveBrokers);
+        let item = portfolio(trades).unwrap();
+        assert_eq!(
+            equities(&item),
+            vec![("MSFT", 400.0, true), ("MSFT", 600.0, false)]
+        );
+        assert_eq!(item.equities()[0].sell().unwrap().price(), 260.0);
+    }
+
+    #[test]
+    fn replaces_the_isins_of_degiro_with_their_tickers() {
+        let csv = b"Date,Time,Product,ISIN,Reference exchange,Venue,Quantity,Price,,Local value
+01-02-2023,15:30,APPLE INC,US0378331005,NDQ,XNAS,5,145.50,USD,-727.50
+02-03-2023,09:05,SAP SE,DE0007164600,XET,XETA,3,110.00,EUR,-330.00
+";
+        let (broker, trades) = parse(csv).unwrap();
+        assert_eq!(broker, Broker::Degiro);
+        let symbols = BTreeMap::from([
+            ("US0378331005".to_string(), Some("AAPL".to_string())),
+            ("DE0007164600".to_string(), Some("SAP.DE".to_string())),
+        ]);
+        let item = portfolio(with_symbols(trades, &symbols).unwrap()).unwrap();
+        assert_eq!(
+            equities(&item),
+            vec![("AAPL", 5.0, false), ("SAP.DE", 3.0, false)]
+        );
+    }
+
+    #[test]
+    fn reports_the_lines_whose_isin_has_no_ticker() {
+        let csv = b"Date,Time,Product,ISIN,Reference exchange,Venue,Quantity,Price,,Local value
+01-02-2023,15:30,APPLE INC,US0378331005,NDQ,XNAS,5,145.50,USD,-727.50
+02-03-2023,09:05,DELISTED CO,XX0000000000,XET,XETA,3,1.00,EUR,-3.00
+";
+        let (_, trades) = parse(csv).unwrap();
+        let symbols = BTreeMap::from([
+            ("US0378331005".to_string(), Some("AAPL".to_string())),
+            ("XX0000000000".to_string(), None),
+        ]);
+        let Err(ImportError::UnknownIsins(violations)) = with_symbols(trades, &symbols) else {
+            panic!("the unknown ISIN wasn't reported");
+        };
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].field, "line 3");
+        assert!(violations[0].message.contains("XX0000000000"));
+    }
+
+    #[test]
+    fn keeps_the_fractional_shares_of_trading212() {
+        let csv =
+            b"Action,Time,ISIN,Ticker,Name,No. of shares,Price / share,Currency (Price / share)
+Deposit,2023-01-31 10:00:00,,,,,,
+Market buy,2023-02-01 14:30:05.123,US0378331005,AAPL,Apple,0.5,145.2,USD
+Limit sell,2023-03-01 14:30:05,US0378331005,AAPL,Apple,0.2,150,USD
+";
+        let (broker, trades) = parse(csv).unwrap();
+        assert_eq!(broker, Broker::Trading212);
+        let item = portfolio(trades).unwrap();
+        let quantities: Vec<f64> = item.equities().iter().map(Equity::quantity).collect();
+        assert_eq!(quantities[0], 0.2);
+        assert!((quantities[1] - 0.3).abs() < 1e-12);
+        assert!(item.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_selling_more_than_was_bought() {
+        let csv =
+            b"Action,Time,ISIN,Ticker,Name,No. of shares,Price / share,Currency (Price / share)
+Market buy,2023-02-01 14:30:05,US0378331005,AAPL,Apple,1,145.2,USD
+Market sell,2023-03-01 14:30:05,US0378331005,AAPL,Apple,1.5,150,USD
+";
+        let (_, trades) = parse(csv).unwrap();
+        assert!(matches!(portfolio(trades), Err(ImportError::Oversold(t)) if t == "AAPL"));
+    }
+}
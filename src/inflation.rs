@@ -0,0 +1,150 @@
+//! Real returns
+//!
+//! Deflates the nominal returns of the portfolio by the consumer price index, so that the growth of multi-decade portfolios
+//! is measured in purchasing power rather than in dollars.
+//!
+//! The index can be supplied by date (YYYY-MM-DD, or YYYY-MM for monthly figures), in any currency as long as it's the one
+//! the returns are in, and is otherwise the US CPI for all urban consumers (CPIAUCSL) from
+//! [FRED](https://fred.stlouisfed.org/series/CPIAUCSL), or the euro area HICP from the
+//! [ECB](https://data.ecb.europa.eu/data/datasets/ICP) if asked for. Every date takes the last level published on or before it, so the
+//! monthly index is flat within every month and the latest months carry the last known level.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::inflation::{real_returns, RealRequest};
+//! # use modus::stock_returns::Interval;
+//! # async fn run(request: RealRequest) {
+//!  if let Ok(Some(s)) = real_returns(&request, Interval::Daily).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::ecb::{self, HICP};
+use crate::fred::{self, CPI};
+use crate::stock_returns::{
+    portfolio_range, total_returns, Interval, Portfolio, StocksError, Violation,
+};
+
+/// The published price indices, the US CPI by default
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PriceIndex {
+    #[default]
+    UsCpi,
+    EuroHicp,
+}
+
+/// Holds the portfolio along with the price index by date, which is fetched from FRED or the ECB when missing
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RealRequest {
+    #[serde(flatten)]
+    portfolio: Portfolio,
+    #[serde(default)]
+    cpi: Option<BTreeMap<String, f64>>,
+    #[serde(default)]
+    index: PriceIndex,
+}
+
+impl RealRequest {
+    pub fn portfolio(&self) -> &Portfolio {
+        &self.portfolio
+    }
+
+    /// Checks the portfolio and that every level of the index is positive and dated
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = self.portfolio.validate().err().unwrap_or_default();
+        for (date, level) in self.cpi.iter().flatten() {
+            if parse_date(date).is_none() {
+                violations.push(Violation::new(
+                    format!("cpi.{date}"),
+                    "must be dated YYYY-MM-DD or YYYY-MM",
+                ));
+            }
+            if !(level.is_finite() && *level > 0.0) {
+                violations.push(Violation::new(format!("cpi.{date}"), "must be positive"));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Holds the nominal and real cumulative returns in percentage and the inflation over the whole period
+#[derive(Debug, Serialize)]
+pub struct RealReturns {
+    pub nominal: BTreeMap<String, f64>,
+    pub real: BTreeMap<String, f64>,
+    pub inflation: f64,
+}
+
+/// Returns the real returns of the portfolio, None if the index doesn't go back to the start of the returns
+pub async fn real_returns(
+    item: &RealRequest,
+    interval: Interval,
+) -> Result<Option<RealReturns>, StocksError> {
+    let nominal = total_returns(&item.portfolio, interval).await?;
+    let index = match &item.cpi {
+        Some(cpi) => cpi
+            .iter()
+            .filter_map(|(date, level)| Some((parse_date(date)?, *level)))
+            .collect(),
+        None => published(item.index, &item.portfolio).await?,
+    };
+    let level = |date: &String| {
+        let date = date.parse::<NaiveDate>().ok()?;
+        index.range(..=date).next_back().map(|(_, level)| *level)
+    };
+    let base = match nominal.keys().next().and_then(level) {
+        Some(base) => base,
+        None => return Ok(None),
+    };
+    let mut inflation = 0.0;
+    let real = nominal
+        .iter()
+        .filter_map(|(date, r)| {
+            let deflator = level(date)? / base;
+            inflation = (deflator - 1.0) * 100.0;
+            Some((date.clone(), ((1.0 + r / 100.0) / deflator - 1.0) * 100.0))
+        })
+        .collect();
+    Ok(Some(RealReturns {
+        nominal,
+        real,
+        inflation,
+    }))
+}
+
+// the index from the start of the month of the first buy, so that its level is known
+async fn published(
+    index: PriceIndex,
+    item: &Portfolio,
+) -> Result<BTreeMap<NaiveDate, f64>, StocksError> {
+    let (start, end) = portfolio_range(item)?;
+    let date = |d: OffsetDateTime| {
+        DateTime::from_timestamp(d.unix_timestamp(), 0)
+            .unwrap_or_default()
+            .date_naive()
+    };
+    let (start, end) = (date(start), date(end));
+    let start = start.with_day(1).unwrap_or(start);
+    Ok(match index {
+        PriceIndex::UsCpi => fred::get_series(CPI, start, end).await?,
+        PriceIndex::EuroHicp => ecb::get_series(HICP, start, end).await?,
+    })
+}
+
+// the date of a level of the index, the first of the month for monthly ones
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    date.parse()
+        .ok()
+        .or_else(|| format!("{date}-01").parse().ok())
+}
@@ -10,6 +10,44 @@
 //!
 //! To calculate option value and provide optimal betting size
 
+pub mod activity;
+pub mod allocation;
+mod alpha_vantage;
+pub mod analysis;
+pub mod backtest;
+pub mod benchmark;
+mod coingecko;
+pub mod comparison;
+pub mod dividends;
+mod ecb;
+pub mod factors;
+mod files;
+mod fixtures;
+mod fred;
+pub mod fx;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod holdings;
+mod http;
+pub mod import;
+pub mod inflation;
+pub mod market;
 pub mod options;
+pub mod performance;
+mod polygon;
+pub mod portfolio_optimization;
+pub mod projection;
+pub mod provider;
+#[cfg(feature = "sqlite")]
+pub mod quote_cache;
+pub mod risk;
+mod statistics;
 pub mod stock_returns;
+mod stooq;
+#[cfg(feature = "sqlite")]
+pub mod storage;
+mod tiingo;
+pub mod valuation;
 mod yahoo_finance;
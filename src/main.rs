@@ -1,32 +1,1277 @@
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
-use modus::options::{bs_price, expected, kelly_ratio, Options};
-use modus::stock_returns::{total_returns, Portfolio, StocksError};
+// the futures of the handlers nest the providers deep enough for checking that they are Send to go past the default limit
+#![recursion_limit = "256"]
+
+use std::collections::BTreeMap;
+use std::pin::pin;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::ServerHandle;
+use actix_web::http::header;
+use actix_web::middleware::{from_fn, Compress};
+use actix_web::rt::time::timeout;
+use actix_web::{
+    get, guard, web, App, FromRequest, Handler, HttpRequest, HttpResponse, HttpServer, Responder,
+    Route,
+};
+use actix_ws::{Message, MessageStream, Session};
+use chrono::{Duration, NaiveDate, Utc};
+use futures::future::{select, Either};
+use futures::{stream, StreamExt};
+use modus::activity::activity;
+use modus::allocation::breakdown;
+use modus::analysis::analyze;
+use modus::backtest::{dca, rebalance, Plan, Strategy};
+use modus::benchmark::compare;
+use modus::comparison::{compare_portfolios, Portfolios};
+use modus::dividends::{corporate_actions, dividend_income, trailing_yield};
+use modus::factors::{exposure, FactorRequest};
+use modus::fx::{ecb_series, fx_series, FxService};
+use modus::holdings::correlation;
+use modus::import::{import, ImportError};
+use modus::inflation::{real_returns, RealRequest};
+use modus::market::{fundamentals, live_quote, live_quotes, search};
+use modus::options::{bs_price, estimates, expected, kelly_ratio, Options};
+use modus::performance::{sub_period_returns, Method};
+use modus::portfolio_optimization::{
+    black_litterman, efficient_frontier, risk_parity, Universe, Views,
+};
+use modus::projection::{project, Projection, ProjectionError};
+use modus::provider::{DataProvider, Provider, Source};
+use modus::risk::{risk, rolling, volatility};
+use modus::stock_returns::{
+    aggregate, cagr, contributions, decompose, hedged_returns, mean_return, profit_and_loss,
+    stream_returns, total_returns_in, weights, years, Aggregation, Currency, Interval, Portfolio,
+    ProviderError, Snapshots, StocksError, Violation,
+};
+use modus::valuation::{live_value, value_at};
+use serde::Deserialize;
 use serde_json::json;
+use tracing::{debug, info, warn, Instrument};
+use utoipa::{IntoParams, OpenApi};
+
+use crate::extract::Json;
+
+const ENDPOINTS: &str = "Available endpoints: \n /v1/equities/returns \n /v1/equities/benchmark \n /v1/equities/risk \n /v1/equities/analysis \n /v1/equities/rolling \n /v1/equities/correlation \n /v1/equities/optimization \n /v1/equities/black-litterman \n /v1/equities/risk-parity \n /v1/equities/rebalancing \n /v1/equities/dca \n /v1/equities/projection \n /v1/equities/allocation \n /v1/equities/dividends \n /v1/equities/yield \n /v1/equities/events \n /v1/equities/quote/{ticker} \n /v1/equities/quotes \n /v1/equities/search \n /v1/equities/fundamentals/{ticker} \n /v1/equities/contribution \n /v1/equities/value \n /v1/equities/import \n /v1/equities/comparison \n /v1/equities/merge \n /v1/equities/diff \n /v1/equities/hedged \n /v1/equities/decomposition \n /v1/equities/factors \n /v1/equities/performance \n /v1/equities/real \n /v1/equities/pnl \n /v1/equities/weights \n /v1/equities/activity \n /v1/options/bs \n /v1/options/kelly \n /v1/options/mc \n /v1/fx/{pair} \n /v1/ws/portfolio \n /v1/jobs/returns \n /v1/jobs/analysis \n /v1/jobs/projection \n /v1/jobs/mc \n /v1/jobs/{id} \n /openapi.json \n /docs \n /healthz \n /readyz";
+
+#[cfg(feature = "sqlite")]
+const STORAGE_ENDPOINTS: &str =
+    " \n /v1/portfolios \n /v1/portfolios/{id} \n /v1/portfolios/{id}/returns";
+#[cfg(not(feature = "sqlite"))]
+const STORAGE_ENDPOINTS: &str = "";
+
+#[cfg(feature = "graphql")]
+const GRAPHQL_ENDPOINTS: &str = " \n /v1/graphql";
+#[cfg(not(feature = "graphql"))]
+const GRAPHQL_ENDPOINTS: &str = "";
+
 #[get("/")]
 async fn hello() -> impl Responder {
-    HttpResponse::Ok().body(
-        "Available enpoints: \n /equities/returns \n /options/bs \n /options/kelly \n /options/mc",
+    HttpResponse::Ok().body(format!("{ENDPOINTS}{STORAGE_ENDPOINTS}{GRAPHQL_ENDPOINTS}"))
+}
+
+fn stocks_error(e: StocksError) -> HttpResponse {
+    warn!(error = ?e, "the returns couldn't be computed");
+    match e {
+        StocksError::ComponentRange(e) => HttpResponse::BadRequest()
+            .json(json!({"Error": "Failed to convert the date", "Reason": e.to_string()})),
+        // the ticker and range that failed, when known
+        StocksError::ProviderError(ProviderError::FetchError(e)) => {
+            HttpResponse::InternalServerError().json(json!({
+                "Error": "The provider gave a wrong response or didn't respond",
+                "Ticker": e.ticker,
+                "Start": e.start,
+                "End": e.end,
+                "Reason": e.message
+            }))
+        }
+        StocksError::ProviderError(ProviderError::Timeout(e)) => HttpResponse::GatewayTimeout()
+            .json(json!({
+                "Error": "The provider didn't respond in time",
+                "Reason": e.to_string()
+            })),
+        StocksError::ProviderError(e) => HttpResponse::InternalServerError().json(json!({
+            "Error": "The provider gave a wrong response or didn't respond",
+            "Reason": e.to_string()
+        })),
+    }
+}
+
+// 422 with every violation of the input
+fn invalid(violations: Vec<Violation>) -> HttpResponse {
+    HttpResponse::UnprocessableEntity()
+        .json(json!({"Error": "The input is invalid", "Violations": violations}))
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Json,
+    Csv,
+    Ndjson,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Granularity {
+    /// daily (the default), monthly or yearly
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    aggregation: Aggregation,
+    /// 1d (the default), 1wk, 1mo, 1m, 5m or 1h
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    interval: Interval,
+    /// usd (the default), local or hedged
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    currency: Currency,
+    /// json (the default), csv or ndjson
+    #[param(value_type = Option<String>)]
+    format: Option<Format>,
+}
+
+// the series as a spreadsheet with a row per date
+fn csv(returns: &BTreeMap<String, f64>) -> HttpResponse {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let written = writer.write_record(["Date", "Returns"]).and_then(|_| {
+        returns
+            .iter()
+            .try_for_each(|(date, r)| writer.write_record([date.clone(), r.to_string()]))
+    });
+    match written
+        .map_err(|e| e.to_string())
+        .and_then(|_| writer.into_inner().map_err(|e| e.to_string()))
+    {
+        Ok(body) => HttpResponse::Ok().content_type("text/csv").body(body),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(json!({"Error": "The returns couldn't be written as CSV"})),
+    }
+}
+
+// the series as newline-delimited JSON with an object per date, sent as it's iterated
+fn ndjson(returns: impl Iterator<Item = (String, f64)> + 'static) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream::iter(returns.map(|(date, r)| {
+            let mut line = json!({"Date": date, "Returns": r}).to_string();
+            line.push('\n');
+            Ok::<_, actix_web::Error>(web::Bytes::from(line))
+        })))
+}
+
+/// The cumulative returns of the portfolio in percentage since the first buy
+#[utoipa::path(
+    post,
+    path = "/v1/equities/returns",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Granularity),
+    responses(
+        (status = 200, description = "The returns by date along with the annualized volatility, the CAGR, the years and the mean return, or the series alone as CSV or NDJSON"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn returns(
+    req: HttpRequest,
+    item: Json<Portfolio>,
+    query: web::Query<Granularity>,
+) -> impl Responder {
+    // the format parameter takes precedence over the Accept header
+    let format = query.format.unwrap_or_else(|| {
+        let accept = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|a| a.to_str().ok())
+            .unwrap_or_default();
+        if accept.contains("text/csv") {
+            Format::Csv
+        } else if accept.contains("application/x-ndjson") {
+            Format::Ndjson
+        } else {
+            Format::Json
+        }
+    });
+    returns_as(item, query, format).await
+}
+
+// the returns in the format, which the jobs ask for as JSON
+async fn returns_as(
+    item: Json<Portfolio>,
+    query: web::Query<Granularity>,
+    format: Format,
+) -> HttpResponse {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    // the daily series is sent line by line without being collected, the aggregated ones need the whole of it
+    if let (Format::Ndjson, Aggregation::Daily) = (format, query.aggregation) {
+        return match stream_returns(&item, query.interval, query.currency).await {
+            Ok(res) => ndjson(res),
+            Err(e) => stocks_error(e),
+        };
+    }
+    match total_returns_in(&item, query.interval, query.currency).await {
+        Ok(res) => match format {
+            Format::Csv => csv(&aggregate(&res, query.aggregation)),
+            Format::Ndjson => ndjson(aggregate(&res, query.aggregation).into_iter()),
+            Format::Json => HttpResponse::Ok().json(json!({
+                "Returns": aggregate(&res, query.aggregation),
+                "Annualized volatility": volatility(&res, query.interval),
+                "CAGR": cagr(&res),
+                "Years": years(&res),
+                "Mean return": mean_return(&res, query.aggregation)
+            })),
+        },
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Measurement {
+    /// twr (the default), mwr or modified-dietz
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    method: Method,
+    /// monthly (the default) or yearly
+    #[param(value_type = Option<String>)]
+    aggregation: Option<Aggregation>,
+}
+
+/// The return of the portfolio within every calendar month or year
+#[utoipa::path(
+    post,
+    path = "/v1/equities/performance",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Measurement),
+    responses(
+        (status = 200, description = "The return of every period in percentage"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn performance(item: Json<Portfolio>, query: web::Query<Measurement>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    let aggregation = query.aggregation.unwrap_or(Aggregation::Monthly);
+    match sub_period_returns(&item, query.method, aggregation).await {
+        Ok(res) => HttpResponse::Ok().json(json!({"Returns": res})),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The returns of the portfolio deflated by a price index
+#[utoipa::path(
+    post,
+    path = "/v1/equities/real",
+    tag = "equities",
+    request_body = RealRequest,
+    params(Granularity),
+    responses(
+        (status = 200, description = "The real and nominal returns, the cumulative inflation and the real CAGR"),
+        (status = 400, description = "The price index doesn't go back to the first buy"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn real(item: Json<RealRequest>, query: web::Query<Granularity>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match real_returns(&item, query.interval).await {
+        Ok(Some(res)) => HttpResponse::Ok().json(json!({
+            "Returns": aggregate(&res.real, query.aggregation),
+            "Nominal": aggregate(&res.nominal, query.aggregation),
+            "Inflation": res.inflation,
+            "CAGR": cagr(&res.real)
+        })),
+        Ok(None) => HttpResponse::BadRequest()
+            .json(json!({"Error": "The price index doesn't go back to the first buy"})),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The profit or loss in USD made on every date
+#[utoipa::path(
+    post,
+    path = "/v1/equities/pnl",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Granularity),
+    responses(
+        (status = 200, description = "The profit or loss of every date and the cumulative one"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn pnl(item: Json<Portfolio>, query: web::Query<Granularity>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match profit_and_loss(&item, query.interval, query.currency).await {
+        Ok(res) => {
+            let res = res.aggregate(query.aggregation);
+            HttpResponse::Ok().json(json!({
+                "Profit and loss": res.daily,
+                "Cumulative": res.cumulative
+            }))
+        }
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The weight in percentage of every ticker at the end of every date
+#[utoipa::path(
+    post,
+    path = "/v1/equities/weights",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Granularity),
+    responses(
+        (status = 200, description = "The weights by date and ticker"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn drift(item: Json<Portfolio>, query: web::Query<Granularity>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match weights(&item, query.interval, query.currency, query.aggregation).await {
+        Ok(res) => HttpResponse::Ok().json(json!({"Weights": res})),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// A summary of the trading from the transactions alone
+#[utoipa::path(
+    post,
+    path = "/v1/equities/activity",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The value bought and sold, the trades, the open positions and the holding periods"),
+        (status = 422, description = "The input isn't valid")
+    )
+)]
+async fn trading(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    HttpResponse::Ok().json(activity(&item))
+}
+
+/// The portfolios merged into a single one
+#[utoipa::path(
+    post,
+    path = "/v1/equities/merge",
+    tag = "equities",
+    request_body = Portfolios,
+    responses(
+        (status = 200, description = "The merged portfolio"),
+        (status = 422, description = "The input isn't valid")
+    )
+)]
+async fn merge(item: Json<Portfolios>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    HttpResponse::Ok().json(item.into_inner().merged())
+}
+
+/// What changed between two snapshots of a portfolio
+#[utoipa::path(
+    post,
+    path = "/v1/equities/diff",
+    tag = "equities",
+    request_body = Snapshots,
+    responses(
+        (status = 200, description = "The equities added and removed and the settings changed"),
+        (status = 422, description = "The input isn't valid")
+    )
+)]
+async fn diff(item: Json<Snapshots>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    HttpResponse::Ok().json(item.before.diff(&item.after))
+}
+
+/// The returns of the portfolio in USD alongside the hedged ones
+#[utoipa::path(
+    post,
+    path = "/v1/equities/hedged",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Granularity),
+    responses(
+        (status = 200, description = "The unhedged and hedged returns and the currency effect on the CAGR"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn hedged(item: Json<Portfolio>, query: web::Query<Granularity>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match hedged_returns(&item, query.interval).await {
+        Ok(res) => HttpResponse::Ok().json(json!({
+            "Unhedged": aggregate(&res.unhedged, query.aggregation),
+            "Hedged": aggregate(&res.hedged, query.aggregation),
+            "Currency effect": cagr(&res.unhedged) - cagr(&res.hedged)
+        })),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The returns of the portfolio split into the price and the currency effects
+#[utoipa::path(
+    post,
+    path = "/v1/equities/decomposition",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Granularity),
+    responses(
+        (status = 200, description = "The returns along with their price and currency effects"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn decomposition(item: Json<Portfolio>, query: web::Query<Granularity>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match decompose(&item, query.interval, query.aggregation).await {
+        Ok(res) => HttpResponse::Ok().json(json!({
+            "Returns": res.total,
+            "Price effect": res.price,
+            "Currency effect": res.fx
+        })),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The exposure of the portfolio to the Fama-French factors
+#[utoipa::path(
+    post,
+    path = "/v1/equities/factors",
+    tag = "equities",
+    request_body = FactorRequest,
+    responses(
+        (status = 200, description = "The annualized alpha, the loadings and the R squared"),
+        (status = 400, description = "There aren't enough dates in common with the factors"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn factors(item: Json<FactorRequest>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match exposure(&item).await {
+        Ok(Some(res)) => HttpResponse::Ok().json(res),
+        Ok(None) => HttpResponse::BadRequest().json(
+            json!({"Error": "There aren't enough dates in common with the factors to regress on them"}),
+        ),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The portfolio compared against its benchmark
+#[utoipa::path(
+    post,
+    path = "/v1/equities/benchmark",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The excess return, beta, alpha, correlation, tracking error and information ratio"),
+        (status = 400, description = "The portfolio has no benchmark"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn benchmark(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match compare(&item).await {
+        Ok(Some(res)) => HttpResponse::Ok().json(res),
+        Ok(None) => HttpResponse::BadRequest()
+            .json(json!({"Error": "You haven't included the benchmark ticker"})),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// Several named portfolios compared on the dates they have in common
+#[utoipa::path(
+    post,
+    path = "/v1/equities/comparison",
+    tag = "equities",
+    request_body = Portfolios,
+    responses(
+        (status = 200, description = "The rebased returns and the metrics of every portfolio"),
+        (status = 400, description = "The portfolios don't have any dates in common"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn comparison(item: Json<Portfolios>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match compare_portfolios(&item).await {
+        Ok(Some(res)) => HttpResponse::Ok().json(res),
+        Ok(None) => HttpResponse::BadRequest()
+            .json(json!({"Error": "The portfolios don't have any dates in common"})),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The returns, the risk metrics and the benchmark comparison of the portfolio, over a single fetch of the quotes
+#[utoipa::path(
+    post,
+    path = "/v1/equities/analysis",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The returns and their summary, the risk report and the benchmark comparison, null without a benchmark"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn analysis(item: Json<Portfolio>) -> HttpResponse {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match analyze(&item).await {
+        Ok(res) => HttpResponse::Ok().json(json!({
+            "Returns": res.returns,
+            "Annualized volatility": res.volatility,
+            "CAGR": res.cagr,
+            "Years": res.years,
+            "Mean return": res.mean_return,
+            "Risk": res.risk,
+            "Benchmark": res.benchmark
+        })),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The risk metrics of the portfolio
+#[utoipa::path(
+    post,
+    path = "/v1/equities/risk",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The volatility, the ratios, the drawdowns and the VaR and CVaR"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn risk_report(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match risk(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Window {
+    /// the days of every window, 252 by default
+    window: Option<usize>,
+}
+
+/// The return, volatility and Sharpe ratio over a rolling window
+#[utoipa::path(
+    post,
+    path = "/v1/equities/rolling",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Window),
+    responses(
+        (status = 200, description = "The rolling statistics by date"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn rolling_stats(item: Json<Portfolio>, query: web::Query<Window>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    // a year of trading days unless told otherwise
+    match rolling(&item, query.window.unwrap_or(252)).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The correlation matrix of the daily returns of the tickers
+#[utoipa::path(
+    post,
+    path = "/v1/equities/correlation",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The correlations keyed by ticker in both dimensions"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn correlation_matrix(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match correlation(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The efficient frontier of a set of tickers
+#[utoipa::path(
+    post,
+    path = "/v1/equities/optimization",
+    tag = "equities",
+    request_body = Universe,
+    responses(
+        (status = 200, description = "The frontier and the minimum-variance and tangency allocations"),
+        (status = 400, description = "The covariance matrix of the tickers is singular"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn optimization(item: Json<Universe>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match efficient_frontier(&item).await {
+        Ok(Some(res)) => HttpResponse::Ok().json(res),
+        Ok(None) => HttpResponse::BadRequest()
+            .json(json!({"Error": "The covariance matrix of the tickers is singular"})),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The Black-Litterman allocation of a set of tickers given some views
+#[utoipa::path(
+    post,
+    path = "/v1/equities/black-litterman",
+    tag = "equities",
+    request_body = Views,
+    responses(
+        (status = 200, description = "The blended returns and the allocation"),
+        (status = 400, description = "The covariance matrix of the tickers is singular"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn views(item: Json<Views>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match black_litterman(&item).await {
+        Ok(Some(res)) => HttpResponse::Ok().json(res),
+        Ok(None) => HttpResponse::BadRequest()
+            .json(json!({"Error": "The covariance matrix of the tickers is singular"})),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The risk parity allocation of a set of tickers
+#[utoipa::path(
+    post,
+    path = "/v1/equities/risk-parity",
+    tag = "equities",
+    request_body = Universe,
+    responses(
+        (status = 200, description = "The weights every ticker contributes the same risk with"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn parity(item: Json<Universe>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match risk_parity(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// A backtest of rebalancing to target weights periodically
+#[utoipa::path(
+    post,
+    path = "/v1/equities/rebalancing",
+    tag = "equities",
+    request_body = Strategy,
+    responses(
+        (status = 200, description = "The strategy compared against buy-and-hold along with its risk metrics"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn rebalancing(item: Json<Strategy>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match rebalance(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// A simulation of investing a fixed amount periodically
+#[utoipa::path(
+    post,
+    path = "/v1/equities/dca",
+    tag = "equities",
+    request_body = Plan,
+    responses(
+        (status = 200, description = "The units bought, their cost basis and value and the return on what has been invested"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
     )
+)]
+async fn averaging(item: Json<Plan>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match dca(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
 }
 
-async fn returns(item: web::Json<Portfolio>) -> impl Responder {
-    match total_returns(&item).await {
+/// A Monte-Carlo projection of the value of a portfolio
+#[utoipa::path(
+    post,
+    path = "/v1/equities/projection",
+    tag = "equities",
+    request_body = Projection,
+    responses(
+        (status = 200, description = "The percentiles of the value at every day"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes couldn't be fetched or some iterations couldn't be completed")
+    )
+)]
+async fn projection(item: Json<Projection>) -> HttpResponse {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match project(&item).await {
         Ok(res) => HttpResponse::Ok().json(res),
         Err(e) => match e {
-            StocksError::ComponentRange => {
-                HttpResponse::BadRequest().json(json!({"Error": "Failed to convert the date"}))
+            ProjectionError::StocksError(e) => stocks_error(e),
+            ProjectionError::RecvError | ProjectionError::JoinError => {
+                HttpResponse::InternalServerError()
+                    .json(json!({"Error": "Some iterations couldn't be completed"}))
             }
-            StocksError::ProviderError => HttpResponse::InternalServerError()
-                .json(json!({"Error": "Yahoo provided a wrong response or didn't respond"})),
         },
     }
 }
 
-async fn bs(item: web::Json<Options>) -> impl Responder {
-    HttpResponse::Ok().json(json!({"Price": bs_price(&item)}))
+/// The current allocation of the portfolio
+#[utoipa::path(
+    post,
+    path = "/v1/equities/allocation",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The allocation by currency, exchange, instrument type and sector"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn allocation(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match breakdown(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Period {
+    /// yearly (the default) or monthly
+    #[param(value_type = Option<String>)]
+    aggregation: Option<Aggregation>,
+}
+
+/// The trailing dividend yield of every ticker still held
+#[utoipa::path(
+    post,
+    path = "/v1/equities/yield",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The dividends of the last twelve months and the yields on the price and on cost"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn yields(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match trailing_yield(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The dividend income of the portfolio
+#[utoipa::path(
+    post,
+    path = "/v1/equities/dividends",
+    tag = "equities",
+    request_body = Portfolio,
+    params(Period),
+    responses(
+        (status = 200, description = "The gross income and the yield on cost of every ticker by period"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn dividends(item: Json<Portfolio>, query: web::Query<Period>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match dividend_income(&item, query.aggregation.unwrap_or(Aggregation::Yearly)).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The dividends, splits and capital gain distributions of every ticker
+#[utoipa::path(
+    post,
+    path = "/v1/equities/events",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The corporate actions by ticker"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn events(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match corporate_actions(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The latest quote of a ticker
+#[utoipa::path(
+    get,
+    path = "/v1/equities/quote/{ticker}",
+    tag = "equities",
+    params(("ticker" = String, Path, description = "The ticker, e.g., AAPL")),
+    responses(
+        (status = 200, description = "The latest price, when it was traded and its change since the previous close"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn quote(ticker: web::Path<String>) -> impl Responder {
+    match live_quote(&ticker).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Symbols {
+    /// the comma-separated tickers, e.g., AAPL,MSFT,ITX.MC
+    symbols: String,
+}
+
+/// The latest quotes of several tickers
+#[utoipa::path(
+    get,
+    path = "/v1/equities/quotes",
+    tag = "equities",
+    params(Symbols),
+    responses(
+        (status = 200, description = "The quotes keyed by ticker"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn quotes(query: web::Query<Symbols>) -> impl Responder {
+    match live_quotes(
+        query
+            .symbols
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty()),
+    )
+    .await
+    {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// The fundamentals of a ticker
+#[utoipa::path(
+    get,
+    path = "/v1/equities/fundamentals/{ticker}",
+    tag = "equities",
+    params(("ticker" = String, Path, description = "The ticker, e.g., AAPL")),
+    responses(
+        (status = 200, description = "The market capitalization, EPS, P/E, dividend yield, sector and industry"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn summary(ticker: web::Path<String>) -> impl Responder {
+    match fundamentals(&ticker).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Search {
+    /// the symbol or name to look for, e.g., inditex
+    q: String,
+}
+
+/// The tickers matching a query
+#[utoipa::path(
+    get,
+    path = "/v1/equities/search",
+    tag = "equities",
+    params(Search),
+    responses(
+        (status = 200, description = "The symbol, name, exchange and type of every match"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn lookup(query: web::Query<Search>) -> impl Responder {
+    match search(&query.q).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Range {
+    /// YYYY-MM-DD, a year before the end by default
+    start: Option<String>,
+    /// YYYY-MM-DD, today by default
+    end: Option<String>,
+    /// provider (the default), the one MODUS_PROVIDER sets, or ecb
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    source: RateSource,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RateSource {
+    // yahoo is still taken for it, as the rates were Yahoo's before the provider could be set
+    #[default]
+    #[serde(alias = "yahoo")]
+    Provider,
+    Ecb,
+}
+
+/// The daily exchange rates of a pair
+#[utoipa::path(
+    get,
+    path = "/v1/fx/{pair}",
+    tag = "fx",
+    params(("pair" = String, Path, description = "The base and quote currencies, e.g., EURGBP"), Range),
+    responses(
+        (status = 200, description = "The units of the quote currency a unit of the base one was worth, by date"),
+        (status = 400, description = "The pair or the dates aren't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn rates(pair: web::Path<String>, query: web::Query<Range>) -> impl Responder {
+    if pair.len() != 6 || !pair.is_ascii() {
+        return HttpResponse::BadRequest()
+            .json(json!({"Error": "The pair must be two currency codes, e.g., EURGBP"}));
+    }
+    let (base, quote) = pair.split_at(3);
+    let date = |d: &Option<String>| d.as_deref().map(str::parse::<NaiveDate>).transpose();
+    let (Ok(start), Ok(end)) = (date(&query.start), date(&query.end)) else {
+        return HttpResponse::BadRequest()
+            .json(json!({"Error": "The dates must be in the YYYY-MM-DD format"}));
+    };
+    let end = end.unwrap_or_else(|| Utc::now().date_naive());
+    let start = start.unwrap_or(end - Duration::days(365));
+    if start > end {
+        return HttpResponse::BadRequest()
+            .json(json!({"Error": "The start must not be after the end"}));
+    }
+    let rates = match query.source {
+        RateSource::Provider => match Provider::from_env() {
+            Ok(provider) => fx_series(&provider, base, quote, start..=end).await,
+            Err(e) => Err(ProviderError::from(e).into()),
+        },
+        RateSource::Ecb => ecb_series(base, quote, start..=end).await,
+    };
+    match rates {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Refresh {
+    /// the seconds between the quotes, 5 by default and 1 at the least
+    interval: Option<u64>,
+}
+
+/// The live value of a portfolio, over a WebSocket
+///
+/// The client sends the portfolio as a text message, and another one to replace it. The quotes are polled every `interval`
+/// seconds and the valuation at them, converted at the rates of the configured provider, is sent as a text message
+/// whenever it changed since the last poll, or the error if it couldn't be computed.
+#[utoipa::path(
+    get,
+    path = "/v1/ws/portfolio",
+    tag = "equities",
+    params(Refresh),
+    responses(
+        (status = 101, description = "The connection is upgraded to a WebSocket"),
+        (status = 400, description = "The request isn't a WebSocket handshake"),
+        (status = 503, description = "The provider isn't configured, and why")
+    )
+)]
+async fn live(
+    req: HttpRequest,
+    payload: web::Payload,
+    query: web::Query<Refresh>,
+) -> actix_web::Result<HttpResponse> {
+    let provider = match Provider::from_env() {
+        Ok(provider) => provider,
+        Err(e) => return Ok(stocks_error(ProviderError::from(e).into())),
+    };
+    let (response, session, messages) = actix_ws::handle(&req, payload)?;
+    let interval = std::time::Duration::from_secs(query.interval.unwrap_or(5).max(1));
+    actix_web::rt::spawn(poll(session, messages, provider, interval).in_current_span());
+    Ok(response)
+}
+
+// the body of the response, which the errors are sent as the text of a message
+fn text(response: HttpResponse) -> String {
+    response
+        .into_body()
+        .try_into_bytes()
+        .map(|body| String::from_utf8_lossy(&body).into_owned())
+        .unwrap_or_default()
+}
+
+// polls the quotes of the portfolio the client sent every interval, sending the valuation or the error when it changed
+// since the last poll, until the client leaves
+async fn poll(
+    mut session: Session,
+    mut messages: MessageStream,
+    provider: Provider,
+    interval: std::time::Duration,
+) {
+    let fx = FxService::new(&provider);
+    let mut ticks = actix_web::rt::time::interval(interval);
+    let (mut portfolio, mut last): (Option<Portfolio>, Option<String>) = (None, None);
+    loop {
+        let message = match select(pin!(messages.recv()), pin!(ticks.tick())).await {
+            Either::Left((message, _)) => Some(message),
+            Either::Right(_) => None,
+        };
+        let update = match message {
+            Some(Some(Ok(Message::Text(body)))) => match serde_json::from_str::<Portfolio>(&body) {
+                Ok(item) => match item.validate() {
+                    Ok(()) => {
+                        // valued right away rather than at the next tick
+                        (portfolio, last) = (Some(item), None);
+                        ticks.reset_immediately();
+                        continue;
+                    }
+                    Err(v) => text(invalid(v)),
+                },
+                Err(e) => {
+                    json!({"Error": "The portfolio couldn't be read", "Reason": e.to_string()})
+                        .to_string()
+                }
+            },
+            Some(Some(Ok(Message::Ping(bytes)))) => match session.pong(&bytes).await {
+                Ok(()) => continue,
+                Err(_) => return,
+            },
+            Some(Some(Ok(Message::Close(reason)))) => {
+                let _ = session.close(reason).await;
+                return;
+            }
+            Some(Some(Ok(_))) => continue,
+            Some(Some(Err(_)) | None) => return,
+            None => {
+                let Some(item) = &portfolio else {
+                    continue;
+                };
+                let update = match live_value(item, &fx).await {
+                    Ok(valuation) => json!(valuation).to_string(),
+                    Err(e) => text(stocks_error(e)),
+                };
+                if last.as_ref() == Some(&update) {
+                    continue;
+                }
+                last = Some(update.clone());
+                update
+            }
+        };
+        if session.text(update).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The contribution of every ticker to the total return
+#[utoipa::path(
+    post,
+    path = "/v1/equities/contribution",
+    tag = "equities",
+    request_body = Portfolio,
+    responses(
+        (status = 200, description = "The contributions by ticker"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn contribution(item: Json<Portfolio>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match contributions(&item).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ValueDate {
+    /// YYYY-MM-DD
+    date: String,
+}
+
+/// The value of every position held at a date
+#[utoipa::path(
+    post,
+    path = "/v1/equities/value",
+    tag = "equities",
+    request_body = Portfolio,
+    params(ValueDate),
+    responses(
+        (status = 200, description = "The market value, cost basis and unrealized profit or loss in USD"),
+        (status = 400, description = "The date isn't valid"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The quotes or rates couldn't be fetched")
+    )
+)]
+async fn value(item: Json<Portfolio>, query: web::Query<ValueDate>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    let date = match query.date.parse() {
+        Ok(date) => date,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"Error": "The date must be in the YYYY-MM-DD format"}))
+        }
+    };
+    match value_at(&item, date).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => stocks_error(e),
+    }
+}
+
+/// A broker export converted into a portfolio
+#[utoipa::path(
+    post,
+    path = "/v1/equities/import",
+    tag = "equities",
+    request_body(content = String, content_type = "text/csv", description = "The CSV export of Interactive Brokers, Degiro or Trading212"),
+    responses(
+        (status = 200, description = "The portfolio"),
+        (status = 400, description = "The export couldn't be read"),
+        (status = 422, description = "Some ISINs of a Degiro export don't match any ticker"),
+        (status = 500, description = "The tickers of the ISINs couldn't be looked up")
+    )
+)]
+async fn upload(body: web::Bytes) -> impl Responder {
+    match import(&body).await {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(ImportError::UnknownIsins(v)) => invalid(v),
+        Err(ImportError::Lookup(e)) => stocks_error(e),
+        Err(e) => HttpResponse::BadRequest().json(json!({"Error": e.to_string()})),
+    }
+}
+
+/// The Black-Scholes value of an option
+#[utoipa::path(
+    post,
+    path = "/v1/options/bs",
+    tag = "options",
+    request_body = Options,
+    responses(
+        (status = 200, description = "The price"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The risk-free rate couldn't be fetched")
+    )
+)]
+async fn bs(item: Json<Options>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    match item.into_inner().with_market_rfr().await {
+        Ok(item) => HttpResponse::Ok().json(json!({"Price": bs_price(&item)})),
+        Err(e) => stocks_error(e),
+    }
 }
 
-async fn kelly(item: web::Json<Options>) -> impl Responder {
+/// The optimal betting size by the Kelly criterion
+#[utoipa::path(
+    post,
+    path = "/v1/options/kelly",
+    tag = "options",
+    request_body = Options,
+    responses(
+        (status = 200, description = "The Kelly fraction"),
+        (status = 400, description = "The option has no market price"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The risk-free rate couldn't be fetched")
+    )
+)]
+async fn kelly(item: Json<Options>) -> impl Responder {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    let item = match item.into_inner().with_market_rfr().await {
+        Ok(item) => item,
+        Err(e) => return stocks_error(e),
+    };
     match kelly_ratio(&item) {
         None => HttpResponse::BadRequest()
             .json(json!({"Error": "You haven't included the current market price"})),
@@ -34,31 +1279,1796 @@ async fn kelly(item: web::Json<Options>) -> impl Responder {
     }
 }
 
-async fn montecarlo(item: web::Json<Options>) -> impl Responder {
-    match expected(&item) {
-        Ok(res) => {
-            HttpResponse::Ok().json(json!({"Monte-Carlo value based on 10000 simulations": res}))
-        }
-        Err(_) => HttpResponse::InternalServerError()
-            .json(json!({"Error": "Some iterations couldn't be completed"})),
-    }
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Progress {
+    /// the simulations between the estimates sent as server-sent events, 1000 by default
+    every: Option<usize>,
+}
+
+/// The Monte-Carlo value of an option
+///
+/// With `Accept: text/event-stream` the running estimate and its 95% confidence interval are sent as server-sent
+/// events every so many simulations, an `estimate` event each and a `result` one for the last, and the simulations
+/// stop when the client disconnects.
+#[utoipa::path(
+    post,
+    path = "/v1/options/mc",
+    tag = "options",
+    request_body = Options,
+    params(Progress),
+    responses(
+        (status = 200, description = "The value based on 10000 simulations, or its estimates as they converge as server-sent events"),
+        (status = 422, description = "The input isn't valid"),
+        (status = 500, description = "The risk-free rate couldn't be fetched or some iterations couldn't be completed")
+    )
+)]
+async fn montecarlo(
+    req: HttpRequest,
+    item: Json<Options>,
+    query: web::Query<Progress>,
+) -> HttpResponse {
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .unwrap_or_default();
+    if !accept.contains("text/event-stream") {
+        return montecarlo_value(item).await;
+    }
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    const SIMULATIONS: usize = 10000;
+    let item = match item.into_inner().with_market_rfr().await {
+        Ok(item) => item,
+        Err(e) => return stocks_error(e),
+    };
+    let (sender, receiver) = futures::channel::mpsc::unbounded();
+    let every = query.every.unwrap_or(1000);
+    // the estimates are sent as they're made, the simulations stopping once the client is gone and the stream dropped,
+    // on the bounded pool of threads for blocking work rather than on a thread of their own
+    actix_web::rt::task::spawn_blocking(move || {
+        for estimate in estimates(&item, SIMULATIONS, every) {
+            if sender.unbounded_send(estimate).is_err() {
+                return;
+            }
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // left uncompressed, as the compression would hold the events back until it had enough of them
+        .insert_header((header::CONTENT_ENCODING, "identity"))
+        .streaming(receiver.map(|estimate| {
+            let event = match estimate.simulations {
+                SIMULATIONS => "result",
+                _ => "estimate",
+            };
+            Ok::<_, actix_web::Error>(web::Bytes::from(format!(
+                "event: {event}\ndata: {}\n\n",
+                json!(estimate)
+            )))
+        }))
+}
+
+// the value based on all the simulations, which the jobs ask for
+async fn montecarlo_value(item: Json<Options>) -> HttpResponse {
+    if let Err(v) = item.validate() {
+        return invalid(v);
+    }
+    let item = match item.into_inner().with_market_rfr().await {
+        Ok(item) => item,
+        Err(e) => return stocks_error(e),
+    };
+    match web::block(move || expected(&item)).await {
+        Ok(Ok(res)) => {
+            HttpResponse::Ok().json(json!({"Monte-Carlo value based on 10000 simulations": res}))
+        }
+        _ => HttpResponse::InternalServerError()
+            .json(json!({"Error": "Some iterations couldn't be completed"})),
+    }
+}
+
+/// Whether the process is up, for liveness probes
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The process is up"))
+)]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(json!({"Status": "ok"}))
+}
+
+// the paths of the probes, which load balancers and orchestrators query without an API key and as often as they like
+const PROBES: [&str; 2] = ["/healthz", "/readyz"];
+
+// how long the provider is given to answer a probe, and how long its answer is reused for, so that frequent probes don't
+// use up the quota of the provider
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+const PROBE_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+// whether the provider in MODUS_PROVIDER can serve quotes, and why not
+async fn probe() -> Result<(), String> {
+    let provider = Provider::from_env().map_err(|e| e.to_string())?;
+    match timeout(PROBE_TIMEOUT, provider.probe()).await {
+        Ok(probed) => probed.map_err(|e| e.to_string()),
+        Err(_) => Err(format!(
+            "the provider didn't answer in {} seconds",
+            PROBE_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+/// Whether the provider the quotes are fetched from can be reached, for readiness probes
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "The provider serves quotes"),
+        (status = 503, description = "The provider can't be reached or isn't configured, and why")
+    )
+)]
+async fn readyz() -> impl Responder {
+    static LAST: Mutex<Option<(Instant, Result<(), String>)>> = Mutex::new(None);
+    let cached = LAST
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .filter(|(at, _)| at.elapsed() < PROBE_TTL);
+    let probed = match cached {
+        Some((_, probed)) => probed,
+        None => {
+            let probed = probe().await;
+            *LAST.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some((Instant::now(), probed.clone()));
+            probed
+        }
+    };
+    let source = Source::from_env().ok();
+    match probed {
+        Ok(()) => HttpResponse::Ok().json(json!({"Status": "ready", "Provider": source})),
+        Err(reason) => HttpResponse::ServiceUnavailable()
+            .json(json!({"Status": "unavailable", "Provider": source, "Reason": reason})),
+    }
+}
+
+/// The specification of the endpoints, every handler being annotated with its path, its body and its parameters
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Long term portfolio performance and option valuation"),
+    paths(
+        returns,
+        benchmark,
+        risk_report,
+        analysis,
+        rolling_stats,
+        correlation_matrix,
+        optimization,
+        views,
+        parity,
+        rebalancing,
+        averaging,
+        projection,
+        allocation,
+        dividends,
+        yields,
+        events,
+        quote,
+        quotes,
+        lookup,
+        summary,
+        contribution,
+        value,
+        upload,
+        comparison,
+        merge,
+        diff,
+        hedged,
+        decomposition,
+        factors,
+        performance,
+        real,
+        pnl,
+        drift,
+        trading,
+        bs,
+        kelly,
+        montecarlo,
+        rates,
+        live,
+        healthz,
+        readyz
+    )
+)]
+struct ApiDoc;
+
+// the specification as JSON, along with that of the saved portfolios when they're built in
+async fn openapi() -> impl Responder {
+    let mut spec = ApiDoc::openapi();
+    spec.merge(jobs::ApiDoc::openapi());
+    #[cfg(feature = "sqlite")]
+    spec.merge(portfolios::ApiDoc::openapi());
+    #[cfg(feature = "graphql")]
+    spec.merge(graphql::ApiDoc::openapi());
+    // the crate doesn't declare a license, which would be given an empty name
+    spec.info.license = None;
+    HttpResponse::Ok().json(spec)
+}
+
+// Swagger UI, loaded from a CDN, to browse the specification and try the endpoints from the browser
+async fn docs() -> impl Responder {
+    HttpResponse::Ok().content_type("text/html").body(DOCS)
+}
+
+const DOCS: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>Modus</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>"##;
+
+// saved portfolios, created, read, updated and deleted by id
+#[cfg(feature = "sqlite")]
+mod portfolios {
+    use actix_web::{web, HttpResponse, Responder};
+    use modus::risk::volatility;
+    use modus::stock_returns::{cagr, total_returns, years, Interval, Portfolio};
+    use modus::storage::{Storage, StorageError};
+    use serde::Deserialize;
+    use serde_json::json;
+    use utoipa::{IntoParams, OpenApi};
+
+    use crate::extract::Json;
+    use crate::{invalid, stocks_error};
+
+    /// The specification of the endpoints of the saved portfolios, merged into that of the others
+    #[derive(OpenApi)]
+    #[openapi(paths(create, list, get, update, delete, returns))]
+    pub struct ApiDoc;
+
+    pub fn routes(config: &mut web::ServiceConfig) {
+        config.service(
+            web::scope("/portfolios")
+                .route("", web::post().to(create))
+                .route("", web::get().to(list))
+                .route("/{id}", web::get().to(get))
+                .route("/{id}", web::put().to(update))
+                .route("/{id}", web::delete().to(delete))
+                .route("/{id}/returns", web::get().to(returns)),
+        );
+    }
+
+    fn storage_error(e: StorageError) -> HttpResponse {
+        HttpResponse::InternalServerError().json(json!({"Error": e.to_string()}))
+    }
+
+    fn not_found() -> HttpResponse {
+        HttpResponse::NotFound().json(json!({"Error": "There is no portfolio with that id"}))
+    }
+
+    /// Saves a portfolio
+    #[utoipa::path(
+        post,
+        path = "/v1/portfolios",
+        tag = "portfolios",
+        request_body = Portfolio,
+        responses(
+            (status = 201, description = "The id of the saved portfolio"),
+            (status = 422, description = "The input isn't valid"),
+            (status = 500, description = "The portfolio couldn't be saved")
+        )
+    )]
+    async fn create(storage: web::Data<Storage>, item: Json<Portfolio>) -> impl Responder {
+        if let Err(v) = item.validate() {
+            return invalid(v);
+        }
+        match storage.create(&item) {
+            Ok(id) => HttpResponse::Created().json(json!({"Id": id})),
+            Err(e) => storage_error(e),
+        }
+    }
+
+    /// The saved portfolios
+    #[utoipa::path(
+        get,
+        path = "/v1/portfolios",
+        tag = "portfolios",
+        responses(
+            (status = 200, description = "The saved portfolios by id"),
+            (status = 500, description = "The portfolios couldn't be read")
+        )
+    )]
+    async fn list(storage: web::Data<Storage>) -> impl Responder {
+        match storage.list() {
+            Ok(res) => HttpResponse::Ok().json(res),
+            Err(e) => storage_error(e),
+        }
+    }
+
+    /// A saved portfolio
+    #[utoipa::path(
+        get,
+        path = "/v1/portfolios/{id}",
+        tag = "portfolios",
+        params(("id" = i64, Path, description = "The id of the portfolio")),
+        responses(
+            (status = 200, description = "The portfolio"),
+            (status = 404, description = "There is no portfolio with that id")
+        )
+    )]
+    async fn get(storage: web::Data<Storage>, id: web::Path<i64>) -> impl Responder {
+        match storage.get(*id) {
+            Ok(Some(res)) => HttpResponse::Ok().json(res),
+            Ok(None) => not_found(),
+            Err(e) => storage_error(e),
+        }
+    }
+
+    /// Replaces a saved portfolio
+    #[utoipa::path(
+        put,
+        path = "/v1/portfolios/{id}",
+        tag = "portfolios",
+        request_body = Portfolio,
+        params(("id" = i64, Path, description = "The id of the portfolio")),
+        responses(
+            (status = 204, description = "The portfolio was replaced"),
+            (status = 404, description = "There is no portfolio with that id"),
+            (status = 422, description = "The input isn't valid")
+        )
+    )]
+    async fn update(
+        storage: web::Data<Storage>,
+        id: web::Path<i64>,
+        item: Json<Portfolio>,
+    ) -> impl Responder {
+        if let Err(v) = item.validate() {
+            return invalid(v);
+        }
+        match storage.update(*id, &item) {
+            Ok(true) => HttpResponse::NoContent().finish(),
+            Ok(false) => not_found(),
+            Err(e) => storage_error(e),
+        }
+    }
+
+    /// Deletes a saved portfolio
+    #[utoipa::path(
+        delete,
+        path = "/v1/portfolios/{id}",
+        tag = "portfolios",
+        params(("id" = i64, Path, description = "The id of the portfolio")),
+        responses(
+            (status = 204, description = "The portfolio was deleted"),
+            (status = 404, description = "There is no portfolio with that id")
+        )
+    )]
+    async fn delete(storage: web::Data<Storage>, id: web::Path<i64>) -> impl Responder {
+        match storage.delete(*id) {
+            Ok(true) => HttpResponse::NoContent().finish(),
+            Ok(false) => not_found(),
+            Err(e) => storage_error(e),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    struct Bars {
+        /// 1d (the default), 1wk, 1mo, 1m, 5m or 1h
+        #[serde(default)]
+        #[param(value_type = Option<String>)]
+        interval: Interval,
+    }
+
+    /// The returns of a saved portfolio, the daily ones being computed at most once a day
+    #[utoipa::path(
+        get,
+        path = "/v1/portfolios/{id}/returns",
+        operation_id = "saved_returns",
+        tag = "portfolios",
+        params(("id" = i64, Path, description = "The id of the portfolio"), Bars),
+        responses(
+            (status = 200, description = "The returns by date along with the annualized volatility, the CAGR and the years"),
+            (status = 404, description = "There is no portfolio with that id"),
+            (status = 500, description = "The quotes or rates couldn't be fetched")
+        )
+    )]
+    async fn returns(
+        storage: web::Data<Storage>,
+        id: web::Path<i64>,
+        query: web::Query<Bars>,
+    ) -> impl Responder {
+        let saved = match query.interval {
+            Interval::Daily => storage.returns(*id),
+            _ => Ok(None),
+        };
+        let res = match saved {
+            Ok(Some(res)) => res,
+            Ok(None) => {
+                let portfolio = match storage.get(*id) {
+                    Ok(Some(portfolio)) => portfolio,
+                    Ok(None) => return not_found(),
+                    Err(e) => return storage_error(e),
+                };
+                let res = match total_returns(&portfolio, query.interval).await {
+                    Ok(res) => res,
+                    Err(e) => return stocks_error(e),
+                };
+                if query.interval == Interval::Daily {
+                    if let Err(e) = storage.save_returns(*id, &res) {
+                        return storage_error(e);
+                    }
+                }
+                res
+            }
+            Err(e) => return storage_error(e),
+        };
+        HttpResponse::Ok().json(json!({
+            "Returns": res,
+            "Annualized volatility": volatility(&res, query.interval),
+            "CAGR": cagr(&res),
+            "Years": years(&res)
+        }))
+    }
+}
+
+// the quotes, portfolios and options as one graph, queried with a POST and explored with GraphiQL in the browser
+#[cfg(feature = "graphql")]
+mod graphql {
+    use actix_web::{web, HttpResponse, Responder};
+    use async_graphql::http::GraphiQLSource;
+    use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+    use modus::graphql::ModusSchema;
+    use utoipa::OpenApi;
+
+    /// The specification of the GraphQL endpoint, merged into that of the others
+    #[derive(OpenApi)]
+    #[openapi(paths(execute))]
+    pub struct ApiDoc;
+
+    pub fn routes(config: &mut web::ServiceConfig) {
+        config.service(
+            web::resource("/graphql")
+                .route(web::post().to(execute))
+                .route(web::get().to(graphiql)),
+        );
+    }
+
+    /// Executes a GraphQL query on the quotes, the portfolios and the options
+    #[utoipa::path(
+        post,
+        path = "/v1/graphql",
+        tag = "graphql",
+        request_body(content = String, content_type = "application/json", description = "The query, its variables and its operation"),
+        responses((status = 200, description = "The data asked for, along with the errors of the fields that failed"))
+    )]
+    async fn execute(schema: web::Data<ModusSchema>, request: GraphQLRequest) -> GraphQLResponse {
+        schema.execute(request.into_inner()).await.into()
+    }
+
+    // GraphiQL, loaded from a CDN, to write the queries with the schema at hand
+    async fn graphiql() -> impl Responder {
+        HttpResponse::Ok()
+            .content_type("text/html")
+            .body(GraphiQLSource::build().endpoint("/v1/graphql").finish())
+    }
+}
+
+// the long computations, such as large Monte Carlo runs and the returns of multi-decade portfolios, run in the background
+// by a few threads of their own, created with a POST that answers right away and polled by id until they're done
+mod jobs {
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    use actix_web::body::MessageBody;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::rt::System;
+    use actix_web::{web, HttpResponse, Responder};
+    use modus::options::Options;
+    use modus::projection::Projection;
+    use modus::stock_returns::Portfolio;
+    use rand::Rng;
+    use serde_json::{json, Value};
+    use tracing::{info_span, warn, Instrument};
+    use utoipa::OpenApi;
+
+    use crate::extract::Json;
+    use crate::{
+        analysis, invalid, montecarlo_value, parsed, projection, returns_as, Format, Granularity,
+    };
+
+    /// The specification of the endpoints of the jobs, merged into that of the others
+    #[derive(OpenApi)]
+    #[openapi(paths(returns, analyze, project, simulate, status))]
+    pub struct ApiDoc;
+
+    pub fn routes(config: &mut web::ServiceConfig) {
+        config.service(
+            web::scope("/jobs")
+                .route("/returns", web::post().to(returns))
+                .route("/analysis", web::post().to(analyze))
+                .route("/projection", web::post().to(project))
+                .route("/mc", web::post().to(simulate))
+                .route("/{id}", web::get().to(status)),
+        );
+    }
+
+    // the response of the endpoint the job runs, the future being made by the thread that runs it
+    type Task = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = HttpResponse>>> + Send>;
+
+    enum State {
+        Queued,
+        Running,
+        Finished {
+            code: StatusCode,
+            body: Value,
+            at: Instant,
+        },
+    }
+
+    // how long the result of a job is kept once it's finished
+    const KEPT: Duration = Duration::from_secs(3600);
+
+    /// The jobs by id, along with the queue of those that haven't started, which is gone once the jobs are stopped, and the
+    /// threads that run them
+    pub struct Jobs {
+        states: Mutex<HashMap<String, State>>,
+        queue: Mutex<Option<SyncSender<(String, Task)>>>,
+        workers: Mutex<Vec<JoinHandle<()>>>,
+    }
+
+    impl Jobs {
+        // the jobs run --job-workers or MODUS_JOB_WORKERS at a time, 2 by default, and up to --job-queue or MODUS_JOB_QUEUE
+        // of them, 64 by default, wait for their turn
+        pub fn from_env() -> std::io::Result<Arc<Jobs>> {
+            let workers: usize = parsed("--job-workers", "MODUS_JOB_WORKERS")?.unwrap_or(2);
+            let capacity: usize = parsed("--job-queue", "MODUS_JOB_QUEUE")?.unwrap_or(64);
+            if workers == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--job-workers 0 isn't valid: there must be at least one worker",
+                ));
+            }
+            let (queue, receiver) = sync_channel(capacity);
+            let jobs = Arc::new(Jobs {
+                states: Mutex::new(HashMap::new()),
+                queue: Mutex::new(Some(queue)),
+                workers: Mutex::new(Vec::new()),
+            });
+            let receiver = Arc::new(Mutex::new(receiver));
+            for i in 0..workers {
+                let (worker, receiver) = (jobs.clone(), receiver.clone());
+                let handle = std::thread::Builder::new()
+                    .name(format!("modus-job-{i}"))
+                    .spawn(move || System::new().block_on(worker.run(receiver)))?;
+                jobs.workers
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(handle);
+            }
+            Ok(jobs)
+        }
+
+        // stops taking jobs and drops those that haven't started, which are failed with a 503 so that they aren't seen as
+        // queued for good, waiting up to the timeout for the running ones to finish and their workers to exit
+        pub async fn stop(&self, timeout: Duration) {
+            self.queue.lock().unwrap_or_else(|e| e.into_inner()).take();
+            for state in self
+                .states
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .values_mut()
+            {
+                if let State::Queued = state {
+                    *state = State::Finished {
+                        code: StatusCode::SERVICE_UNAVAILABLE,
+                        body: json!({"Error": "The server stopped before the job started"}),
+                        at: Instant::now(),
+                    };
+                }
+            }
+            let workers =
+                std::mem::take(&mut *self.workers.lock().unwrap_or_else(|e| e.into_inner()));
+            let joined = actix_web::rt::task::spawn_blocking(move || {
+                for worker in workers {
+                    let _ = worker.join();
+                }
+            });
+            if actix_web::rt::time::timeout(timeout, joined).await.is_err() {
+                warn!(
+                    timeout_s = timeout.as_secs(),
+                    "the jobs still running didn't finish in time"
+                );
+            }
+        }
+
+        fn stopped(&self) -> bool {
+            self.queue
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_none()
+        }
+
+        fn set(&self, id: &str, state: State) {
+            self.states
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(id.to_string(), state);
+        }
+
+        // runs the jobs one after the other as they're queued, on a runtime of its own so that the CPU bound ones don't
+        // hold up the requests, until the jobs are stopped and those left in the queue are dropped
+        async fn run(&self, receiver: Arc<Mutex<Receiver<(String, Task)>>>) {
+            loop {
+                let next = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                let Ok((id, task)) = next else {
+                    return;
+                };
+                if self.stopped() {
+                    continue;
+                }
+                self.set(&id, State::Running);
+                let response = task().instrument(info_span!("job", id)).await;
+                let code = response.status();
+                // the results are all JSON, as the jobs don't stream them
+                let body = response
+                    .into_body()
+                    .try_into_bytes()
+                    .ok()
+                    .and_then(|body| serde_json::from_slice(&body).ok())
+                    .unwrap_or(Value::Null);
+                self.set(
+                    &id,
+                    State::Finished {
+                        code,
+                        body,
+                        at: Instant::now(),
+                    },
+                );
+            }
+        }
+
+        // queues the task, answering with its id, or with a 503 if too many are waiting
+        fn submit(&self, task: Task) -> HttpResponse {
+            let id = format!("{:016x}", rand::thread_rng().gen::<u64>());
+            {
+                let mut states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+                states.retain(|_, state| match state {
+                    State::Finished { at, .. } => at.elapsed() < KEPT,
+                    _ => true,
+                });
+                states.insert(id.clone(), State::Queued);
+            }
+            let sent = match &*self.queue.lock().unwrap_or_else(|e| e.into_inner()) {
+                Some(queue) => queue.try_send((id.clone(), task)),
+                None => Err(TrySendError::Disconnected((id.clone(), task))),
+            };
+            match sent {
+                Ok(()) => HttpResponse::Accepted()
+                    .insert_header((header::LOCATION, format!("/v1/jobs/{id}")))
+                    .json(json!({"Id": id, "Status": "queued"})),
+                Err(e) => {
+                    self.states
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .remove(&id);
+                    match e {
+                        TrySendError::Full(_) => HttpResponse::ServiceUnavailable()
+                            .json(json!({"Error": "Too many jobs are waiting, try again later"})),
+                        TrySendError::Disconnected(_) => HttpResponse::InternalServerError()
+                            .json(json!({"Error": "The jobs can't be run"})),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queues the returns of a portfolio, as /v1/equities/returns gives them in JSON
+    #[utoipa::path(
+        post,
+        path = "/v1/jobs/returns",
+        tag = "jobs",
+        request_body = Portfolio,
+        params(Granularity),
+        responses(
+            (status = 202, description = "The id of the job, to poll at its Location"),
+            (status = 422, description = "The input isn't valid"),
+            (status = 503, description = "Too many jobs are waiting")
+        )
+    )]
+    async fn returns(
+        jobs: web::Data<Jobs>,
+        item: Json<Portfolio>,
+        query: web::Query<Granularity>,
+    ) -> impl Responder {
+        if let Err(v) = item.validate() {
+            return invalid(v);
+        }
+        jobs.submit(Box::new(|| Box::pin(returns_as(item, query, Format::Json))))
+    }
+
+    /// Queues the analysis of a portfolio, as /v1/equities/analysis gives it
+    #[utoipa::path(
+        post,
+        path = "/v1/jobs/analysis",
+        tag = "jobs",
+        request_body = Portfolio,
+        responses(
+            (status = 202, description = "The id of the job, to poll at its Location"),
+            (status = 422, description = "The input isn't valid"),
+            (status = 503, description = "Too many jobs are waiting")
+        )
+    )]
+    async fn analyze(jobs: web::Data<Jobs>, item: Json<Portfolio>) -> impl Responder {
+        if let Err(v) = item.validate() {
+            return invalid(v);
+        }
+        jobs.submit(Box::new(|| Box::pin(analysis(item))))
+    }
+
+    /// Queues the Monte-Carlo projection of a portfolio, as /v1/equities/projection gives it
+    #[utoipa::path(
+        post,
+        path = "/v1/jobs/projection",
+        tag = "jobs",
+        request_body = Projection,
+        responses(
+            (status = 202, description = "The id of the job, to poll at its Location"),
+            (status = 422, description = "The input isn't valid"),
+            (status = 503, description = "Too many jobs are waiting")
+        )
+    )]
+    async fn project(jobs: web::Data<Jobs>, item: Json<Projection>) -> impl Responder {
+        if let Err(v) = item.validate() {
+            return invalid(v);
+        }
+        jobs.submit(Box::new(|| Box::pin(projection(item))))
+    }
+
+    /// Queues the Monte-Carlo value of an option, as /v1/options/mc gives it
+    #[utoipa::path(
+        post,
+        path = "/v1/jobs/mc",
+        tag = "jobs",
+        request_body = Options,
+        responses(
+            (status = 202, description = "The id of the job, to poll at its Location"),
+            (status = 422, description = "The input isn't valid"),
+            (status = 503, description = "Too many jobs are waiting")
+        )
+    )]
+    async fn simulate(jobs: web::Data<Jobs>, item: Json<Options>) -> impl Responder {
+        if let Err(v) = item.validate() {
+            return invalid(v);
+        }
+        jobs.submit(Box::new(|| Box::pin(montecarlo_value(item))))
+    }
+
+    /// Whether a job is queued, running or finished, with the status code and the body the endpoint answered with once
+    /// it's finished, kept for an hour
+    #[utoipa::path(
+        get,
+        path = "/v1/jobs/{id}",
+        tag = "jobs",
+        params(("id" = String, Path, description = "The id of the job")),
+        responses(
+            (status = 200, description = "The status of the job, along with its result once it's finished"),
+            (status = 404, description = "There is no job with that id, or its result is no longer kept")
+        )
+    )]
+    async fn status(jobs: web::Data<Jobs>, id: web::Path<String>) -> impl Responder {
+        let states = jobs.states.lock().unwrap_or_else(|e| e.into_inner());
+        match states.get(id.as_str()) {
+            Some(State::Queued) => HttpResponse::Ok().json(json!({"Id": *id, "Status": "queued"})),
+            Some(State::Running) => {
+                HttpResponse::Ok().json(json!({"Id": *id, "Status": "running"}))
+            }
+            Some(State::Finished { code, body, at }) if at.elapsed() < KEPT => HttpResponse::Ok()
+                .json(json!({
+                    "Id": *id,
+                    "Status": if code.is_success() { "done" } else { "failed" },
+                    "Code": code.as_u16(),
+                    "Result": body
+                })),
+            _ => HttpResponse::NotFound().json(json!({"Error": "There is no job with that id"})),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn state(jobs: &Jobs, id: &str) -> &'static str {
+            match jobs.states.lock().unwrap().get(id) {
+                Some(State::Queued) => "queued",
+                Some(State::Running) => "running",
+                Some(State::Finished { .. }) => "finished",
+                None => "missing",
+            }
+        }
+
+        // a job that takes a while, answering with a 200
+        fn slow() -> Task {
+            Box::new(|| {
+                Box::pin(async {
+                    actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+                    HttpResponse::Ok().json(json!({"Done": true}))
+                })
+            })
+        }
+
+        #[actix_web::test]
+        async fn stopping_waits_for_the_running_jobs_and_refuses_new_ones() {
+            let jobs = Jobs::from_env().unwrap();
+            let response = jobs.submit(slow());
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            let id = jobs.states.lock().unwrap().keys().next().unwrap().clone();
+            while state(&jobs, &id) != "running" {
+                actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+            }
+            jobs.stop(Duration::from_secs(5)).await;
+            assert_eq!(state(&jobs, &id), "finished");
+            assert!(jobs.workers.lock().unwrap().is_empty());
+            assert_eq!(
+                jobs.submit(slow()).status(),
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+
+        #[actix_web::test]
+        async fn the_jobs_left_in_the_queue_fail_once_stopped() {
+            let jobs = Jobs::from_env().unwrap();
+            let workers = jobs.workers.lock().unwrap().len();
+            for _ in 0..=workers {
+                assert_eq!(jobs.submit(slow()).status(), StatusCode::ACCEPTED);
+            }
+            let running = || {
+                let states = jobs.states.lock().unwrap();
+                states
+                    .values()
+                    .filter(|s| matches!(s, State::Running))
+                    .count()
+            };
+            while running() < workers {
+                actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+            }
+            jobs.stop(Duration::from_secs(5)).await;
+            let states = jobs.states.lock().unwrap();
+            let mut codes: Vec<_> = states
+                .values()
+                .map(|state| match state {
+                    State::Finished { code, .. } => code.as_u16(),
+                    _ => 0,
+                })
+                .collect();
+            codes.sort();
+            let mut expected = vec![200; workers];
+            expected.push(503);
+            assert_eq!(codes, expected);
+        }
+    }
+}
+
+// the bodies of the requests, the ones that don't fit the type of the endpoint being answered with a 422 naming the field
+mod extract {
+    use std::ops::{Deref, DerefMut};
+
+    use actix_web::dev::Payload;
+    use actix_web::error::{InternalError, JsonPayloadError};
+    use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+    use futures::future::LocalBoxFuture;
+    use modus::stock_returns::Violation;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+
+    use crate::invalid;
+
+    /// A JSON body, like web::Json, whose fields that are missing or have the wrong type are reported by their path, e.g.,
+    /// portfolio[0].buy.price, as the violations of the validation are
+    pub struct Json<T>(pub T);
+
+    impl<T> Json<T> {
+        pub fn into_inner(self) -> T {
+            self.0
+        }
+    }
+
+    impl<T> Deref for Json<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.0
+        }
+    }
+
+    impl<T> DerefMut for Json<T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.0
+        }
+    }
+
+    // the field the error is about, the missing ones being named after the object they're missing from
+    fn violation(e: serde_path_to_error::Error<serde_json::Error>) -> Violation {
+        let path = e.path().to_string();
+        let message = e.into_inner().to_string();
+        let missing = message
+            .strip_prefix("missing field `")
+            .and_then(|m| m.strip_suffix('`'));
+        match (missing, path.as_str()) {
+            (Some(field), ".") => Violation {
+                field: field.to_string(),
+                message: "is missing".to_string(),
+            },
+            (Some(field), _) => Violation {
+                field: format!("{path}.{field}"),
+                message: "is missing".to_string(),
+            },
+            (None, _) => Violation {
+                field: path,
+                message,
+            },
+        }
+    }
+
+    // the body is read as any JSON first, so that it's then known where in it what the type expects is wrong or missing
+    impl<T: DeserializeOwned + 'static> FromRequest for Json<T> {
+        type Error = actix_web::Error;
+        type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+        fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+            let body = web::Json::<Value>::from_request(req, payload);
+            Box::pin(async move {
+                let body = body.await?.into_inner();
+                serde_path_to_error::deserialize(body)
+                    .map(Json)
+                    .map_err(|e| {
+                        let response = invalid(vec![violation(e)]);
+                        InternalError::from_response("the body doesn't fit the endpoint", response)
+                            .into()
+                    })
+            })
+        }
+    }
+
+    /// Answers the bodies that aren't JSON at all with the reason as JSON, rather than as the plain text of actix
+    pub fn config() -> web::JsonConfig {
+        web::JsonConfig::default().error_handler(|e, _| {
+            let response = match &e {
+                JsonPayloadError::ContentType => HttpResponse::UnsupportedMediaType()
+                    .json(json!({"Error": "The body must be JSON, sent with Content-Type: application/json"})),
+                JsonPayloadError::Overflow { limit } | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                    HttpResponse::PayloadTooLarge()
+                        .json(json!({"Error": "The body is too large", "Limit": limit}))
+                }
+                _ => HttpResponse::BadRequest()
+                    .json(json!({"Error": "The body isn't valid JSON", "Reason": e.to_string()})),
+            };
+            InternalError::from_response(e, response).into()
+        })
+    }
+}
+
+mod auth {
+    use std::collections::HashSet;
+
+    use actix_web::body::MessageBody;
+    use actix_web::dev::{ServiceRequest, ServiceResponse};
+    use actix_web::http::header;
+    use actix_web::middleware::Next;
+    use actix_web::{web, HttpResponse};
+    use serde_json::json;
+
+    use crate::{setting, PROBES};
+
+    /// The API keys the requests must carry, none letting every request through
+    pub struct ApiKeys(HashSet<String>);
+
+    impl ApiKeys {
+        // the comma-separated keys given by --api-keys or MODUS_API_KEYS along with those in the file given by --api-keys-file
+        // or MODUS_API_KEYS_FILE, one per line
+        pub fn from_env() -> std::io::Result<ApiKeys> {
+            let mut keys: HashSet<String> = setting("--api-keys", "MODUS_API_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+                .map(str::to_string)
+                .collect();
+            if let Some(path) = setting("--api-keys-file", "MODUS_API_KEYS_FILE") {
+                keys.extend(
+                    std::fs::read_to_string(path)?
+                        .lines()
+                        .map(str::trim)
+                        .filter(|k| !k.is_empty() && !k.starts_with('#'))
+                        .map(str::to_string),
+                );
+            }
+            Ok(ApiKeys(keys))
+        }
+
+        pub fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    // the key in the X-Api-Key header or as the bearer token of the Authorization one
+    pub fn key(req: &ServiceRequest) -> Option<&str> {
+        let headers = req.headers();
+        headers
+            .get("x-api-key")
+            .and_then(|k| k.to_str().ok())
+            .or_else(|| {
+                headers
+                    .get(header::AUTHORIZATION)
+                    .and_then(|a| a.to_str().ok())
+                    .and_then(|a| a.strip_prefix("Bearer "))
+            })
+            .map(str::trim)
+    }
+
+    // rejects the requests without one of the keys before they reach the handlers, so that they can't start any fetch
+    pub async fn authenticate(
+        req: ServiceRequest,
+        next: Next<impl MessageBody + 'static>,
+    ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+        let allowed = match req.app_data::<web::Data<ApiKeys>>() {
+            _ if PROBES.contains(&req.path()) => true,
+            Some(keys) if !keys.0.is_empty() => key(&req).is_some_and(|k| keys.0.contains(k)),
+            _ => true,
+        };
+        if !allowed {
+            let response = HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .json(json!({"Error": "The request needs a valid API key"}));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+        Ok(next.call(req).await?.map_into_left_body())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use actix_web::http::StatusCode;
+        use actix_web::middleware::from_fn;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::App;
+
+        use super::*;
+
+        // the status of the request to the path with the headers, the keys being required if there are any
+        async fn status(keys: &[&str], path: &str, headers: &[(&str, &str)]) -> StatusCode {
+            let keys = ApiKeys(keys.iter().map(|k| k.to_string()).collect());
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(keys))
+                    .wrap(from_fn(authenticate))
+                    .default_service(web::to(HttpResponse::Ok)),
+            )
+            .await;
+            let mut request = TestRequest::get().uri(path);
+            for &header in headers {
+                request = request.insert_header(header);
+            }
+            call_service(&app, request.to_request()).await.status()
+        }
+
+        #[actix_web::test]
+        async fn only_the_requests_with_a_key_get_through() {
+            let keys = ["k1", "k2"];
+            assert_eq!(
+                status(&keys, "/v1/returns", &[]).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                status(&keys, "/v1/returns", &[("X-Api-Key", "k3")]).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                status(&keys, "/v1/returns", &[("X-Api-Key", "k1")]).await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                status(&keys, "/v1/returns", &[("Authorization", "Bearer k2")]).await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                status(&keys, "/v1/returns", &[("Authorization", "Basic k2")]).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        #[actix_web::test]
+        async fn the_probes_and_the_servers_without_keys_need_none() {
+            assert_eq!(status(&["k1"], "/healthz", &[]).await, StatusCode::OK);
+            assert_eq!(status(&["k1"], "/readyz", &[]).await, StatusCode::OK);
+            assert_eq!(status(&[], "/v1/returns", &[]).await, StatusCode::OK);
+        }
+    }
+}
+
+mod limits {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use actix_web::body::MessageBody;
+    use actix_web::dev::{ServiceRequest, ServiceResponse};
+    use actix_web::http::header;
+    use actix_web::middleware::Next;
+    use actix_web::{web, HttpResponse};
+    use serde_json::json;
+
+    use crate::{auth, parsed, PROBES};
+
+    // the requests a client can still send right away
+    struct Bucket {
+        tokens: f64,
+        updated: Instant,
+    }
+
+    /// The requests every client, told apart by its API key when keys are required or else by its IP address, can send a
+    /// minute, with bursts of up to a limit
+    pub struct RateLimiter {
+        rate: f64,
+        burst: f64,
+        // the buckets kept before the least recently used half is dropped
+        clients: usize,
+        buckets: Mutex<HashMap<String, Bucket>>,
+    }
+
+    const CLIENTS: usize = 10_000;
+
+    impl RateLimiter {
+        // the requests a minute given by --rate-limit or MODUS_CLIENT_RATE_LIMIT, 0 by default lifting the limit, and the
+        // burst by --rate-burst or MODUS_CLIENT_RATE_BURST, the requests of a minute by default
+        pub fn from_env() -> std::io::Result<RateLimiter> {
+            let rate: u32 = parsed("--rate-limit", "MODUS_CLIENT_RATE_LIMIT")?.unwrap_or(0);
+            let burst: u32 = parsed("--rate-burst", "MODUS_CLIENT_RATE_BURST")?.unwrap_or(rate);
+            Ok(RateLimiter {
+                rate: rate as f64 / 60.0,
+                burst: burst.max(1) as f64,
+                clients: CLIENTS,
+                buckets: Mutex::new(HashMap::new()),
+            })
+        }
+
+        pub fn enabled(&self) -> bool {
+            self.rate > 0.0
+        }
+
+        // takes a request from the bucket of the client, returning the seconds until it can send another one if it's empty
+        fn take(&self, client: &str) -> Result<(), u64> {
+            let now = Instant::now();
+            let (rate, burst) = (self.rate, self.burst);
+            let refilled = |b: &Bucket| {
+                (b.tokens + now.duration_since(b.updated).as_secs_f64() * rate).min(burst)
+            };
+            let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            if buckets.len() >= self.clients {
+                // a client whose bucket is dropped starts over with a full one, which is why the ones dropped are those
+                // that have waited the longest, however many clients send requests
+                let mut updated: Vec<Instant> = buckets.values().map(|b| b.updated).collect();
+                let oldest = buckets.len() - self.clients / 2;
+                let (_, cutoff, _) = updated.select_nth_unstable(oldest);
+                let cutoff = *cutoff;
+                buckets.retain(|_, b| b.updated >= cutoff);
+            }
+            let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+            });
+            bucket.tokens = refilled(bucket);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                return Err(((1.0 - bucket.tokens) / rate).ceil() as u64);
+            }
+            bucket.tokens -= 1.0;
+            Ok(())
+        }
+    }
+
+    // answers the requests of the clients over their limit with a 429 before they reach the handlers
+    pub async fn limit(
+        req: ServiceRequest,
+        next: Next<impl MessageBody + 'static>,
+    ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+        let limited = match req.app_data::<web::Data<RateLimiter>>() {
+            Some(limiter) if limiter.enabled() && !PROBES.contains(&req.path()) => {
+                // the keys are only known to be valid when they're required, otherwise every request could carry a new one
+                let keyed = req
+                    .app_data::<web::Data<auth::ApiKeys>>()
+                    .is_some_and(|keys| keys.len() > 0);
+                let client = match auth::key(&req).filter(|_| keyed) {
+                    Some(key) => format!("key {key}"),
+                    None => format!(
+                        "ip {}",
+                        req.peer_addr()
+                            .map(|a| a.ip().to_string())
+                            .unwrap_or_default()
+                    ),
+                };
+                limiter.take(&client).err()
+            }
+            _ => None,
+        };
+        if let Some(seconds) = limited {
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, seconds.to_string()))
+                .json(json!({"Error": "Too many requests", "Retry after": seconds}));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+        Ok(next.call(req).await?.map_into_left_body())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::time::Duration;
+
+        use super::*;
+
+        fn limiter(rate: f64, burst: f64, clients: usize) -> RateLimiter {
+            RateLimiter {
+                rate: rate / 60.0,
+                burst,
+                clients,
+                buckets: Mutex::new(HashMap::new()),
+            }
+        }
+
+        #[test]
+        fn refuses_the_requests_past_the_burst_until_the_bucket_refills() {
+            let limiter = limiter(60.0, 2.0, CLIENTS);
+            assert_eq!(limiter.take("a"), Ok(()));
+            assert_eq!(limiter.take("a"), Ok(()));
+            assert_eq!(limiter.take("a"), Err(1));
+            // the other clients have buckets of their own
+            assert_eq!(limiter.take("b"), Ok(()));
+            // a second later the bucket has a request again
+            limiter
+                .buckets
+                .lock()
+                .unwrap()
+                .get_mut("a")
+                .unwrap()
+                .updated -= Duration::from_secs(1);
+            assert_eq!(limiter.take("a"), Ok(()));
+            assert_eq!(limiter.take("a"), Err(1));
+        }
+
+        #[test]
+        fn drops_the_least_recently_used_buckets_past_the_clients() {
+            let limiter = limiter(1.0, 1.0, 4);
+            let start = Instant::now() - Duration::from_secs(10);
+            for (i, client) in ["a", "b", "c", "d"].into_iter().enumerate() {
+                assert_eq!(limiter.take(client), Ok(()));
+                limiter
+                    .buckets
+                    .lock()
+                    .unwrap()
+                    .get_mut(client)
+                    .unwrap()
+                    .updated = start + Duration::from_secs(i as u64);
+            }
+            // the new client makes room by dropping a and b, which have waited the longest
+            assert_eq!(limiter.take("e"), Ok(()));
+            let buckets = limiter.buckets.lock().unwrap();
+            let mut clients: Vec<&str> = buckets.keys().map(String::as_str).collect();
+            clients.sort();
+            assert_eq!(clients, ["c", "d", "e"]);
+            drop(buckets);
+            // the clients kept are still limited
+            assert!(limiter.take("d").is_err());
+        }
+
+        #[test]
+        fn keeps_a_bounded_number_of_buckets() {
+            let limiter = limiter(1.0, 1.0, 100);
+            for i in 0..1000 {
+                assert_eq!(limiter.take(&format!("key {i}")), Ok(()));
+            }
+            assert!(limiter.buckets.lock().unwrap().len() <= 100);
+        }
+    }
+}
+
+mod logging {
+    use std::time::Instant;
+
+    use actix_web::body::{to_bytes, BoxBody, MessageBody};
+    use actix_web::dev::{ServiceRequest, ServiceResponse};
+    use actix_web::http::header::{self, HeaderName, HeaderValue};
+    use actix_web::middleware::Next;
+    use rand::Rng;
+    use serde_json::Value;
+    use tracing::{info, info_span, warn, Instrument};
+    use tracing_subscriber::EnvFilter;
+
+    use crate::setting;
+
+    // logs to stderr at the level given by --log-level or MODUS_LOG, info by default, which can be any filter such as
+    // modus=debug,warn, as text or as a JSON object per line if --log-format or MODUS_LOG_FORMAT is json
+    pub fn init() -> std::io::Result<()> {
+        let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+        let level = setting("--log-level", "MODUS_LOG").unwrap_or_else(|| "info".to_string());
+        let filter = EnvFilter::try_new(&level)
+            .map_err(|e| invalid(format!("--log-level {level} isn't valid: {e}")))?;
+        let logs = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr);
+        match setting("--log-format", "MODUS_LOG_FORMAT").as_deref() {
+            None | Some("text") => logs.init(),
+            Some("json") => logs.json().init(),
+            Some(format) => {
+                return Err(invalid(format!(
+                    "--log-format {format} isn't valid: it must be text or json"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    const REQUEST_ID: &str = "x-request-id";
+
+    // the id the client gave the request in X-Request-Id, if it's up to 128 visible ASCII characters, or else a new one
+    fn request_id(req: &ServiceRequest) -> String {
+        req.headers()
+            .get(REQUEST_ID)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| (1..=128).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic()))
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{:016x}", rand::thread_rng().gen::<u64>()))
+    }
+
+    // every request in a span of its own with its id, which the events of the handler, the provider and the return engine
+    // belong to, ending with its status and how long it took. The id is sent back in X-Request-Id and, in the JSON
+    // errors, as their Request id, so that a client can tell which logs are those of a call that failed
+    pub async fn trace(
+        req: ServiceRequest,
+        next: Next<impl MessageBody + 'static>,
+    ) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+        let id = request_id(&req);
+        let span = info_span!("request", id, method = %req.method(), path = req.path());
+        let started = Instant::now();
+        let response = next.call(req).instrument(span.clone()).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let response = match response {
+            Ok(response) => response.map_into_boxed_body(),
+            Err(e) => {
+                span.in_scope(|| warn!(error = %e, elapsed_ms, "failed"));
+                return Err(e);
+            }
+        };
+        let status = response.status();
+        span.in_scope(|| match status.is_server_error() {
+            true => warn!(status = status.as_u16(), elapsed_ms, "served"),
+            false => info!(status = status.as_u16(), elapsed_ms, "served"),
+        });
+        let mut response = match status.is_client_error() || status.is_server_error() {
+            true => with_id(response, &id).await,
+            false => response,
+        };
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID), value);
+        }
+        Ok(response)
+    }
+
+    // the error with the id of the request, if it's a JSON object
+    async fn with_id(response: ServiceResponse<BoxBody>, id: &str) -> ServiceResponse<BoxBody> {
+        let json = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+        if !json {
+            return response;
+        }
+        let (request, response) = response.into_parts();
+        let (response, body) = response.into_parts();
+        let body = match to_bytes(body).await {
+            Ok(body) => body,
+            Err(_) => return ServiceResponse::new(request, response.set_body(BoxBody::new(()))),
+        };
+        let body = match serde_json::from_slice::<Value>(&body) {
+            Ok(Value::Object(mut error)) => {
+                error.insert("Request id".to_string(), Value::from(id));
+                BoxBody::new(Value::Object(error).to_string())
+            }
+            _ => BoxBody::new(body),
+        };
+        ServiceResponse::new(request, response.set_body(body))
+    }
+}
+
+// the endpoints of the first version of the API, under /v1. The breaking changes go under /v2, the clients of /v1
+// being left as they are
+fn v1(config: &mut web::ServiceConfig) {
+    config
+        .service(
+            web::scope("/equities")
+                .route("/returns", body(returns))
+                .route("/benchmark", body(benchmark))
+                .route("/risk", body(risk_report))
+                .route("/analysis", body(analysis))
+                .route("/rolling", body(rolling_stats))
+                .route("/correlation", body(correlation_matrix))
+                .route("/optimization", body(optimization))
+                .route("/black-litterman", body(views))
+                .route("/risk-parity", body(parity))
+                .route("/rebalancing", body(rebalancing))
+                .route("/dca", body(averaging))
+                .route("/projection", body(projection))
+                .route("/allocation", body(allocation))
+                .route("/dividends", body(dividends))
+                .route("/yield", body(yields))
+                .route("/events", body(events))
+                .route("/quote/{ticker}", web::get().to(quote))
+                .route("/quotes", web::get().to(quotes))
+                .route("/search", web::get().to(lookup))
+                .route("/fundamentals/{ticker}", web::get().to(summary))
+                .route("/contribution", body(contribution))
+                .route("/value", body(value))
+                .route("/import", body(upload))
+                .route("/comparison", body(comparison))
+                .route("/merge", body(merge))
+                .route("/diff", body(diff))
+                .route("/hedged", body(hedged))
+                .route("/decomposition", body(decomposition))
+                .route("/factors", body(factors))
+                .route("/performance", body(performance))
+                .route("/real", body(real))
+                .route("/pnl", body(pnl))
+                .route("/weights", body(drift))
+                .route("/activity", body(trading)),
+        )
+        .service(
+            web::scope("/options")
+                .route("/bs", body(bs))
+                .route("/kelly", body(kelly))
+                .route("/mc", body(montecarlo)),
+        )
+        .service(web::scope("/fx").route("/{pair}", web::get().to(rates)))
+        .route("/ws/portfolio", web::get().to(live));
+    config.configure(jobs::routes);
+    #[cfg(feature = "sqlite")]
+    config.configure(portfolios::routes);
+    #[cfg(feature = "graphql")]
+    config.configure(graphql::routes);
+}
+
+// the route of an endpoint that takes a body, for POST as many clients, proxies and caches don't send a body along with
+// a GET, and for GET as it always was
+fn body<F, Args>(handler: F) -> Route
+where
+    F: Handler<Args>,
+    Args: FromRequest + 'static,
+    F::Output: Responder + 'static,
+{
+    web::route()
+        .guard(guard::Any(guard::Post()).or(guard::Get()))
+        .to(handler)
+}
+
+mod versions {
+    use std::str::FromStr;
+
+    use actix_web::body::MessageBody;
+    use actix_web::dev::{ServiceRequest, ServiceResponse};
+    use actix_web::http::header::{self, HeaderName, HeaderValue};
+    use actix_web::http::Uri;
+    use actix_web::middleware::Next;
+
+    // the paths the endpoints of /v1 had before the API was versioned
+    const UNVERSIONED: [&str; 4] = ["/equities", "/options", "/fx", "/portfolios"];
+
+    // serves the old paths as those under /v1, telling the clients with the Deprecation and Link headers where they moved
+    pub async fn unversioned(
+        mut req: ServiceRequest,
+        next: Next<impl MessageBody + 'static>,
+    ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+        let path = req.path();
+        let old = UNVERSIONED.iter().any(|p| {
+            path.strip_prefix(p)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        let moved = old
+            .then(|| {
+                let path_and_query = req.uri().path_and_query().map_or(path, |p| p.as_str());
+                Uri::from_str(&format!("/v1{path_and_query}")).ok()
+            })
+            .flatten();
+        let Some(uri) = moved else {
+            return next.call(req).await;
+        };
+        let link = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", uri.path()));
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+        let mut response = next.call(req).await?;
+        let headers = response.headers_mut();
+        headers.insert(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static("true"),
+        );
+        if let Ok(link) = link {
+            headers.insert(header::LINK, link);
+        }
+        Ok(response)
+    }
+}
+
+// the value of the command line flag, given as --flag value or --flag=value, or else of the environment variable
+fn setting(flag: &str, variable: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    args.iter()
+        .enumerate()
+        .find_map(|(i, arg)| match arg.strip_prefix(flag)? {
+            "" => args.get(i + 1).cloned(),
+            value => value.strip_prefix('=').map(str::to_string),
+        })
+        .or_else(|| std::env::var(variable).ok())
+}
+
+// the setting parsed, failing at startup if it isn't valid
+fn parsed<T: std::str::FromStr>(flag: &str, variable: &str) -> std::io::Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    setting(flag, variable)
+        .map(|value| {
+            value.parse().map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{flag} {value} isn't valid: {e}"),
+                )
+            })
+        })
+        .transpose()
+}
+
+// the TLS configuration with the certificate chain and the private key in the PEM files
+#[cfg(feature = "rustls")]
+fn tls(cert: &str, key: &str) -> std::io::Result<rustls::ServerConfig> {
+    use std::io::BufReader;
+    let invalid = |path: &str, e: &dyn std::fmt::Display| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{path} isn't valid: {e}"),
+        )
+    };
+    let chain = rustls_pemfile::certs(&mut BufReader::new(std::fs::File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(cert, &e))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(std::fs::File::open(key)?))
+        .map_err(|e| invalid(key, &e))?
+        .ok_or_else(|| invalid(key, &"there's no private key in it"))?;
+    rustls::ServerConfig::builder_with_provider(std::sync::Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .and_then(|builder| builder.with_no_client_auth().with_single_cert(chain, key))
+    .map_err(|e| invalid(cert, &e))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("Modus now running on localhost:8080 \n Available endpoints: \n /equities/returns \n /options/bs \n /options/kelly \n /options/mc");
-    HttpServer::new(|| {
-        App::new()
+    logging::init()?;
+    // the bundled fixtures are served instead of the quotes of the providers, for demos and for working without the network
+    if std::env::args().skip(1).any(|arg| arg == "--offline") {
+        std::env::set_var("MODUS_PROVIDER", "fixtures");
+    }
+    // fails at startup rather than on every request if MODUS_PROVIDER is wrong or its API key is missing, or the proxy or
+    // the certificates the requests go through are
+    modus::provider::Provider::from_env().map_err(std::io::Error::other)?;
+    modus::provider::check_client().map_err(std::io::Error::other)?;
+    #[cfg(feature = "sqlite")]
+    modus::quote_cache::QuoteCache::from_env().map_err(|e| std::io::Error::other(e.to_string()))?;
+    // the database is given by MODUS_DATABASE, modus.db in the working directory by default
+    #[cfg(feature = "sqlite")]
+    let storage = web::Data::new(
+        modus::storage::Storage::open(
+            std::env::var("MODUS_DATABASE").unwrap_or_else(|_| "modus.db".to_string()),
+        )
+        .map_err(std::io::Error::other)?,
+    );
+    // the address and port are given by --host and --port or MODUS_HOST and MODUS_PORT, 127.0.0.1:8080 by default, e.g.,
+    // 0.0.0.0 in a container, and the number of workers by --workers or MODUS_WORKERS, one per CPU core by default
+    let host = setting("--host", "MODUS_HOST").unwrap_or_else(|| "127.0.0.1".to_string());
+    let port: u16 = parsed("--port", "MODUS_PORT")?.unwrap_or(8080);
+    let workers: Option<usize> = parsed("--workers", "MODUS_WORKERS")?;
+    if workers == Some(0) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--workers 0 isn't valid: there must be at least one worker",
+        ));
+    }
+    // HTTPS is served instead of HTTP with the certificate chain and the private key in the PEM files given by --tls-cert
+    // and --tls-key or MODUS_TLS_CERT and MODUS_TLS_KEY, when built with the rustls feature
+    let certificate = match (
+        setting("--tls-cert", "MODUS_TLS_CERT"),
+        setting("--tls-key", "MODUS_TLS_KEY"),
+    ) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "HTTPS needs both --tls-cert and --tls-key",
+            ))
+        }
+    };
+    #[cfg(feature = "rustls")]
+    let tls = certificate
+        .as_ref()
+        .map(|(cert, key)| tls(cert, key))
+        .transpose()?;
+    #[cfg(not(feature = "rustls"))]
+    if certificate.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "HTTPS needs modus to be built with the rustls feature",
+        ));
+    }
+    let scheme = match certificate {
+        Some(_) => "https",
+        None => "http",
+    };
+    info!("Modus now running on {scheme}://{host}:{port}");
+    debug!("{ENDPOINTS}{STORAGE_ENDPOINTS}{GRAPHQL_ENDPOINTS}");
+    let keys = web::Data::new(auth::ApiKeys::from_env()?);
+    if keys.len() > 0 {
+        info!(keys = keys.len(), "requests need one of the API keys");
+    }
+    let limiter = web::Data::new(limits::RateLimiter::from_env()?);
+    let jobs = web::Data::from(jobs::Jobs::from_env()?);
+    let running = jobs.clone();
+    #[cfg(feature = "graphql")]
+    let schema = web::Data::new(modus::graphql::schema());
+    let server = HttpServer::new(move || {
+        // the keys are checked first, so that the requests with made up ones are refused before they're counted, and
+        // every request is logged, the refused ones too. The old paths are moved under /v1 last, so that the logs have
+        // the paths the clients asked for, and the responses are compressed once the errors carry their request id
+        let app = App::new()
+            .app_data(keys.clone())
+            .app_data(limiter.clone())
+            .app_data(jobs.clone())
+            .app_data(extract::config())
+            .wrap(from_fn(versions::unversioned))
+            .wrap(from_fn(limits::limit))
+            .wrap(from_fn(auth::authenticate))
+            .wrap(from_fn(logging::trace))
+            .wrap(Compress::default())
             .service(hello)
-            .service(web::scope("/equities").route("/returns", web::get().to(returns)))
-            .service(
-                web::scope("/options")
-                    .route("/bs", web::get().to(bs))
-                    .route("/kelly", web::get().to(kelly))
-                    .route("/mc", web::get().to(montecarlo)),
-            )
-    })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+            .route("/healthz", web::get().to(healthz))
+            .route("/readyz", web::get().to(readyz))
+            .route("/openapi.json", web::get().to(openapi))
+            .route("/docs", web::get().to(docs))
+            .service(web::scope("/v1").configure(v1));
+        #[cfg(feature = "sqlite")]
+        let app = app.app_data(storage.clone());
+        #[cfg(feature = "graphql")]
+        let app = app.app_data(schema.clone());
+        app
+    });
+    let server = match workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    // the requests in flight, such as Monte Carlo runs and the returns of long histories, are given --shutdown-timeout
+    // or MODUS_SHUTDOWN_TIMEOUT seconds to finish once the server is stopped, 30 by default
+    let drain: u64 = parsed("--shutdown-timeout", "MODUS_SHUTDOWN_TIMEOUT")?.unwrap_or(30);
+    let server = server.shutdown_timeout(drain).disable_signals();
+    #[cfg(feature = "rustls")]
+    let server = match tls {
+        Some(tls) => server.bind_rustls_0_23((host.as_str(), port), tls)?,
+        None => server.bind((host.as_str(), port))?,
+    };
+    #[cfg(not(feature = "rustls"))]
+    let server = server.bind((host.as_str(), port))?;
+    // the gRPC service is served on --grpc-port or MODUS_GRPC_PORT too, at the same host, when modus is built with the grpc
+    // feature, until the signal that stops the HTTP server
+    let grpc_port: Option<u16> = parsed("--grpc-port", "MODUS_GRPC_PORT")?;
+    #[cfg(not(feature = "grpc"))]
+    if grpc_port.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "gRPC needs modus to be built with the grpc feature",
+        ));
+    }
+    #[cfg(feature = "grpc")]
+    let grpc = match grpc_port {
+        Some(port) => Some(grpc(&host, port)?),
+        None => None,
+    };
+    let server = server.run();
+    actix_web::rt::spawn(shutdown(server.handle(), drain));
+    server.await?;
+    #[cfg(feature = "grpc")]
+    if let Some(Ok(Err(e))) = match grpc {
+        Some(grpc) => Some(grpc.await),
+        None => None,
+    } {
+        warn!(error = %e, "the gRPC server failed");
+    }
+    // the jobs running are given as long as the requests were, before the quote cache they may write to is flushed
+    running.stop(std::time::Duration::from_secs(drain)).await;
+    #[cfg(feature = "sqlite")]
+    if let Ok(Some(cache)) = modus::quote_cache::QuoteCache::from_env() {
+        if let Err(e) = cache.flush() {
+            warn!(error = %e, "the quote cache couldn't be flushed");
+        }
+    }
+    info!("Modus stopped");
+    Ok(())
+}
+
+// binds the gRPC service to the port, failing at startup if it's taken, and serves it until a signal
+#[cfg(feature = "grpc")]
+fn grpc(
+    host: &str,
+    port: u16,
+) -> std::io::Result<actix_web::rt::task::JoinHandle<Result<(), tonic::transport::Error>>> {
+    use std::net::ToSocketAddrs;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::Server;
+
+    let address = (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{host} isn't an address"),
+        )
+    })?;
+    let incoming = TcpIncoming::new(address, true, None).map_err(std::io::Error::other)?;
+    info!("gRPC now running on {address}");
+    Ok(actix_web::rt::spawn(
+        Server::builder()
+            .add_service(modus::grpc::service())
+            .serve_with_incoming_shutdown(incoming, signal()),
+    ))
+}
+
+// resolves on SIGINT or SIGTERM
+async fn signal() {
+    let interrupt = async {
+        let _ = actix_web::rt::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    select(pin!(interrupt), pin!(terminate)).await;
+}
+
+// stops accepting connections on the first signal, letting the requests in flight finish, and stops right away on another
+async fn shutdown(server: ServerHandle, drain: u64) {
+    signal().await;
+    info!(
+        timeout_s = drain,
+        "stopping, the requests in flight are given time to finish"
+    );
+    if let Either::Right(_) = select(pin!(server.stop(true)), pin!(signal())).await {
+        warn!("stopping right away");
+        server.stop(false).await;
+    }
 }
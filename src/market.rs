@@ -0,0 +1,338 @@
+//! Market data
+//!
+//! The latest price of a ticker as Yahoo Finance reports it, fetched without the history the other analyses ask for.
+//! The change is the one since the previous close, in the currency of the ticker, and the prices of the pre-market and
+//! post-market of the day are given apart, so that positions can be valued outside the regular session. The quotes of many tickers are fetched
+//! with live_quotes in batches of up to 50 symbols a request.
+//!
+//! The symbols the rest of the library expects can be looked up by the name of the company or fund with search,
+//! and the fundamentals of a ticker, its market capitalization, earnings, valuation ratios, dividend yield and sector, are
+//! those of Yahoo's latest summary.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::market::{fundamentals, live_quote, live_quotes, search};
+//! # async fn run() {
+//!  if let Ok(s) = live_quote("AAPL").await { println!("{:?}", s); }
+//!  if let Ok(s) = live_quotes(["AAPL", "MSFT"]).await { println!("{:?}", s); }
+//!  if let Ok(s) = search("inditex").await { println!("{:?}", s); }
+//!  if let Ok(s) = fundamentals("MSFT").await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use futures::{stream, StreamExt, TryStreamExt};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::stock_returns::StocksError;
+use crate::yahoo_finance::{get_json, symbol, ProviderError, YResponse, YahooError};
+
+/// Holds the latest price of the ticker in its currency, the unix timestamp it was traded at and how much it changed
+/// since the previous close, the percentage change in percentage, along with the latest prices of the extended hours,
+/// if the ticker traded in them
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveQuote {
+    pub symbol: String,
+    pub currency: String,
+    pub price: f64,
+    pub time: u64,
+    pub previous_close: f64,
+    pub change: f64,
+    pub change_percent: f64,
+    pub pre_market: Option<f64>,
+    pub post_market: Option<f64>,
+}
+
+/// Returns the latest price of the ticker, which some exchanges delay
+pub async fn live_quote(ticker: &str) -> Result<LiveQuote, StocksError> {
+    let now = OffsetDateTime::now_utc();
+    let quote = async {
+        // the bars of the day by the minute, those of the extended hours included
+        let response: YResponse = get_json(&format!(
+            "https://query1.finance.yahoo.com/v8/finance/chart/{}?range=1d&interval=1m&includePrePost=true",
+            symbol(ticker)
+        ))
+        .await?;
+        let (pre_market, post_market) = response.extended();
+        let meta = &response
+            .chart
+            .result
+            .first()
+            .ok_or(YahooError::EmptyDataSet)?
+            .meta;
+        let (Some(price), Some(previous_close)) =
+            (meta.regular_market_price, meta.chart_previous_close)
+        else {
+            return Err(ProviderError::from(YahooError::EmptyDataSet));
+        };
+        Ok(LiveQuote {
+            symbol: meta.symbol.clone(),
+            currency: meta.currency.clone(),
+            price,
+            time: meta.regular_market_time.unwrap_or_default(),
+            previous_close,
+            change: price - previous_close,
+            change_percent: (price / previous_close - 1.0) * 100.0,
+            pre_market,
+            post_market,
+        })
+    };
+    Ok(quote.await.map_err(|e| e.context(ticker, &now, &now))?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YQuotes {
+    quote_response: YQuoteResult,
+}
+
+#[derive(Deserialize)]
+struct YQuoteResult {
+    #[serde(default)]
+    result: Vec<YQuote>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YQuote {
+    symbol: String,
+    currency: Option<String>,
+    regular_market_price: Option<f64>,
+    regular_market_time: Option<u64>,
+    regular_market_previous_close: Option<f64>,
+    pre_market_price: Option<f64>,
+    post_market_price: Option<f64>,
+}
+
+// the symbols in every request for quotes and the requests in flight at the same time
+const BATCH: usize = 50;
+const CONCURRENT_BATCHES: usize = 4;
+
+// the quotes of the symbols in a single request, keyed by symbol
+async fn batch(symbols: &[&str]) -> Result<BTreeMap<String, LiveQuote>, ProviderError> {
+    let url = Url::parse_with_params(
+        "https://query2.finance.yahoo.com/v7/finance/quote",
+        &[("symbols", symbols.join(","))],
+    )
+    .map_err(|e| YahooError::FetchFailed(e.to_string()))?;
+    let response: YQuotes = get_json(url.as_str()).await?;
+    Ok(response
+        .quote_response
+        .result
+        .into_iter()
+        .filter_map(|q| {
+            let (price, previous_close) =
+                (q.regular_market_price?, q.regular_market_previous_close?);
+            Some((
+                q.symbol.clone(),
+                LiveQuote {
+                    symbol: q.symbol,
+                    currency: q.currency.unwrap_or_default(),
+                    price,
+                    time: q.regular_market_time.unwrap_or_default(),
+                    previous_close,
+                    change: price - previous_close,
+                    change_percent: (price / previous_close - 1.0) * 100.0,
+                    pre_market: q.pre_market_price,
+                    post_market: q.post_market_price,
+                },
+            ))
+        })
+        .collect())
+}
+
+/// Returns the latest price of every ticker keyed by it, the tickers Yahoo doesn't know being left out. If Yahoo refuses
+/// the batches, the quotes are fetched a ticker at a time, still concurrently, an unknown ticker then failing them all
+pub async fn live_quotes<'a>(
+    tickers: impl IntoIterator<Item = &'a str>,
+) -> Result<BTreeMap<String, LiveQuote>, StocksError> {
+    let tickers: Vec<&str> = tickers.into_iter().collect();
+    let symbols: Vec<&str> = tickers.iter().map(|t| symbol(t)).collect();
+    let batches: Result<Vec<BTreeMap<String, LiveQuote>>, ProviderError> =
+        stream::iter(symbols.chunks(BATCH))
+            .map(batch)
+            .buffered(CONCURRENT_BATCHES)
+            .try_collect()
+            .await;
+    let quotes: BTreeMap<String, LiveQuote> = match batches {
+        Ok(batches) => batches.into_iter().flatten().collect(),
+        Err(_) => {
+            // made up front, so that the future holds no closure over the tickers and stays Send
+            let requests: Vec<_> = tickers
+                .iter()
+                .map(|ticker| async move {
+                    Ok::<_, StocksError>((symbol(ticker).to_string(), live_quote(ticker).await?))
+                })
+                .collect();
+            stream::iter(requests).buffered(BATCH).try_collect().await?
+        }
+    };
+    Ok(tickers
+        .iter()
+        .filter_map(|ticker| Some((ticker.to_string(), quotes.get(symbol(ticker))?.clone())))
+        .collect())
+}
+
+/// A ticker matching the search, with the name of the company or fund, the exchange it trades on and its type, e.g., EQUITY or ETF
+#[derive(Debug, Clone, Serialize)]
+pub struct Match {
+    pub symbol: String,
+    pub name: String,
+    pub exchange: String,
+    pub instrument_type: String,
+}
+
+#[derive(Deserialize)]
+struct YSearch {
+    #[serde(default)]
+    quotes: Vec<YMatch>,
+}
+
+// news and other results have no symbol
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YMatch {
+    symbol: Option<String>,
+    longname: Option<String>,
+    shortname: Option<String>,
+    exch_disp: Option<String>,
+    exchange: Option<String>,
+    quote_type: Option<String>,
+}
+
+// the number of matches asked for
+const MATCHES: usize = 10;
+
+/// Returns the tickers whose symbol or name matches the query, the best matches first
+pub async fn search(query: &str) -> Result<Vec<Match>, StocksError> {
+    let url = Url::parse_with_params(
+        "https://query2.finance.yahoo.com/v1/finance/search",
+        &[
+            ("q", query),
+            ("quotesCount", &MATCHES.to_string()),
+            ("newsCount", "0"),
+        ],
+    )
+    .map_err(|e| ProviderError::from(YahooError::FetchFailed(e.to_string())))?;
+    let response: YSearch = get_json(url.as_str()).await?;
+    Ok(response
+        .quotes
+        .into_iter()
+        .filter_map(|m| {
+            let symbol = m.symbol?;
+            Some(Match {
+                name: m.longname.or(m.shortname).unwrap_or_else(|| symbol.clone()),
+                symbol,
+                exchange: m.exch_disp.or(m.exchange).unwrap_or_default(),
+                instrument_type: m.quote_type.unwrap_or_default(),
+            })
+        })
+        .collect())
+}
+
+/// Holds the market capitalization and the trailing earnings per share in the currency of the ticker, the trailing and forward
+/// price to earnings ratios, the dividend yield in percentage and the sector and industry, each one missing if Yahoo doesn't have it,
+/// as for the P/E of companies with losses or the sector of funds
+#[derive(Debug, Clone, Serialize)]
+pub struct Fundamentals {
+    pub symbol: String,
+    pub currency: Option<String>,
+    pub market_cap: Option<f64>,
+    pub eps: Option<f64>,
+    pub trailing_pe: Option<f64>,
+    pub forward_pe: Option<f64>,
+    pub dividend_yield: Option<f64>,
+    pub sector: Option<String>,
+    pub industry: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YSummary {
+    quote_summary: YSummaryResult,
+}
+
+#[derive(Deserialize)]
+struct YSummaryResult {
+    #[serde(default)]
+    result: Option<Vec<YModules>>,
+}
+
+// the modules asked for, any of which can be missing
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct YModules {
+    price: YPrice,
+    summary_detail: YSummaryDetail,
+    default_key_statistics: YKeyStatistics,
+    asset_profile: YAssetProfile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct YPrice {
+    currency: Option<String>,
+    market_cap: YValue,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct YSummaryDetail {
+    #[serde(rename = "trailingPE")]
+    trailing_pe: YValue,
+    #[serde(rename = "forwardPE")]
+    forward_pe: YValue,
+    dividend_yield: YValue,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct YKeyStatistics {
+    trailing_eps: YValue,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct YAssetProfile {
+    sector: Option<String>,
+    industry: Option<String>,
+}
+
+// a figure along with the way Yahoo formats it, {} when there's none
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct YValue {
+    raw: Option<f64>,
+}
+
+/// Returns the fundamentals of the ticker
+pub async fn fundamentals(ticker: &str) -> Result<Fundamentals, StocksError> {
+    let now = OffsetDateTime::now_utc();
+    let symbol = symbol(ticker);
+    let summary = async {
+        let response: YSummary = get_json(&format!(
+            "https://query2.finance.yahoo.com/v10/finance/quoteSummary/{symbol}?modules=price,summaryDetail,defaultKeyStatistics,assetProfile"
+        ))
+        .await?;
+        response
+            .quote_summary
+            .result
+            .and_then(|r| r.into_iter().next())
+            .ok_or(ProviderError::from(YahooError::EmptyDataSet))
+    };
+    let modules = summary.await.map_err(|e| e.context(ticker, &now, &now))?;
+    Ok(Fundamentals {
+        symbol: symbol.to_string(),
+        currency: modules.price.currency,
+        market_cap: modules.price.market_cap.raw,
+        eps: modules.default_key_statistics.trailing_eps.raw,
+        trailing_pe: modules.summary_detail.trailing_pe.raw,
+        forward_pe: modules.summary_detail.forward_pe.raw,
+        dividend_yield: modules.summary_detail.dividend_yield.raw.map(|y| y * 100.0),
+        sector: modules.asset_profile.sector,
+        industry: modules.asset_profile.industry,
+    })
+}
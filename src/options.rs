@@ -7,16 +7,9 @@
 //! Because it uses the Black-Scholes formula, it has the same limitations, chiefly among them, the constant volatility
 //!
 //! # Usage:
-//! ```
-//!  let a_option = Options{
-//!     form: OptionType::Call,
-//!     underlying: 43.0,
-//!     strike: 55.0,
-//!     maturity: 3,
-//!     volatility: 0.7,
-//!     rfr: 0.3,
-//!     market_price: None,
-//!  };
+//! ```no_run
+//! # use modus::options::{bs_price, OptionType, Options};
+//!  let a_option = Options::new(OptionType::Call, 43.0, 55.0, 3, 0.7).with_rfr(0.3);
 //!  println!("{}", bs_price(&a_option));
 //! ```
 //!
@@ -24,19 +17,21 @@
 //! Alternatively, it performs a [Monte-Carlo analysis](https://en.wikipedia.org/wiki/Monte_Carlo_method) to calculate the option price.
 //!
 //! # Usage:
-//! ```
-//! let a_option = Options{
-//!     form: OptionType::Call,
-//!     underlying: 43.0,
-//!     strike: 55.0,
-//!     maturity: 3,
-//!     volatility: 0.7,
-//!     rfr: 0.3,
-//!     market_price: None,
-//!  };
+//! ```no_run
+//! # use modus::options::{expected, OptionType, Options};
+//!  let a_option = Options::new(OptionType::Call, 43.0, 55.0, 3, 0.7).with_rfr(0.3);
 //!  if let Ok(s) = expected(&a_option) { println!("{:?}", s); }
 //! ```
 //!
+//! The estimates converge as the simulations add up, which estimates yields as it goes, every so many simulations, along
+//! with their 95% confidence intervals.
+//!
+//! ```no_run
+//! # use modus::options::{estimates, OptionType, Options};
+//! # let a_option = Options::new(OptionType::Call, 43.0, 55.0, 3, 0.7).with_rfr(0.3);
+//!  for e in estimates(&a_option, 10000, 1000) { println!("{:?}", e); }
+//! ```
+//!
 //! # Kelly Criterion
 //! If one were to be able to consistently find theoretical market values of the options different from their market values one could design an optimal strategy where the
 //! expected geometric growth rate is maximized by finding the fraction of the bankroll that maximizes the expected value of the logarithm of wealth, also known as the
@@ -45,16 +40,9 @@
 //! consider a more mature crate for this.
 //!
 //! # Usage:
-//! ```
-//! let a_option = Options{
-//!     form: OptionType::Call,
-//!     underlying: 43.0,
-//!     strike: 55.0,
-//!     maturity: 3,
-//!     volatility: 0.7,
-//!     rfr: 0.3,
-//!     market_price: Some(19.0),
-//!  };
+//! ```no_run
+//! # use modus::options::{kelly_ratio, OptionType, Options};
+//!  let a_option = Options::new(OptionType::Call, 43.0, 55.0, 3, 0.7).with_rfr(0.3).with_market_price(19.0);
 //!  if let Some(s) = kelly_ratio(&a_option) { println!("{:?}", s); }
 //! ```
 
@@ -65,21 +53,113 @@ use std::thread;
 use rstat::univariate::normal::Normal;
 use rstat::Distribution;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-/// Holds the option data
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+use crate::fred::treasury_yield;
+use crate::provider::Source;
+use crate::stock_returns::{StocksError, Violation};
+
+/// Holds the option data, the maturity in years. A missing risk-free rate is the yield of the US Treasury securities of the
+/// closest maturity once with_market_rfr fetches it, and zero until then or if the provider is one that needs no network
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, ToSchema)]
 pub struct Options {
     form: OptionType,
     underlying: f64,
     strike: f64,
     maturity: u8,
     volatility: f64,
-    rfr: f64,
+    /// The annual risk-free rate as a fraction, the latest yield from FRED of the US Treasury securities closest to the
+    /// maturity if it's missing, or zero with the fixtures and files providers (e.g., with --offline), which need no network
+    #[serde(default)]
+    rfr: Option<f64>,
     market_price: Option<f64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
-enum OptionType {
+impl Options {
+    /// An option with no risk-free rate, which with_market_rfr fills in, and no market price
+    pub fn new(
+        form: OptionType,
+        underlying: f64,
+        strike: f64,
+        maturity: u8,
+        volatility: f64,
+    ) -> Options {
+        Options {
+            form,
+            underlying,
+            strike,
+            maturity,
+            volatility,
+            rfr: None,
+            market_price: None,
+        }
+    }
+
+    /// Sets the annual risk-free rate
+    pub fn with_rfr(self, rfr: f64) -> Options {
+        Options {
+            rfr: Some(rfr),
+            ..self
+        }
+    }
+
+    /// Sets the market price of the option, which the Kelly fraction needs
+    pub fn with_market_price(self, market_price: f64) -> Options {
+        Options {
+            market_price: Some(market_price),
+            ..self
+        }
+    }
+
+    /// Checks that the underlying, the strike, the volatility and the market price are positive, the maturity at least a
+    /// year and the risk-free rate a number, returning all the violations found
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        for (field, value) in [
+            ("underlying", Some(self.underlying)),
+            ("strike", Some(self.strike)),
+            ("volatility", Some(self.volatility)),
+            ("market_price", self.market_price),
+        ] {
+            if value.is_some_and(|value| !value.is_finite() || value <= 0.0) {
+                violations.push(Violation::new(field, "must be positive"));
+            }
+        }
+        if self.maturity == 0 {
+            violations.push(Violation::new("maturity", "must be at least a year"));
+        }
+        if self.rfr.is_some_and(|rfr| !rfr.is_finite()) {
+            violations.push(Violation::new("rfr", "must be a number"));
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn rfr(&self) -> f64 {
+        self.rfr.unwrap_or_default()
+    }
+
+    /// Fills in the missing risk-free rate with the latest yield from FRED of the Treasury securities closest to the maturity,
+    /// unless the provider in MODUS_PROVIDER is fixtures or files, which need no network, the rate being left at zero then
+    pub async fn with_market_rfr(self) -> Result<Options, StocksError> {
+        self.with_rfr_for(Source::from_env().unwrap_or_default())
+            .await
+    }
+
+    async fn with_rfr_for(self, source: Source) -> Result<Options, StocksError> {
+        match (self.rfr, source) {
+            (Some(_), _) | (None, Source::Fixtures | Source::Files) => Ok(self),
+            (None, _) => Ok(self.with_rfr(treasury_yield(self.maturity).await?)),
+        }
+    }
+}
+
+/// Whether the option is a call or a put
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, ToSchema)]
+pub enum OptionType {
     Call,
     Put,
 }
@@ -92,11 +172,11 @@ pub fn bs_price(item: &Options) -> f64 {
         OptionType::Call => {
             item.underlying * Normal::standard().cdf(&d1)
                 - item.strike
-                    * (-item.rfr * item.maturity as f64).exp()
+                    * (-item.rfr() * item.maturity as f64).exp()
                     * Normal::standard().cdf(&d2)
         }
         OptionType::Put => {
-            item.strike * (-item.rfr * item.maturity as f64).exp() * Normal::standard().cdf(&-d2)
+            item.strike * (-item.rfr() * item.maturity as f64).exp() * Normal::standard().cdf(&-d2)
                 - item.underlying * Normal::standard().cdf(&-d1)
         }
     }
@@ -104,7 +184,7 @@ pub fn bs_price(item: &Options) -> f64 {
 
 fn d1(item: &Options) -> f64 {
     ((item.underlying / item.strike).ln()
-        + (item.rfr + (item.volatility.powi(2) / 2.0)) * item.maturity as f64)
+        + (item.rfr() + (item.volatility.powi(2) / 2.0)) * item.maturity as f64)
         / (item.volatility * (item.maturity as f64).sqrt())
 }
 
@@ -123,40 +203,162 @@ pub fn kelly_ratio(item: &Options) -> Option<f64> {
 
 /// Performs a Monte-Carlo analysis with 10000 simulations
 pub fn expected(item: &Options) -> Result<f64, RecvError> {
-    // an arc because the value is immutable between threads
-    let values = Arc::new(*item);
+    let values = *item;
+    let v = simulate(10000, move || terminal(&values))?;
+    // calculates the return for each iteration
+    let returns: Vec<f64> = v.iter().map(|&x| payoff(item, x)).collect();
+    // computes the average
+    Ok(returns.iter().sum::<f64>() / returns.len() as f64)
+}
+
+// a price of the underlying at maturity, drawn from its lognormal distribution
+fn terminal(item: &Options) -> f64 {
+    item.underlying
+        * ((item.rfr() - item.volatility.powi(2) / 2.0) * item.maturity as f64
+            + item.volatility
+                * (item.maturity as f64).sqrt()
+                * Normal::standard().sample(&mut rand::thread_rng()))
+        .exp()
+}
+
+// the present value of the option if the underlying ends at the price
+fn payoff(item: &Options, x: f64) -> f64 {
+    match item.form {
+        OptionType::Call => match x <= item.strike {
+            true => 0.0,
+            false => (x - item.strike) / (1.0 + item.rfr()).powi(item.maturity as i32),
+        },
+        OptionType::Put => match x >= item.strike {
+            true => 0.0,
+            false => (item.strike - x) / (1.0 + item.rfr()).powi(item.maturity as i32),
+        },
+    }
+}
+
+/// Holds the Monte-Carlo value after a number of simulations and the bounds of its 95% confidence interval
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Estimate {
+    pub simulations: usize,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Performs a Monte-Carlo analysis with the simulations one after the other, yielding the running estimate every `every`
+/// of them and after the last one, so that it can be stopped once the interval is narrow enough
+pub fn estimates(
+    item: &Options,
+    simulations: usize,
+    every: usize,
+) -> impl Iterator<Item = Estimate> {
+    let (item, every) = (*item, every.max(1));
+    // the mean and the sum of the squared deviations from it, updated with every simulation as Welford's algorithm does
+    let (mut mean, mut squares) = (0.0, 0.0);
+    (1..=simulations).filter_map(move |n| {
+        let x = payoff(&item, terminal(&item));
+        let delta = x - mean;
+        mean += delta / n as f64;
+        squares += delta * (x - mean);
+        if n % every != 0 && n != simulations {
+            return None;
+        }
+        let error = match n {
+            1 => 0.0,
+            _ => 1.96 * (squares / (n - 1) as f64 / n as f64).sqrt(),
+        };
+        Some(Estimate {
+            simulations: n,
+            value: mean,
+            lower: mean - error,
+            upper: mean + error,
+        })
+    })
+}
+
+// runs the simulations on as many threads as there are cores, each one running its share of them, and collects the results
+// as they arrive
+pub(crate) fn simulate<T, F>(simulations: usize, simulation: F) -> Result<Vec<T>, RecvError>
+where
+    T: Send + 'static,
+    F: Fn() -> T + Send + Sync + 'static,
+{
+    // an arc because the simulation is immutable between threads
+    let simulation = Arc::new(simulation);
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(simulations.max(1));
     let (tx, rx) = mpsc::channel();
-    for _ in 0..10000 {
-        let (values, tx) = (values.clone(), tx.clone());
+    for i in 0..threads {
+        let (simulation, tx) = (simulation.clone(), tx.clone());
+        // the first threads run one more when they don't divide evenly
+        let share = simulations / threads + usize::from(i < simulations % threads);
         thread::spawn(move || {
-            let data = values.underlying
-                * ((values.rfr - values.volatility.powi(2) / 2.0) * values.maturity as f64
-                    + values.volatility
-                        * (values.maturity as f64).sqrt()
-                        * Normal::standard().sample(&mut rand::thread_rng()))
-                .exp();
-            tx.send(data)
+            for _ in 0..share {
+                if tx.send(simulation()).is_err() {
+                    break;
+                }
+            }
         });
     }
-    let mut v: Vec<f64> = Vec::new();
+    let mut v = Vec::with_capacity(simulations);
     // receives the result of an iteration and propagates it
-    for _ in 0..10000 {
+    for _ in 0..simulations {
         v.push(rx.recv()?);
     }
-    // calculates the return for each iteration
-    let returns: Vec<f64> = v
-        .iter()
-        .map(|&x| match item.form {
-            OptionType::Call => match x <= item.strike {
-                true => 0.0,
-                false => (x - item.strike) / (1.0 + item.rfr).powi(item.maturity as i32),
-            },
-            OptionType::Put => match x >= item.strike {
-                true => 0.0,
-                false => (item.strike - x) / (1.0 + item.rfr).powi(item.maturity as i32),
-            },
-        })
-        .collect();
-    // computes the average
-    Ok(returns.iter().sum::<f64>() / returns.len() as f64)
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn the_rate_is_left_at_zero_by_the_providers_without_network() {
+        let option = Options::new(OptionType::Call, 100.0, 100.0, 1, 0.2);
+        for source in [Source::Fixtures, Source::Files] {
+            let offline = option.with_rfr_for(source).await.unwrap();
+            assert_eq!(offline.rfr, None);
+            assert_eq!(bs_price(&offline), bs_price(&option.with_rfr(0.0)));
+        }
+        // a rate that is given is never fetched
+        let given = option
+            .with_rfr(0.03)
+            .with_rfr_for(Source::Yahoo)
+            .await
+            .unwrap();
+        assert_eq!(given.rfr, Some(0.03));
+    }
+
+    #[test]
+    fn the_violations_name_every_field_out_of_range() {
+        assert!(Options::new(OptionType::Put, 100.0, 90.0, 1, 0.2)
+            .with_market_price(5.0)
+            .validate()
+            .is_ok());
+        let violations = Options::new(OptionType::Call, 0.0, -1.0, 0, f64::NAN)
+            .with_market_price(0.0)
+            .validate()
+            .unwrap_err();
+        let fields: Vec<_> = violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "underlying",
+                "strike",
+                "volatility",
+                "market_price",
+                "maturity"
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_every_simulation_once() {
+        for simulations in [0, 1, 7, 1000] {
+            let mut v = simulate(simulations, || 1_u32).unwrap();
+            assert_eq!(v.len(), simulations);
+            v.dedup();
+            assert!(v.len() <= 1);
+        }
+    }
 }
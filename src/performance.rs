@@ -0,0 +1,203 @@
+//! Sub-period returns
+//!
+//! Splits the holding period into days, months or years and measures the return within each of them with one of three methods:
+//! - TWR, the time-weighted return, chains the daily returns of the holdings so that the size and timing of the buys and sells
+//!   don't matter, as in every other endpoint
+//! - MWR, the money-weighted return, is the internal rate of return of the period, which rewards buying before a rise
+//! - [Modified Dietz](https://en.wikipedia.org/wiki/Modified_Dietz_method), the standard in performance reporting when exact
+//!   valuations at every flow are impractical, approximates the MWR by weighting every flow by the share of the period it was invested
+//!
+//! For the MWR and Modified Dietz, the portfolio is valued in USD at the closing price of every date, so dividends are left out,
+//! while the buys (inflows) and sells (outflows) are taken at their own price converted at the exchange rate of their date.
+//! Flows are assumed to happen at the start of their day. As the first period starts with nothing invested, its return is
+//! measured from the first buy. Periods with no capital invested have no return and are left out, as are those whose
+//! internal rate of return doesn't exist.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::performance::{sub_period_returns, Method};
+//! # use modus::stock_returns::{Aggregation, Portfolio};
+//! # async fn run(portfolio: Portfolio) {
+//!  if let Ok(s) = sub_period_returns(&portfolio, Method::ModifiedDietz, Aggregation::Monthly).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::stock_returns::{
+    find_dates, histories, total_returns, within_periods, Aggregation, Interval, Portfolio,
+    StocksError,
+};
+use crate::yahoo_finance::History;
+
+/// The method the return of every period is measured with
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Method {
+    #[default]
+    Twr,
+    Mwr,
+    ModifiedDietz,
+}
+
+// a period from the day after the end of the previous one to its last date
+struct Period {
+    start: NaiveDate,
+    end: NaiveDate,
+    opening: f64,
+    closing: f64,
+    // the flows by date, positive when invested
+    flows: Vec<(NaiveDate, f64)>,
+}
+
+impl Period {
+    fn days(&self) -> f64 {
+        ((self.end - self.start).num_days() + 1) as f64
+    }
+
+    // the flows along with the share of the period they were invested, the whole of it if made on the first day
+    fn weighted(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.flows.iter().map(|(date, flow)| {
+            (
+                *flow,
+                ((self.end - *date).num_days() + 1) as f64 / self.days(),
+            )
+        })
+    }
+
+    fn modified_dietz(&self) -> Option<f64> {
+        let net: f64 = self.flows.iter().map(|(_, f)| f).sum();
+        let invested = self.opening + self.weighted().map(|(f, w)| f * w).sum::<f64>();
+        (invested > 0.0).then(|| (self.closing - self.opening - net) / invested)
+    }
+
+    // the rate that grows the opening value and every flow into the closing value, by bisection
+    fn money_weighted(&self) -> Option<f64> {
+        let excess = |r: f64| {
+            self.opening * (1.0 + r)
+                + self
+                    .weighted()
+                    .map(|(f, w)| f * (1.0 + r).powf(w))
+                    .sum::<f64>()
+                - self.closing
+        };
+        let (mut low, mut high) = (-0.999_999, 10.0);
+        if excess(low).signum() == excess(high).signum() {
+            return None;
+        }
+        for _ in 0..200 {
+            let mid = (low + high) / 2.0;
+            if excess(mid).signum() == excess(low).signum() {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Some((low + high) / 2.0)
+    }
+}
+
+/// Returns the return in percentage within every period, keyed by the period (YYYY-MM-DD, YYYY-MM or YYYY)
+pub async fn sub_period_returns(
+    item: &Portfolio,
+    method: Method,
+    aggregation: Aggregation,
+) -> Result<BTreeMap<String, f64>, StocksError> {
+    if let Method::Twr = method {
+        let cumulative = total_returns(item, Interval::Daily).await?;
+        return Ok(within_periods(&cumulative, aggregation));
+    }
+    let histories = histories(&item.data_provider()?, item, Interval::Daily).await?;
+    Ok(periods(item, &histories, aggregation)
+        .into_iter()
+        .filter_map(|(period, p)| {
+            match method {
+                Method::ModifiedDietz => p.modified_dietz(),
+                _ => p.money_weighted(),
+            }
+            .map(|r| (period, r * 100.0))
+        })
+        .collect())
+}
+
+// the value of the portfolio at the end of every period along with the flows within it
+fn periods(
+    item: &Portfolio,
+    histories: &BTreeMap<&str, History>,
+    aggregation: Aggregation,
+) -> BTreeMap<String, Period> {
+    let mut flows = flows(item, histories);
+    let mut periods: BTreeMap<String, Period> = BTreeMap::new();
+    // the first period starts at the first buy
+    let (mut start, mut opening) = match flows.first() {
+        Some((date, _)) => (*date, 0.0),
+        None => return periods,
+    };
+    let dates: Vec<NaiveDate> = find_dates(histories)
+        .into_iter()
+        .filter(|d| *d >= start)
+        .collect();
+    for (i, date) in dates.iter().enumerate() {
+        let period = aggregation.period(&date.to_string()).to_string();
+        // the last date of the period closes it
+        let closes = dates
+            .get(i + 1)
+            .is_none_or(|next| aggregation.period(&next.to_string()) != period);
+        if !closes {
+            continue;
+        }
+        let (within, later) = flows.into_iter().partition(|(d, _)| d <= date);
+        flows = later;
+        let closing = value(item, histories, *date);
+        periods.insert(
+            period,
+            Period {
+                start,
+                end: *date,
+                opening,
+                closing,
+                flows: within,
+            },
+        );
+        start = *date + Duration::days(1);
+        opening = closing;
+    }
+    periods
+}
+
+// every buy (positive) and sell (negative) in USD by date, the ones after the last quote being left out
+fn flows(item: &Portfolio, histories: &BTreeMap<&str, History>) -> Vec<(NaiveDate, f64)> {
+    let mut flows: Vec<(NaiveDate, f64)> = Vec::new();
+    for n in item.lots() {
+        let history = &histories[n.ticker()];
+        let quantity = n.quantity();
+        if let Some(date) = n.buy_date() {
+            flows.push((date, n.buy_price() * quantity * history.rate_on(date)));
+        }
+        if let (Some(date), Some(price)) = (n.sell_date(), n.sell_price()) {
+            flows.push((date, -price * quantity * history.rate_on(date)));
+        }
+    }
+    flows.sort_by_key(|(date, _)| *date);
+    flows
+}
+
+// the value in USD at the close of the date of every position held then, those sold that day excluded
+fn value(item: &Portfolio, histories: &BTreeMap<&str, History>, date: NaiveDate) -> f64 {
+    item.lots()
+        .iter()
+        .filter(|n| {
+            n.buy_date().is_some_and(|buy| buy <= date)
+                && n.sell_date().is_none_or(|sell| sell > date)
+        })
+        .map(|n| {
+            let history = &histories[n.ticker()];
+            // bought before its first quote, e.g., on a weekend
+            let close = history.close_at(date).unwrap_or(n.buy_price());
+            close * n.quantity() * history.rate_on(date)
+        })
+        .sum()
+}
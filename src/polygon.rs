@@ -0,0 +1,203 @@
+use serde::Deserialize;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::http::{client, send};
+use crate::provider::{within, DataProvider, Metadata};
+use crate::yahoo_finance::{Interval, ProviderError, Quote};
+
+#[derive(Error, Debug)]
+pub enum PolygonError {
+    #[error("deserializing the response from Polygon.io failed: {0}")]
+    DeserializeFailed(#[from] serde_json::Error),
+    #[error("Polygon.io refused the request: {0}")]
+    Refused(String),
+}
+
+/// Polygon.io, which needs an API key, read from MODUS_POLYGON_KEY by from_env
+///
+/// The aggregates are only adjusted for splits, so the adjusted close is the close and the returns leave the dividends out.
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    key: String,
+}
+
+// a page of aggregates, the next one being at next_url
+#[derive(Deserialize, Debug)]
+struct Aggregates {
+    #[serde(default)]
+    results: Vec<Bar>,
+    next_url: Option<String>,
+    error: Option<String>,
+    // the reason when the plan doesn't cover the request
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Bar {
+    o: f64,
+    h: f64,
+    l: f64,
+    c: f64,
+    #[serde(default)]
+    v: f64,
+    // the start of the bar in milliseconds
+    t: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Reference {
+    results: Option<Ticker>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Ticker {
+    ticker: String,
+    currency_name: Option<String>,
+}
+
+impl Polygon {
+    pub fn new(key: impl Into<String>) -> Polygon {
+        Polygon { key: key.into() }
+    }
+
+    /// Returns the provider with the key in MODUS_POLYGON_KEY, None if it isn't set
+    pub fn from_env() -> Option<Polygon> {
+        std::env::var("MODUS_POLYGON_KEY").ok().map(Polygon::new)
+    }
+
+    // the key goes in the header so that it doesn't have to be added to the next pages
+    async fn get<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, ProviderError> {
+        let response = send(client()?.get(url).bearer_auth(&self.key))
+            .await?
+            .text()
+            .await?;
+        Ok(serde_json::from_str(&response).map_err(PolygonError::from)?)
+    }
+
+    // every page of the aggregates of the ticker in the range
+    async fn aggregates(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let (multiplier, timespan) = match interval {
+            Interval::Daily => (1, "day"),
+            Interval::Weekly => (1, "week"),
+            Interval::Monthly => (1, "month"),
+            Interval::Minute => (1, "minute"),
+            Interval::FiveMinutes => (5, "minute"),
+            Interval::Hourly => (1, "hour"),
+        };
+        let mut url = Some(format!(
+            "https://api.polygon.io/v2/aggs/ticker/{ticker}/range/{multiplier}/{timespan}/{}/{}?adjusted=true&sort=asc&limit=50000",
+            start.date(),
+            end.date()
+        ));
+        let mut quotes = Vec::new();
+        while let Some(page) = url {
+            let (page, next) = bars(self.get(&page).await?)?;
+            quotes.extend(page);
+            url = next;
+        }
+        Ok(within(quotes, start, end))
+    }
+}
+
+// the quotes of a page, already adjusted for the splits and dividends, along with the URL of the next one
+fn bars(aggregates: Aggregates) -> Result<(Vec<Quote>, Option<String>), PolygonError> {
+    if let Some(error) = aggregates.error.or(aggregates.message) {
+        return Err(PolygonError::Refused(error));
+    }
+    let quotes = aggregates
+        .results
+        .into_iter()
+        .map(|bar| Quote {
+            timestamp: bar.t / 1000,
+            open: bar.o,
+            high: bar.h,
+            low: bar.l,
+            volume: bar.v as u64,
+            close: bar.c,
+            adjclose: bar.c,
+            ..Default::default()
+        })
+        .collect();
+    Ok((quotes, aggregates.next_url))
+}
+
+impl DataProvider for Polygon {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        self.aggregates(ticker, start, end, interval)
+            .await
+            .map_err(|e| e.context(ticker, start, end))
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        let reference: Reference = self
+            .get(&format!(
+                "https://api.polygon.io/v3/reference/tickers/{ticker}"
+            ))
+            .await?;
+        match reference.results {
+            Some(t) => Ok(Metadata {
+                symbol: t.ticker,
+                // the reference data has no currency for some tickers, which trade in USD
+                currency: t
+                    .currency_name
+                    .map_or("USD".to_string(), |c| c.to_uppercase()),
+            }),
+            None => Err(PolygonError::Refused(reference.error.unwrap_or_default()).into()),
+        }
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let pair = format!("C:{currency}USD");
+        self.aggregates(&pair, start, end, interval)
+            .await
+            .map_err(|e| e.context(&pair, start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_bars_of_a_page_and_the_next_one() {
+        let page = r#"{"ticker": "AAPL", "status": "OK", "results": [
+            {"v": 70790813, "o": 182.35, "c": 185.64, "h": 188.44, "l": 183.89, "t": 1704171600000, "n": 1008871},
+            {"o": 184.22, "c": 184.25, "h": 185.88, "l": 183.43, "t": 1704258000000}
+        ], "next_url": "https://api.polygon.io/v2/aggs/ticker/AAPL/range/1/day/1704344400000/2024-01-31"}"#;
+        let (quotes, next) = bars(serde_json::from_str(page).unwrap()).unwrap();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].timestamp, 1704171600);
+        assert_eq!((quotes[0].close, quotes[0].adjclose), (185.64, 185.64));
+        assert_eq!((quotes[0].volume, quotes[1].volume), (70790813, 0));
+        assert!(next.unwrap().contains("1704344400000"));
+    }
+
+    #[test]
+    fn a_page_with_an_error_is_refused() {
+        let page = r#"{"status": "NOT_AUTHORIZED", "message": "Your plan doesn't include this data timeframe."}"#;
+        let refused = bars(serde_json::from_str(page).unwrap());
+        assert!(matches!(refused, Err(PolygonError::Refused(reason)) if reason.contains("plan")));
+        let last: Aggregates = serde_json::from_str(r#"{"status": "OK", "results": []}"#).unwrap();
+        assert!(matches!(bars(last), Ok((quotes, None)) if quotes.is_empty()));
+    }
+}
@@ -0,0 +1,514 @@
+//! Mean-variance optimization
+//!
+//! Finds the allocations of a set of tickers that minimize the risk for a given expected return, that is, the
+//! [efficient frontier](https://en.wikipedia.org/wiki/Efficient_frontier) of [Markowitz (1952)](https://doi.org/10.2307/2975974),
+//! along with the minimum-variance and the tangency (maximum Sharpe ratio) portfolios.
+//!
+//! The expected returns and the covariance matrix are estimated from the daily returns over the last years of history (5 by default),
+//! using only the dates every ticker has in common.
+//! The weights have a closed-form solution because there are no constraints other than being fully invested, which means that
+//! short positions, negative weights, are allowed.
+//!
+//! # Black-Litterman
+//! Alternatively, the [Black-Litterman model](https://en.wikipedia.org/wiki/Black%E2%80%93Litterman_model) blends the returns implied by the
+//! market weights (equal weights unless told otherwise) with the views of the user, which are expected returns for some tickers along with
+//! how confident one is in them, from 0 to 1. The confidence scales the uncertainty of the views as proposed by [Idzorek (2005)](https://doi.org/10.1016/B978-075068321-0.50003-0).
+//! The resulting posterior expected returns are then used to find the tangency portfolio.
+//!
+//! # Risk parity
+//! As an alternative to mean-variance, the [risk parity](https://en.wikipedia.org/wiki/Risk_parity) allocation ignores the expected returns
+//! and looks for the long-only weights that make the contribution of every ticker to the variance of the portfolio the same.
+//! It is solved by cyclical coordinate descent as in [Griveau-Billion et al. (2013)](https://arxiv.org/abs/1311.4057).
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::portfolio_optimization::{efficient_frontier, Universe};
+//! # use serde_json::json;
+//! # async fn run() -> serde_json::Result<()> {
+//!  let universe: Universe = serde_json::from_value(json!({"tickers": ["MSFT", "KO"], "years": 5, "rfr": 0.03, "points": 20}))?;
+//!  if let Ok(Some(s)) = efficient_frontier(&universe).await { println!("{:?}", s); }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+
+use crate::holdings::ticker_returns;
+use crate::provider::{get_ticker_quotes, Interval, Source};
+use crate::statistics::{covariance, dot, mean, solve, PERIODS_PER_YEAR};
+use crate::stock_returns::{data_provider, validate_weights, StocksError, Violation};
+
+/// Holds the tickers to allocate among and the history used to estimate their returns and risk
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Universe {
+    tickers: Vec<String>,
+    #[serde(default = "default_years")]
+    years: u32,
+    // annual risk-free rate for the tangency portfolio
+    #[serde(default)]
+    rfr: f64,
+    // number of portfolios along the frontier
+    #[serde(default = "default_points")]
+    points: usize,
+    // the provider the quotes are fetched from, the one in MODUS_PROVIDER by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<Source>,
+}
+
+// the most portfolios along the frontier
+const MAX_POINTS: usize = 1000;
+
+impl Universe {
+    /// Checks the tickers, the years of history, the risk-free rate and the points of the frontier, returning all the
+    /// violations found
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        self.check(&mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn check(&self, violations: &mut Vec<Violation>) {
+        if self.tickers.is_empty() {
+            violations.push(Violation::new("tickers", "must have at least one ticker"));
+        }
+        for (i, ticker) in self.tickers.iter().enumerate() {
+            if ticker.trim().is_empty() {
+                violations.push(Violation::new(format!("tickers[{i}]"), "must not be empty"));
+            } else if self.tickers[..i].contains(ticker) {
+                violations.push(Violation::new(
+                    format!("tickers[{i}]"),
+                    "must not be repeated",
+                ));
+            }
+        }
+        if self.years == 0 {
+            violations.push(Violation::new("years", "must be positive"));
+        }
+        if !self.rfr.is_finite() {
+            violations.push(Violation::new("rfr", "must be a number"));
+        }
+        if !(1..=MAX_POINTS).contains(&self.points) {
+            violations.push(Violation::new(
+                "points",
+                format!("must be between 1 and {MAX_POINTS}"),
+            ));
+        }
+    }
+}
+
+fn default_years() -> u32 {
+    5
+}
+
+fn default_points() -> usize {
+    20
+}
+
+/// Holds the universe along with the market weights and the views of the user for the Black-Litterman model
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Views {
+    #[serde(flatten)]
+    universe: Universe,
+    // equal weights when missing
+    #[serde(default)]
+    market_weights: Option<BTreeMap<String, f64>>,
+    views: Vec<View>,
+    #[serde(default = "default_risk_aversion")]
+    risk_aversion: f64,
+    // scales the uncertainty of the prior
+    #[serde(default = "default_tau")]
+    tau: f64,
+}
+
+/// Holds an annual expected return for a ticker and the confidence in it, from 0 to 1
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct View {
+    ticker: String,
+    expected_return: f64,
+    confidence: f64,
+}
+
+impl Views {
+    /// Checks the universe, the market weights, the views, the risk aversion and tau, returning all the violations found
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        self.universe.check(&mut violations);
+        if let Some(weights) = &self.market_weights {
+            validate_weights("market_weights", weights, &mut violations);
+        }
+        for (i, view) in self.views.iter().enumerate() {
+            if !view.expected_return.is_finite() {
+                violations.push(Violation::new(
+                    format!("views[{i}].expected_return"),
+                    "must be a number",
+                ));
+            }
+            if !(0.0..=1.0).contains(&view.confidence) {
+                violations.push(Violation::new(
+                    format!("views[{i}].confidence"),
+                    "must be from 0 to 1",
+                ));
+            }
+        }
+        for (field, value) in [("risk_aversion", self.risk_aversion), ("tau", self.tau)] {
+            if !value.is_finite() || value <= 0.0 {
+                violations.push(Violation::new(field, "must be positive"));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+fn default_risk_aversion() -> f64 {
+    2.5
+}
+
+fn default_tau() -> f64 {
+    0.05
+}
+
+/// Holds the weights of an allocation and its annualized expected return, volatility and Sharpe ratio, in percentage
+#[derive(Debug, Serialize)]
+pub struct Allocation {
+    pub weights: BTreeMap<String, f64>,
+    pub expected_return: f64,
+    pub volatility: f64,
+    pub sharpe: f64,
+}
+
+/// Holds the minimum-variance and tangency portfolios and the candidate allocations along the efficient frontier
+#[derive(Debug, Serialize)]
+pub struct EfficientFrontier {
+    pub min_variance: Allocation,
+    pub tangency: Allocation,
+    pub frontier: Vec<Allocation>,
+}
+
+/// Holds the market-implied and the posterior annual expected returns in percentage and the allocation that results from the latter
+#[derive(Debug, Serialize)]
+pub struct BlackLitterman {
+    pub prior: BTreeMap<String, f64>,
+    pub posterior: BTreeMap<String, f64>,
+    pub allocation: Allocation,
+}
+
+// the annualized expected returns and covariance matrix of a set of tickers, in the same order as the tickers
+pub(crate) struct Estimates {
+    pub(crate) tickers: Vec<String>,
+    pub(crate) returns: Vec<f64>,
+    pub(crate) covariance: Vec<Vec<f64>>,
+}
+
+/// Returns the efficient frontier of the tickers, None if their covariance matrix is singular
+pub async fn efficient_frontier(item: &Universe) -> Result<Option<EfficientFrontier>, StocksError> {
+    let estimates = estimate(item.provider, &item.tickers, item.years).await?;
+    Ok(frontier(&estimates, item.rfr, item.points))
+}
+
+/// Returns the posterior expected returns and the tangency portfolio of the Black-Litterman model, None if the covariance matrix is singular
+pub async fn black_litterman(item: &Views) -> Result<Option<BlackLitterman>, StocksError> {
+    let universe = &item.universe;
+    let estimates = estimate(universe.provider, &universe.tickers, universe.years).await?;
+    Ok(posterior(&estimates, item))
+}
+
+/// Returns the allocation in which every ticker contributes the same to the variance of the portfolio
+pub async fn risk_parity(item: &Universe) -> Result<Allocation, StocksError> {
+    let estimates = estimate(item.provider, &item.tickers, item.years).await?;
+    Ok(allocation(
+        &estimates,
+        &equal_risk(&estimates.covariance),
+        item.rfr,
+    ))
+}
+
+// estimates the annualized expected returns and covariance from the daily returns over the last years, the tickers being
+// fetched concurrently from the provider
+pub(crate) async fn estimate(
+    provider: Option<Source>,
+    tickers: &[String],
+    years: u32,
+) -> Result<Estimates, StocksError> {
+    let end = OffsetDateTime::now_utc();
+    let start = end - Duration::days(365 * years as i64);
+    let quotes = get_ticker_quotes(
+        &data_provider(provider)?,
+        tickers.iter().map(String::as_str),
+        &start,
+        &end,
+        Interval::Daily,
+    )
+    .await?;
+    let returns = ticker_returns(&quotes);
+    // only the dates every ticker has in common, so that the covariance matrix is consistent
+    let dates: Vec<_> = returns
+        .values()
+        .next()
+        .map(|r| {
+            r.keys()
+                .filter(|date| returns.values().all(|other| other.contains_key(date)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let series: Vec<Vec<f64>> = returns
+        .values()
+        .map(|r| dates.iter().map(|date| r[*date]).collect())
+        .collect();
+    Ok(Estimates {
+        tickers: returns.keys().cloned().collect(),
+        returns: series.iter().map(|s| mean(s) * PERIODS_PER_YEAR).collect(),
+        covariance: series
+            .iter()
+            .map(|x| {
+                series
+                    .iter()
+                    .map(|y| covariance(x, y) * PERIODS_PER_YEAR)
+                    .collect()
+            })
+            .collect(),
+    })
+}
+
+fn frontier(estimates: &Estimates, rfr: f64, points: usize) -> Option<EfficientFrontier> {
+    let n = estimates.tickers.len();
+    // Σ⁻¹·1 and Σ⁻¹·μ span the whole frontier
+    let inverse_ones = solve(&estimates.covariance, &vec![1.0; n])?;
+    let inverse_returns = solve(&estimates.covariance, &estimates.returns)?;
+    let a: f64 = inverse_ones.iter().sum();
+    let b: f64 = inverse_returns.iter().sum();
+    let c: f64 = dot(&estimates.returns, &inverse_returns);
+    let d = a * c - b * b;
+    let min_variance: Vec<f64> = inverse_ones.iter().map(|x| x / a).collect();
+    // from the minimum-variance return up to the highest expected return of any ticker
+    let lowest = b / a;
+    let highest = estimates
+        .returns
+        .iter()
+        .fold(lowest, |acc: f64, r| acc.max(*r));
+    let frontier = (0..points)
+        .map(|i| {
+            let target = lowest + (highest - lowest) * i as f64 / (points.max(2) - 1) as f64;
+            let (lambda, gamma) = ((c - target * b) / d, (target * a - b) / d);
+            let weights: Vec<f64> = inverse_ones
+                .iter()
+                .zip(inverse_returns.iter())
+                .map(|(o, r)| lambda * o + gamma * r)
+                .collect();
+            allocation(estimates, &weights, rfr)
+        })
+        .collect();
+    Some(EfficientFrontier {
+        min_variance: allocation(estimates, &min_variance, rfr),
+        tangency: allocation(estimates, &tangency(estimates, rfr)?, rfr),
+        frontier,
+    })
+}
+
+// Σ⁻¹·(μ - rf), normalized to be fully invested
+fn tangency(estimates: &Estimates, rfr: f64) -> Option<Vec<f64>> {
+    let excess: Vec<f64> = estimates.returns.iter().map(|r| r - rfr).collect();
+    let weights = solve(&estimates.covariance, &excess)?;
+    let total: f64 = weights.iter().sum();
+    Some(weights.iter().map(|w| w / total).collect())
+}
+
+fn posterior(estimates: &Estimates, item: &Views) -> Option<BlackLitterman> {
+    let n = estimates.tickers.len();
+    let market: Vec<f64> = estimates
+        .tickers
+        .iter()
+        .map(|t| match &item.market_weights {
+            Some(weights) => weights.get(t).copied().unwrap_or(0.0),
+            None => 1.0 / n as f64,
+        })
+        .collect();
+    // the returns implied by the market weights, Π = δ·Σ·w
+    let prior: Vec<f64> = estimates
+        .covariance
+        .iter()
+        .map(|row| item.risk_aversion * dot(row, &market))
+        .collect();
+    // views on tickers that aren't in the universe are ignored
+    let views: Vec<(usize, &View)> = item
+        .views
+        .iter()
+        .filter_map(|v| {
+            estimates
+                .tickers
+                .iter()
+                .position(|t| *t == v.ticker)
+                .map(|i| (i, v))
+        })
+        .collect();
+    let scaled = |i: usize, j: usize| item.tau * estimates.covariance[i][j];
+    // P·τΣ·Pᵀ + Ω, where the uncertainty of each view grows as its confidence falls
+    let m: Vec<Vec<f64>> = views
+        .iter()
+        .map(|(i, v)| {
+            views
+                .iter()
+                .map(|(j, _)| {
+                    let confidence = v.confidence.clamp(1e-6, 1.0);
+                    scaled(*i, *j)
+                        + if i == j {
+                            scaled(*i, *i) * (1.0 - confidence) / confidence
+                        } else {
+                            0.0
+                        }
+                })
+                .collect()
+        })
+        .collect();
+    let surprise: Vec<f64> = views
+        .iter()
+        .map(|(i, v)| v.expected_return - prior[*i])
+        .collect();
+    let x = solve(&m, &surprise)?;
+    // μ = Π + τΣ·Pᵀ·(P·τΣ·Pᵀ + Ω)⁻¹·(Q - P·Π)
+    let returns: Vec<f64> = (0..n)
+        .map(|k| {
+            prior[k]
+                + views
+                    .iter()
+                    .zip(x.iter())
+                    .map(|((i, _), xi)| scaled(k, *i) * xi)
+                    .sum::<f64>()
+        })
+        .collect();
+    let posterior = Estimates {
+        tickers: estimates.tickers.clone(),
+        returns,
+        covariance: estimates.covariance.clone(),
+    };
+    let percentage = |values: &[f64]| -> BTreeMap<String, f64> {
+        estimates
+            .tickers
+            .iter()
+            .cloned()
+            .zip(values.iter().map(|r| r * 100.0))
+            .collect()
+    };
+    Some(BlackLitterman {
+        prior: percentage(&prior),
+        posterior: percentage(&posterior.returns),
+        allocation: allocation(
+            &posterior,
+            &tangency(&posterior, item.universe.rfr)?,
+            item.universe.rfr,
+        ),
+    })
+}
+
+pub(crate) fn allocation(estimates: &Estimates, weights: &[f64], rfr: f64) -> Allocation {
+    let expected_return = dot(weights, &estimates.returns);
+    let volatility = estimates
+        .covariance
+        .iter()
+        .zip(weights.iter())
+        .map(|(row, w)| w * dot(row, weights))
+        .sum::<f64>()
+        .sqrt();
+    Allocation {
+        weights: estimates
+            .tickers
+            .iter()
+            .cloned()
+            .zip(weights.iter().map(|w| w * 100.0))
+            .collect(),
+        expected_return: expected_return * 100.0,
+        volatility: volatility * 100.0,
+        sharpe: (expected_return - rfr) / volatility,
+    }
+}
+
+// minimizes ½·yᵀΣy - Σ ln(yᵢ)/n one coordinate at a time, the normalized minimum being the risk parity weights
+fn equal_risk(covariance: &[Vec<f64>]) -> Vec<f64> {
+    let n = covariance.len();
+    let budget = 1.0 / n as f64;
+    let mut y: Vec<f64> = covariance
+        .iter()
+        .enumerate()
+        .map(|(i, row)| 1.0 / row[i].sqrt())
+        .collect();
+    for _ in 0..10000 {
+        let previous = y.clone();
+        for i in 0..n {
+            let sigma = covariance[i][i];
+            // the covariance of the ticker with the rest of the portfolio
+            let c = dot(&covariance[i], &y) - sigma * y[i];
+            y[i] = (-c + (c * c + 4.0 * sigma * budget).sqrt()) / (2.0 * sigma);
+        }
+        let change = y
+            .iter()
+            .zip(previous.iter())
+            .fold(0.0, |acc: f64, (a, b)| acc.max((a - b).abs()));
+        if change < 1e-12 {
+            break;
+        }
+    }
+    let total: f64 = y.iter().sum();
+    y.iter().map(|w| w / total).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn fields(violations: Result<(), Vec<Violation>>) -> Vec<String> {
+        violations
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| v.field)
+            .collect()
+    }
+
+    fn views(body: Value) -> Views {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn rejects_repeated_tickers_and_frontiers_without_points() {
+        let item: Universe =
+            serde_json::from_value(json!({"tickers": ["MSFT", "KO", "MSFT"], "points": 0}))
+                .unwrap();
+        assert_eq!(fields(item.validate()), vec!["tickers[2]", "points"]);
+    }
+
+    #[test]
+    fn rejects_confidences_outside_zero_to_one_and_negative_market_weights() {
+        let item = views(json!({
+            "tickers": ["MSFT", "KO"],
+            "market_weights": {"MSFT": 1.0, "KO": -0.5},
+            "views": [{"ticker": "MSFT", "expected_return": 0.1, "confidence": 1.5}],
+            "tau": 0.0
+        }));
+        assert_eq!(
+            fields(item.validate()),
+            vec!["market_weights.KO", "views[0].confidence", "tau"]
+        );
+    }
+
+    #[test]
+    fn accepts_views_on_tickers_outside_the_universe() {
+        let item = views(json!({
+            "tickers": ["MSFT", "KO"],
+            "views": [{"ticker": "AAPL", "expected_return": 0.1, "confidence": 0.0}]
+        }));
+        assert!(item.validate().is_ok());
+    }
+}
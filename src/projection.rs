@@ -0,0 +1,248 @@
+//! Portfolio projection
+//!
+//! Projects the value of a portfolio, given as weights of tickers, over a horizon of trading days by Monte-Carlo simulation,
+//! using the same engine as the option valuation.
+//! Every simulated day the tickers follow a geometric Brownian motion with correlated shocks, the drift and covariance
+//! being estimated from the daily returns over the last years of history (5 by default) unless they are overridden,
+//! in which case they are annual figures.
+//! The positions are bought at the beginning and held until the end.
+//!
+//! The result is a fan chart: the value of the portfolio at every day for the 5th, 25th, 50th, 75th and 95th percentiles.
+//! The horizon is at most 10 years of trading days and there are at most 10000 simulations, which run on as many threads
+//! as there are cores, away from the tasks serving the requests.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::projection::{project, Projection};
+//! # use serde_json::json;
+//! # async fn run() -> serde_json::Result<()> {
+//!  let projection: Projection = serde_json::from_value(json!({"weights": {"MSFT": 0.6, "KO": 0.4}, "value": 10000.0,
+//!     "horizon": 252, "years": 5, "simulations": 1000}))?;
+//!  if let Ok(s) = project(&projection).await { println!("{:?}", s); }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::RecvError;
+
+use actix_web::rt::task::{spawn_blocking, JoinError};
+use modus_derive::From;
+use rstat::univariate::normal::Normal;
+use rstat::Distribution;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::holdings::Matrix;
+use crate::options::simulate;
+use crate::portfolio_optimization::estimate;
+use crate::provider::Source;
+use crate::statistics::{cholesky, PERIODS_PER_YEAR};
+use crate::stock_returns::{validate_weights, StocksError, Violation};
+
+const PERCENTILES: [f64; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+
+// the longest horizon, 10 years of trading days, and the most simulations
+const MAX_HORIZON: usize = 2520;
+const MAX_SIMULATIONS: usize = 10000;
+
+/// Holds the weights of the portfolio, its initial value, the horizon in trading days and optionally overrides of the annual
+/// expected returns and covariance matrix
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Projection {
+    weights: BTreeMap<String, f64>,
+    #[serde(default = "default_value")]
+    value: f64,
+    #[serde(default = "default_horizon")]
+    horizon: usize,
+    #[serde(default = "default_years")]
+    years: u32,
+    #[serde(default = "default_simulations")]
+    simulations: usize,
+    #[serde(default)]
+    expected_returns: Option<BTreeMap<String, f64>>,
+    #[serde(default)]
+    #[schema(value_type = Option<BTreeMap<String, BTreeMap<String, f64>>>)]
+    covariance: Option<Matrix>,
+    // the provider the quotes are fetched from, the one in MODUS_PROVIDER by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<Source>,
+}
+
+impl Projection {
+    /// Checks the weights, the value, the horizon and the number of simulations, returning all the violations found
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        validate_weights("weights", &self.weights, &mut violations);
+        if !self.value.is_finite() || self.value <= 0.0 {
+            violations.push(Violation::new("value", "must be positive"));
+        }
+        if !(1..=MAX_HORIZON).contains(&self.horizon) {
+            violations.push(Violation::new(
+                "horizon",
+                format!("must be between 1 and {MAX_HORIZON} trading days"),
+            ));
+        }
+        if self.years == 0 {
+            violations.push(Violation::new("years", "must be positive"));
+        }
+        if !(1..=MAX_SIMULATIONS).contains(&self.simulations) {
+            violations.push(Violation::new(
+                "simulations",
+                format!("must be between 1 and {MAX_SIMULATIONS}"),
+            ));
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+fn default_value() -> f64 {
+    1.0
+}
+
+fn default_horizon() -> usize {
+    252
+}
+
+fn default_years() -> u32 {
+    5
+}
+
+fn default_simulations() -> usize {
+    1000
+}
+
+/// Holds the percentiles and the value of the portfolio at each of them for every day of the horizon
+#[derive(Debug, Serialize)]
+pub struct FanChart {
+    pub percentiles: Vec<f64>,
+    pub values: BTreeMap<usize, Vec<f64>>,
+}
+
+/// States the reason the projection couldn't be completed
+#[derive(From)]
+pub enum ProjectionError {
+    StocksError(StocksError),
+    RecvError,
+    JoinError,
+}
+
+/// Projects the value of the portfolio over the horizon
+pub async fn project(item: &Projection) -> Result<FanChart, ProjectionError> {
+    let tickers: Vec<String> = item.weights.keys().cloned().collect();
+    let mut estimates = estimate(item.provider, &tickers, item.years).await?;
+    // the overrides replace the historical estimates of the tickers they include
+    for (i, a) in estimates.tickers.iter().enumerate() {
+        if let Some(r) = item.expected_returns.as_ref().and_then(|r| r.get(a)) {
+            estimates.returns[i] = *r;
+        }
+        for (j, b) in estimates.tickers.iter().enumerate() {
+            if let Some(c) = item.covariance.as_ref().and_then(|c| c.get(a)?.get(b)) {
+                estimates.covariance[i][j] = *c;
+            }
+        }
+    }
+    let total: f64 = item.weights.values().sum();
+    let weights: Vec<f64> = estimates
+        .tickers
+        .iter()
+        .map(|t| item.weights.get(t).unwrap_or(&0.0) / total * item.value)
+        .collect();
+    // daily figures, the drift already corrected by the variance since the prices are log-normal
+    let daily = |x: f64| x / PERIODS_PER_YEAR;
+    let drift: Vec<f64> = estimates
+        .returns
+        .iter()
+        .enumerate()
+        .map(|(i, r)| daily(*r) - daily(estimates.covariance[i][i]) / 2.0)
+        .collect();
+    let l: Vec<Vec<f64>> = cholesky(&estimates.covariance)
+        .iter()
+        .map(|row| row.iter().map(|x| x / PERIODS_PER_YEAR.sqrt()).collect())
+        .collect();
+    let (horizon, simulations) = (item.horizon, item.simulations);
+    // the simulations block, so they run on the threads for blocking work rather than on those serving the requests
+    let paths = spawn_blocking(move || {
+        simulate(simulations, move || {
+            let mut positions = weights.clone();
+            let mut path = Vec::with_capacity(horizon + 1);
+            path.push(positions.iter().sum::<f64>());
+            for _ in 0..horizon {
+                let shocks: Vec<f64> = (0..positions.len())
+                    .map(|_| Normal::standard().sample(&mut rand::thread_rng()))
+                    .collect();
+                for (i, position) in positions.iter_mut().enumerate() {
+                    let shock: f64 = l[i].iter().zip(shocks.iter()).map(|(a, z)| a * z).sum();
+                    *position *= (drift[i] + shock).exp();
+                }
+                path.push(positions.iter().sum::<f64>());
+            }
+            path
+        })
+    })
+    .await??;
+    Ok(FanChart {
+        percentiles: PERCENTILES.to_vec(),
+        values: (0..=horizon)
+            .map(|day| {
+                let mut values: Vec<f64> = paths.iter().map(|p| p[day]).collect();
+                values.sort_by(|a, b| a.total_cmp(b));
+                (
+                    day,
+                    PERCENTILES
+                        .iter()
+                        .map(|p| {
+                            values
+                                [((p / 100.0 * values.len() as f64) as usize).min(values.len() - 1)]
+                        })
+                        .collect(),
+                )
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fields(body: serde_json::Value) -> Vec<String> {
+        let item: Projection = serde_json::from_value(body).unwrap();
+        match item.validate() {
+            Ok(()) => Vec::new(),
+            Err(violations) => violations.into_iter().map(|v| v.field).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        assert!(fields(json!({"weights": {"MSFT": 0.6, "KO": 0.4}})).is_empty());
+    }
+
+    #[test]
+    fn rejects_the_horizons_and_simulations_out_of_bounds() {
+        let body = json!({"weights": {"MSFT": 1.0}, "horizon": 0, "simulations": 10001});
+        assert_eq!(fields(body), vec!["horizon", "simulations"]);
+        let body = json!({"weights": {"MSFT": 1.0}, "horizon": 2521, "simulations": 0});
+        assert_eq!(fields(body), vec!["horizon", "simulations"]);
+    }
+
+    #[test]
+    fn rejects_the_empty_negative_and_zero_weights() {
+        assert_eq!(fields(json!({"weights": {}})), vec!["weights"]);
+        assert_eq!(
+            fields(json!({"weights": {"MSFT": -1.0, "KO": 2.0}})),
+            vec!["weights.MSFT"]
+        );
+        assert_eq!(
+            fields(json!({"weights": {"MSFT": 0.0, "KO": 0.0}})),
+            vec!["weights"]
+        );
+    }
+}
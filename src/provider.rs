@@ -0,0 +1,552 @@
+//! Market data providers
+//!
+//! Every figure the library computes comes from the quotes of the tickers, which a DataProvider fetches. Yahoo! Finance is the
+//! one used by default, and any other source, e.g., another API or quotes kept on disk for offline testing, can be plugged in
+//! by implementing the trait: the returns are computed the same way whatever the provider.
+//!
+//! A provider only has to return the quotes of a ticker in its own currency, the currency it trades in and the exchange rates
+//! of that currency, which are applied to the adjusted close of the quotes to have them in USD. Providers that can fetch
+//! the quotes along with their currency, as Yahoo does, can override get_local_quotes to save the extra request, and the
+//! rates of every currency are only fetched once for all the tickers of a portfolio.
+//!
+//! The provider can also be chosen by name, as the server does: MODUS_PROVIDER sets the default one (yahoo, alpha-vantage, polygon,
+//! stooq, tiingo, coingecko, files or fixtures) and every portfolio can pick another one in its provider field, the API keys being read from the environment.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::provider::{Interval, Yahoo};
+//! # use modus::stock_returns::{total_returns_with, Currency, Portfolio};
+//! # async fn run(portfolio: Portfolio) {
+//!  if let Ok(s) = total_returns_with(&Yahoo, &portfolio, Interval::Daily, Currency::Usd).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use actix_web::rt::time::timeout;
+use chrono::{NaiveDate, NaiveTime};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::OffsetDateTime;
+use tracing::{debug, instrument, warn};
+use utoipa::ToSchema;
+
+pub use crate::alpha_vantage::AlphaVantage;
+pub use crate::coingecko::CoinGecko;
+pub use crate::files::Files;
+pub use crate::fixtures::Fixtures;
+use crate::fx::{FxService, LOOKBACK};
+use crate::http::deadline;
+pub use crate::polygon::Polygon;
+pub use crate::stooq::Stooq;
+pub use crate::tiingo::Tiingo;
+pub use crate::yahoo_finance::{Events, History, Interval, ProviderError, Quote, Timeout, Yahoo};
+
+/// The currency the ticker trades in, under the symbol the provider knows it by
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    pub symbol: String,
+    pub currency: String,
+}
+
+/// A source of quotes, metadata and exchange rates
+pub trait DataProvider: Sync {
+    /// Returns the quotes of the ticker in the range in its own currency, none if the provider has no data for it
+    fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> impl Future<Output = Result<Vec<Quote>, ProviderError>> + Send;
+
+    /// Returns the metadata of the ticker
+    fn metadata(
+        &self,
+        ticker: &str,
+    ) -> impl Future<Output = Result<Metadata, ProviderError>> + Send;
+
+    /// Returns the exchange rates of the currency in the range, which the prices in that currency are multiplied by to be in USD
+    fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> impl Future<Output = Result<Vec<Quote>, ProviderError>> + Send;
+
+    /// Returns the currency of the ticker along with its quotes in the range in that currency, USD if there are none
+    fn get_local_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> impl Future<Output = Result<(String, Vec<Quote>), ProviderError>> + Send {
+        local(self, ticker, start, end, interval)
+    }
+
+    /// Returns the quotes of the ticker along with the exchange rates used to convert them to USD,
+    /// an empty history if there are no quotes, as for delisted tickers
+    fn get_history(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> impl Future<Output = Result<History, ProviderError>> + Send {
+        async move {
+            let fx = FxService::new(self).with_interval(interval);
+            assemble(&fx, ticker, start, end, interval).await
+        }
+    }
+
+    /// Returns the currency of the ticker and its dividends, splits and capital gains in the range, in that currency,
+    /// which only the providers that report them have
+    fn get_events(
+        &self,
+        _ticker: &str,
+        _start: &OffsetDateTime,
+        _end: &OffsetDateTime,
+    ) -> impl Future<Output = Result<(String, Events), ProviderError>> + Send {
+        async { Err(ConfigError::Unsupported("the dividends, splits and capital gains").into()) }
+    }
+
+    /// The name the data of the provider is kept under by the caches, so that providers don't mix
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Checks that the provider can serve quotes, by fetching those of the last week of SPY
+    fn probe(&self) -> impl Future<Output = Result<(), ProviderError>> + Send {
+        async {
+            let end = OffsetDateTime::now_utc();
+            self.get_quotes(
+                "SPY",
+                &(end - time::Duration::days(7)),
+                &end,
+                Interval::Daily,
+            )
+            .await
+            .map(|_| ())
+        }
+    }
+}
+
+// the quotes along with the currency of the ticker, which is only asked for when there are quotes
+pub(crate) async fn local(
+    provider: &(impl DataProvider + ?Sized),
+    ticker: &str,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+    interval: Interval,
+) -> Result<(String, Vec<Quote>), ProviderError> {
+    let quotes = provider.get_quotes(ticker, start, end, interval).await?;
+    if quotes.is_empty() {
+        return Ok(("USD".to_string(), quotes));
+    }
+    Ok((provider.metadata(ticker).await?.currency, quotes))
+}
+
+// the quotes of the provider of the service converted with the exchange rates it keeps, so that the tickers of a portfolio
+// in the same currency share them
+async fn assemble<P: DataProvider + ?Sized>(
+    fx: &FxService<'_, P>,
+    ticker: &str,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+    interval: Interval,
+) -> Result<History, ProviderError> {
+    let context = |e: ProviderError| e.context(ticker, start, end);
+    let (currency, local) = fx
+        .provider()
+        .get_local_quotes(ticker, start, end, interval)
+        .await
+        .map_err(context)?;
+    if local.is_empty() {
+        return Ok(History::default());
+    }
+    // from before the start as well, so that the first quotes have a rate of their date or of one before it
+    let fx = fx
+        .usd_series(&currency, &(*start - LOOKBACK), end)
+        .await
+        .map_err(context)?;
+    Ok(History::new(local, fx))
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum ConfigError {
+    #[error("{0} needs {1} to be set")]
+    Missing(&'static str, &'static str),
+    #[error("{0} isn't valid: {1}")]
+    Invalid(&'static str, String),
+    #[error("{0} isn't a provider, it must be one of yahoo, alpha-vantage, polygon, stooq, tiingo, coingecko, files or fixtures")]
+    UnknownProvider(String),
+    #[error("the provider doesn't report {0}, only yahoo does")]
+    Unsupported(&'static str),
+}
+
+/// The providers that can be chosen by name
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    #[default]
+    Yahoo,
+    AlphaVantage,
+    Polygon,
+    Stooq,
+    Tiingo,
+    #[serde(rename = "coingecko")]
+    CoinGecko,
+    Files,
+    Fixtures,
+}
+
+impl Source {
+    /// Returns the provider named in MODUS_PROVIDER, Yahoo if it isn't set
+    pub fn from_env() -> Result<Source, ConfigError> {
+        match std::env::var("MODUS_PROVIDER") {
+            Ok(name) => serde_json::from_value(serde_json::Value::String(name.clone()))
+                .map_err(|_| ConfigError::UnknownProvider(name)),
+            Err(_) => Ok(Source::Yahoo),
+        }
+    }
+}
+
+/// A provider chosen by name, which delegates to it
+#[derive(Debug, Clone)]
+pub enum Provider {
+    Yahoo(Yahoo),
+    AlphaVantage(AlphaVantage),
+    Polygon(Polygon),
+    Stooq(Stooq),
+    Tiingo(Tiingo),
+    CoinGecko(CoinGecko),
+    Files(Files),
+    Fixtures(Fixtures),
+}
+
+impl Provider {
+    /// Returns the provider with its API key or directory from the environment, failing if it needs one that isn't set
+    pub fn new(source: Source) -> Result<Provider, ConfigError> {
+        let missing = |name, variable| ConfigError::Missing(name, variable);
+        Ok(match source {
+            Source::Yahoo => Provider::Yahoo(Yahoo),
+            Source::AlphaVantage => Provider::AlphaVantage(
+                AlphaVantage::from_env()
+                    .ok_or(missing("Alpha Vantage", "MODUS_ALPHA_VANTAGE_KEY"))?,
+            ),
+            Source::Polygon => Provider::Polygon(
+                Polygon::from_env().ok_or(missing("Polygon.io", "MODUS_POLYGON_KEY"))?,
+            ),
+            Source::Stooq => Provider::Stooq(Stooq),
+            Source::Tiingo => {
+                Provider::Tiingo(Tiingo::from_env().ok_or(missing("Tiingo", "MODUS_TIINGO_KEY"))?)
+            }
+            Source::CoinGecko => Provider::CoinGecko(CoinGecko::from_env()),
+            Source::Files => Provider::Files(
+                Files::from_env().ok_or(missing("The files provider", "MODUS_DATA_DIR"))?,
+            ),
+            Source::Fixtures => Provider::Fixtures(Fixtures),
+        })
+    }
+
+    /// Returns the provider named in MODUS_PROVIDER, Yahoo if it isn't set
+    pub fn from_env() -> Result<Provider, ConfigError> {
+        Provider::new(Source::from_env()?)
+    }
+}
+
+impl DataProvider for Provider {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        match self {
+            Provider::Yahoo(p) => p.get_quotes(ticker, start, end, interval).await,
+            Provider::AlphaVantage(p) => p.get_quotes(ticker, start, end, interval).await,
+            Provider::Polygon(p) => p.get_quotes(ticker, start, end, interval).await,
+            Provider::Stooq(p) => p.get_quotes(ticker, start, end, interval).await,
+            Provider::Tiingo(p) => p.get_quotes(ticker, start, end, interval).await,
+            Provider::CoinGecko(p) => p.get_quotes(ticker, start, end, interval).await,
+            Provider::Files(p) => p.get_quotes(ticker, start, end, interval).await,
+            Provider::Fixtures(p) => p.get_quotes(ticker, start, end, interval).await,
+        }
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        match self {
+            Provider::Yahoo(p) => p.metadata(ticker).await,
+            Provider::AlphaVantage(p) => p.metadata(ticker).await,
+            Provider::Polygon(p) => p.metadata(ticker).await,
+            Provider::Stooq(p) => p.metadata(ticker).await,
+            Provider::Tiingo(p) => p.metadata(ticker).await,
+            Provider::CoinGecko(p) => p.metadata(ticker).await,
+            Provider::Files(p) => p.metadata(ticker).await,
+            Provider::Fixtures(p) => p.metadata(ticker).await,
+        }
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        match self {
+            Provider::Yahoo(p) => p.fx_rates(currency, start, end, interval).await,
+            Provider::AlphaVantage(p) => p.fx_rates(currency, start, end, interval).await,
+            Provider::Polygon(p) => p.fx_rates(currency, start, end, interval).await,
+            Provider::Stooq(p) => p.fx_rates(currency, start, end, interval).await,
+            Provider::Tiingo(p) => p.fx_rates(currency, start, end, interval).await,
+            Provider::CoinGecko(p) => p.fx_rates(currency, start, end, interval).await,
+            Provider::Files(p) => p.fx_rates(currency, start, end, interval).await,
+            Provider::Fixtures(p) => p.fx_rates(currency, start, end, interval).await,
+        }
+    }
+
+    // the overrides, such as Yahoo's, are kept
+    async fn get_local_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<(String, Vec<Quote>), ProviderError> {
+        match self {
+            Provider::Yahoo(p) => p.get_local_quotes(ticker, start, end, interval).await,
+            Provider::AlphaVantage(p) => p.get_local_quotes(ticker, start, end, interval).await,
+            Provider::Polygon(p) => p.get_local_quotes(ticker, start, end, interval).await,
+            Provider::Stooq(p) => p.get_local_quotes(ticker, start, end, interval).await,
+            Provider::Tiingo(p) => p.get_local_quotes(ticker, start, end, interval).await,
+            Provider::CoinGecko(p) => p.get_local_quotes(ticker, start, end, interval).await,
+            Provider::Files(p) => p.get_local_quotes(ticker, start, end, interval).await,
+            Provider::Fixtures(p) => p.get_local_quotes(ticker, start, end, interval).await,
+        }
+    }
+
+    async fn get_events(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<(String, Events), ProviderError> {
+        match self {
+            Provider::Yahoo(p) => p.get_events(ticker, start, end).await,
+            Provider::AlphaVantage(p) => p.get_events(ticker, start, end).await,
+            Provider::Polygon(p) => p.get_events(ticker, start, end).await,
+            Provider::Stooq(p) => p.get_events(ticker, start, end).await,
+            Provider::Tiingo(p) => p.get_events(ticker, start, end).await,
+            Provider::CoinGecko(p) => p.get_events(ticker, start, end).await,
+            Provider::Files(p) => p.get_events(ticker, start, end).await,
+            Provider::Fixtures(p) => p.get_events(ticker, start, end).await,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Provider::Yahoo(p) => p.name(),
+            Provider::AlphaVantage(p) => p.name(),
+            Provider::Polygon(p) => p.name(),
+            Provider::Stooq(p) => p.name(),
+            Provider::Tiingo(p) => p.name(),
+            Provider::CoinGecko(p) => p.name(),
+            Provider::Files(p) => p.name(),
+            Provider::Fixtures(p) => p.name(),
+        }
+    }
+
+    async fn probe(&self) -> Result<(), ProviderError> {
+        match self {
+            Provider::Yahoo(p) => p.probe().await,
+            Provider::AlphaVantage(p) => p.probe().await,
+            Provider::Polygon(p) => p.probe().await,
+            Provider::Stooq(p) => p.probe().await,
+            Provider::Tiingo(p) => p.probe().await,
+            Provider::CoinGecko(p) => p.probe().await,
+            Provider::Files(p) => p.probe().await,
+            Provider::Fixtures(p) => p.probe().await,
+        }
+    }
+}
+
+/// Checks that the client all the requests are sent with can be configured with the proxy in MODUS_PROXY and the root
+/// certificates in the file MODUS_CA_CERT points to
+pub fn check_client() -> Result<(), ConfigError> {
+    crate::http::check()
+}
+
+// the timestamp of the start of the date in UTC, which the providers that only date their quotes use
+pub(crate) fn timestamp(date: NaiveDate) -> u64 {
+    date.and_time(NaiveTime::MIN).and_utc().timestamp() as u64
+}
+
+// the quotes in the range, in chronological order
+pub(crate) fn within(
+    mut quotes: Vec<Quote>,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+) -> Vec<Quote> {
+    quotes.retain(|q| {
+        (start.unix_timestamp()..=end.unix_timestamp()).contains(&(q.timestamp as i64))
+    });
+    quotes.sort_by_key(|q| q.timestamp);
+    quotes
+}
+
+// the number of requests to the provider in flight at the same time
+const CONCURRENT_REQUESTS: usize = 8;
+
+/// Fetches the history of every ticker concurrently, returning them in the same order as the tickers, unless it takes longer
+/// than MODUS_DEADLINE seconds, the requests still in flight being then cancelled. The exchange rates of every currency
+/// are fetched once for all the tickers in it
+#[instrument(skip_all, fields(provider = provider.name(), ?interval))]
+pub async fn get_histories<'a>(
+    provider: &impl DataProvider,
+    tickers: impl IntoIterator<Item = &'a str>,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+    interval: Interval,
+) -> Result<Vec<History>, ProviderError> {
+    let deadline = deadline();
+    let started = std::time::Instant::now();
+    let fx = FxService::new(provider).with_interval(interval);
+    // the requests are made up front, so that the future holds no closure over the tickers and stays Send
+    let requests: Vec<_> = tickers
+        .into_iter()
+        .map(|ticker| assemble(&fx, ticker, start, end, interval))
+        .collect();
+    let histories = stream::iter(requests)
+        .buffered(CONCURRENT_REQUESTS)
+        .try_collect();
+    let histories: Vec<History> = timeout(deadline, histories).await.map_err(|_| {
+        warn!(
+            deadline_s = deadline.as_secs(),
+            "the histories took too long"
+        );
+        Timeout(deadline)
+    })??;
+    debug!(
+        histories = histories.len(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "fetched the histories"
+    );
+    Ok(histories)
+}
+
+/// Fetches the quotes in USD of every ticker concurrently as get_histories does, keyed by the ticker
+pub async fn get_ticker_quotes<'a>(
+    provider: &impl DataProvider,
+    tickers: impl IntoIterator<Item = &'a str>,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+    interval: Interval,
+) -> Result<BTreeMap<String, Vec<Quote>>, ProviderError> {
+    let tickers: Vec<&str> = tickers.into_iter().collect();
+    let histories = get_histories(provider, tickers.iter().copied(), start, end, interval).await?;
+    Ok(tickers
+        .into_iter()
+        .map(str::to_string)
+        .zip(histories.into_iter().map(|h| h.quotes))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    // a provider with a quote of 10 every day, the tickers ending in .DE being in EUR, whose rates it counts the requests for
+    #[derive(Default)]
+    struct Counted {
+        rates: AtomicU32,
+    }
+
+    impl DataProvider for Counted {
+        async fn get_quotes(
+            &self,
+            _ticker: &str,
+            start: &OffsetDateTime,
+            end: &OffsetDateTime,
+            _interval: Interval,
+        ) -> Result<Vec<Quote>, ProviderError> {
+            let days = start.unix_timestamp() as u64 / DAY..=end.unix_timestamp() as u64 / DAY;
+            Ok(days
+                .map(|d| Quote {
+                    timestamp: d * DAY,
+                    close: 10.0,
+                    adjclose: 10.0,
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+            Ok(Metadata {
+                symbol: ticker.to_string(),
+                currency: match ticker.ends_with(".DE") {
+                    true => "EUR",
+                    false => "USD",
+                }
+                .to_string(),
+            })
+        }
+
+        // a rate of 1.1 from the first day and of 1.2 from the fourth one on
+        async fn fx_rates(
+            &self,
+            _currency: &str,
+            start: &OffsetDateTime,
+            end: &OffsetDateTime,
+            _interval: Interval,
+        ) -> Result<Vec<Quote>, ProviderError> {
+            self.rates.fetch_add(1, Ordering::SeqCst);
+            let rate = |day: u64, rate: f64| Quote {
+                timestamp: day * DAY,
+                close: rate,
+                adjclose: rate,
+                ..Default::default()
+            };
+            Ok(within(vec![rate(0, 1.1), rate(4, 1.2)], start, end))
+        }
+    }
+
+    fn day(d: u64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp((d * DAY) as i64).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn the_tickers_in_a_currency_share_its_rates() {
+        let provider = Counted::default();
+        let tickers = ["SAP.DE", "AAPL", "ADS.DE", "BMW.DE"];
+        let histories = get_histories(&provider, tickers, &day(8), &day(12), Interval::Daily)
+            .await
+            .unwrap();
+        assert_eq!(provider.rates.load(Ordering::SeqCst), 1);
+        assert!(histories[1].fx.is_empty());
+        assert_eq!(histories[1].quotes[0].adjclose, 10.0);
+        // the rates from before the start convert its first quotes
+        let usd: Vec<f64> = histories[0].quotes.iter().map(|q| q.adjclose).collect();
+        assert_eq!(usd, [12.0; 5]);
+    }
+
+    #[actix_web::test]
+    async fn the_quotes_are_converted_at_the_last_rate_up_to_their_date() {
+        let history = Counted::default()
+            .get_history("SAP.DE", &day(2), &day(6), Interval::Daily)
+            .await
+            .unwrap();
+        let usd: Vec<f64> = history.quotes.iter().map(|q| q.adjclose).collect();
+        assert_eq!(usd, [11.0, 11.0, 12.0, 12.0, 12.0]);
+        assert_eq!(history.local[0].adjclose, 10.0);
+    }
+}
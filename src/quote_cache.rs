@@ -0,0 +1,522 @@
+//! Persistent quote cache
+//!
+//! Keeps the daily quotes and exchange rates fetched from any provider in a SQLite database, only available with the sqlite
+//! feature, so that the next requests only fetch the quotes after the last ones kept rather than decades of data again.
+//! The server uses the database in MODUS_QUOTE_CACHE when it's set.
+//!
+//! As the adjusted close of the past changes with every dividend and the prices with every split, the quotes are fetched again
+//! from the second to last one kept, the last one being possibly that of a day that hadn't closed yet, and the kept ones are
+//! scaled by how much that quote changed. A range that starts before the first quote kept is fetched whole. Weekly and monthly
+//! quotes aren't kept, and if the database fails the quotes are fetched from the provider as if it weren't there.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::provider::{Interval, Yahoo};
+//! # use modus::quote_cache::{Cached, QuoteCache};
+//! # use modus::stock_returns::{total_returns_with, Currency, Portfolio};
+//! # use modus::storage::StorageError;
+//! # async fn run(portfolio: Portfolio) -> Result<(), StorageError> {
+//!  let cache = QuoteCache::open("quotes.db")?;
+//!  let provider = Cached::new(Yahoo, Some(&cache));
+//!  if let Ok(s) = total_returns_with(&provider, &portfolio, Interval::Daily, Currency::Usd).await { println!("{:?}", s); }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use time::OffsetDateTime;
+
+use crate::provider::{local, DataProvider, Metadata};
+use crate::storage::StorageError;
+use crate::yahoo_finance::{Events, Interval, ProviderError, Quote};
+
+/// The database, safe to share between the workers of the server
+pub struct QuoteCache {
+    connection: Mutex<Connection>,
+}
+
+// the range of the series that is kept, as unix timestamps
+struct Coverage {
+    start: i64,
+    end: i64,
+}
+
+impl QuoteCache {
+    /// Opens the database at the path, creating it and its tables if they don't exist
+    pub fn open(path: impl AsRef<Path>) -> Result<QuoteCache, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS quotes (
+                series TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                open REAL NOT NULL,
+                high REAL NOT NULL,
+                low REAL NOT NULL,
+                volume INTEGER NOT NULL,
+                close REAL NOT NULL,
+                adjclose REAL NOT NULL,
+                PRIMARY KEY (series, timestamp)
+            );
+            CREATE TABLE IF NOT EXISTS coverage (
+                series TEXT PRIMARY KEY,
+                start INTEGER NOT NULL,
+                end INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS metadata (
+                ticker TEXT PRIMARY KEY,
+                symbol TEXT NOT NULL,
+                currency TEXT NOT NULL
+            );",
+        )?;
+        Ok(QuoteCache {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Returns the database in MODUS_QUOTE_CACHE, opened the first time, None if it isn't set
+    pub fn from_env() -> Result<Option<&'static QuoteCache>, &'static StorageError> {
+        static CACHE: OnceLock<Option<Result<QuoteCache, StorageError>>> = OnceLock::new();
+        match CACHE.get_or_init(|| {
+            std::env::var("MODUS_QUOTE_CACHE")
+                .ok()
+                .map(QuoteCache::open)
+        }) {
+            Some(Ok(cache)) => Ok(Some(cache)),
+            Some(Err(e)) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the pages SQLite still holds in memory to the database, as the one in MODUS_QUOTE_CACHE is never closed
+    pub fn flush(&self) -> Result<(), StorageError> {
+        Ok(self.connection()?.cache_flush()?)
+    }
+
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>, StorageError> {
+        Ok(self.connection.lock()?)
+    }
+
+    fn coverage(&self, series: &str) -> Result<Option<Coverage>, StorageError> {
+        Ok(self
+            .connection()?
+            .query_row(
+                "SELECT start, end FROM coverage WHERE series = ?1",
+                params![series],
+                |row| {
+                    Ok(Coverage {
+                        start: row.get(0)?,
+                        end: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    fn quotes(&self, series: &str, start: i64, end: i64) -> Result<Vec<Quote>, StorageError> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT timestamp, open, high, low, volume, close, adjclose FROM quotes
+            WHERE series = ?1 AND timestamp BETWEEN ?2 AND ?3 ORDER BY timestamp",
+        )?;
+        let rows = statement.query_map(params![series, start, end], |row| {
+            Ok(Quote {
+                timestamp: row.get::<_, i64>(0)? as u64,
+                open: row.get(1)?,
+                high: row.get(2)?,
+                low: row.get(3)?,
+                volume: row.get::<_, i64>(4)? as u64,
+                close: row.get(5)?,
+                adjclose: row.get(6)?,
+                ..Default::default()
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // the timestamp of the second to last quote kept, from which the series is fetched again
+    fn resume(&self, series: &str) -> Result<Option<i64>, StorageError> {
+        Ok(self
+            .connection()?
+            .query_row(
+                "SELECT timestamp FROM quotes WHERE series = ?1 ORDER BY timestamp DESC LIMIT 1 OFFSET 1",
+                params![series],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    // keeps the quotes fetched from the timestamp on, the previous ones being scaled by how much the quote at it changed,
+    // or replaces the whole series if there's no timestamp
+    fn save(
+        &self,
+        series: &str,
+        from: Option<i64>,
+        quotes: &[Quote],
+        coverage: Coverage,
+    ) -> Result<(), StorageError> {
+        let mut connection = self.connection()?;
+        let transaction = connection.transaction()?;
+        match from {
+            Some(from) => {
+                let kept: Option<(f64, f64)> = transaction
+                    .query_row(
+                        "SELECT close, adjclose FROM quotes WHERE series = ?1 AND timestamp = ?2",
+                        params![series, from],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?;
+                let fetched = quotes.iter().find(|q| q.timestamp as i64 == from);
+                if let (Some((close, adjclose)), Some(q)) = (kept, fetched) {
+                    transaction.execute(
+                        "UPDATE quotes SET open = open * ?1, high = high * ?1, low = low * ?1, close = close * ?1,
+                        adjclose = adjclose * ?2 WHERE series = ?3 AND timestamp < ?4",
+                        params![q.close / close, q.adjclose / adjclose, series, from],
+                    )?;
+                }
+                transaction.execute(
+                    "DELETE FROM quotes WHERE series = ?1 AND timestamp >= ?2",
+                    params![series, from],
+                )?;
+            }
+            None => {
+                transaction.execute("DELETE FROM quotes WHERE series = ?1", params![series])?;
+            }
+        }
+        for q in quotes {
+            transaction.execute(
+                "INSERT OR REPLACE INTO quotes (series, timestamp, open, high, low, volume, close, adjclose)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    series,
+                    q.timestamp as i64,
+                    q.open,
+                    q.high,
+                    q.low,
+                    q.volume as i64,
+                    q.close,
+                    q.adjclose
+                ],
+            )?;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO coverage (series, start, end) VALUES (?1, ?2, ?3)",
+            params![series, coverage.start, coverage.end],
+        )?;
+        Ok(transaction.commit()?)
+    }
+
+    fn metadata(&self, ticker: &str) -> Result<Option<Metadata>, StorageError> {
+        Ok(self
+            .connection()?
+            .query_row(
+                "SELECT symbol, currency FROM metadata WHERE ticker = ?1",
+                params![ticker],
+                |row| {
+                    Ok(Metadata {
+                        symbol: row.get(0)?,
+                        currency: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    fn save_metadata(&self, ticker: &str, metadata: &Metadata) -> Result<(), StorageError> {
+        self.connection()?.execute(
+            "INSERT OR REPLACE INTO metadata (ticker, symbol, currency) VALUES (?1, ?2, ?3)",
+            params![ticker, metadata.symbol, metadata.currency],
+        )?;
+        Ok(())
+    }
+}
+
+// the series kept, the exchange rates being told apart from the tickers
+enum Series<'a> {
+    Ticker(&'a str),
+    Rates(&'a str),
+}
+
+impl Series<'_> {
+    // under the name of the provider
+    fn key(&self, provider: &str) -> String {
+        match self {
+            Series::Ticker(ticker) => format!("{provider}:{ticker}"),
+            Series::Rates(currency) => format!("{provider}:{currency}USD=FX"),
+        }
+    }
+}
+
+/// A provider whose daily quotes are kept in the cache, fetched from it as they are when there's no cache
+pub struct Cached<'a, P> {
+    provider: P,
+    cache: Option<&'a QuoteCache>,
+}
+
+impl<'a, P: DataProvider> Cached<'a, P> {
+    pub fn new(provider: P, cache: Option<&'a QuoteCache>) -> Cached<'a, P> {
+        Cached { provider, cache }
+    }
+
+    async fn fetch(
+        &self,
+        series: &Series<'_>,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        match series {
+            Series::Ticker(ticker) => {
+                self.provider
+                    .get_quotes(ticker, start, end, Interval::Daily)
+                    .await
+            }
+            Series::Rates(currency) => {
+                self.provider
+                    .fx_rates(currency, start, end, Interval::Daily)
+                    .await
+            }
+        }
+    }
+
+    async fn daily(
+        &self,
+        cache: &QuoteCache,
+        series: Series<'_>,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let key = series.key(self.provider.name());
+        let (start, end) = (start.unix_timestamp(), end.unix_timestamp());
+        let kept = cache.coverage(&key).ok().flatten();
+        let from = match kept {
+            Some(ref kept) if kept.start <= start => {
+                if end <= kept.end {
+                    if let Ok(quotes) = cache.quotes(&key, start, end) {
+                        return Ok(quotes);
+                    }
+                }
+                cache.resume(&key).ok().flatten()
+            }
+            _ => None,
+        };
+        let datetime =
+            |t: i64| OffsetDateTime::from_unix_timestamp(t).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        let fetched = self
+            .fetch(&series, &datetime(from.unwrap_or(start)), &datetime(end))
+            .await?;
+        let coverage = Coverage {
+            start: match (from, kept) {
+                (Some(_), Some(kept)) => kept.start,
+                _ => start,
+            },
+            end,
+        };
+        // nothing after the quotes kept, which are left as they are
+        if fetched.is_empty() && from.is_some() {
+            if let Ok(quotes) = cache.quotes(&key, start, end) {
+                return Ok(quotes);
+            }
+        }
+        match cache
+            .save(&key, from, &fetched, coverage)
+            .and_then(|_| cache.quotes(&key, start, end))
+        {
+            Ok(quotes) => Ok(quotes),
+            // only the tail was fetched, so the whole range is fetched without the database
+            Err(_) if from.is_some() => self.fetch(&series, &datetime(start), &datetime(end)).await,
+            Err(_) => Ok(fetched),
+        }
+    }
+}
+
+impl<P: DataProvider> DataProvider for Cached<'_, P> {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        match (self.cache, interval) {
+            (Some(cache), Interval::Daily) => {
+                self.daily(cache, Series::Ticker(ticker), start, end).await
+            }
+            _ => self.provider.get_quotes(ticker, start, end, interval).await,
+        }
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        let Some(cache) = self.cache else {
+            return self.provider.metadata(ticker).await;
+        };
+        let key = Series::Ticker(ticker).key(self.provider.name());
+        if let Ok(Some(metadata)) = cache.metadata(&key) {
+            return Ok(metadata);
+        }
+        let metadata = self.provider.metadata(ticker).await?;
+        // failing to keep it only means fetching it again
+        let _ = cache.save_metadata(&key, &metadata);
+        Ok(metadata)
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        match (self.cache, interval) {
+            (Some(cache), Interval::Daily) => {
+                self.daily(cache, Series::Rates(currency), start, end).await
+            }
+            _ => self.provider.fx_rates(currency, start, end, interval).await,
+        }
+    }
+
+    // the provider's own quotes, which may come with their currency, when there's nothing to keep
+    async fn get_local_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<(String, Vec<Quote>), ProviderError> {
+        match (self.cache, interval) {
+            (Some(_), Interval::Daily) => local(self, ticker, start, end, interval).await,
+            _ => {
+                self.provider
+                    .get_local_quotes(ticker, start, end, interval)
+                    .await
+            }
+        }
+    }
+
+    // the events aren't kept, as the latest ones are only known once they're fetched again
+    async fn get_events(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<(String, Events), ProviderError> {
+        self.provider.get_events(ticker, start, end).await
+    }
+
+    fn name(&self) -> &str {
+        self.provider.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    // a provider with the daily quotes given, which records the ranges it's asked for
+    #[derive(Default)]
+    struct Recorded {
+        quotes: Mutex<Vec<Quote>>,
+        fetched: Mutex<Vec<(u64, u64)>>,
+        metadata: Mutex<u32>,
+    }
+
+    impl DataProvider for Recorded {
+        async fn get_quotes(
+            &self,
+            _ticker: &str,
+            start: &OffsetDateTime,
+            end: &OffsetDateTime,
+            _interval: Interval,
+        ) -> Result<Vec<Quote>, ProviderError> {
+            let (start, end) = (start.unix_timestamp() as u64, end.unix_timestamp() as u64);
+            self.fetched.lock().unwrap().push((start / DAY, end / DAY));
+            Ok(self
+                .quotes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|q| (start..=end).contains(&q.timestamp))
+                .cloned()
+                .collect())
+        }
+
+        async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+            *self.metadata.lock().unwrap() += 1;
+            Ok(Metadata {
+                symbol: ticker.to_string(),
+                currency: "EUR".to_string(),
+            })
+        }
+
+        async fn fx_rates(
+            &self,
+            _currency: &str,
+            _start: &OffsetDateTime,
+            _end: &OffsetDateTime,
+            _interval: Interval,
+        ) -> Result<Vec<Quote>, ProviderError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn day(d: u64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp((d * DAY) as i64).unwrap()
+    }
+
+    fn quotes(days: std::ops::RangeInclusive<u64>, dividend: f64) -> Vec<Quote> {
+        days.map(|d| Quote {
+            timestamp: d * DAY,
+            close: 10.0 + d as f64,
+            adjclose: (10.0 + d as f64) * dividend,
+            ..Default::default()
+        })
+        .collect()
+    }
+
+    #[actix_web::test]
+    async fn only_the_tail_is_fetched_again_and_the_kept_quotes_are_adjusted_to_it() {
+        let cache = QuoteCache::open(":memory:").unwrap();
+        let cached = Cached::new(Recorded::default(), Some(&cache));
+        let recorded = &cached.provider;
+        *recorded.quotes.lock().unwrap() = quotes(1..=10, 1.0);
+        let first = cached
+            .get_quotes("SAP.DE", &day(1), &day(10), Interval::Daily)
+            .await
+            .unwrap();
+        assert_eq!(first.len(), 10);
+        let kept = cached
+            .get_quotes("SAP.DE", &day(2), &day(9), Interval::Daily)
+            .await
+            .unwrap();
+        assert_eq!(kept, first[1..9]);
+        assert_eq!(*recorded.fetched.lock().unwrap(), [(1, 10)]);
+        // a dividend halves the adjusted closes before the new quotes
+        *recorded.quotes.lock().unwrap() = quotes(1..=12, 0.5);
+        let updated = cached
+            .get_quotes("SAP.DE", &day(1), &day(12), Interval::Daily)
+            .await
+            .unwrap();
+        assert_eq!(*recorded.fetched.lock().unwrap(), [(1, 10), (9, 12)]);
+        assert_eq!(updated, quotes(1..=12, 0.5));
+        // the weekly quotes aren't kept
+        cached
+            .get_quotes("SAP.DE", &day(1), &day(12), Interval::Weekly)
+            .await
+            .unwrap();
+        assert_eq!(recorded.fetched.lock().unwrap().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn the_metadata_is_fetched_once() {
+        let cache = QuoteCache::open(":memory:").unwrap();
+        let cached = Cached::new(Recorded::default(), Some(&cache));
+        let recorded = &cached.provider;
+        for _ in 0..2 {
+            assert_eq!(cached.metadata("SAP.DE").await.unwrap().currency, "EUR");
+        }
+        assert_eq!(*recorded.metadata.lock().unwrap(), 1);
+    }
+}
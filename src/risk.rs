@@ -0,0 +1,339 @@
+//! Risk metrics
+//!
+//! Computes the annualized risk-adjusted performance of the portfolio from the daily return series produced by total_returns:
+//! the [Sharpe ratio](https://en.wikipedia.org/wiki/Sharpe_ratio), the [Sortino ratio](https://en.wikipedia.org/wiki/Sortino_ratio)
+//! and the [Calmar ratio](https://en.wikipedia.org/wiki/Calmar_ratio).
+//! For drawdown-sensitive investors it adds the [Omega ratio](https://en.wikipedia.org/wiki/Omega_ratio), the daily gains over
+//! the risk-free rate against the losses under it, the annualized downside deviation the Sortino ratio is based on, and the
+//! [Ulcer index](https://en.wikipedia.org/wiki/Ulcer_index), the root mean square of the drawdowns, which accounts for both
+//! their depth and duration.
+//! The annualized volatility is reported as well, being the standard deviation of the daily returns scaled to a year.
+//! It also includes the running drawdown, that is, how far below its previous peak the portfolio is at every date,
+//! along with the depth of the maximum drawdown and the duration in days of the longest one.
+//! The risk-free rate is taken from the portfolio, as an annual rate (0.03 for 3%), and defaults to zero.
+//!
+//! The daily [Value-at-Risk](https://en.wikipedia.org/wiki/Value_at_risk) and [expected shortfall](https://en.wikipedia.org/wiki/Expected_shortfall)
+//! are computed by historical simulation, i.e., from the empirical distribution of the daily returns, for every confidence level
+//! included in the portfolio (95% and 99% by default).
+//!
+//! Through the portfolio, the report also includes the covariance matrix of the holdings and the contribution of each one
+//! to the variance of the portfolio, see the holdings module.
+//!
+//! Finally, rolling computes the return, volatility and Sharpe ratio over a moving window of N days (e.g., 30, 90 or 252),
+//! so that one can see how they evolve over time.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::risk::risk;
+//! # use modus::stock_returns::{Equity, Portfolio, Transaction, TransactionDate};
+//! # async fn run() {
+//!  let buy = Transaction::new(TransactionDate::new(2023, 2, 1), 354.0);
+//!  let portfolio = Portfolio::new(vec![Equity::new("MSFT", buy, None, 3.0)]).with_rfr(0.03);
+//!  if let Ok(s) = risk(&portfolio).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::holdings::{risk_contribution, RiskContribution};
+use crate::statistics::{mean, period_returns, std_dev, PERIODS_PER_YEAR};
+use crate::stock_returns::{cagr, total_returns, Interval, Portfolio, StocksError};
+
+/// Holds the annualized risk-adjusted ratios of the portfolio and its drawdowns, in percentage
+#[derive(Debug, Serialize)]
+pub struct RiskReport {
+    pub volatility: f64,
+    pub sharpe: f64,
+    pub sortino: f64,
+    pub calmar: f64,
+    pub omega: f64,
+    pub downside_deviation: f64,
+    pub ulcer_index: f64,
+    pub max_drawdown: f64,
+    pub max_drawdown_duration: i64,
+    pub drawdown: BTreeMap<String, f64>,
+    pub value_at_risk: Vec<ValueAtRisk>,
+    // only available when the report is computed from the portfolio rather than from the returns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contribution: Option<RiskContribution>,
+}
+
+/// Holds the daily loss in percentage that is only exceeded with a probability of 1 - confidence and the average loss when it is
+#[derive(Debug, Serialize)]
+pub struct ValueAtRisk {
+    pub confidence: f64,
+    pub var: f64,
+    pub cvar: f64,
+}
+
+/// Holds the return, annualized volatility and annualized Sharpe ratio of the window ending at a date, in percentage
+#[derive(Debug, Serialize)]
+pub struct RollingStats {
+    pub returns: f64,
+    pub volatility: f64,
+    pub sharpe: f64,
+}
+
+/// Returns the risk report of the portfolio
+pub async fn risk(item: &Portfolio) -> Result<RiskReport, StocksError> {
+    Ok(RiskReport {
+        contribution: Some(risk_contribution(item).await?),
+        ..risk_metrics(
+            &total_returns(item, Interval::Daily).await?,
+            item.rfr(),
+            item.confidence(),
+        )
+    })
+}
+
+/// Returns the rolling statistics of the portfolio over a window of the given number of days
+pub async fn rolling(
+    item: &Portfolio,
+    window: usize,
+) -> Result<BTreeMap<String, RollingStats>, StocksError> {
+    Ok(rolling_metrics(
+        &total_returns(item, Interval::Daily).await?,
+        window,
+        item.rfr(),
+    ))
+}
+
+/// Computes the rolling statistics from the cumulative returns in percentage, the first date being the end of the first full window
+pub fn rolling_metrics(
+    returns: &BTreeMap<String, f64>,
+    window: usize,
+    rfr: f64,
+) -> BTreeMap<String, RollingStats> {
+    let window = window.max(1);
+    let values = values(returns);
+    let daily = period_returns(&values);
+    let daily_rfr = rfr / PERIODS_PER_YEAR;
+    returns
+        .keys()
+        .enumerate()
+        .skip(window - 1)
+        .map(|(i, date)| {
+            // the daily returns of the window ending at this date
+            let slice = &daily[i + 1 - window..=i];
+            (
+                date.clone(),
+                RollingStats {
+                    returns: (values[i + 1] / values[i + 1 - window] - 1.0) * 100.0,
+                    volatility: std_dev(slice) * PERIODS_PER_YEAR.sqrt() * 100.0,
+                    sharpe: (mean(slice) - daily_rfr) / std_dev(slice) * PERIODS_PER_YEAR.sqrt(),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Computes the risk report from the cumulative returns in percentage, as returned by total_returns
+pub fn risk_metrics(returns: &BTreeMap<String, f64>, rfr: f64, confidence: &[f64]) -> RiskReport {
+    let values = values(returns);
+    let daily = period_returns(&values);
+    let daily_rfr = rfr / PERIODS_PER_YEAR;
+    let excess = mean(&daily) - daily_rfr;
+    // only the returns below the risk-free rate count as risk
+    let downside = (daily
+        .iter()
+        .map(|r| (r - daily_rfr).min(0.0).powi(2))
+        .sum::<f64>()
+        / daily.len() as f64)
+        .sqrt();
+    let drawdown = drawdown(returns);
+    let max_drawdown = drawdown.values().fold(0.0, |worst: f64, &d| worst.min(d));
+    RiskReport {
+        volatility: volatility(returns, Interval::Daily),
+        sharpe: excess / std_dev(&daily) * PERIODS_PER_YEAR.sqrt(),
+        sortino: excess / downside * PERIODS_PER_YEAR.sqrt(),
+        calmar: cagr(returns) / max_drawdown.abs(),
+        omega: omega(&daily, daily_rfr),
+        downside_deviation: downside * PERIODS_PER_YEAR.sqrt() * 100.0,
+        ulcer_index: (drawdown.values().map(|d| d.powi(2)).sum::<f64>() / drawdown.len() as f64)
+            .sqrt(),
+        max_drawdown,
+        max_drawdown_duration: longest_drawdown(&drawdown),
+        drawdown,
+        value_at_risk: confidence
+            .iter()
+            .map(|c| value_at_risk(&daily, *c))
+            .collect(),
+        contribution: None,
+    }
+}
+
+// the gains over the threshold against the losses under it
+fn omega(daily: &[f64], threshold: f64) -> f64 {
+    let (gains, losses) = daily.iter().fold((0.0, 0.0), |(g, l), r| {
+        (g + (r - threshold).max(0.0), l + (threshold - r).max(0.0))
+    });
+    gains / losses
+}
+
+// historical simulation, the losses are reported as positive percentages
+fn value_at_risk(daily: &[f64], confidence: f64) -> ValueAtRisk {
+    let mut sorted = daily.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    // the returns in the tail, at least the worst one
+    let tail = ((1.0 - confidence) * sorted.len() as f64) as usize;
+    let tail = &sorted[..tail.max(1).min(sorted.len())];
+    ValueAtRisk {
+        confidence,
+        var: -tail.last().unwrap_or(&f64::NAN) * 100.0,
+        cvar: -mean(tail) * 100.0,
+    }
+}
+
+/// Returns the annualized standard deviation in percentage of the returns between consecutive quotes at the given interval
+pub fn volatility(returns: &BTreeMap<String, f64>, interval: Interval) -> f64 {
+    std_dev(&period_returns(&values(returns))) * interval.periods_per_year().sqrt() * 100.0
+}
+
+/// Returns the running drawdown in percentage at every date of the cumulative returns
+pub fn drawdown(returns: &BTreeMap<String, f64>) -> BTreeMap<String, f64> {
+    // the initial investment is the first peak
+    let mut peak: f64 = 1.0;
+    returns
+        .iter()
+        .map(|(date, r)| {
+            let value = 1.0 + r / 100.0;
+            peak = peak.max(value);
+            (date.clone(), (value / peak - 1.0) * 100.0)
+        })
+        .collect()
+}
+
+// the value of one dollar invested at the beginning, starting with the dollar itself
+fn values(returns: &BTreeMap<String, f64>) -> Vec<f64> {
+    std::iter::once(1.0)
+        .chain(returns.values().map(|r| 1.0 + r / 100.0))
+        .collect()
+}
+
+// the days from a peak until it was recovered, or until the last date if it hasn't been yet
+fn longest_drawdown(drawdown: &BTreeMap<String, f64>) -> i64 {
+    let dates: Vec<(NaiveDate, f64)> = drawdown
+        .iter()
+        .filter_map(|(date, d)| date.parse().ok().map(|date| (date, *d)))
+        .collect();
+    let (mut longest, mut underwater) = (0, false);
+    let mut peak = dates.first().map(|(date, _)| *date);
+    for (date, d) in dates.iter() {
+        // the day the peak is recovered still counts towards the drawdown
+        if let (Some(p), true) = (peak, underwater || *d < 0.0) {
+            longest = longest.max((*date - p).num_days());
+        }
+        underwater = *d < 0.0;
+        if !underwater {
+            peak = Some(*date);
+        }
+    }
+    longest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the cumulative returns of a dollar worth 1.1, 1.0, 0.9, 1.05 and 1.2 at the closes of consecutive days
+    fn returns() -> BTreeMap<String, f64> {
+        [
+            "2023-01-02",
+            "2023-01-03",
+            "2023-01-04",
+            "2023-01-05",
+            "2023-01-06",
+        ]
+        .into_iter()
+        .zip([10.0, 0.0, -10.0, 5.0, 20.0])
+        .map(|(date, r)| (date.to_string(), r))
+        .collect()
+    }
+
+    fn close(x: f64, y: f64) -> bool {
+        (x - y).abs() < 1e-9
+    }
+
+    #[test]
+    fn the_drawdown_is_measured_from_the_last_peak() {
+        let drawdown = drawdown(&returns());
+        let expected = [0.0, 1.0 / 1.1 - 1.0, 0.9 / 1.1 - 1.0, 1.05 / 1.1 - 1.0, 0.0];
+        for (d, e) in drawdown.values().zip(expected) {
+            assert!(close(*d, e * 100.0));
+        }
+        // from the peak of the 2nd until it was recovered on the 6th
+        assert_eq!(longest_drawdown(&drawdown), 4);
+    }
+
+    #[test]
+    fn the_value_at_risk_is_the_loss_at_the_tail_of_the_daily_returns() {
+        let report = risk_metrics(&returns(), 0.0, &[0.8, 0.6]);
+        // the daily returns are 10%, -9.09%, -10%, 16.67% and 14.29%
+        assert!(close(report.value_at_risk[0].var, 10.0));
+        assert!(close(report.value_at_risk[0].cvar, 10.0));
+        assert!(close(
+            report.value_at_risk[1].var,
+            (1.0 - 1.0 / 1.1) * 100.0
+        ));
+        assert!(close(
+            report.value_at_risk[1].cvar,
+            (10.0 + (1.0 - 1.0 / 1.1) * 100.0) / 2.0
+        ));
+        assert!(close(report.max_drawdown, (0.9 / 1.1 - 1.0) * 100.0));
+        assert_eq!(report.max_drawdown_duration, 4);
+    }
+
+    #[test]
+    fn the_ratios_are_annualized_from_the_daily_returns() {
+        let daily = [
+            0.1,
+            1.0 / 1.1 - 1.0,
+            -0.1,
+            1.05 / 0.9 - 1.0,
+            1.2 / 1.05 - 1.0,
+        ];
+        let report = risk_metrics(&returns(), 0.0, &[]);
+        assert!(close(
+            report.sharpe,
+            mean(&daily) / std_dev(&daily) * PERIODS_PER_YEAR.sqrt()
+        ));
+        assert!(close(
+            report.volatility,
+            std_dev(&daily) * PERIODS_PER_YEAR.sqrt() * 100.0
+        ));
+        assert!(report.value_at_risk.is_empty());
+    }
+
+    #[test]
+    fn the_omega_ratio_weighs_the_gains_against_the_losses() {
+        let daily = [
+            0.1,
+            1.0 / 1.1 - 1.0,
+            -0.1,
+            1.05 / 0.9 - 1.0,
+            1.2 / 1.05 - 1.0,
+        ];
+        let report = risk_metrics(&returns(), 0.0, &[]);
+        let gains: f64 = daily.iter().filter(|r| **r > 0.0).sum();
+        let losses: f64 = -daily.iter().filter(|r| **r < 0.0).sum::<f64>();
+        assert!(close(report.omega, gains / losses));
+        let drawdown: Vec<f64> = drawdown(&returns()).into_values().collect();
+        let squares: f64 = drawdown.iter().map(|d| d * d).sum();
+        assert!(close(report.ulcer_index, (squares / 5.0).sqrt()));
+    }
+
+    #[test]
+    fn the_rolling_windows_start_once_they_are_full() {
+        let rolling = rolling_metrics(&returns(), 3, 0.0);
+        assert_eq!(
+            rolling.keys().collect::<Vec<_>>(),
+            vec!["2023-01-04", "2023-01-05", "2023-01-06"]
+        );
+        assert!(close(rolling["2023-01-04"].returns, -10.0));
+        // the three days from the close of the 3rd
+        assert!(close(rolling["2023-01-06"].returns, 20.0));
+    }
+}
@@ -0,0 +1,141 @@
+// descriptive statistics shared by the analytics modules
+
+// trading days in a year, used to annualize daily figures
+pub(crate) const PERIODS_PER_YEAR: f64 = 252.0;
+
+pub(crate) fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// sample covariance, NaN if there are less than two observations
+pub(crate) fn covariance(x: &[f64], y: &[f64]) -> f64 {
+    let (mean_x, mean_y) = (mean(x), mean(y));
+    x.iter()
+        .zip(y.iter())
+        .fold(0.0, |acc, (a, b)| acc + (a - mean_x) * (b - mean_y))
+        / (x.len().min(y.len()) as f64 - 1.0)
+}
+
+pub(crate) fn variance(values: &[f64]) -> f64 {
+    covariance(values, values)
+}
+
+pub(crate) fn std_dev(values: &[f64]) -> f64 {
+    variance(values).sqrt()
+}
+
+pub(crate) fn dot(x: &[f64], y: &[f64]) -> f64 {
+    x.iter().zip(y.iter()).map(|(a, b)| a * b).sum()
+}
+
+// simple returns between consecutive values
+pub(crate) fn period_returns(values: &[f64]) -> Vec<f64> {
+    values.windows(2).map(|w| w[1] / w[0] - 1.0).collect()
+}
+
+// solves a·x = b by Gaussian elimination with partial pivoting, None if a is singular
+pub(crate) fn solve(a: &[Vec<f64>], b: &[f64]) -> Option<Vec<f64>> {
+    let n = b.len();
+    // the augmented matrix
+    let mut m: Vec<Vec<f64>> = a
+        .iter()
+        .zip(b.iter())
+        .map(|(row, bi)| row.iter().copied().chain(std::iter::once(*bi)).collect())
+        .collect();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| m[i][col].abs().total_cmp(&m[j][col].abs()))?;
+        if m[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        m.swap(col, pivot);
+        let pivot_row = m[col].clone();
+        for row in m.iter_mut().skip(col + 1) {
+            let factor = row[col] / pivot_row[col];
+            for (x, p) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                *x -= factor * p;
+            }
+        }
+    }
+    // back substitution
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| m[row][k] * x[k]).sum();
+        x[row] = (m[row][n] - sum) / m[row][row];
+    }
+    Some(x)
+}
+
+// lower triangular l such that l·lᵀ = a, the dimensions that aren't positive definite are left at zero
+pub(crate) fn cholesky(a: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let n = a.len();
+    let mut l = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| l[i][k] * l[j][k]).sum();
+            l[i][j] = if i == j {
+                (a[i][i] - sum).max(0.0).sqrt()
+            } else if l[j][j] > 0.0 {
+                (a[i][j] - sum) / l[j][j]
+            } else {
+                0.0
+            };
+        }
+    }
+    l
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(x: &[f64], y: &[f64]) -> bool {
+        x.len() == y.len() && x.iter().zip(y).all(|(a, b)| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn solves_systems_whose_first_pivot_is_zero() {
+        let a = vec![
+            vec![0.0, 2.0, 1.0],
+            vec![1.0, 1.0, 0.0],
+            vec![2.0, 0.0, 3.0],
+        ];
+        // x = [1, 2, 3]
+        let x = solve(&a, &[7.0, 3.0, 11.0]).unwrap();
+        assert!(close(&x, &[1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn gives_up_on_singular_systems() {
+        let a = vec![vec![1.0, 2.0], vec![2.0, 4.0]];
+        assert!(solve(&a, &[1.0, 2.0]).is_none());
+    }
+
+    #[test]
+    fn factors_a_positive_definite_matrix() {
+        let a = vec![
+            vec![4.0, 2.0, 0.4],
+            vec![2.0, 3.0, 0.5],
+            vec![0.4, 0.5, 1.0],
+        ];
+        let l = cholesky(&a);
+        assert!(close(&l[0], &[2.0, 0.0, 0.0]));
+        assert!(close(&l[1], &[1.0, 2.0_f64.sqrt(), 0.0]));
+        for i in 0..3 {
+            let row: Vec<f64> = (0..3).map(|j| dot(&l[i], &l[j])).collect();
+            assert!(close(&row, &a[i]));
+        }
+    }
+
+    #[test]
+    fn leaves_the_dimensions_without_variance_at_zero() {
+        let a = vec![vec![1.0, 0.0], vec![0.0, 0.0]];
+        assert_eq!(cholesky(&a), vec![vec![1.0, 0.0], vec![0.0, 0.0]]);
+    }
+
+    #[test]
+    fn the_sample_covariance_divides_by_one_less_than_the_observations() {
+        assert!((variance(&[1.0, 2.0, 3.0, 4.0]) - 5.0 / 3.0).abs() < 1e-12);
+        assert!(variance(&[1.0]).is_nan());
+        assert!(close(&period_returns(&[1.0, 1.1, 0.99]), &[0.1, -0.1]));
+    }
+}
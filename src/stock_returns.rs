@@ -7,61 +7,587 @@
 //! StocksError being a custom error enum for the error types that can occur.
 //!
 //! Usage:
+//! ```no_run
+//! # use modus::stock_returns::{total_returns, Equity, Interval, Portfolio, Transaction, TransactionDate};
+//! # async fn run() {
+//!  let buy = Transaction::new(TransactionDate::new(2023, 2, 1), 354.0);
+//!  let portfolio = Portfolio::new(vec![Equity::new("MSFT", buy, None, 3.0)]);
+//!  if let Ok(s) = total_returns(&portfolio, Interval::Daily).await { println!("{:?}", s); }
+//! # }
 //! ```
-//!  let portfolio = Portfolio{portfolio: vec![Equity{ticker: "MSFT".to_string(), buy: Transaction { date: TransactionDate {
-//!         year: 2023,
-//!         month: 2,
-//!         day: 1,
-//!     }, price: 354.0 }, sell: None, quantity: 3 }]};
-//!  if let Ok(s) = total_returns(&portfolio).await { println!("{:?}", s); }
-//! ```
+//!
+//! Portfolios can mix stocks with tickers that trade every day, such as cryptocurrencies (e.g., BTC-USD): every date any ticker
+//! has a quote on is included, and on the dates a ticker has no quote, like weekends or the holidays of its exchange when another
+//! one is open, its last known price is carried forward.
+//!
+//! Renamed tickers (e.g., FB, now META) are fetched under their current symbol. The positions in tickers that Yahoo has no data for,
+//! usually because they were delisted, are left out of the returns instead of failing the whole portfolio, unless no ticker has any data.
+//!
+//! The daily series can be rolled up into calendar-month or calendar-year returns with aggregate, as fund factsheets do,
+//! and summarized by the length of the period in years and the compound annual growth rate with years and cagr.
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
-use chrono::{DateTime, NaiveDate};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime};
 pub use modus_derive::From;
 use serde::{Deserialize, Serialize};
 use time::error::ComponentRange;
 use time::macros::time;
-use time::{Date, Month, OffsetDateTime};
+use time::{Date, Month, OffsetDateTime, Time};
+use tracing::{debug, instrument};
+use utoipa::ToSchema;
 
-use crate::yahoo_finance::{check_currency, get_quotes, ProviderError, Quote};
+use crate::provider::{get_histories, DataProvider, Provider, Source};
+#[cfg(feature = "sqlite")]
+use crate::quote_cache::{Cached, QuoteCache};
+pub use crate::yahoo_finance::Interval;
+pub use crate::yahoo_finance::{FetchError, ProviderError};
+use crate::yahoo_finance::{History, Quote, YahooError};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Position {
+    ticker: String,
     old_price: f64,
     price: f64,
-    quantity: u32,
+    quantity: f64,
 }
 
 /// Holds the historical data about your portfolio
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Portfolio {
     portfolio: Vec<Equity>,
+    // ticker the portfolio is compared against, only required for the benchmark comparison
+    #[serde(default)]
+    benchmark: Option<String>,
+    // annual risk-free rate used by the risk metrics
+    #[serde(default)]
+    rfr: f64,
+    // confidence levels of the Value-at-Risk
+    #[serde(default = "default_confidence")]
+    confidence: Vec<f64>,
+    // the provider the quotes are fetched from, the one in MODUS_PROVIDER by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<Source>,
+}
+
+fn default_confidence() -> Vec<f64> {
+    vec![0.95, 0.99]
+}
+
+/// A field of the input that isn't valid, e.g., portfolio[0].buy.date, and why
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+impl Violation {
+    pub(crate) fn new(field: impl Into<String>, message: impl Into<String>) -> Violation {
+        Violation {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Equity {
+// checks that the weights have a ticker at least and that they're numbers, none of them negative and not all of them zero
+pub(crate) fn validate_weights(
+    field: &str,
+    weights: &BTreeMap<String, f64>,
+    violations: &mut Vec<Violation>,
+) {
+    if weights.is_empty() {
+        violations.push(Violation::new(field, "must have at least one ticker"));
+    }
+    for (ticker, weight) in weights {
+        if ticker.trim().is_empty() {
+            violations.push(Violation::new(field, "must not have an empty ticker"));
+        }
+        if !weight.is_finite() || *weight < 0.0 {
+            violations.push(Violation::new(
+                format!("{field}.{ticker}"),
+                "must be a number that isn't negative",
+            ));
+        }
+    }
+    if !weights.is_empty() && weights.values().all(|w| *w == 0.0) {
+        violations.push(Violation::new(field, "must not all be zero"));
+    }
+}
+
+impl Portfolio {
+    /// Checks every equity of the portfolio and its settings, returning all the violations found rather than only the first one
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        if self.portfolio.is_empty() {
+            violations.push(Violation::new("portfolio", "must have at least one equity"));
+        }
+        for (i, n) in self.portfolio.iter().enumerate() {
+            let field = |name: &str| format!("portfolio[{i}].{name}");
+            if n.ticker.trim().is_empty() {
+                violations.push(Violation::new(field("ticker"), "must not be empty"));
+            }
+            if !(n.quantity.is_finite() && n.quantity > 0.0) {
+                violations.push(Violation::new(field("quantity"), "must be positive"));
+            }
+            let buy = n.buy.validate(&field("buy"), &mut violations);
+            let mut last = None;
+            if let Some(sell) = n.sell.as_ref() {
+                last = sell.validate(&field("sell"), &mut violations);
+                match (buy, last) {
+                    (Some(buy), Some(sell)) if sell < buy => violations.push(Violation::new(
+                        field("sell.date"),
+                        "must not be before the buy date",
+                    )),
+                    _ => {}
+                }
+            }
+            for (j, sale) in n.sales.iter().enumerate() {
+                let sale_field = field(&format!("sales[{j}]"));
+                if !(sale.quantity.is_finite() && sale.quantity > 0.0) {
+                    violations.push(Violation::new(
+                        format!("{sale_field}.quantity"),
+                        "must be positive",
+                    ));
+                }
+                match (buy, sale.sell.validate(&sale_field, &mut violations), last) {
+                    (Some(buy), Some(date), _) if date < buy => violations.push(Violation::new(
+                        format!("{sale_field}.date"),
+                        "must not be before the buy date",
+                    )),
+                    (_, Some(date), Some(last)) if date > last => violations.push(Violation::new(
+                        format!("{sale_field}.date"),
+                        "must not be after the sell date",
+                    )),
+                    _ => {}
+                }
+            }
+            if n.sales.iter().map(|s| s.quantity).sum::<f64>() > n.quantity + DUST {
+                violations.push(Violation::new(
+                    field("sales"),
+                    "must not sell more shares than were bought",
+                ));
+            }
+        }
+        if !self.rfr.is_finite() {
+            violations.push(Violation::new("rfr", "must be a number"));
+        }
+        if let Some(Err(e)) = self.provider.map(Provider::new) {
+            violations.push(Violation::new("provider", e.to_string()));
+        }
+        for (i, c) in self.confidence.iter().enumerate() {
+            if !(*c > 0.0 && *c < 1.0) {
+                violations.push(Violation::new(
+                    format!("confidence[{i}]"),
+                    "must be between 0 and 1",
+                ));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// A portfolio with no benchmark, no risk-free rate and the default confidence levels of the Value-at-Risk (95% and 99%)
+    pub fn new(portfolio: Vec<Equity>) -> Portfolio {
+        Portfolio {
+            portfolio,
+            benchmark: None,
+            rfr: 0.0,
+            confidence: default_confidence(),
+            provider: None,
+        }
+    }
+
+    /// Sets the ticker the portfolio is compared against
+    pub fn with_benchmark(self, benchmark: impl Into<String>) -> Portfolio {
+        Portfolio {
+            benchmark: Some(benchmark.into()),
+            ..self
+        }
+    }
+
+    /// Sets the annual risk-free rate used by the risk metrics
+    pub fn with_rfr(self, rfr: f64) -> Portfolio {
+        Portfolio { rfr, ..self }
+    }
+
+    /// Sets the confidence levels of the Value-at-Risk, e.g., 0.95
+    pub fn with_confidence(self, confidence: Vec<f64>) -> Portfolio {
+        Portfolio { confidence, ..self }
+    }
+
+    /// Sets the provider the quotes are fetched from instead of the default one
+    pub fn with_provider(self, provider: Source) -> Portfolio {
+        Portfolio {
+            provider: Some(provider),
+            ..self
+        }
+    }
+
+    pub fn benchmark(&self) -> Option<&str> {
+        self.benchmark.as_deref()
+    }
+
+    pub fn rfr(&self) -> f64 {
+        self.rfr
+    }
+
+    pub fn confidence(&self) -> &[f64] {
+        &self.confidence
+    }
+
+    pub fn provider(&self) -> Option<Source> {
+        self.provider
+    }
+
+    // the provider of the portfolio, or the default one
+    pub(crate) fn data_provider(&self) -> Result<impl DataProvider, ProviderError> {
+        data_provider(self.provider)
+    }
+
+    /// Combines the equities of both portfolios, e.g., held at different brokers, keeping the settings of this one
+    pub fn merge(mut self, other: Portfolio) -> Portfolio {
+        self.portfolio.extend(other.portfolio);
+        self
+    }
+
+    /// Returns what changed from this snapshot of the portfolio to a later one: the equities that were added or removed
+    /// (an equity that changed, e.g., because it was sold, being removed and added again) and the shares held of every ticker
+    /// whose position changed
+    pub fn diff(&self, later: &Portfolio) -> PortfolioDiff {
+        let mut removed: Vec<&Equity> = self.portfolio.iter().collect();
+        let mut added = Vec::new();
+        for n in later.portfolio.iter() {
+            match removed.iter().position(|m| *m == n) {
+                Some(i) => {
+                    removed.remove(i);
+                }
+                None => added.push(n.clone()),
+            }
+        }
+        let (before, after) = (self.open_quantities(), later.open_quantities());
+        PortfolioDiff {
+            added,
+            removed: removed.into_iter().cloned().collect(),
+            holdings: before
+                .keys()
+                .chain(after.keys())
+                .filter_map(|ticker| {
+                    let change = HoldingChange {
+                        before: before.get(ticker).copied().unwrap_or(0.0),
+                        after: after.get(ticker).copied().unwrap_or(0.0),
+                    };
+                    (change.before != change.after).then(|| (ticker.to_string(), change))
+                })
+                .collect(),
+        }
+    }
+
+    // every ticker in the portfolio without repetitions
+    pub(crate) fn tickers(&self) -> BTreeSet<&str> {
+        self.portfolio.iter().map(|n| n.ticker.as_str()).collect()
+    }
+
+    pub fn equities(&self) -> &[Equity] {
+        &self.portfolio
+    }
+
+    // the total quantity of every ticker that is still held
+    pub(crate) fn open_quantities(&self) -> BTreeMap<&str, f64> {
+        let mut quantities = BTreeMap::new();
+        for n in self.portfolio.iter().filter(|n| n.sell.is_none()) {
+            *quantities.entry(n.ticker.as_str()).or_insert(0.0) += n.remaining();
+        }
+        quantities
+    }
+
+    // every equity split into the shares sold at each of its partial sales and the rest, so that each lot is bought and sold at once
+    pub(crate) fn lots(&self) -> Vec<Equity> {
+        self.portfolio.iter().flat_map(Equity::lots).collect()
+    }
+}
+
+// the provider chosen, or the default one, its daily quotes being kept in the quote cache if there's one
+pub(crate) fn data_provider(source: Option<Source>) -> Result<impl DataProvider, ProviderError> {
+    let provider = match source {
+        Some(source) => Provider::new(source)?,
+        None => Provider::from_env()?,
+    };
+    #[cfg(feature = "sqlite")]
+    let provider = Cached::new(provider, QuoteCache::from_env().ok().flatten());
+    Ok(provider)
+}
+
+/// Holds what changed between two snapshots of a portfolio
+#[derive(Debug, Serialize)]
+pub struct PortfolioDiff {
+    pub added: Vec<Equity>,
+    pub removed: Vec<Equity>,
+    pub holdings: BTreeMap<String, HoldingChange>,
+}
+
+/// Holds the shares of a ticker held before and after
+#[derive(Debug, Serialize)]
+pub struct HoldingChange {
+    pub before: f64,
+    pub after: f64,
+}
+
+/// Holds two snapshots of a portfolio to diff
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Snapshots {
+    pub before: Portfolio,
+    pub after: Portfolio,
+}
+
+impl Snapshots {
+    /// Checks both portfolios, the fields of the violations being prefixed by the snapshot
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        for (name, portfolio) in [("before", &self.before), ("after", &self.after)] {
+            if let Err(v) = portfolio.validate() {
+                violations.extend(v.into_iter().map(|v| Violation {
+                    field: format!("{name}.{}", v.field),
+                    ..v
+                }));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+// the shares below which what's left of an equity is taken as sold, the fractional sales not always adding up exactly
+const DUST: f64 = 1e-9;
+
+/// A number of shares of a ticker bought at once, which may be fractional, part of which can be sold before the sell,
+/// which sells whatever remains
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Equity {
     ticker: String,
     buy: Transaction,
     sell: Option<Transaction>,
-    quantity: u32,
+    quantity: f64,
+    // partial sales, in any order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sales: Vec<Sale>,
+}
+
+/// A sale of part of the shares of an equity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Sale {
+    #[serde(flatten)]
+    sell: Transaction,
+    quantity: f64,
+}
+
+impl Sale {
+    pub fn new(sell: Transaction, quantity: f64) -> Sale {
+        Sale { sell, quantity }
+    }
+
+    pub fn sell(&self) -> &Transaction {
+        &self.sell
+    }
+
+    pub fn quantity(&self) -> f64 {
+        self.quantity
+    }
+}
+
+impl Equity {
+    /// The shares of the ticker bought, and sold unless the sell is None
+    pub fn new(
+        ticker: impl Into<String>,
+        buy: Transaction,
+        sell: Option<Transaction>,
+        quantity: f64,
+    ) -> Equity {
+        Equity {
+            ticker: ticker.into(),
+            buy,
+            sell,
+            quantity,
+            sales: Vec::new(),
+        }
+    }
+
+    /// Sells part of the shares before the sell
+    pub fn with_sales(self, sales: Vec<Sale>) -> Equity {
+        Equity { sales, ..self }
+    }
+
+    pub fn ticker(&self) -> &str {
+        &self.ticker
+    }
+
+    pub fn buy(&self) -> &Transaction {
+        &self.buy
+    }
+
+    /// None if it hasn't been sold
+    pub fn sell(&self) -> Option<&Transaction> {
+        self.sell.as_ref()
+    }
+
+    pub fn quantity(&self) -> f64 {
+        self.quantity
+    }
+
+    pub fn sales(&self) -> &[Sale] {
+        &self.sales
+    }
+
+    // the shares left after the partial sales
+    fn remaining(&self) -> f64 {
+        let remaining = self.sales.iter().fold(self.quantity, |q, s| q - s.quantity);
+        if remaining > DUST {
+            remaining
+        } else {
+            0.0
+        }
+    }
+
+    fn lots(&self) -> Vec<Equity> {
+        let lot = |sell: Option<Transaction>, quantity: f64| Equity {
+            ticker: self.ticker.clone(),
+            buy: self.buy.clone(),
+            sell,
+            quantity,
+            sales: Vec::new(),
+        };
+        let mut lots: Vec<Equity> = self
+            .sales
+            .iter()
+            .map(|s| lot(Some(s.sell.clone()), s.quantity))
+            .collect();
+        if self.remaining() > 0.0 {
+            lots.push(lot(self.sell.clone(), self.remaining()));
+        }
+        lots
+    }
+
+    pub(crate) fn buy_price(&self) -> f64 {
+        self.buy.price
+    }
+
+    pub(crate) fn sell_price(&self) -> Option<f64> {
+        self.sell.as_ref().map(|s| s.price)
+    }
+
+    pub(crate) fn buy_date(&self) -> Option<NaiveDate> {
+        self.buy.date.naive()
+    }
+
+    // None if it hasn't been sold
+    pub(crate) fn sell_date(&self) -> Option<NaiveDate> {
+        self.sell.as_ref().and_then(|s| s.date.naive())
+    }
+
+    // from the buy date to the sell date, or now if it hasn't been sold
+    pub(crate) fn range(&self) -> Result<(OffsetDateTime, OffsetDateTime), ComponentRange> {
+        get_range(self)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Transaction {
+/// The date of a buy or sell and the price per share, split-adjusted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Transaction {
     date: TransactionDate,
     price: f64,
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
-struct TransactionDate {
+impl Transaction {
+    pub fn new(date: TransactionDate, price: f64) -> Transaction {
+        Transaction { date, price }
+    }
+
+    pub fn date(&self) -> TransactionDate {
+        self.date
+    }
+
+    pub fn price(&self) -> f64 {
+        self.price
+    }
+
+    // adds the violations of the transaction under the field, returning its date if it's a real one
+    fn validate(&self, field: &str, violations: &mut Vec<Violation>) -> Option<Date> {
+        if !(self.price.is_finite() && self.price > 0.0) {
+            violations.push(Violation::new(format!("{field}.price"), "must be positive"));
+        }
+        self.date.validate(&format!("{field}.date"), violations)
+    }
+}
+
+/// A calendar date, which is only checked when the portfolio is validated
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TransactionDate {
     year: i32,
     month: u32,
     day: u8,
 }
 
+impl From<NaiveDate> for TransactionDate {
+    fn from(date: NaiveDate) -> Self {
+        TransactionDate {
+            year: date.year(),
+            month: date.month(),
+            day: date.day() as u8,
+        }
+    }
+}
+
 impl TransactionDate {
+    pub fn new(year: i32, month: u32, day: u8) -> TransactionDate {
+        TransactionDate { year, month, day }
+    }
+
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    pub fn month(&self) -> u32 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    // the date, or None along with a violation unless the year, month and day make up a real date
+    pub(crate) fn validate(&self, field: &str, violations: &mut Vec<Violation>) -> Option<Date> {
+        let date = self.date();
+        if date.is_none() {
+            violations.push(Violation::new(field, "must be a real calendar date"));
+        }
+        date
+    }
+
+    // None unless the year, month and day make up a real date
+    fn date(&self) -> Option<Date> {
+        let month = Month::try_from(u8::try_from(self.month).ok()?).ok()?;
+        Date::from_calendar_date(self.year, month, self.day).ok()
+    }
+
+    fn naive(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year, self.month, self.day.into())
+    }
+
+    // the date at the given time in UTC
+    pub(crate) fn at(&self, time: Time) -> Result<OffsetDateTime, ComponentRange> {
+        Ok(OffsetDateTime::new_utc(
+            Date::from_calendar_date(self.year, self.match_month(), self.day)?,
+            time,
+        ))
+    }
+
     fn match_month(&self) -> Month {
         match self.month {
             1 => Month::January,
@@ -81,20 +607,67 @@ impl TransactionDate {
     }
 }
 
+/// Granularity of the returns, the daily ones are cumulative whereas the monthly and yearly ones are the returns within each period
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    #[default]
+    Daily,
+    Monthly,
+    Yearly,
+}
+
+impl Aggregation {
+    // the part of a YYYY-MM-DD date that identifies its period
+    pub(crate) fn period<'a>(&self, date: &'a str) -> &'a str {
+        match self {
+            Aggregation::Daily => date,
+            Aggregation::Monthly => &date[.."YYYY-MM".len()],
+            Aggregation::Yearly => &date[.."YYYY".len()],
+        }
+    }
+}
+
+/// Currency the prices are taken in, by default every one is converted to USD at the exchange rate of each date whereas
+/// the local currency leaves them in the currency the ticker trades in, so that the performance of the assets can be told apart
+/// from the movements of the exchange rates. As prices in different currencies can't be added up,
+/// local returns are only meaningful for portfolios whose tickers share the currency.
+/// Hedged returns weigh the holdings by their value in USD but let each one grow as it did in its own currency,
+/// as if the exchange rate risk had been fully hedged (the cost of hedging and the interest rate differential are left out)
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Currency {
+    #[default]
+    Usd,
+    Local,
+    Hedged,
+}
+
 /// This custom error uses the custom derive macro From to implement the From trait
 ///
-/// Example:
-/// ```
+/// Example, the impls being those the derive generates for the enum:
+/// ```no_run
+/// # use modus::stock_returns::ProviderError;
+/// # use time::error::ComponentRange;
+/// # enum StocksError {
+/// #     ComponentRange(ComponentRange),
+/// #     ProviderError(ProviderError),
+/// # }
 ///  impl From<ComponentRange> for StocksError {
-///      fn from (_e: ComponentRange) -> Self {
-///          StocksError::ComponentRange
+///      fn from (e: ComponentRange) -> Self {
+///          StocksError::ComponentRange(e)
+///      }
+///  }
+///  impl From<ProviderError> for StocksError {
+///      fn from (e: ProviderError) -> Self {
+///          StocksError::ProviderError(e)
 ///      }
 ///  }
 /// ```
-#[derive(From)]
+#[derive(From, Debug)]
 pub enum StocksError {
-    ComponentRange,
-    ProviderError,
+    ComponentRange(ComponentRange),
+    ProviderError(ProviderError),
 }
 
 // the Ok variant is a range with dates in YYYY-MM_DD
@@ -117,51 +690,137 @@ fn get_range(n: &Equity) -> Result<(OffsetDateTime, OffsetDateTime), ComponentRa
     Ok((start, end))
 }
 
-// returns a Result<HashSet<NaiveDate>, StocksError> where the Ok variant is a HashSet with all the holidays
-async fn find_dates(item: &Portfolio) -> Result<BTreeSet<NaiveDate>, StocksError> {
-    {
-        let mut range: Vec<(OffsetDateTime, OffsetDateTime)> = Vec::new();
-        for n in item.portfolio.iter() {
-            let (start, end) = get_range(n)?;
-            range.push((start, end));
+// the earliest buy and the latest sell (or now) of the whole portfolio
+pub(crate) fn portfolio_range(
+    item: &Portfolio,
+) -> Result<(OffsetDateTime, OffsetDateTime), ComponentRange> {
+    let mut range: Vec<(OffsetDateTime, OffsetDateTime)> = Vec::new();
+    for n in item.portfolio.iter() {
+        let (start, end) = get_range(n)?;
+        range.push((start, end));
+    }
+    // finds the earliest and latest date and assigns them to start and end, respectively
+    Ok(range
+        .iter()
+        .fold((range[0].0, range[0].1), |(s, e), (rs, re)| {
+            (s.min(*rs), e.max(*re))
+        }))
+}
+
+// the history of every ticker over the range of the whole portfolio, so that every ticker is only fetched once,
+// the requests being sent concurrently
+pub(crate) async fn histories<'a>(
+    provider: &impl DataProvider,
+    item: &'a Portfolio,
+    interval: Interval,
+) -> Result<BTreeMap<&'a str, History>, StocksError> {
+    let (start, end) = portfolio_range(item)?;
+    let tickers = item.tickers();
+    let histories =
+        get_histories(provider, tickers.iter().copied(), &start, &end, interval).await?;
+    // the delisted tickers are left out, unless none of them has any data
+    if histories.iter().all(|h| h.quotes.is_empty()) {
+        let tickers: Vec<&str> = tickers.into_iter().collect();
+        return Err(ProviderError::from(YahooError::EmptyDataSet)
+            .context(&tickers.join(", "), &start, &end)
+            .into());
+    }
+    Ok(tickers.into_iter().zip(histories).collect())
+}
+
+// returns a BTreeSet with every date any ticker has a quote on
+pub(crate) fn find_dates(histories: &BTreeMap<&str, History>) -> BTreeSet<NaiveDate> {
+    let every_timestamp = histories
+        .values()
+        .flat_map(|f| f.quotes.iter().map(|g| g.timestamp));
+    let mut every_date = BTreeSet::new();
+    for timestamp in every_timestamp {
+        let date = DateTime::from_timestamp(timestamp as i64, 0)
+            .unwrap_or_default()
+            .date_naive();
+        every_date.insert(date);
+    }
+    every_date
+}
+
+// the period a quote falls in: its date, or its time at the intraday intervals, the returns being keyed by it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Bar {
+    Date(NaiveDate),
+    Time(NaiveDateTime),
+}
+
+impl Bar {
+    fn of(quote: &Quote, interval: Interval) -> Bar {
+        let time = DateTime::from_timestamp(quote.timestamp as i64, 0)
+            .unwrap_or_default()
+            .naive_utc();
+        if interval.is_intraday() {
+            Bar::Time(time)
+        } else {
+            Bar::Date(time.date())
         }
-        // finds the earliest and latest date and assigns them to start and end, respectively
-        let (start, end) = range
-            .iter()
-            .fold((range[0].0, range[0].1), |(s, e), (rs, re)| {
-                (s.min(*rs), e.max(*re))
-            });
-        let mut historical_data: Vec<Vec<Quote>> = Vec::new();
-        for n in item.portfolio.iter() {
-            historical_data.push(get_quotes(&n.ticker, &start, &end).await?);
-        }
-        let every_timestamp = historical_data
-            .iter()
-            .flat_map(|f| f.iter().map(|g| g.timestamp));
-        let mut every_date = BTreeSet::new();
-        for timestamp in every_timestamp {
-            let date = DateTime::from_timestamp(timestamp as i64, 0)
-                .unwrap_or_default()
-                .date_naive();
-            // inserts the date into the HashSet, if it can't, removes the existing one from the HashSet without replacing it
-            every_date.insert(date);
+    }
+}
+
+// the dates as YYYY-MM-DD and the times in UTC as YYYY-MM-DD HH:MM
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bar::Date(date) => date.fmt(f),
+            Bar::Time(time) => time.format("%Y-%m-%d %H:%M").fmt(f),
         }
-        Ok(every_date)
     }
 }
 
-/// Returns a Result<BTreeMap<String, f64>, StocksError> where the BTreeMap is composed of a date as key and a percentage gain as value
-/// and StocksError is an enum with the different types of Error that might have occurred
-pub async fn total_returns(item: &Portfolio) -> Result<BTreeMap<String, f64>, StocksError> {
+// returns the value of every position at the beginning and at the end of every date
+#[instrument(skip_all, fields(equities = item.equities().len(), ?currency))]
+async fn positions(
+    provider: &impl DataProvider,
+    item: &Portfolio,
+    interval: Interval,
+    currency: Currency,
+) -> Result<BTreeMap<Bar, Vec<Position>>, StocksError> {
+    let histories = histories(provider, item, interval).await?;
+    let positions = match currency {
+        Currency::Usd => value_positions(item, &histories, interval, false)?,
+        Currency::Local => value_positions(item, &histories, interval, true)?,
+        Currency::Hedged => hedge(
+            value_positions(item, &histories, interval, false)?,
+            &value_positions(item, &histories, interval, true)?,
+        ),
+    };
+    debug!(bars = positions.len(), "valued the positions");
+    Ok(positions)
+}
+
+// the positions valued in USD, or in the currency of every ticker if local
+fn value_positions(
+    item: &Portfolio,
+    histories: &BTreeMap<&str, History>,
+    interval: Interval,
+    local: bool,
+) -> Result<BTreeMap<Bar, Vec<Position>>, StocksError> {
     // a BTreeMap because the data should be ordered by key
     let mut returns = BTreeMap::new();
-    let every_date = find_dates(item).await?;
-    // iterates over every element in the portfolio
-    for n in item.portfolio.iter() {
+    let every_date: BTreeSet<Bar> = histories
+        .values()
+        .flat_map(|h| h.quotes.iter().map(|q| Bar::of(q, interval)))
+        .collect();
+    // iterates over every lot in the portfolio
+    for n in item.lots().iter() {
         let (start, end) = get_range(n)?;
-        // exchange rate at the buy and end dates to convert them
-        let start_currency_adjustment = check_currency(&n.ticker, &start).await?;
-        let end_currency_adjustment = check_currency(&n.ticker, &end).await?;
+        let history = &histories[n.ticker.as_str()];
+        // exchange rate at the buy and end dates to convert them, none in the local currency
+        let (history_quotes, start_currency_adjustment, end_currency_adjustment) = if local {
+            (&history.local, 1.0, 1.0)
+        } else {
+            (
+                &history.quotes,
+                history.rate_at(&start),
+                history.rate_at(&end),
+            )
+        };
         // buy price in USD at the date of buying
         let mut old_price = n.buy.price * start_currency_adjustment;
         // sets price to the price in USD at the time of selling
@@ -169,98 +828,835 @@ pub async fn total_returns(item: &Portfolio) -> Result<BTreeMap<String, f64>, St
             price: s.price * end_currency_adjustment,
             ..*s
         });
-        // returns all the quotes for that ticker in the specified range
-        let quotes = get_quotes(&n.ticker, &start, &end).await?;
-        let mut previous_date = NaiveDate::MIN;
-        for (i, m) in quotes.iter().enumerate() {
-            // converts the date from a timestamp to a NaiveDate for a more human-readable YYYY-MM-DD
-            let date = DateTime::from_timestamp(m.timestamp as i64, 0)
-                .unwrap_or_default()
-                .date_naive();
-            // checks if it's 5pm somewhere, if it is, grabs a beer
-            if i > 0 {
-                let previous_index = every_date
-                    .iter()
-                    .position(|&last_date| last_date == previous_date)
-                    .unwrap_or(0);
-                let current_index = every_date
-                    .iter()
-                    .position(|&now| now == date)
-                    .unwrap_or(previous_index);
-                if current_index - previous_index > 1 {
-                    for missing_date_index in (previous_index + 1)..current_index {
-                        if let Some(missing_date) = every_date.iter().nth(missing_date_index) {
-                            returns
-                                .entry(*missing_date)
-                                .or_insert_with(Vec::new)
-                                .push(Position {
-                                    // prices don't change when the market is closed
-                                    old_price: old_price * m.close / m.adjclose,
-                                    price: old_price * m.close / m.adjclose,
-                                    quantity: n.quantity,
-                                });
+        // all the quotes for that ticker in the specified range
+        let quotes: Vec<&Quote> = history_quotes
+            .iter()
+            .filter(|q| {
+                (start.unix_timestamp()..=end.unix_timestamp()).contains(&(q.timestamp as i64))
+            })
+            .collect();
+        let date_of = |m: &Quote| Bar::of(m, interval);
+        let (first_date, last_date) = match (quotes.first(), quotes.last(), every_date.last()) {
+            // a position that is still held is carried until the last date any ticker traded, 24/7 ones such as BTC-USD included
+            (Some(first), Some(last), Some(latest)) => (
+                date_of(first),
+                if n.sell.is_some() {
+                    date_of(last)
+                } else {
+                    *latest
+                },
+            ),
+            _ => continue,
+        };
+        let mut indexed = quotes.iter().enumerate().peekable();
+        // aligns the position on the dates any ticker traded
+        for date in every_date.range(first_date..=last_date) {
+            let mut traded = false;
+            while let Some((i, m)) = indexed.next_if(|(_, m)| date_of(m) <= *date) {
+                traded = true;
+                returns
+                    .entry(*date)
+                    .or_insert_with(Vec::new)
+                    .push(if i + 1 == quotes.len() {
+                        Position {
+                            // if it's the last quote, weights the old price by the difference between the close and adjclose to avoid distortions...
+                            old_price: old_price * m.close / m.adjclose,
+                            // ... and sets the selling price in USD if it has been sold and does the same weighting or keeps the adjclose otherwise
+                            price: adjusted_selling_data
+                                .as_ref()
+                                .map(|sell| sell.price * m.close / m.adjclose)
+                                .unwrap_or_else(|| m.adjclose),
+                            ticker: n.ticker.clone(),
+                            quantity: n.quantity,
                         }
-                    }
-                }
-            }
-            returns
-                .entry(date)
-                .or_insert_with(Vec::new)
-                .push(if i == quotes.len() - 1 {
-                    Position {
-                        // if it's the last quote, weights the old price by the difference between the close and adjclose to avoid distortions...
-                        old_price: old_price * m.close / m.adjclose,
-                        // ... and sets the selling price in USD if it has been sold and does the same weighting or keeps the adjclose otherwise
-                        price: adjusted_selling_data
-                            .as_ref()
-                            .map(|sell| sell.price * m.close / m.adjclose)
-                            .unwrap_or_else(|| m.adjclose),
-                        quantity: n.quantity,
-                    }
-                } else if i == 0 {
-                    Position {
-                        // if it's the first quote weights the old price and the price (buy price in this case) as previously described
-                        old_price: old_price * m.close / m.adjclose,
-                        price: m.close * start_currency_adjustment * m.close / m.adjclose,
-                        quantity: n.quantity,
-                    }
+                    } else if i == 0 {
+                        Position {
+                            // if it's the first quote weights the old price and the price (buy price in this case) as previously described
+                            old_price: old_price * m.close / m.adjclose,
+                            price: m.close * start_currency_adjustment * m.close / m.adjclose,
+                            ticker: n.ticker.clone(),
+                            quantity: n.quantity,
+                        }
+                    } else {
+                        Position {
+                            old_price,
+                            price: m.adjclose,
+                            ticker: n.ticker.clone(),
+                            quantity: n.quantity,
+                        }
+                    });
+                // if the next quote is the last, sets the old price as the close price converted to USD by the exchange rate
+                old_price = if i + 2 == quotes.len() {
+                    m.close * end_currency_adjustment
                 } else {
-                    Position {
+                    m.adjclose
+                };
+            }
+            // on the dates its exchange was closed and another one traded, the position keeps its last known price
+            if !traded {
+                returns
+                    .entry(*date)
+                    .or_insert_with(Vec::new)
+                    .push(Position {
                         old_price,
-                        price: m.adjclose,
+                        price: old_price,
+                        ticker: n.ticker.clone(),
                         quantity: n.quantity,
-                    }
-                });
-            // if the next quote is the last, sets the old price as the close price converted to USD by the exchange rate
-            old_price = if i == quotes.len() - 2 {
-                m.close * end_currency_adjustment
-            } else {
-                m.adjclose
-            };
-            previous_date = date;
+                    });
+            }
         }
     }
-    let mut cumulative: f64 = 1.0;
-    Ok(returns
+    Ok(returns)
+}
+
+// keeps the value in USD of every position at the beginning of every date and lets it grow as it did in its own currency,
+// both valuations having the same positions in the same order
+fn hedge(
+    mut usd: BTreeMap<Bar, Vec<Position>>,
+    local: &BTreeMap<Bar, Vec<Position>>,
+) -> BTreeMap<Bar, Vec<Position>> {
+    for (date, positions) in usd.iter_mut() {
+        for (pos, local) in positions.iter_mut().zip(local[date].iter()) {
+            pos.price = pos.old_price * local.price / local.old_price;
+        }
+    }
+    usd
+}
+
+/// Returns a Result<BTreeMap<String, f64>, StocksError> where the BTreeMap is composed of a date as key and a percentage gain as value
+/// and StocksError is an enum with the different types of Error that might have occurred.
+/// There is a data point for every quote at the given interval, weekly or monthly ones keeping long histories small
+pub async fn total_returns(
+    item: &Portfolio,
+    interval: Interval,
+) -> Result<BTreeMap<String, f64>, StocksError> {
+    total_returns_in(item, interval, Currency::Usd).await
+}
+
+/// Returns the same series as total_returns with the prices in the given currency
+pub async fn total_returns_in(
+    item: &Portfolio,
+    interval: Interval,
+    currency: Currency,
+) -> Result<BTreeMap<String, f64>, StocksError> {
+    total_returns_with(&item.data_provider()?, item, interval, currency).await
+}
+
+/// Returns the same series as total_returns_in with the quotes fetched from the given provider
+pub async fn total_returns_with(
+    provider: &impl DataProvider,
+    item: &Portfolio,
+    interval: Interval,
+    currency: Currency,
+) -> Result<BTreeMap<String, f64>, StocksError> {
+    Ok(cumulative(
+        &positions(provider, item, interval, currency).await?,
+    ))
+}
+
+/// Holds the returns of the portfolio in USD and as if the exchange rate risk of the holdings not in USD had been hedged
+#[derive(Debug, Serialize)]
+pub struct HedgedReturns {
+    pub unhedged: BTreeMap<String, f64>,
+    pub hedged: BTreeMap<String, f64>,
+}
+
+/// Returns both the unhedged and the hedged series, fetching the quotes only once
+pub async fn hedged_returns(
+    item: &Portfolio,
+    interval: Interval,
+) -> Result<HedgedReturns, StocksError> {
+    let histories = histories(&item.data_provider()?, item, interval).await?;
+    let usd = value_positions(item, &histories, interval, false)?;
+    let hedged = hedge(
+        usd.clone(),
+        &value_positions(item, &histories, interval, true)?,
+    );
+    Ok(HedgedReturns {
+        unhedged: cumulative(&usd),
+        hedged: cumulative(&hedged),
+    })
+}
+
+// the cumulative returns in USD of the histories already fetched, for the analyses that need the quotes too
+pub(crate) fn histories_returns(
+    item: &Portfolio,
+    histories: &BTreeMap<&str, History>,
+    interval: Interval,
+) -> Result<BTreeMap<String, f64>, StocksError> {
+    Ok(cumulative(&value_positions(
+        item, histories, interval, false,
+    )?))
+}
+
+/// Holds the return in percentage within every period split into the movement of the prices in their own currencies
+/// and that of the exchange rates, which compound into the total return in USD
+#[derive(Debug, Serialize)]
+pub struct Decomposition {
+    pub total: BTreeMap<String, f64>,
+    pub price: BTreeMap<String, f64>,
+    pub fx: BTreeMap<String, f64>,
+}
+
+/// Returns the decomposition of the return within every period (the daily ones included), the price effect being the hedged return
+/// and the currency effect what the exchange rates added to it
+pub async fn decompose(
+    item: &Portfolio,
+    interval: Interval,
+    aggregation: Aggregation,
+) -> Result<Decomposition, StocksError> {
+    let returns = hedged_returns(item, interval).await?;
+    let total = within_periods(&returns.unhedged, aggregation);
+    let price = within_periods(&returns.hedged, aggregation);
+    let fx = total
+        .iter()
+        .filter_map(|(period, t)| {
+            let p = price.get(period)?;
+            Some((
+                period.clone(),
+                ((1.0 + t / 100.0) / (1.0 + p / 100.0) - 1.0) * 100.0,
+            ))
+        })
+        .collect();
+    Ok(Decomposition { total, price, fx })
+}
+
+// the cumulative return in percentage at every date
+fn cumulative(positions: &BTreeMap<Bar, Vec<Position>>) -> BTreeMap<String, f64> {
+    compound(
+        positions
+            .iter()
+            .map(|(date, positions)| (*date, growth(positions))),
+    )
+    .collect()
+}
+
+// the growth of the positions over a date
+fn growth(positions: &[Position]) -> f64 {
+    // calculates the total value of every position at the beginning of the day and sums it up for every day
+    let cap = positions
+        .iter()
+        .fold(0.0, |acc, pos| acc + pos.old_price * pos.quantity);
+    // calculates the value of every position at the end of the day and divides it by the total value at the beginning of the day and sums it up for every day
+    positions
+        .iter()
+        .fold(0.0, |acc, pos| acc + pos.price * pos.quantity / cap)
+}
+
+// transforms the daily aggregate growth into continuous growth in percentage
+fn compound(growth: impl Iterator<Item = (Bar, f64)>) -> impl Iterator<Item = (String, f64)> {
+    growth.scan(1.0, |cumulative, (date, rate)| {
+        *cumulative *= rate;
+        Some((date.to_string(), (*cumulative - 1.0) * 100.0))
+    })
+}
+
+/// Returns the same series as total_returns_in as an iterator that compounds the returns date by date as it's iterated,
+/// so that long series can be sent without being collected first. The positions of every date are still valued at once,
+/// as the quotes of every ticker have to be fetched before any return is known
+pub async fn stream_returns(
+    item: &Portfolio,
+    interval: Interval,
+    currency: Currency,
+) -> Result<impl Iterator<Item = (String, f64)>, StocksError> {
+    let positions = positions(&item.data_provider()?, item, interval, currency).await?;
+    Ok(compound(
+        positions
+            .into_iter()
+            .map(|(date, positions)| (date, growth(&positions))),
+    ))
+}
+
+/// Returns the weight in percentage of every ticker at the end of every date, i.e., the value of its positions over the value of the portfolio,
+/// so that the allocation drift can be followed. Monthly and yearly weights are those at the end of every period
+pub async fn weights(
+    item: &Portfolio,
+    interval: Interval,
+    currency: Currency,
+    aggregation: Aggregation,
+) -> Result<BTreeMap<String, BTreeMap<String, f64>>, StocksError> {
+    Ok(weigh(
+        &positions(&item.data_provider()?, item, interval, currency).await?,
+        aggregation,
+    ))
+}
+
+// the weight of every ticker at the end of every date, or of the last date of every period
+fn weigh(
+    positions: &BTreeMap<Bar, Vec<Position>>,
+    aggregation: Aggregation,
+) -> BTreeMap<String, BTreeMap<String, f64>> {
+    positions
         .iter()
         .map(|(date, positions)| {
-            (date.to_string(), {
-                // calculates the total value of every position at the beginning of the day and sums it up for every day
-                let cap = positions
-                    .iter()
-                    .fold(0.0, |acc, pos| acc + pos.old_price * pos.quantity as f64);
-                // calculates the value of every position at the end of the day and divides it by the total value at the beginning of the day and sums it up for every day
-                positions
-                    .iter()
-                    .fold(0.0, |acc, pos| acc + pos.price * pos.quantity as f64 / cap)
-            })
+            let total = positions
+                .iter()
+                .fold(0.0, |acc, pos| acc + pos.price * pos.quantity);
+            let mut weights = BTreeMap::new();
+            for pos in positions.iter() {
+                *weights.entry(pos.ticker.clone()).or_insert(0.0) +=
+                    pos.price * pos.quantity / total * 100.0;
+            }
+            // the last date of every period overwrites the previous ones
+            (aggregation.period(&date.to_string()).to_string(), weights)
         })
-        // transforms the daily aggregate growth into continuous growth in percentage
-        .map(|(date, rate)| {
-            (date, {
-                cumulative *= rate;
-                (cumulative - 1.0) * 100.0
+        .collect()
+}
+
+/// Holds the profit or loss of the portfolio in the currency of the prices, made on every date and accumulated up to it
+#[derive(Debug, Serialize)]
+pub struct ProfitAndLoss {
+    pub daily: BTreeMap<String, f64>,
+    pub cumulative: BTreeMap<String, f64>,
+}
+
+impl ProfitAndLoss {
+    /// Sums the profit or loss within every calendar month or year, the cumulative one being the one at the end of the period
+    pub fn aggregate(&self, aggregation: Aggregation) -> ProfitAndLoss {
+        let mut daily: BTreeMap<String, f64> = BTreeMap::new();
+        for (date, pnl) in self.daily.iter() {
+            *daily
+                .entry(aggregation.period(date).to_string())
+                .or_insert(0.0) += pnl;
+        }
+        ProfitAndLoss {
+            daily,
+            cumulative: self
+                .cumulative
+                .iter()
+                .map(|(date, pnl)| (aggregation.period(date).to_string(), *pnl))
+                .collect(),
+        }
+    }
+}
+
+/// Returns the profit or loss of the positions held on every date, i.e., the change of their value times the quantity,
+/// in USD unless another currency is given. The positions are valued at the close of every date, from the buy price to
+/// the sell price, so that the profit or loss adds up to what was made on the shares; the dividends aren't part of it
+pub async fn profit_and_loss(
+    item: &Portfolio,
+    interval: Interval,
+    currency: Currency,
+) -> Result<ProfitAndLoss, StocksError> {
+    let histories = histories(&item.data_provider()?, item, interval).await?;
+    let mut total = 0.0;
+    let mut daily = BTreeMap::new();
+    let mut cumulative = BTreeMap::new();
+    for (date, pnl) in daily_pnl(item, &histories, interval, currency)? {
+        total += pnl;
+        daily.insert(date.to_string(), pnl);
+        cumulative.insert(date.to_string(), total);
+    }
+    Ok(ProfitAndLoss { daily, cumulative })
+}
+
+// the change of the value of every lot on every date it traded. The hedged value is converted at the exchange rate of the
+// buy throughout, so that it only changes with the price
+fn daily_pnl(
+    item: &Portfolio,
+    histories: &BTreeMap<&str, History>,
+    interval: Interval,
+    currency: Currency,
+) -> Result<BTreeMap<Bar, f64>, ComponentRange> {
+    let mut daily = BTreeMap::new();
+    for n in item.lots().iter() {
+        let (start, end) = get_range(n)?;
+        let history = &histories[n.ticker.as_str()];
+        let rate = |quote: &Quote| match currency {
+            Currency::Usd => history.rate_on(
+                DateTime::from_timestamp(quote.timestamp as i64, 0)
+                    .unwrap_or_default()
+                    .date_naive(),
+            ),
+            Currency::Local => 1.0,
+            Currency::Hedged => history.rate_at(&start),
+        };
+        let quotes: Vec<&Quote> = history
+            .local
+            .iter()
+            .filter(|q| {
+                (start.unix_timestamp()..=end.unix_timestamp()).contains(&(q.timestamp as i64))
             })
+            .collect();
+        // the value of a share before its first close is what was paid for it
+        let mut previous = n.buy.price
+            * match currency {
+                Currency::Local => 1.0,
+                _ => history.rate_at(&start),
+            };
+        for (i, quote) in quotes.iter().enumerate() {
+            // the shares sold are valued at the sell price on the last date
+            let value = match &n.sell {
+                Some(sell) if i + 1 == quotes.len() => sell.price * rate(quote),
+                _ => quote.close * rate(quote),
+            };
+            *daily.entry(Bar::of(quote, interval)).or_insert(0.0) +=
+                (value - previous) * n.quantity;
+            previous = value;
+        }
+    }
+    Ok(daily)
+}
+
+/// Holds the total return of the portfolio and the contribution of every ticker to it, both in percentage
+#[derive(Debug, Serialize)]
+pub struct Contribution {
+    pub total: f64,
+    pub holdings: BTreeMap<String, f64>,
+}
+
+/// Decomposes the total return into the contribution of every ticker, that is, its weight at the beginning of every day times its return that day,
+/// compounded by the growth of the portfolio up to then so that the contributions add up to the total return
+pub async fn contributions(item: &Portfolio) -> Result<Contribution, StocksError> {
+    let mut cumulative: f64 = 1.0;
+    let mut holdings = BTreeMap::new();
+    for positions in positions(&item.data_provider()?, item, Interval::Daily, Currency::Usd)
+        .await?
+        .values()
+    {
+        let cap = positions
+            .iter()
+            .fold(0.0, |acc, pos| acc + pos.old_price * pos.quantity);
+        let mut rate = 0.0;
+        for pos in positions.iter() {
+            // the daily return of the position weighted by its share of the portfolio
+            let contribution = (pos.price - pos.old_price) * pos.quantity / cap;
+            *holdings.entry(pos.ticker.clone()).or_insert(0.0) += cumulative * contribution * 100.0;
+            rate += pos.price * pos.quantity / cap;
+        }
+        cumulative *= rate;
+    }
+    Ok(Contribution {
+        total: (cumulative - 1.0) * 100.0,
+        holdings,
+    })
+}
+
+/// Rolls the cumulative daily returns in percentage up into calendar-month (YYYY-MM) or calendar-year (YYYY) returns in percentage
+pub fn aggregate(
+    returns: &BTreeMap<String, f64>,
+    aggregation: Aggregation,
+) -> BTreeMap<String, f64> {
+    if let Aggregation::Daily = aggregation {
+        return returns.clone();
+    }
+    within_periods(returns, aggregation)
+}
+
+// the returns within every period from the cumulative ones, daily periods included
+pub(crate) fn within_periods(
+    returns: &BTreeMap<String, f64>,
+    aggregation: Aggregation,
+) -> BTreeMap<String, f64> {
+    // the cumulative growth at the end of every period, the last date of the period overwriting the previous ones
+    let closing: BTreeMap<&str, f64> = returns
+        .iter()
+        .map(|(date, r)| (aggregation.period(date), 1.0 + r / 100.0))
+        .collect();
+    let mut previous = 1.0;
+    closing
+        .into_iter()
+        .map(|(period, value)| {
+            let rate = (value / previous - 1.0) * 100.0;
+            previous = value;
+            (period.to_string(), rate)
         })
-        .collect())
+        .collect()
+}
+
+/// Returns the length in years of the period covered by the returns
+pub fn years(returns: &BTreeMap<String, f64>) -> f64 {
+    // the intraday returns are keyed by their time as well
+    let date = |date: Option<&String>| date?.get(.."YYYY-MM-DD".len())?.parse::<NaiveDate>().ok();
+    match (date(returns.keys().next()), date(returns.keys().last())) {
+        (Some(first), Some(last)) => (last - first).num_days() as f64 / 365.25,
+        _ => 0.0,
+    }
+}
+
+/// Returns the compound annual growth rate in percentage of the cumulative returns, zero if they don't span any time, as
+/// with a single date
+pub fn cagr(returns: &BTreeMap<String, f64>) -> f64 {
+    let years = years(returns);
+    if years <= 0.0 {
+        return 0.0;
+    }
+    let growth = 1.0 + returns.values().last().unwrap_or(&0.0) / 100.0;
+    (growth.powf(1.0 / years) - 1.0) * 100.0
+}
+
+/// Holds the average return per period (day, month or year) in percentage, both compounded and simple
+#[derive(Debug, Serialize)]
+pub struct MeanReturn {
+    pub period: Aggregation,
+    // the constant return that compounds into the total return, lower than the arithmetic one whenever returns vary
+    pub geometric: f64,
+    // the plain average of the returns, which doesn't compound into the total return
+    pub arithmetic: f64,
+    pub periods: usize,
+}
+
+/// Returns the geometric and arithmetic means of the returns within every period of the cumulative returns in percentage,
+/// both zero if there are no periods
+pub fn mean_return(returns: &BTreeMap<String, f64>, aggregation: Aggregation) -> MeanReturn {
+    let periodic: Vec<f64> = within_periods(returns, aggregation)
+        .values()
+        .map(|r| r / 100.0)
+        .collect();
+    if periodic.is_empty() {
+        return MeanReturn {
+            period: aggregation,
+            geometric: 0.0,
+            arithmetic: 0.0,
+            periods: 0,
+        };
+    }
+    let n = periodic.len() as f64;
+    let growth: f64 = periodic.iter().map(|r| 1.0 + r).product();
+    MeanReturn {
+        period: aggregation,
+        geometric: (growth.powf(1.0 / n) - 1.0) * 100.0,
+        arithmetic: periodic.iter().sum::<f64>() / n * 100.0,
+        periods: periodic.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+
+    // a quote at noon of the date, its adjusted close being lower as if there had been dividends after it
+    fn quote(year: i32, month: u32, day: u32, close: f64) -> Quote {
+        let timestamp = NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap())
+            .and_utc()
+            .timestamp() as u64;
+        Quote {
+            timestamp,
+            close,
+            adjclose: close * 0.9,
+            ..Quote::default()
+        }
+    }
+
+    fn buy(day: u8, price: f64) -> Transaction {
+        Transaction::new(TransactionDate::new(2023, 1, day), price)
+    }
+
+    fn histories(ticker: &str, quotes: Vec<Quote>) -> BTreeMap<&str, History> {
+        BTreeMap::from([(ticker, History::new(quotes, Vec::new()))])
+    }
+
+    #[test]
+    fn the_cumulative_pnl_of_a_sold_position_is_the_sell_less_the_buy() {
+        let item = Portfolio::new(vec![Equity::new(
+            "MSFT",
+            buy(2, 100.0),
+            Some(buy(5, 130.0)),
+            3.0,
+        )]);
+        let quotes = vec![
+            quote(2023, 1, 2, 101.0),
+            quote(2023, 1, 3, 110.0),
+            quote(2023, 1, 4, 120.0),
+            quote(2023, 1, 5, 125.0),
+            quote(2023, 1, 6, 128.0),
+        ];
+        let pnl = daily_pnl(
+            &item,
+            &histories("MSFT", quotes),
+            Interval::Daily,
+            Currency::Usd,
+        )
+        .unwrap();
+        let daily: Vec<f64> = pnl.values().copied().collect();
+        assert_eq!(daily, [3.0, 27.0, 30.0, 30.0]);
+        assert_eq!(daily.iter().sum::<f64>(), (130.0 - 100.0) * 3.0);
+    }
+
+    #[test]
+    fn the_pnl_of_the_partial_sales_adds_up_to_what_every_lot_made() {
+        let item = Portfolio::new(vec![Equity::new("MSFT", buy(2, 100.0), None, 10.0)
+            .with_sales(vec![Sale::new(buy(3, 115.0), 4.0)])]);
+        let quotes = vec![
+            quote(2023, 1, 2, 100.0),
+            quote(2023, 1, 3, 110.0),
+            quote(2023, 1, 4, 120.0),
+        ];
+        let pnl = daily_pnl(
+            &item,
+            &histories("MSFT", quotes),
+            Interval::Daily,
+            Currency::Usd,
+        )
+        .unwrap();
+        let total: f64 = pnl.values().sum();
+        // the 4 shares sold made 15 each and the 6 still held 20 each
+        assert_eq!(total, 4.0 * 15.0 + 6.0 * 20.0);
+    }
+
+    // a quote at noon of a day of January 2023 without dividends after it
+    fn priced(day: u32, close: f64) -> Quote {
+        Quote {
+            adjclose: close,
+            ..quote(2023, 1, day, close)
+        }
+    }
+
+    fn jan(day: u32) -> Bar {
+        Bar::Date(NaiveDate::from_ymd_opt(2023, 1, day).unwrap())
+    }
+
+    #[test]
+    fn the_positions_keep_their_last_price_on_the_days_their_exchange_is_closed() {
+        let item = Portfolio::new(vec![
+            Equity::new("SPY", buy(6, 100.0), None, 1.0),
+            Equity::new("BTC-USD", buy(6, 20000.0), None, 1.0),
+        ]);
+        // the weekend of the 7th and 8th, which BTC-USD trades on
+        let histories = BTreeMap::from([
+            (
+                "SPY",
+                History::new(vec![priced(6, 100.0), priced(9, 110.0)], Vec::new()),
+            ),
+            (
+                "BTC-USD",
+                History::new(
+                    vec![
+                        priced(6, 20000.0),
+                        priced(7, 22000.0),
+                        priced(8, 22000.0),
+                        priced(9, 22000.0),
+                    ],
+                    Vec::new(),
+                ),
+            ),
+        ]);
+        let positions = value_positions(&item, &histories, Interval::Daily, false).unwrap();
+        assert_eq!(
+            positions.keys().copied().collect::<Vec<_>>(),
+            vec![jan(6), jan(7), jan(8), jan(9)]
+        );
+        for day in [7, 8] {
+            let spy = &positions[&jan(day)][0];
+            assert_eq!(
+                (spy.ticker.as_str(), spy.old_price, spy.price),
+                ("SPY", 100.0, 100.0)
+            );
+        }
+        let returns = super::cumulative(&positions);
+        assert!(close(
+            returns["2023-01-07"],
+            (22100.0 / 20100.0 - 1.0) * 100.0
+        ));
+        assert!(close(returns["2023-01-08"], returns["2023-01-07"]));
+        assert!(close(
+            returns["2023-01-09"],
+            (22110.0 / 20100.0 - 1.0) * 100.0
+        ));
+    }
+
+    #[test]
+    fn the_hedged_returns_grow_as_the_prices_did_in_their_own_currency() {
+        let item = Portfolio::new(vec![Equity::new("SAP.DE", buy(2, 100.0), None, 1.0)]);
+        // the euro goes from 1 to 1.2 USD as the shares go from 100 to 110 EUR
+        let histories = BTreeMap::from([(
+            "SAP.DE",
+            History::new(
+                vec![priced(2, 100.0), priced(3, 110.0)],
+                vec![priced(2, 1.0), priced(3, 1.2)],
+            ),
+        )]);
+        let usd = value_positions(&item, &histories, Interval::Daily, false).unwrap();
+        let local = value_positions(&item, &histories, Interval::Daily, true).unwrap();
+        let hedged = super::cumulative(&hedge(usd.clone(), &local));
+        assert!(close(super::cumulative(&usd)["2023-01-03"], 32.0));
+        assert!(close(super::cumulative(&local)["2023-01-03"], 10.0));
+        assert!(close(hedged["2023-01-03"], 10.0));
+    }
+
+    #[test]
+    fn the_weights_drift_with_the_prices_and_the_periods_keep_their_last_ones() {
+        let item = Portfolio::new(vec![
+            Equity::new("MSFT", buy(30, 100.0), None, 1.0),
+            Equity::new("KO", buy(30, 100.0), None, 3.0),
+        ]);
+        let february = |close| Quote {
+            adjclose: close,
+            ..quote(2023, 2, 1, close)
+        };
+        let histories = BTreeMap::from([
+            (
+                "MSFT",
+                History::new(vec![priced(30, 100.0), priced(31, 200.0)], Vec::new()),
+            ),
+            (
+                "KO",
+                History::new(
+                    vec![priced(30, 100.0), priced(31, 100.0), february(300.0)],
+                    Vec::new(),
+                ),
+            ),
+        ]);
+        let positions = value_positions(&item, &histories, Interval::Daily, false).unwrap();
+        let daily = weigh(&positions, Aggregation::Daily);
+        assert!(close(daily["2023-01-30"]["MSFT"], 25.0));
+        assert!(close(daily["2023-01-31"]["MSFT"], 40.0));
+        assert!(close(daily["2023-01-31"]["KO"], 60.0));
+        let monthly = weigh(&positions, Aggregation::Monthly);
+        assert_eq!(
+            monthly.keys().collect::<Vec<_>>(),
+            vec!["2023-01", "2023-02"]
+        );
+        assert!(close(monthly["2023-01"]["MSFT"], 40.0));
+        // MSFT is carried at its last price into February
+        assert!(close(monthly["2023-02"]["MSFT"], 200.0 / 1100.0 * 100.0));
+    }
+
+    fn cumulative(returns: &[(&str, f64)]) -> BTreeMap<String, f64> {
+        returns.iter().map(|(d, r)| (d.to_string(), *r)).collect()
+    }
+
+    fn close(x: f64, y: f64) -> bool {
+        (x - y).abs() < 1e-9
+    }
+
+    #[test]
+    fn the_returns_within_every_period_compound_into_the_total() {
+        let returns = cumulative(&[
+            ("2023-01-15", 10.0),
+            ("2023-01-31", 21.0),
+            ("2023-02-28", 33.1),
+            ("2024-01-02", 46.41),
+        ]);
+        let monthly = within_periods(&returns, Aggregation::Monthly);
+        assert_eq!(
+            monthly.keys().collect::<Vec<_>>(),
+            vec!["2023-01", "2023-02", "2024-01"]
+        );
+        assert!(close(monthly["2023-01"], 21.0));
+        assert!(close(monthly["2023-02"], 10.0));
+        assert!(close(monthly["2024-01"], 10.0));
+        let yearly = within_periods(&returns, Aggregation::Yearly);
+        assert!(close(yearly["2023"], 33.1));
+        assert!(close(yearly["2024"], 10.0));
+    }
+
+    #[test]
+    fn the_cagr_compounds_over_the_years_between_the_first_and_last_dates() {
+        // 1461 days, four years of 365.25 days
+        let returns = cumulative(&[("2020-01-01", 0.0), ("2024-01-01", 46.41)]);
+        assert!(close(years(&returns), 4.0));
+        assert!(close(cagr(&returns), 10.0));
+        // the intraday returns are keyed by their time too
+        let intraday = cumulative(&[("2020-01-01 09:30", 0.0), ("2020-01-02 16:00", 1.0)]);
+        assert!(close(years(&intraday), 1.0 / 365.25));
+        assert_eq!(years(&BTreeMap::new()), 0.0);
+    }
+
+    #[test]
+    fn the_cagr_of_returns_that_span_no_time_is_zero() {
+        assert_eq!(cagr(&BTreeMap::new()), 0.0);
+        assert_eq!(cagr(&cumulative(&[("2023-01-02", 5.0)])), 0.0);
+        let same_day = cumulative(&[("2023-01-02 09:30", 0.0), ("2023-01-02 16:00", 1.0)]);
+        assert_eq!(cagr(&same_day), 0.0);
+    }
+
+    #[test]
+    fn the_mean_returns_compound_into_the_total_and_are_zero_without_periods() {
+        let returns = cumulative(&[
+            ("2023-01-31", 10.0),
+            ("2023-02-28", 21.0),
+            ("2023-03-31", 21.0),
+        ]);
+        let mean = mean_return(&returns, Aggregation::Monthly);
+        assert_eq!(mean.periods, 3);
+        assert!(close(mean.arithmetic, 20.0 / 3.0));
+        assert!(close(
+            mean.geometric,
+            (1.21_f64.powf(1.0 / 3.0) - 1.0) * 100.0
+        ));
+        let none = mean_return(&BTreeMap::new(), Aggregation::Yearly);
+        assert_eq!(
+            (none.geometric, none.arithmetic, none.periods),
+            (0.0, 0.0, 0)
+        );
+    }
+
+    #[test]
+    fn reports_every_violation_of_the_portfolio() {
+        let equities = vec![
+            Equity::new(" ", buy(2, 100.0), None, 0.0),
+            Equity::new(
+                "MSFT",
+                Transaction::new(TransactionDate::new(2023, 2, 30), -1.0),
+                None,
+                1.0,
+            ),
+            Equity::new("KO", buy(5, 50.0), Some(buy(3, 55.0)), 2.0),
+            Equity::new("SPY", buy(2, 400.0), None, 1.5).with_sales(vec![
+                Sale::new(buy(3, 410.0), 1.0),
+                Sale::new(buy(4, 420.0), 1.0),
+            ]),
+        ];
+        let violations = Portfolio::new(equities).validate().unwrap_err();
+        let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "portfolio[0].ticker",
+                "portfolio[0].quantity",
+                "portfolio[1].buy.price",
+                "portfolio[1].buy.date",
+                "portfolio[2].sell.date",
+                "portfolio[3].sales",
+            ]
+        );
+        let fractional = Portfolio::new(vec![Equity::new("MSFT", buy(2, 100.0), None, 0.25)]);
+        assert!(fractional.validate().is_ok());
+    }
+
+    #[test]
+    fn merging_keeps_the_settings_of_the_first_portfolio() {
+        let first = Portfolio::new(vec![Equity::new("MSFT", buy(2, 100.0), None, 1.0)])
+            .with_rfr(0.03)
+            .with_benchmark("SPY");
+        let second =
+            Portfolio::new(vec![Equity::new("KO", buy(3, 50.0), None, 2.0)]).with_rfr(0.05);
+        let merged = first.merge(second);
+        let tickers: Vec<&str> = merged.equities().iter().map(Equity::ticker).collect();
+        assert_eq!(tickers, vec!["MSFT", "KO"]);
+        assert_eq!(merged.rfr(), 0.03);
+        assert_eq!(merged.benchmark(), Some("SPY"));
+    }
+
+    #[test]
+    fn the_diff_reports_the_equities_that_changed_and_the_shares_held() {
+        let msft = Equity::new("MSFT", buy(2, 100.0), None, 10.0);
+        let before = Portfolio::new(vec![
+            msft.clone(),
+            Equity::new("KO", buy(2, 50.0), None, 3.0),
+        ]);
+        let after = Portfolio::new(vec![
+            Equity::new("KO", buy(2, 50.0), None, 3.0),
+            msft.with_sales(vec![Sale::new(buy(4, 110.0), 4.0)]),
+            Equity::new("SPY", buy(5, 400.0), None, 0.5),
+        ]);
+        let diff = before.diff(&after);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].sales().len(), 0);
+        let added: Vec<&str> = diff.added.iter().map(Equity::ticker).collect();
+        assert_eq!(added, vec!["MSFT", "SPY"]);
+        assert_eq!(
+            diff.holdings.keys().collect::<Vec<_>>(),
+            vec!["MSFT", "SPY"]
+        );
+        assert_eq!(diff.holdings["MSFT"].before, 10.0);
+        assert_eq!(diff.holdings["MSFT"].after, 6.0);
+        assert_eq!(diff.holdings["SPY"].before, 0.0);
+        assert_eq!(diff.holdings["SPY"].after, 0.5);
+    }
 }
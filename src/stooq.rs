@@ -0,0 +1,174 @@
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::http::{client, send};
+use crate::provider::{timestamp, within, DataProvider, Metadata};
+use crate::yahoo_finance::{Interval, ProviderError, Quote};
+
+#[derive(Error, Debug)]
+pub enum StooqError {
+    #[error("the quotes from Stooq couldn't be read: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Stooq doesn't know the market of the ticker")]
+    UnknownMarket,
+    #[error("Stooq has no intraday quotes to download")]
+    Intraday,
+}
+
+/// Stooq, whose CSV downloads need no API key
+///
+/// Tickers are suffixed by their market as Stooq lists them, e.g., VOD.UK or SAP.DE, those without one being taken as US ones.
+/// The prices are already adjusted for splits and dividends, so the close and the adjusted close are the same.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stooq;
+
+// the markets Stooq suffixes the tickers with along with the currency they trade in
+const MARKETS: [(&str, &str); 6] = [
+    ("US", "USD"),
+    ("UK", "GBP"),
+    ("DE", "EUR"),
+    ("JP", "JPY"),
+    ("HK", "HKD"),
+    ("HU", "HUF"),
+];
+
+// the symbol of the ticker in Stooq and the currency of its market
+fn market(ticker: &str) -> Result<(String, &'static str), StooqError> {
+    let (symbol, suffix) = ticker.rsplit_once('.').unwrap_or((ticker, "US"));
+    MARKETS
+        .iter()
+        .find(|(m, _)| m.eq_ignore_ascii_case(suffix))
+        .map(|(m, currency)| (format!("{symbol}.{m}").to_lowercase(), *currency))
+        .ok_or(StooqError::UnknownMarket)
+}
+
+async fn download(
+    symbol: &str,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+    interval: Interval,
+) -> Result<Vec<Quote>, ProviderError> {
+    let day = |d: &OffsetDateTime| {
+        let date = d.date();
+        format!("{}{:02}{:02}", date.year(), date.month() as u8, date.day())
+    };
+    let interval = match interval {
+        Interval::Daily => "d",
+        Interval::Weekly => "w",
+        Interval::Monthly => "m",
+        Interval::Minute | Interval::FiveMinutes | Interval::Hourly => {
+            return Err(StooqError::Intraday.into())
+        }
+    };
+    let response = send(client()?.get(format!(
+        "https://stooq.com/q/d/l/?s={symbol}&d1={}&d2={}&i={interval}",
+        day(start),
+        day(end)
+    )))
+    .await?
+    .text()
+    .await?;
+    Ok(within(parse(&response)?, start, end))
+}
+
+// a header with the date, open, high, low, close and volume, a row per quote and "No data" for unknown tickers
+fn parse(csv: &str) -> Result<Vec<Quote>, StooqError> {
+    let mut quotes = Vec::new();
+    for record in ReaderBuilder::new().from_reader(csv.as_bytes()).records() {
+        let record = record?;
+        let field = |i: usize| record.get(i).and_then(|v| v.parse::<f64>().ok());
+        let (Some(date), Some(close)) = (
+            record.get(0).and_then(|d| d.parse::<NaiveDate>().ok()),
+            field(4),
+        ) else {
+            continue;
+        };
+        quotes.push(Quote {
+            timestamp: timestamp(date),
+            open: field(1).unwrap_or(0.0),
+            high: field(2).unwrap_or(0.0),
+            low: field(3).unwrap_or(0.0),
+            // indices have no volume
+            volume: field(5).unwrap_or(0.0) as u64,
+            close,
+            adjclose: close,
+            ..Default::default()
+        });
+    }
+    Ok(quotes)
+}
+
+impl DataProvider for Stooq {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let quotes = async {
+            let (symbol, _) = market(ticker)?;
+            download(&symbol, start, end, interval).await
+        };
+        quotes
+            .await
+            .map_err(|e: ProviderError| e.context(ticker, start, end))
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        let (symbol, currency) = market(ticker)?;
+        Ok(Metadata {
+            symbol: symbol.to_uppercase(),
+            currency: currency.to_string(),
+        })
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let pair = format!("{currency}USD").to_lowercase();
+        download(&pair, start, end, interval)
+            .await
+            .map_err(|e| e.context(&pair.to_uppercase(), start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_rows_of_the_download() {
+        let csv = "Date,Open,High,Low,Close,Volume\n\
+                   2024-05-02,168.1,170.0,167.5,169.3,4500000\n\
+                   2024-05-03,169.9,171.2,168.8,170.7,3900000\n";
+        let quotes = parse(csv).unwrap();
+        assert_eq!(quotes.len(), 2);
+        let first = NaiveDate::from_ymd_opt(2024, 5, 2).unwrap();
+        assert_eq!(quotes[0].timestamp, timestamp(first));
+        assert_eq!((quotes[0].close, quotes[0].adjclose), (169.3, 169.3));
+        assert_eq!((quotes[1].low, quotes[1].volume), (168.8, 3900000));
+    }
+
+    #[test]
+    fn indices_have_no_volume_and_unknown_tickers_no_rows() {
+        let csv = "Date,Open,High,Low,Close\n2024-05-03,5073.2,5139.1,5069.9,5127.8\n";
+        let quotes = parse(csv).unwrap();
+        assert_eq!((quotes[0].close, quotes[0].volume), (5127.8, 0));
+        assert!(parse("No data").unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_tickers_without_a_market_are_us_ones() {
+        assert_eq!(market("AAPL").unwrap(), ("aapl.us".to_string(), "USD"));
+        assert_eq!(market("SAP.de").unwrap(), ("sap.de".to_string(), "EUR"));
+        assert_eq!(market("BRK.B.US").unwrap(), ("brk.b.us".to_string(), "USD"));
+        assert!(matches!(market("BHP.AX"), Err(StooqError::UnknownMarket)));
+    }
+}
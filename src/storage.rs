@@ -0,0 +1,181 @@
+//! Persistence
+//!
+//! Saves portfolios in a SQLite database, only available with the sqlite feature, so that they don't have to be sent with
+//! every request. Every portfolio gets an id when it's created, and its return series is saved along with the day it was
+//! computed, so that it's only computed again the next day or when the portfolio is updated.
+//!
+//! Usage:
+//! ```no_run
+//! # use modus::stock_returns::Portfolio;
+//! # use modus::storage::{Storage, StorageError};
+//! # fn run(portfolio: Portfolio) -> Result<(), StorageError> {
+//!  let storage = Storage::open("modus.db")?;
+//!  let id = storage.create(&portfolio)?;
+//!  if let Some(portfolio) = storage.get(id)? { println!("{:?}", portfolio); }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chrono::{NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::stock_returns::Portfolio;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("the database query failed")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("the stored data couldn't be (de)serialized")]
+    Json(#[from] serde_json::Error),
+    #[error("the database connection is poisoned")]
+    Poisoned,
+}
+
+impl<T> From<PoisonError<T>> for StorageError {
+    fn from(_e: PoisonError<T>) -> Self {
+        StorageError::Poisoned
+    }
+}
+
+/// A saved portfolio along with its id
+#[derive(Debug, Serialize)]
+pub struct StoredPortfolio {
+    pub id: i64,
+    pub portfolio: Portfolio,
+}
+
+/// The database, safe to share between the workers of the server
+pub struct Storage {
+    connection: Mutex<Connection>,
+}
+
+impl Storage {
+    /// Opens the database at the path, creating it and its tables if they don't exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Storage, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS portfolios (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                portfolio TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS returns (
+                portfolio INTEGER PRIMARY KEY REFERENCES portfolios(id) ON DELETE CASCADE,
+                computed TEXT NOT NULL,
+                returns TEXT NOT NULL
+            );",
+        )?;
+        Ok(Storage {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>, StorageError> {
+        Ok(self.connection.lock()?)
+    }
+
+    /// Saves a new portfolio and returns its id
+    pub fn create(&self, portfolio: &Portfolio) -> Result<i64, StorageError> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT INTO portfolios (portfolio) VALUES (?1)",
+            params![serde_json::to_string(portfolio)?],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    /// Returns every saved portfolio
+    pub fn list(&self) -> Result<Vec<StoredPortfolio>, StorageError> {
+        let connection = self.connection()?;
+        let mut statement =
+            connection.prepare("SELECT id, portfolio FROM portfolios ORDER BY id")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut portfolios = Vec::new();
+        for row in rows {
+            let (id, portfolio) = row?;
+            portfolios.push(StoredPortfolio {
+                id,
+                portfolio: serde_json::from_str(&portfolio)?,
+            });
+        }
+        Ok(portfolios)
+    }
+
+    /// Returns the portfolio with that id, None if there isn't one
+    pub fn get(&self, id: i64) -> Result<Option<Portfolio>, StorageError> {
+        let portfolio: Option<String> = self
+            .connection()?
+            .query_row(
+                "SELECT portfolio FROM portfolios WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match portfolio {
+            Some(portfolio) => Some(serde_json::from_str(&portfolio)?),
+            None => None,
+        })
+    }
+
+    /// Replaces the portfolio with that id, discarding its saved returns, false if there isn't one
+    pub fn update(&self, id: i64, portfolio: &Portfolio) -> Result<bool, StorageError> {
+        let connection = self.connection()?;
+        let updated = connection.execute(
+            "UPDATE portfolios SET portfolio = ?1 WHERE id = ?2",
+            params![serde_json::to_string(portfolio)?, id],
+        )?;
+        connection.execute("DELETE FROM returns WHERE portfolio = ?1", params![id])?;
+        Ok(updated > 0)
+    }
+
+    /// Deletes the portfolio with that id along with its returns, false if there isn't one
+    pub fn delete(&self, id: i64) -> Result<bool, StorageError> {
+        Ok(self
+            .connection()?
+            .execute("DELETE FROM portfolios WHERE id = ?1", params![id])?
+            > 0)
+    }
+
+    /// Returns the saved returns of the portfolio if they were computed today
+    pub fn returns(&self, id: i64) -> Result<Option<BTreeMap<String, f64>>, StorageError> {
+        let saved: Option<(String, String)> = self
+            .connection()?
+            .query_row(
+                "SELECT computed, returns FROM returns WHERE portfolio = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(match saved {
+            Some((computed, returns)) if computed.parse::<NaiveDate>().ok() == Some(today()) => {
+                Some(serde_json::from_str(&returns)?)
+            }
+            _ => None,
+        })
+    }
+
+    /// Saves the returns of the portfolio as computed today
+    pub fn save_returns(
+        &self,
+        id: i64,
+        returns: &BTreeMap<String, f64>,
+    ) -> Result<(), StorageError> {
+        self.connection()?.execute(
+            "INSERT OR REPLACE INTO returns (portfolio, computed, returns) VALUES (?1, ?2, ?3)",
+            params![id, today().to_string(), serde_json::to_string(returns)?],
+        )?;
+        Ok(())
+    }
+}
+
+fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
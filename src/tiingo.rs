@@ -0,0 +1,248 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::http::{client, send};
+use crate::provider::{timestamp, within, DataProvider, Metadata};
+use crate::yahoo_finance::{Interval, ProviderError, Quote};
+
+#[derive(Error, Debug)]
+pub enum TiingoError {
+    #[error("deserializing the response from Tiingo failed: {0}")]
+    DeserializeFailed(#[from] serde_json::Error),
+    #[error("Tiingo refused the request: {0}")]
+    Refused(String),
+    #[error("the intraday quotes from Tiingo aren't supported")]
+    Intraday,
+}
+
+/// Tiingo, which needs an API key, read from MODUS_TIINGO_KEY by from_env
+///
+/// Tiingo's close is the one that was traded, unlike Yahoo's, which is adjusted for the splits that came later. The close is
+/// adjusted in the same way from the split factors, so that the buy and sell prices compare to it as they do with Yahoo,
+/// and its adjusted close, which also accounts for the dividends, is kept as it is.
+#[derive(Debug, Clone)]
+pub struct Tiingo {
+    key: String,
+}
+
+// the errors come as a detail in place of the data
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Response<T> {
+    Data(T),
+    Error { detail: String },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Price {
+    // at midnight, e.g., 2023-01-03T00:00:00.000Z
+    date: String,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    #[serde(default)]
+    volume: f64,
+    adj_close: f64,
+    #[serde(default = "no_split")]
+    split_factor: f64,
+}
+
+fn no_split() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Description {
+    ticker: String,
+    #[serde(default)]
+    exchange_code: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Rate {
+    date: String,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+}
+
+impl Tiingo {
+    pub fn new(key: impl Into<String>) -> Tiingo {
+        Tiingo { key: key.into() }
+    }
+
+    /// Returns the provider with the key in MODUS_TIINGO_KEY, None if it isn't set
+    pub fn from_env() -> Option<Tiingo> {
+        std::env::var("MODUS_TIINGO_KEY").ok().map(Tiingo::new)
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T, ProviderError> {
+        let response = send(
+            client()?
+                .get(format!("https://api.tiingo.com/{path}"))
+                .header("Authorization", format!("Token {}", self.key)),
+        )
+        .await?
+        .text()
+        .await?;
+        match serde_json::from_str(&response).map_err(TiingoError::from)? {
+            Response::Data(data) => Ok(data),
+            Response::Error { detail } => Err(TiingoError::Refused(detail).into()),
+        }
+    }
+}
+
+// the timestamp of the day the price is dated
+fn day(date: &str) -> u64 {
+    date.get(..10)
+        .and_then(|d| d.parse::<NaiveDate>().ok())
+        .map_or(0, timestamp)
+}
+
+// adjusts the close of every price for the splits after it, from the latest one backwards
+fn split_adjusted(prices: Vec<Price>) -> Vec<Quote> {
+    let mut factor = 1.0;
+    let mut quotes: Vec<Quote> = prices
+        .into_iter()
+        .rev()
+        .map(|p| {
+            let quote = Quote {
+                timestamp: day(&p.date),
+                open: p.open / factor,
+                high: p.high / factor,
+                low: p.low / factor,
+                volume: (p.volume * factor) as u64,
+                close: p.close / factor,
+                adjclose: p.adj_close,
+                ..Default::default()
+            };
+            // the split took place that day, so only the earlier prices are affected
+            factor *= p.split_factor;
+            quote
+        })
+        .collect();
+    quotes.reverse();
+    quotes
+}
+
+impl DataProvider for Tiingo {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let frequency = match interval {
+            Interval::Daily => "daily",
+            Interval::Weekly => "weekly",
+            Interval::Monthly => "monthly",
+            Interval::Minute | Interval::FiveMinutes | Interval::Hourly => {
+                return Err(ProviderError::from(TiingoError::Intraday).context(ticker, start, end))
+            }
+        };
+        // up to today, so that the splits after the end are accounted for
+        let prices = self
+            .get(&format!(
+                "tiingo/daily/{ticker}/prices?startDate={}&resampleFreq={frequency}",
+                start.date()
+            ))
+            .await
+            .map_err(|e| e.context(ticker, start, end))?;
+        Ok(within(split_adjusted(prices), start, end))
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        let description: Description = self.get(&format!("tiingo/daily/{ticker}")).await?;
+        Ok(Metadata {
+            // besides the US exchanges, Tiingo only covers the ones in Shanghai and Shenzhen
+            currency: match description.exchange_code.as_str() {
+                "SHG" | "SHE" => "CNY",
+                _ => "USD",
+            }
+            .to_string(),
+            symbol: description.ticker.to_uppercase(),
+        })
+    }
+
+    // the daily rates whatever the interval, the quotes taking the one of their date
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        _interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let pair = format!("{currency}USD");
+        let rates: Vec<Rate> = self
+            .get(&format!(
+                "tiingo/fx/{}/prices?startDate={}&endDate={}&resampleFreq=1day",
+                pair.to_lowercase(),
+                start.date(),
+                end.date()
+            ))
+            .await
+            .map_err(|e| e.context(&pair, start, end))?;
+        Ok(within(
+            rates
+                .into_iter()
+                .map(|r| Quote {
+                    timestamp: day(&r.date),
+                    open: r.open,
+                    high: r.high,
+                    low: r.low,
+                    volume: 0,
+                    close: r.close,
+                    adjclose: r.close,
+                    ..Default::default()
+                })
+                .collect(),
+            start,
+            end,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_closes_before_a_split_are_adjusted_for_it() {
+        let prices: Response<Vec<Price>> = serde_json::from_str(
+            r#"[
+                {"date": "2024-06-06T00:00:00.000Z", "open": 1200.0, "high": 1250.0, "low": 1190.0,
+                    "close": 1210.0, "volume": 1000, "adjClose": 120.9},
+                {"date": "2024-06-07T00:00:00.000Z", "open": 1220.0, "high": 1230.0, "low": 1200.0,
+                    "close": 1208.0, "volume": 2000, "adjClose": 120.7},
+                {"date": "2024-06-10T00:00:00.000Z", "open": 120.0, "high": 123.0, "low": 117.0,
+                    "close": 121.8, "volume": 30000, "adjClose": 121.8, "splitFactor": 10.0}
+            ]"#,
+        )
+        .unwrap();
+        let Response::Data(prices) = prices else {
+            panic!("the prices were read as an error");
+        };
+        let quotes = split_adjusted(prices);
+        let first = NaiveDate::from_ymd_opt(2024, 6, 6).unwrap();
+        assert_eq!(quotes[0].timestamp, timestamp(first));
+        assert_eq!((quotes[0].close, quotes[0].volume), (121.0, 10000));
+        assert_eq!((quotes[1].high, quotes[1].adjclose), (123.0, 120.7));
+        // the day of the split already trades at the new price
+        assert_eq!((quotes[2].close, quotes[2].volume), (121.8, 30000));
+    }
+
+    #[test]
+    fn the_refusals_come_as_a_detail() {
+        let response: Response<Vec<Price>> =
+            serde_json::from_str(r#"{"detail": "Invalid token."}"#).unwrap();
+        assert!(matches!(response, Response::Error { detail } if detail == "Invalid token."));
+        assert_eq!(day("not a date"), 0);
+    }
+}
@@ -0,0 +1,195 @@
+//! Point-in-time valuation
+//!
+//! Values every position held at the end of a given date: its market value at the close of that date (or the latest close before it),
+//! what was paid for it and the unrealized profit or loss, all of them in USD.
+//! The market value uses the exchange rate at that date and the cost basis the exchange rate at the date of buying, the rates of
+//! every currency being fetched once for all the positions in it. The closes and rates come from the provider of the portfolio,
+//! the closes of every ticker being fetched once, concurrently, for all its lots.
+//!
+//! The positions still held are valued right now by live_value, at the latest quotes and the exchange rates of the last
+//! close, along with how much they changed since the previous close, the day's profit or loss.
+//!
+//! Usage:
+//! ```no_run
+//! # use chrono::NaiveDate;
+//! # use modus::fx::FxService;
+//! # use modus::provider::Yahoo;
+//! # use modus::stock_returns::Portfolio;
+//! # use modus::valuation::{live_value, value_at};
+//! # async fn run(portfolio: Portfolio) {
+//!  if let Ok(s) = value_at(&portfolio, NaiveDate::from_ymd_opt(2021, 6, 30).unwrap()).await { println!("{:?}", s); }
+//!  if let Ok(s) = live_value(&portfolio, &FxService::new(&Yahoo)).await { println!("{:?}", s); }
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::NaiveDate;
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use time::{Duration, OffsetDateTime, Time};
+
+use crate::fx::FxService;
+use crate::market::live_quotes;
+use crate::provider::{DataProvider, Interval, ProviderError};
+use crate::stock_returns::{Portfolio, StocksError};
+
+// the tickers fetched at the same time
+const CONCURRENT_REQUESTS: usize = 8;
+
+/// Holds the valuation of every position held at the date and the totals, in USD
+#[derive(Debug, Serialize)]
+pub struct Valuation {
+    pub date: String,
+    pub positions: Vec<PositionValue>,
+    pub market_value: f64,
+    pub cost_basis: f64,
+    pub unrealized: f64,
+}
+
+/// Holds the quantity, the close price in the currency of the ticker and the valuation of a position, in USD
+#[derive(Debug, Serialize)]
+pub struct PositionValue {
+    pub ticker: String,
+    pub quantity: f64,
+    pub price: f64,
+    pub market_value: f64,
+    pub cost_basis: f64,
+    pub unrealized: f64,
+}
+
+/// Returns the market value, cost basis and unrealized profit or loss of every position held at the end of the date
+pub async fn value_at(item: &Portfolio, date: NaiveDate) -> Result<Valuation, StocksError> {
+    let end = OffsetDateTime::from_unix_timestamp(
+        date.and_hms_opt(23, 59, 59)
+            .unwrap_or_default()
+            .and_utc()
+            .timestamp(),
+    )?;
+    let provider = item.data_provider()?;
+    let fx = FxService::new(&provider);
+    let mut held = Vec::new();
+    for n in item.lots() {
+        let (buy, sell) = n.range()?;
+        if buy <= end && sell > end {
+            held.push((n, buy));
+        }
+    }
+    let tickers: BTreeSet<&str> = held.iter().map(|(n, _)| n.ticker()).collect();
+    let requests: Vec<_> = tickers
+        .into_iter()
+        .map(|ticker| {
+            let provider = &provider;
+            let end = &end;
+            async move { Ok::<_, ProviderError>((ticker, close_at(provider, ticker, end).await?)) }
+        })
+        .collect();
+    let closes: BTreeMap<&str, Option<(String, f64)>> = stream::iter(requests)
+        .buffered(CONCURRENT_REQUESTS)
+        .try_collect()
+        .await?;
+    let mut positions = Vec::new();
+    for (n, buy) in &held {
+        // the close of the last day the market was open, along with the currency it's in
+        let Some((currency, price)) = closes[n.ticker()].clone() else {
+            continue;
+        };
+        let market_value = price * fx.usd_rate(&currency, &end).await? * n.quantity();
+        let cost_basis = n.buy_price() * fx.usd_rate(&currency, buy).await? * n.quantity();
+        positions.push(PositionValue {
+            ticker: n.ticker().to_string(),
+            quantity: n.quantity(),
+            price,
+            market_value,
+            cost_basis,
+            unrealized: market_value - cost_basis,
+        });
+    }
+    let market_value = positions.iter().map(|p| p.market_value).sum();
+    let cost_basis = positions.iter().map(|p| p.cost_basis).sum();
+    Ok(Valuation {
+        date: date.to_string(),
+        positions,
+        market_value,
+        cost_basis,
+        unrealized: market_value - cost_basis,
+    })
+}
+
+// returns the currency of the ticker and its last close up to the date from the week before it, None if it has no quotes then
+async fn close_at<P: DataProvider>(
+    provider: &P,
+    ticker: &str,
+    date: &OffsetDateTime,
+) -> Result<Option<(String, f64)>, ProviderError> {
+    let start = *date - Duration::days(7);
+    let quotes = provider
+        .get_quotes(ticker, &start, date, Interval::Daily)
+        .await?;
+    match quotes.last() {
+        Some(quote) => Ok(Some((
+            provider.metadata(ticker).await?.currency,
+            quote.close,
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Holds the value of every position held at the latest quotes and its change since the previous close, in USD, along with
+/// the unix timestamp of the latest trade
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveValuation {
+    pub time: u64,
+    pub positions: Vec<LivePosition>,
+    pub market_value: f64,
+    pub day_change: f64,
+    pub day_change_percent: f64,
+}
+
+/// Holds the shares of a ticker held, its latest price in its currency and the value and change of the position, in USD
+#[derive(Debug, Clone, Serialize)]
+pub struct LivePosition {
+    pub ticker: String,
+    pub quantity: f64,
+    pub currency: String,
+    pub price: f64,
+    pub market_value: f64,
+    pub day_change: f64,
+}
+
+/// Returns the value of the shares held at the latest quotes and how much it changed since the previous close, the shares
+/// being converted to USD at the rates of the last close, which the FxService keeps from a call to the next
+pub async fn live_value<P: DataProvider>(
+    item: &Portfolio,
+    fx: &FxService<'_, P>,
+) -> Result<LiveValuation, StocksError> {
+    let quantities = item.open_quantities();
+    let quotes = live_quotes(quantities.keys().copied()).await?;
+    let now = OffsetDateTime::now_utc();
+    // the start of the day, so that the rates are those of the last close and are fetched once a day
+    let today = now.replace_time(Time::MIDNIGHT);
+    let mut positions = Vec::new();
+    for (ticker, quantity) in quantities {
+        let Some(quote) = quotes.get(ticker) else {
+            continue;
+        };
+        let rate = fx.usd_rate(&quote.currency, &today).await? * quantity;
+        positions.push(LivePosition {
+            ticker: ticker.to_string(),
+            quantity,
+            currency: quote.currency.clone(),
+            price: quote.price,
+            market_value: quote.price * rate,
+            day_change: quote.change * rate,
+        });
+    }
+    let market_value: f64 = positions.iter().map(|p| p.market_value).sum();
+    let day_change: f64 = positions.iter().map(|p| p.day_change).sum();
+    Ok(LiveValuation {
+        time: quotes.values().map(|q| q.time).max().unwrap_or_default(),
+        positions,
+        market_value,
+        day_change,
+        day_change_percent: day_change / (market_value - day_change) * 100.0,
+    })
+}
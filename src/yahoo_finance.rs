@@ -1,17 +1,39 @@
-use chrono::DateTime;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::Instant;
+
+use chrono::{DateTime, NaiveDate};
+use futures::future::try_join_all;
 use modus_derive::From;
-use reqwest::{Client, Error};
-use serde::{Deserialize, Serialize};
+use reqwest::header::{HeaderMap, COOKIE, SET_COOKIE};
+use reqwest::{Error, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 use thiserror::Error;
-use time::OffsetDateTime;
+use time::{Date, Duration, OffsetDateTime};
+use tracing::{debug, warn};
+
+use crate::alpha_vantage::AlphaVantageError;
+use crate::coingecko::CoinGeckoError;
+use crate::ecb::EcbError;
+use crate::files::FilesError;
+use crate::fixtures::FixturesError;
+use crate::fred::FredError;
+use crate::http::{client, send, send_once, transient};
+use crate::polygon::PolygonError;
+use crate::provider::{ConfigError, DataProvider, Metadata};
+use crate::stooq::StooqError;
+use crate::tiingo::TiingoError;
 
 #[derive(Error, Debug)]
 pub enum YahooError {
-    #[error("fetching the data from yahoo! finance failed")]
+    #[error("fetching the data from yahoo! finance failed: {0}")]
     FetchFailed(String),
-    #[error("deserializing response from yahoo! finance failed")]
+    #[error("deserializing response from yahoo! finance failed: {0}")]
     DeserializeFailed(#[from] serde_json::Error),
-    #[error("connection to yahoo! finance server failed")]
+    #[error("connection to yahoo! finance server failed: {0}")]
     ConnectionFailed(#[from] reqwest::Error),
     #[error("yahoo! finance return invalid JSON format")]
     InvalidJson,
@@ -53,13 +75,21 @@ impl YResponse {
         Ok(())
     }
 
-    pub fn from_json(json: serde_json::Value) -> Result<YResponse, YahooError> {
+    // no quotes at all, as for the tickers that have been delisted
+    fn is_empty(&self) -> bool {
+        self.chart
+            .result
+            .first()
+            .is_none_or(|stock| stock.timestamp.is_empty())
+    }
+
+    pub fn from_json(json: Value) -> Result<YResponse, YahooError> {
         Ok(serde_json::from_value(json)?)
     }
 
     pub fn quotes(&self) -> Result<Vec<Quote>, YahooError> {
         self.check_consistency()?;
-        let stock: &YQuoteBlock = &self.chart.result[0];
+        let stock: &YQuoteBlock = self.chart.result.first().ok_or(YahooError::EmptyDataSet)?;
         let mut quotes = Vec::new();
         let n = stock.timestamp.len();
         for i in 0..n {
@@ -69,17 +99,64 @@ impl YResponse {
                 quotes.push(q);
             }
         }
-        Ok(quotes)
+        Ok(match &stock.meta.trading_periods {
+            Some(periods) => fold(quotes, periods),
+            None => quotes,
+        })
+    }
+
+    // the latest prices of the pre-market and the post-market of the current day, from the bars of the extended hours
+    pub(crate) fn extended(&self) -> (Option<f64>, Option<f64>) {
+        let Some(stock) = self.chart.result.first() else {
+            return (None, None);
+        };
+        let Some(current) = &stock.meta.current_trading_period else {
+            return (None, None);
+        };
+        let close = &stock.indicators.quote[0].close;
+        let last = |period: &TradingPeriod| {
+            stock
+                .timestamp
+                .iter()
+                .zip(close)
+                .filter(|(t, _)| period.contains(**t))
+                .filter_map(|(_, c)| *c)
+                .next_back()
+        };
+        (last(&current.pre), last(&current.post))
+    }
+
+    pub fn events(&self) -> Events {
+        let Some(events) = self
+            .chart
+            .result
+            .first()
+            .and_then(|stock| stock.events.as_ref())
+        else {
+            return Events::default();
+        };
+        // the keys are timestamps as strings, which don't sort as numbers do
+        let mut events = Events {
+            dividends: events.dividends.values().cloned().collect(),
+            splits: events.splits.values().cloned().collect(),
+            capital_gains: events.capital_gains.values().cloned().collect(),
+        };
+        events.dividends.sort_by_key(|d| d.date);
+        events.splits.sort_by_key(|s| s.date);
+        events.capital_gains.sort_by_key(|c| c.date);
+        events
     }
 
     pub fn metadata(&self) -> Result<YMetaData, YahooError> {
         self.check_consistency()?;
-        let stock = &self.chart.result[0];
+        let stock = self.chart.result.first().ok_or(YahooError::EmptyDataSet)?;
         Ok(stock.meta.to_owned())
     }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
+/// A bar of quotes. The intraday bars from Yahoo also have the last price of the pre-market before the session, in the first bar
+/// of the day, and of the post-market after it, in the last one, the bars of the extended hours being folded into them
+#[derive(Debug, Default, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct Quote {
     pub timestamp: u64,
     pub open: f64,
@@ -88,21 +165,98 @@ pub struct Quote {
     pub volume: u64,
     pub close: f64,
     pub adjclose: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_market: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_market: Option<f64>,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct YChart {
+    // null when there's no data, e.g., for a delisted ticker
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub result: Vec<YQuoteBlock>,
-    pub error: Option<String>,
+    pub error: Option<YChartError>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+pub struct YChartError {
+    pub code: String,
+    pub description: String,
+}
+
+fn null_as_empty<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Option::<Vec<T>>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 #[derive(Deserialize, Debug)]
 pub struct YQuoteBlock {
     pub meta: YMetaData,
+    // missing when there are no quotes in the range
+    #[serde(default)]
     pub timestamp: Vec<u64>,
     pub indicators: QuoteBlock,
+    #[serde(default)]
+    pub events: Option<YEvents>,
+}
+
+// the events are keyed by their timestamp
+#[derive(Deserialize, Debug)]
+pub struct YEvents {
+    #[serde(default)]
+    pub dividends: BTreeMap<String, Dividend>,
+    #[serde(default)]
+    pub splits: BTreeMap<String, Split>,
+    #[serde(default, rename = "capitalGains")]
+    pub capital_gains: BTreeMap<String, CapitalGain>,
+}
+
+/// A dividend per share, dated on its ex-dividend date
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Dividend {
+    pub amount: f64,
+    pub date: u64,
+}
+
+/// A split, e.g., 4:1 for every share becoming 4, whose prices before it Yahoo already adjusts
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Split {
+    pub date: u64,
+    pub numerator: f64,
+    pub denominator: f64,
+    pub split_ratio: String,
 }
 
+impl Split {
+    /// The number of shares every share becomes
+    pub fn ratio(&self) -> f64 {
+        self.numerator / self.denominator
+    }
+}
+
+/// A capital gain distribution per share, as funds pay
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CapitalGain {
+    pub amount: f64,
+    pub date: u64,
+}
+
+/// The dividends, splits and capital gains of a ticker in chronological order
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Events {
+    pub dividends: Vec<Dividend>,
+    pub splits: Vec<Split>,
+    pub capital_gains: Vec<CapitalGain>,
+}
+
+#[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct YMetaData {
@@ -110,6 +264,74 @@ pub struct YMetaData {
     pub symbol: String,
     pub exchange_name: String,
     pub instrument_type: String,
+    // the latest price and the time it was traded at, along with the close before the range, which only some ranges have
+    pub regular_market_price: Option<f64>,
+    pub regular_market_time: Option<u64>,
+    pub chart_previous_close: Option<f64>,
+    // the sessions of every day in the range, split into the pre-market, regular and post-market ones when the extended hours were asked for
+    #[serde(default)]
+    trading_periods: Option<TradingPeriods>,
+    #[serde(default)]
+    current_trading_period: Option<CurrentTradingPeriod>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TradingPeriod {
+    start: u64,
+    end: u64,
+}
+
+impl TradingPeriod {
+    fn contains(&self, timestamp: u64) -> bool {
+        (self.start..self.end).contains(&timestamp)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum TradingPeriods {
+    Extended {
+        pre: Vec<Vec<TradingPeriod>>,
+        regular: Vec<Vec<TradingPeriod>>,
+        post: Vec<Vec<TradingPeriod>>,
+    },
+    // the regular sessions alone aren't needed
+    Regular(serde::de::IgnoredAny),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct CurrentTradingPeriod {
+    pre: TradingPeriod,
+    post: TradingPeriod,
+}
+
+// the bars of the regular sessions, the close of the last bar of every pre-market being the pre-market price of the first bar
+// of the session that follows and that of every post-market the post-market price of the last bar of the session before it
+fn fold(quotes: Vec<Quote>, periods: &TradingPeriods) -> Vec<Quote> {
+    let TradingPeriods::Extended { pre, regular, post } = periods else {
+        return quotes;
+    };
+    let within =
+        |periods: &[TradingPeriod], q: &Quote| periods.iter().any(|p| p.contains(q.timestamp));
+    let mut folded = Vec::new();
+    for ((pre, regular), post) in pre.iter().zip(regular).zip(post) {
+        let mut session: Vec<Quote> = quotes
+            .iter()
+            .filter(|q| within(regular, q))
+            .cloned()
+            .collect();
+        let last = |periods: &[TradingPeriod]| {
+            quotes.iter().rfind(|q| within(periods, q)).map(|q| q.close)
+        };
+        if let Some(first) = session.first_mut() {
+            first.pre_market = last(pre);
+        }
+        if let Some(close) = session.last_mut() {
+            close.post_market = last(post);
+        }
+        folded.extend(session);
+    }
+    folded
 }
 
 #[derive(Deserialize, Debug)]
@@ -121,9 +343,10 @@ pub struct QuoteBlock {
 
 impl QuoteBlock {
     fn get_ith_quote(&self, timestamp: u64, i: usize) -> Result<Quote, YahooError> {
+        // the intraday quotes have no adjusted close
         let adjclose = match &self.adjclose {
             Some(adjclose) => adjclose[0].adjclose[i],
-            None => None,
+            None => self.quote[0].close[i],
         };
         let quote = &self.quote[0];
         // reject if close is not set
@@ -138,16 +361,19 @@ impl QuoteBlock {
             volume: quote.volume[i].unwrap_or(0),
             close: quote.close[i].unwrap(),
             adjclose: adjclose.unwrap_or(0.0),
+            ..Default::default()
         })
     }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct AdjClose {
+    #[serde(default)]
     adjclose: Vec<Option<f64>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
 pub struct QuoteList {
     pub volume: Vec<Option<u64>>,
     pub high: Vec<Option<f64>>,
@@ -158,123 +384,956 @@ pub struct QuoteList {
 
 /// This custom error uses the custom derive macro From to implement the From trait
 ///
-/// Example:
-/// ```
-///  impl From<YahooError> for ProviderError {
-///      fn from (_e: YahooError) -> Self {
-///          ProviderError::YahooError
+/// Example, the impls being those the derive generates for the enum:
+/// ```no_run
+/// # use modus::provider::Timeout;
+/// # use modus::stock_returns::FetchError;
+/// # enum ProviderError {
+/// #     FetchError(FetchError),
+/// #     Timeout(Timeout),
+/// # }
+///  impl From<Timeout> for ProviderError {
+///      fn from (e: Timeout) -> Self {
+///          ProviderError::Timeout(e)
+///      }
+///  }
+///  impl From<FetchError> for ProviderError {
+///      fn from (e: FetchError) -> Self {
+///          ProviderError::FetchError(e)
 ///      }
 ///  }
 /// ```
-#[derive(From)]
+#[derive(From, Debug)]
 pub enum ProviderError {
-    Error,
-    YahooError,
+    Error(Error),
+    YahooError(YahooError),
+    FredError(FredError),
+    EcbError(EcbError),
+    AlphaVantageError(AlphaVantageError),
+    PolygonError(PolygonError),
+    StooqError(StooqError),
+    TiingoError(TiingoError),
+    CoinGeckoError(CoinGeckoError),
+    FilesError(FilesError),
+    FixturesError(FixturesError),
+    ConfigError(ConfigError),
+    FetchError(FetchError),
+    Timeout(Timeout),
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Error(e) => write!(f, "the request failed: {e}"),
+            // the errors of the providers already say which one failed and why
+            ProviderError::YahooError(e) => e.fmt(f),
+            ProviderError::FredError(e) => e.fmt(f),
+            ProviderError::EcbError(e) => e.fmt(f),
+            ProviderError::AlphaVantageError(e) => e.fmt(f),
+            ProviderError::PolygonError(e) => e.fmt(f),
+            ProviderError::StooqError(e) => e.fmt(f),
+            ProviderError::TiingoError(e) => e.fmt(f),
+            ProviderError::CoinGeckoError(e) => e.fmt(f),
+            ProviderError::FilesError(e) => e.fmt(f),
+            ProviderError::FixturesError(e) => e.fmt(f),
+            ProviderError::ConfigError(e) => write!(f, "the provider isn't configured: {e}"),
+            ProviderError::FetchError(e) => e.fmt(f),
+            ProviderError::Timeout(e) => e.fmt(f),
+        }
+    }
+}
+
+impl ProviderError {
+    // adds the ticker and the range the request was for, unless it already has them
+    pub(crate) fn context(
+        self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> ProviderError {
+        let date = |d: &OffsetDateTime| {
+            DateTime::from_timestamp(d.unix_timestamp(), 0)
+                .unwrap_or_default()
+                .date_naive()
+                .to_string()
+        };
+        match self {
+            // the time ran out for the whole request rather than for the ticker
+            ProviderError::FetchError(_) | ProviderError::Timeout(_) => self,
+            e => ProviderError::FetchError(FetchError {
+                ticker: ticker.to_string(),
+                start: date(start),
+                end: date(end),
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// The ticker and the range (YYYY-MM-DD) whose data couldn't be fetched, along with the reason
+#[derive(Debug, Clone, Serialize)]
+pub struct FetchError {
+    pub ticker: String,
+    pub start: String,
+    pub end: String,
+    pub message: String,
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fetching {} from {} to {} failed: {}",
+            self.ticker, self.start, self.end, self.message
+        )
+    }
+}
+
+/// The time the provider was given to respond, a request or all the requests for a portfolio, which ran out
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout(pub std::time::Duration);
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the provider didn't respond within {} seconds",
+            self.0.as_secs()
+        )
+    }
+}
+
+/// The time between two consecutive quotes
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Interval {
+    #[default]
+    #[serde(rename = "1d")]
+    Daily,
+    #[serde(rename = "1wk")]
+    Weekly,
+    #[serde(rename = "1mo")]
+    Monthly,
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    Hourly,
+}
+
+impl Interval {
+    // the value of the interval parameter of the chart API
+    fn code(&self) -> &'static str {
+        match self {
+            Interval::Daily => "1d",
+            Interval::Weekly => "1wk",
+            Interval::Monthly => "1mo",
+            Interval::Minute => "1m",
+            Interval::FiveMinutes => "5m",
+            Interval::Hourly => "1h",
+        }
+    }
+
+    /// Whether the quotes are within the day, the returns being then keyed by their time in UTC rather than their date
+    pub fn is_intraday(&self) -> bool {
+        matches!(
+            self,
+            Interval::Minute | Interval::FiveMinutes | Interval::Hourly
+        )
+    }
+
+    /// The number of quotes in a year, used to annualize figures computed at this interval
+    pub fn periods_per_year(&self) -> f64 {
+        match self {
+            Interval::Daily => 252.0,
+            Interval::Weekly => 52.0,
+            Interval::Monthly => 12.0,
+            // the 6.5 hours a day the US markets are open, the last hour being a half one
+            Interval::Minute => 252.0 * 390.0,
+            Interval::FiveMinutes => 252.0 * 78.0,
+            Interval::Hourly => 252.0 * 7.0,
+        }
+    }
+}
+
+// tickers that were renamed, by their old symbol, so that old transactions can still be fetched
+const ALIASES: [(&str, &str); 8] = [
+    ("FB", "META"),
+    ("ANTM", "ELV"),
+    ("FISV", "FI"),
+    ("WLTW", "WTW"),
+    ("CTL", "LUMN"),
+    ("UTX", "RTX"),
+    ("PEAK", "DOC"),
+    ("SQ", "XYZ"),
+];
+
+// the current symbol of the ticker
+pub(crate) fn symbol(ticker: &str) -> &str {
+    ALIASES
+        .iter()
+        .find(|(old, _)| old.eq_ignore_ascii_case(ticker))
+        .map_or(ticker, |(_, new)| new)
+}
+
+// the consent cookie and the crumb that goes with it, which Yahoo asks more and more requests for
+#[derive(Debug, Clone, PartialEq)]
+struct Session {
+    cookie: String,
+    crumb: String,
+}
+
+// the session shared by all the requests, None until one is opened and Some(None) if Yahoo didn't give one
+static SESSION: OnceLock<futures::lock::Mutex<Option<Option<Session>>>> = OnceLock::new();
+
+// gets the cookie Yahoo sets on its landing page and then the crumb for it, None if Yahoo gives neither. Neither request is
+// retried, as the one the session is for goes on without it rather than waiting out Yahoo's throttling twice
+async fn open() -> Option<Session> {
+    let response = send_once(client().ok()?.get("https://fc.yahoo.com"))
+        .await
+        .ok()?;
+    let cookie = cookie(response.headers())?;
+    let crumb = send_once(
+        client()
+            .ok()?
+            .get("https://query2.finance.yahoo.com/v1/test/getcrumb")
+            .header(COOKIE, &cookie),
+    )
+    .await
+    .ok()?
+    .error_for_status()
+    .ok()?
+    .text()
+    .await
+    .ok()?;
+    let crumb = crumb_in(&crumb);
+    debug!(opened = crumb.is_some(), "asked Yahoo for a session");
+    Some(Session {
+        cookie,
+        crumb: crumb?,
+    })
+}
+
+// the cookies the response sets, without their attributes, None if it sets none
+fn cookie(headers: &HeaderMap) -> Option<String> {
+    let cookie = headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok()?.split(';').next())
+        .collect::<Vec<_>>()
+        .join("; ");
+    (!cookie.is_empty()).then_some(cookie)
+}
+
+// the crumb in the body, None if it's a refusal, which comes as a page rather than a crumb
+fn crumb_in(body: &str) -> Option<String> {
+    let crumb = body.trim();
+    (!crumb.is_empty() && !crumb.contains(['<', ' '])).then(|| crumb.to_string())
+}
+
+// the current session, opening one the first time or when Yahoo refused the one the request was sent with, unless
+// another request has opened a new one in the meantime
+async fn session(refused: Option<&Option<Session>>) -> Option<Session> {
+    let mut current = SESSION.get_or_init(Default::default).lock().await;
+    match &*current {
+        Some(session) if refused != Some(session) => session.clone(),
+        _ => {
+            let session = open().await;
+            *current = Some(session.clone());
+            session
+        }
+    }
+}
+
+// whether Yahoo refused the session the request was sent with, as it does when it expires. A throttled request isn't one
+// of them, as a new session would only be throttled as well
+fn expired(status: StatusCode) -> bool {
+    matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+}
+
+// sends the request to Yahoo with the session, once more with a new one if Yahoo refuses it
+async fn with_session(url: &str) -> Result<Response, ProviderError> {
+    let client = client()?;
+    let request = |session: &Option<Session>| {
+        let request = client.get(url);
+        match session {
+            Some(Session { cookie, crumb }) => {
+                request.header(COOKIE, cookie).query(&[("crumb", crumb)])
+            }
+            None => request,
+        }
+    };
+    let current = session(None).await;
+    let response = send(request(&current)).await?;
+    match expired(response.status()) {
+        true => Ok(send(request(&session(Some(&current)).await)).await?),
+        false => Ok(response),
+    }
+}
+
+// the hosts Yahoo's API is served from, set by MODUS_YAHOO_HOSTS as a comma-separated list, query1.finance.yahoo.com
+// and query2.finance.yahoo.com by default
+fn hosts() -> &'static [String] {
+    static HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+    HOSTS.get_or_init(|| {
+        let hosts: Vec<String> = std::env::var("MODUS_YAHOO_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .map(str::to_string)
+            .collect();
+        match hosts.is_empty() {
+            true => vec![
+                "query1.finance.yahoo.com".to_string(),
+                "query2.finance.yahoo.com".to_string(),
+            ],
+            false => hosts,
+        }
+    })
+}
+
+// the hosts a request to the host is sent to in turn: the host itself, if it's one of the hosts of the API, and then the
+// others. The hosts that aren't for the API, such as the landing page, only have themselves
+fn failover(host: &str, hosts: &[String]) -> Vec<String> {
+    match host.starts_with("query") && host.ends_with(".finance.yahoo.com") {
+        true => std::iter::once(host.to_string())
+            .filter(|h| hosts.contains(h))
+            .chain(hosts.iter().filter(|h| *h != host).cloned())
+            .collect(),
+        false => vec![host.to_string()],
+    }
+}
+
+// sends the request to the host of the URL and, if it still fails or is throttled once the retries of send run out, to the
+// other hosts of the API in turn, as Yahoo throttles every host on its own
+async fn yahoo(url: &str) -> Result<Response, ProviderError> {
+    let mut url = Url::parse(url).map_err(|e| YahooError::FetchFailed(e.to_string()))?;
+    let hosts = failover(url.host_str().unwrap_or_default(), hosts());
+    for (i, host) in hosts.iter().enumerate() {
+        url.set_host(Some(host))
+            .map_err(|e| YahooError::FetchFailed(e.to_string()))?;
+        let response = with_session(url.as_str()).await;
+        let failed = match &response {
+            Ok(r) => transient(r.status()),
+            Err(e) => matches!(e, ProviderError::Error(_) | ProviderError::Timeout(_)),
+        };
+        if !failed || i == hosts.len() - 1 {
+            return response;
+        }
+        warn!(
+            host,
+            "Yahoo failed or throttled the request, trying the next host"
+        );
+    }
+    Err(YahooError::FetchFailed(format!("{url} has no host")).into())
 }
 
 async fn fuck_429(
     ticker: &str,
     start: &OffsetDateTime,
     end: &OffsetDateTime,
+    interval: Interval,
 ) -> Result<YResponse, ProviderError> {
-    let start = start.unix_timestamp();
-    let end = end.unix_timestamp();
-    // sends the petition to yahoo, a fairly common user agent is necessary because otherwise we get rate limited
-    let response = Client::new()
-        .get(&format!("https://query1.finance.yahoo.com/v8/finance/chart/{ticker}?symbol={ticker}&period1={start}&period2={end}&interval=1d&events=div%7Csplit%7CcapitalGains"))
-        .header("USER-AGENT", "curl/7.68.0")
-        .send()
-        .await
-        ?;
-    // serializes it and returns it
-    Ok(YResponse::from_json(
-        if let Ok(s) = serde_json::from_str(&response.text().await?) {
-            s
-        } else {
-            return Err(ProviderError::YahooError);
-        },
-    )?)
-}
-
-async fn yahoo_it(
-    ticker: &str,
+    let ticker = symbol(ticker);
+    let ranges = chunks(start.unix_timestamp(), end.unix_timestamp(), interval);
+    // the intraday bars of the extended hours, which the daily ones don't have, are folded into those of the sessions
+    let pre_post = interval.is_intraday();
+    let interval = interval.code();
+    // sends the petitions to yahoo at once, stitches them together, serializes them and returns them
+    let responses = try_join_all(ranges.into_iter().map(|(start, end)| {
+        let url = format!("https://query1.finance.yahoo.com/v8/finance/chart/{ticker}?symbol={ticker}&period1={start}&period2={end}&interval={interval}&includePrePost={pre_post}&events=div%7Csplit%7CcapitalGains");
+        async move { get_json::<Value>(&url).await }
+    }))
+    .await?;
+    Ok(YResponse::from_json(stitch(responses))?)
+}
+
+// the years of daily quotes asked for in a single request, set by MODUS_YAHOO_CHUNK_YEARS, 10 by default and 0 to ask for
+// any range at once, as Yahoo truncates the daily quotes of the longest ranges or times out on them
+fn chunk_years() -> u32 {
+    static YEARS: OnceLock<u32> = OnceLock::new();
+    *YEARS.get_or_init(|| {
+        std::env::var("MODUS_YAHOO_CHUNK_YEARS")
+            .ok()
+            .and_then(|years| years.parse().ok())
+            .unwrap_or(10)
+    })
+}
+
+// the consecutive ranges, each up to the chunk long, that a request for the daily quotes between the timestamps is split into
+fn chunks(start: i64, end: i64, interval: Interval) -> Vec<(i64, i64)> {
+    let length = chunk_years() as i64 * 365 * 24 * 60 * 60;
+    if interval != Interval::Daily || length == 0 || end - start <= length {
+        return vec![(start, end)];
+    }
+    (start..end)
+        .step_by(length as usize)
+        .map(|start| (start, (start + length).min(end)))
+        .collect()
+}
+
+// the responses for consecutive ranges as a single one, as if the whole range had been asked for at once: the quotes and
+// events of every response are appended to those of the ones before it, skipping the quotes already there, and the metadata
+// is that of the latest. The ranges without quotes, e.g., before the ticker was listed, are left out
+fn stitch(mut responses: Vec<Value>) -> Value {
+    let has_quotes = |response: &Value| {
+        response
+            .pointer("/chart/result/0/timestamp")
+            .and_then(Value::as_array)
+            .is_some_and(|timestamps| !timestamps.is_empty())
+    };
+    // with no quotes at all the response is that of the latest range, as it would have been for the whole one
+    let Some(first) = responses.iter().position(has_quotes) else {
+        return responses.pop().unwrap_or_default();
+    };
+    let mut stitched = responses[first].take();
+    for response in responses[first + 1..].iter().filter(|r| has_quotes(r)) {
+        if let (Some(into), Some(block)) = (
+            stitched.pointer_mut("/chart/result/0"),
+            response.pointer("/chart/result/0"),
+        ) {
+            append(into, block);
+        }
+    }
+    stitched
+}
+
+// appends the quotes of the block after the last one of the other, along with its events
+fn append(into: &mut Value, block: &Value) {
+    let last = into
+        .pointer("/timestamp")
+        .and_then(Value::as_array)
+        .and_then(|timestamps| timestamps.last())
+        .and_then(Value::as_i64)
+        .unwrap_or(i64::MIN);
+    let new: Vec<usize> = block
+        .pointer("/timestamp")
+        .and_then(Value::as_array)
+        .map(|timestamps| {
+            (0..timestamps.len())
+                .filter(|&i| timestamps[i].as_i64().is_some_and(|t| t > last))
+                .collect()
+        })
+        .unwrap_or_default();
+    for pointer in [
+        "/timestamp",
+        "/indicators/quote/0/open",
+        "/indicators/quote/0/high",
+        "/indicators/quote/0/low",
+        "/indicators/quote/0/close",
+        "/indicators/quote/0/volume",
+        "/indicators/adjclose/0/adjclose",
+    ] {
+        let Some(values) = block.pointer(pointer).and_then(Value::as_array) else {
+            continue;
+        };
+        if let Some(Value::Array(into)) = into.pointer_mut(pointer) {
+            into.extend(new.iter().filter_map(|&i| values.get(i).cloned()));
+        }
+    }
+    // the events are keyed by their timestamps, so the same event in both is only kept once. The block appended to may
+    // have no events, or none of the kind, so their maps are created rather than indexed into, which would leave a null
+    if let (Some(events), Some(into)) = (
+        block.get("events").and_then(Value::as_object),
+        into.as_object_mut(),
+    ) {
+        if let Some(into) = into
+            .entry("events")
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+        {
+            for (kind, events) in events {
+                if let (Some(into), Some(events)) = (
+                    into.entry(kind.as_str())
+                        .or_insert_with(|| Value::Object(Map::new()))
+                        .as_object_mut(),
+                    events.as_object(),
+                ) {
+                    into.extend(events.clone());
+                }
+            }
+        }
+    }
+    if let Some(meta) = block.get("meta") {
+        into["meta"] = meta.clone();
+    }
+}
+
+// the JSON Yahoo responds with to the request
+pub(crate) async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, ProviderError> {
+    Ok(serde_json::from_str(&yahoo(url).await?.text().await?)
+        .map_err(YahooError::DeserializeFailed)?)
+}
+
+/// The quotes of a ticker with the adjclose in USD, along with the exchange rates to USD over the same range,
+/// the latter being empty for tickers in USD, and the quotes as they were in the currency of the ticker
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub quotes: Vec<Quote>,
+    pub fx: Vec<Quote>,
+    pub local: Vec<Quote>,
+}
+
+impl History {
+    /// Converts the adjclose of the quotes to USD with the last exchange rate up to their date, or the first one for the quotes
+    /// before it, leaving them as they are if there are no exchange rates. Both are in chronological order
+    pub fn new(local: Vec<Quote>, fx: Vec<Quote>) -> History {
+        let Some(mut rate) = fx.first() else {
+            return History {
+                quotes: local.clone(),
+                fx,
+                local,
+            };
+        };
+        let mut rates = fx.iter().peekable();
+        let quotes = local
+            .iter()
+            .map(|q| {
+                // the rates are walked along with the quotes, so a quote is never converted at the rate of a later date
+                while let Some(next) = rates.next_if(|x| date(x) <= date(q)) {
+                    rate = next;
+                }
+                Quote {
+                    adjclose: q.adjclose * rate.adjclose,
+                    ..*q
+                }
+            })
+            .collect();
+        History { quotes, fx, local }
+    }
+
+    // the exchange rate at the date, or at the closest one before it if there's no quote that day
+    pub fn rate_at(&self, date: &OffsetDateTime) -> f64 {
+        self.rate_on(
+            DateTime::from_timestamp(date.unix_timestamp(), 0)
+                .unwrap_or_default()
+                .date_naive(),
+        )
+    }
+
+    pub fn rate_on(&self, date: NaiveDate) -> f64 {
+        last_on(&self.fx, date)
+            .or(self.fx.first())
+            .map(|q| q.close)
+            .unwrap_or(1.0)
+    }
+
+    // the close in the currency of the ticker at the date, or at the closest one before it, None before the first quote
+    pub fn close_at(&self, date: NaiveDate) -> Option<f64> {
+        last_on(&self.local, date).map(|q| q.close)
+    }
+}
+
+// the date of the quote in UTC
+fn date(quote: &Quote) -> NaiveDate {
+    DateTime::from_timestamp(quote.timestamp as i64, 0)
+        .unwrap_or_default()
+        .date_naive()
+}
+
+// the last of the quotes up to the date
+fn last_on(quotes: &[Quote], date: NaiveDate) -> Option<&Quote> {
+    quotes.iter().take_while(|q| self::date(q) <= date).last()
+}
+
+// the quotes of the exchange rate of the currency, the USD a unit of it is worth. Yahoo quotes the units of the currency
+// a USD is worth, so they're inverted, the low being the inverse of the high
+async fn fx_quotes(
+    currency: &str,
     start: &OffsetDateTime,
     end: &OffsetDateTime,
+    interval: Interval,
 ) -> Result<Vec<Quote>, ProviderError> {
-    // returns historic quotes with daily interval
-    let provider = fuck_429(&ticker, start, end).await?;
-    // gets the currency the data is in
-    let currency = provider.metadata()?.currency;
-    // converts the adjclose to USD
-    match currency.as_str() {
-        "USD" => Ok(provider.quotes()?),
-        _ => {
-            // returns the exchange rate for the relevant period
-            let currency_quotes = fuck_429(&format!("{}=X", currency), start, end)
-                .await?
-                .quotes()?;
-            // applies the exchange rate to adjclose
-            let usd_quotes: Vec<Quote> = provider
-                .quotes()?
-                .iter()
-                .map(|q| {
-                    let currency_quote = currency_quotes.iter().find(|x| {
-                        DateTime::from_timestamp(x.timestamp as i64, 0)
-                            .unwrap_or_default()
-                            .date_naive()
-                            == DateTime::from_timestamp(q.timestamp as i64, 0)
-                                .unwrap_or_default()
-                                .date_naive()
-                    });
-                    Quote {
-                        adjclose: q.adjclose
-                            * currency_quote
-                                .unwrap_or_else(|| currency_quotes.last().unwrap())
-                                .adjclose,
-                        ..*q
-                    }
-                })
-                .collect();
-            Ok(usd_quotes)
+    let fx = format!("{}=X", currency);
+    let key = (fx.clone(), start.date(), end.date(), interval);
+    if let Some(quotes) = RATES.get(&key) {
+        return Ok(quotes);
+    }
+    let quotes = fuck_429(&fx, start, end, interval)
+        .await
+        .and_then(|r| Ok(r.quotes()?))
+        .map_err(|e| e.context(&fx, start, end))?
+        .into_iter()
+        .map(|q| Quote {
+            open: 1.0 / q.open,
+            high: 1.0 / q.low,
+            low: 1.0 / q.high,
+            close: 1.0 / q.close,
+            adjclose: 1.0 / q.adjclose,
+            ..q
+        })
+        .collect::<Vec<_>>();
+    RATES.insert(key, quotes.clone());
+    Ok(quotes)
+}
+
+// the ticker, the dates of the range and the interval, the time of the day being left out so that the requests
+// held until now hit the cache all day
+type Key = (String, Date, Date, Interval);
+
+// the responses kept in memory, each one until its time to live runs out
+struct Cache<T> {
+    entries: OnceLock<Mutex<HashMap<Key, (Instant, T)>>>,
+}
+
+impl<T: Clone> Cache<T> {
+    const fn new() -> Cache<T> {
+        Cache {
+            entries: OnceLock::new(),
         }
     }
+
+    // a poisoned cache is still consistent as every entry is inserted at once
+    fn entries(&self) -> MutexGuard<'_, HashMap<Key, (Instant, T)>> {
+        self.entries
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self, key: &Key) -> Option<T> {
+        let ttl = ttl();
+        self.entries()
+            .get(key)
+            .filter(|(at, _)| at.elapsed() < ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    // drops the expired entries so that the cache doesn't grow forever
+    fn insert(&self, key: Key, value: T) {
+        let ttl = ttl();
+        if ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries();
+        entries.retain(|_, (at, _)| at.elapsed() < ttl);
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+static QUOTES: Cache<(String, Vec<Quote>)> = Cache::new();
+static RATES: Cache<Vec<Quote>> = Cache::new();
+
+// the time to live of the cache, set in seconds by MODUS_CACHE_TTL, 5 minutes by default and 0 to disable it
+fn ttl() -> std::time::Duration {
+    static TTL: OnceLock<std::time::Duration> = OnceLock::new();
+    *TTL.get_or_init(|| {
+        std::time::Duration::from_secs(
+            std::env::var("MODUS_CACHE_TTL")
+                .ok()
+                .and_then(|ttl| ttl.parse().ok())
+                .unwrap_or(300),
+        )
+    })
 }
 
-pub async fn get_quotes(
+// returns the currency along with the quotes in it, which come in the same response
+async fn get_local_quotes(
     ticker: &str,
     start: &OffsetDateTime,
     end: &OffsetDateTime,
-) -> Result<Vec<Quote>, ProviderError> {
-    yahoo_it(ticker, start, end).await
-}
-
-// returns the exchange rate at a specific date
-async fn price_at_date(ticker: &str, date: &OffsetDateTime) -> Result<f64, ProviderError> {
-    if let Some(c) = fuck_429(&format!("{}=X", ticker), date, date)
-        .await?
-        .quotes()?
-        .first()
-    {
-        Ok(c.close)
-    } else {
-        Err(ProviderError::YahooError)
+    interval: Interval,
+) -> Result<(String, Vec<Quote>), ProviderError> {
+    let key = (
+        symbol(ticker).to_string(),
+        start.date(),
+        end.date(),
+        interval,
+    );
+    if let Some(quotes) = QUOTES.get(&key) {
+        return Ok(quotes);
     }
+    let quotes = async {
+        let response = fuck_429(ticker, start, end, interval).await?;
+        // a delisted ticker has no quotes rather than failing the whole portfolio
+        if response.is_empty() {
+            return Ok(("USD".to_string(), Vec::new()));
+        }
+        Ok((response.metadata()?.currency, response.quotes()?))
+    };
+    let quotes = quotes
+        .await
+        .map_err(|e: ProviderError| e.context(ticker, start, end))?;
+    QUOTES.insert(key, quotes.clone());
+    Ok(quotes)
 }
 
-// returns the exchange rate with respect to the USD
-pub async fn check_currency(ticker: &str, date: &OffsetDateTime) -> Result<f64, ProviderError> {
-    if let Ok(s) = fuck_429(
-        ticker,
-        &OffsetDateTime::now_utc(),
-        &OffsetDateTime::now_utc(),
-    )
-    .await
-    {
-        if let Ok(r) = s.metadata() {
-            if r.currency.as_str().ne("USD") {
-                return price_at_date(r.currency.as_str(), date).await;
+/// Yahoo! Finance, the default provider, which needs no API key
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Yahoo;
+
+impl DataProvider for Yahoo {
+    async fn get_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        let quotes = async {
+            let response = fuck_429(ticker, start, end, interval).await?;
+            if response.is_empty() {
+                return Ok(Vec::new());
             }
+            Ok(response.quotes()?)
         };
+        quotes
+            .await
+            .map_err(|e: ProviderError| e.context(ticker, start, end))
+    }
+
+    async fn metadata(&self, ticker: &str) -> Result<Metadata, ProviderError> {
+        let metadata = get_latest(ticker).await?.0;
+        Ok(Metadata {
+            symbol: metadata.symbol,
+            currency: metadata.currency,
+        })
+    }
+
+    async fn fx_rates(
+        &self,
+        currency: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<Vec<Quote>, ProviderError> {
+        fx_quotes(currency, start, end, interval).await
+    }
+
+    // the quotes and the currency come in the same response
+    async fn get_local_quotes(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        interval: Interval,
+    ) -> Result<(String, Vec<Quote>), ProviderError> {
+        get_local_quotes(ticker, start, end, interval).await
+    }
+
+    async fn get_events(
+        &self,
+        ticker: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+    ) -> Result<(String, Events), ProviderError> {
+        get_events(ticker, start, end).await
+    }
+}
+
+// returns the currency and the dividends, splits and capital gains, in that currency, in the range
+pub async fn get_events(
+    ticker: &str,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+) -> Result<(String, Events), ProviderError> {
+    let events = async {
+        let response = fuck_429(ticker, start, end, Interval::Daily).await?;
+        // a delisted ticker has no events
+        if response.is_empty() {
+            return Ok(("USD".to_string(), Events::default()));
+        }
+        Ok((response.metadata()?.currency, response.events()))
+    };
+    events
+        .await
+        .map_err(|e: ProviderError| e.context(ticker, start, end))
+}
+
+// returns the metadata and the latest quote, in its own currency, from the last week of data
+pub async fn get_latest(ticker: &str) -> Result<(YMetaData, Quote), ProviderError> {
+    let now = OffsetDateTime::now_utc();
+    let start = now - Duration::days(7);
+    let latest = async {
+        let response = fuck_429(ticker, &start, &now, Interval::Daily).await?;
+        match response.quotes()?.last() {
+            Some(quote) => Ok((response.metadata()?, quote.clone())),
+            None => Err(ProviderError::from(YahooError::EmptyDataSet)),
+        }
     };
-    Ok(1.0)
+    latest.await.map_err(|e| e.context(ticker, &start, &now))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // a response for the days at the timestamps, with the events given
+    fn response(timestamps: &[u64], events: Option<Value>) -> Value {
+        let prices: Vec<f64> = timestamps.iter().map(|_| 10.0).collect();
+        let mut block = json!({
+            "meta": {
+                "currency": "USD",
+                "symbol": "MSFT",
+                "exchangeName": "NMS",
+                "instrumentType": "EQUITY",
+            },
+            "timestamp": timestamps,
+            "indicators": {
+                "quote": [{
+                    "open": prices,
+                    "high": prices,
+                    "low": prices,
+                    "close": prices,
+                    "volume": timestamps.iter().map(|_| 100).collect::<Vec<_>>(),
+                }],
+                "adjclose": [{"adjclose": prices}],
+            },
+        });
+        if let Some(events) = events {
+            block["events"] = events;
+        }
+        json!({"chart": {"result": [block], "error": null}})
+    }
+
+    #[test]
+    fn stitches_the_events_of_later_chunks_onto_a_chunk_without_events() {
+        let first = response(&[100, 200], None);
+        let second = response(
+            &[200, 300],
+            Some(json!({
+                "dividends": {"300": {"amount": 0.5, "date": 300}},
+                "splits": {"300": {"date": 300, "numerator": 2.0, "denominator": 1.0, "splitRatio": "2:1"}},
+            })),
+        );
+        let stitched = YResponse::from_json(stitch(vec![first, second])).unwrap();
+        let timestamps: Vec<u64> = stitched
+            .quotes()
+            .unwrap()
+            .iter()
+            .map(|q| q.timestamp)
+            .collect();
+        assert_eq!(timestamps, [100, 200, 300]);
+        let events = stitched.events();
+        assert_eq!(events.dividends.len(), 1);
+        assert_eq!(events.dividends[0].amount, 0.5);
+        assert_eq!(events.splits.len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_events_of_both_chunks_once() {
+        let dividend = |t: u64| json!({"amount": 0.5, "date": t});
+        let first = response(
+            &[100, 200],
+            Some(json!({"dividends": {"200": dividend(200)}})),
+        );
+        let second = response(
+            &[200, 300],
+            Some(json!({"dividends": {"200": dividend(200), "300": dividend(300)}})),
+        );
+        let stitched = YResponse::from_json(stitch(vec![first, second])).unwrap();
+        let dates: Vec<u64> = stitched.events().dividends.iter().map(|d| d.date).collect();
+        assert_eq!(dates, [200, 300]);
+    }
+
+    #[test]
+    fn leaves_out_the_chunks_without_quotes() {
+        let stitched = stitch(vec![response(&[], None), response(&[100, 200], None)]);
+        let timestamps: Vec<u64> = YResponse::from_json(stitched)
+            .unwrap()
+            .quotes()
+            .unwrap()
+            .iter()
+            .map(|q| q.timestamp)
+            .collect();
+        assert_eq!(timestamps, [100, 200]);
+    }
+
+    #[test]
+    fn the_cache_keeps_the_responses_until_they_expire() {
+        let cache: Cache<u64> = Cache::new();
+        let day = |d: u8| Date::from_calendar_date(2024, time::Month::May, d).unwrap();
+        let key = |ticker: &str| (ticker.to_string(), day(1), day(3), Interval::Daily);
+        cache.insert(key("MSFT"), 1);
+        assert_eq!(cache.get(&key("MSFT")), Some(1));
+        assert_eq!(
+            cache.get(&(key("MSFT").0, day(1), day(3), Interval::Weekly)),
+            None
+        );
+        // an entry older than its time to live is missed and dropped at the next insertion
+        let expired = Instant::now().checked_sub(ttl() * 2).unwrap();
+        cache.entries().insert(key("AAPL"), (expired, 2));
+        assert_eq!(cache.get(&key("AAPL")), None);
+        cache.insert(key("SPY"), 3);
+        assert!(!cache.entries().contains_key(&key("AAPL")));
+        assert_eq!(cache.entries().len(), 2);
+    }
+
+    #[test]
+    fn the_session_is_the_cookies_set_and_a_crumb() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(cookie(&headers), None);
+        headers.append(
+            SET_COOKIE,
+            "A3=d=AQABBK; Expires=Sat, 15 May 2027; Domain=.yahoo.com"
+                .parse()
+                .unwrap(),
+        );
+        headers.append(
+            SET_COOKIE,
+            "A1S=d=AQABBK&S=AQAAAs; Domain=.yahoo.com".parse().unwrap(),
+        );
+        assert_eq!(
+            cookie(&headers).unwrap(),
+            "A3=d=AQABBK; A1S=d=AQABBK&S=AQAAAs"
+        );
+        assert_eq!(crumb_in("  fG4.qXh2/Ab\n").unwrap(), "fG4.qXh2/Ab");
+        assert_eq!(crumb_in("Too Many Requests"), None);
+        assert_eq!(crumb_in("<html><body>Unauthorized</body></html>"), None);
+        assert_eq!(crumb_in(""), None);
+    }
+
+    #[test]
+    fn the_quotes_before_the_first_rate_are_converted_at_it() {
+        let day = 24 * 60 * 60;
+        let quote = |d: u64, adjclose: f64| Quote {
+            timestamp: d * day,
+            close: adjclose,
+            adjclose,
+            ..Default::default()
+        };
+        let history = History::new(
+            vec![quote(1, 10.0), quote(2, 10.0), quote(3, 10.0)],
+            vec![quote(2, 2.0), quote(3, 3.0)],
+        );
+        let usd: Vec<f64> = history.quotes.iter().map(|q| q.adjclose).collect();
+        assert_eq!(usd, [20.0, 20.0, 30.0]);
+        assert_eq!(history.rate_on(date(&quote(1, 0.0))), 2.0);
+        let unconverted = History::new(vec![quote(1, 10.0)], Vec::new());
+        assert_eq!(unconverted.quotes, unconverted.local);
+    }
+
+    #[test]
+    fn the_requests_fail_over_to_the_other_hosts_of_the_api() {
+        let hosts = ["query1.finance.yahoo.com", "query2.finance.yahoo.com"].map(str::to_string);
+        assert_eq!(
+            failover("query2.finance.yahoo.com", &hosts),
+            ["query2.finance.yahoo.com", "query1.finance.yahoo.com"]
+        );
+        // a host left out of MODUS_YAHOO_HOSTS isn't tried
+        assert_eq!(failover("query3.finance.yahoo.com", &hosts), hosts);
+        assert_eq!(failover("fc.yahoo.com", &hosts), ["fc.yahoo.com"]);
+        // the sessions are opened again when they expire, not when they're throttled
+        assert!(expired(StatusCode::UNAUTHORIZED) && expired(StatusCode::FORBIDDEN));
+        assert!(!expired(StatusCode::TOO_MANY_REQUESTS));
+    }
+
+    #[test]
+    fn splits_only_the_daily_ranges_longer_than_a_chunk() {
+        let year = 365 * 24 * 60 * 60;
+        assert_eq!(chunks(0, year, Interval::Daily), [(0, year)]);
+        assert_eq!(chunks(0, year, Interval::Weekly), [(0, year)]);
+        let split = chunks(0, 25 * year, Interval::Daily);
+        assert_eq!(
+            split,
+            [
+                (0, 10 * year),
+                (10 * year, 20 * year),
+                (20 * year, 25 * year)
+            ]
+        );
+    }
 }